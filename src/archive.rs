@@ -0,0 +1,158 @@
+use crate::errors::*;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+use walkdir::WalkDir;
+
+/// How often the unpacked size is checked while bsdtar is running
+const SIZE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Extensions of archives and images that bsdtar knows how to unpack
+const EXTENSIONS: &[&str] = &[
+    ".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz2", ".tar.xz", ".txz", ".tar.zst", ".zip", ".jar",
+    ".xpi", ".crx", ".7z", ".rar", ".cpio", ".iso", ".img",
+];
+
+#[must_use]
+pub fn is_archive(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    let name = name.to_ascii_lowercase();
+    EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+/// The path of a file in `dir` as if the archive was a directory, used to match excludes
+#[must_use]
+pub fn logical_path(dir: &Path, archive: &Path, path: &Path) -> Option<PathBuf> {
+    let member = path.strip_prefix(dir).ok()?;
+    if member.as_os_str().is_empty() {
+        Some(archive.to_path_buf())
+    } else {
+        Some(archive.join(member))
+    }
+}
+
+/// An archive that was unpacked into a temporary directory for the duration of a scan
+pub struct Extracted {
+    pub archive: PathBuf,
+    path: PathBuf,
+    /// Removed when this is dropped, `None` if another process unpacked the archive
+    _dir: Option<TempDir>,
+}
+
+/// Combined size of the files below a directory
+fn unpacked_size(dir: &Path) -> u64 {
+    WalkDir::new(dir)
+        .into_iter()
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|md| md.len())
+        .sum()
+}
+
+impl Extracted {
+    /// Unpack the archive, bsdtar is stopped once the members take up more than `max_size` bytes
+    pub fn new(archive: &Path, max_size: u64) -> Result<Extracted> {
+        let dir = tempfile::Builder::new()
+            .prefix("libredefender-archive-")
+            .tempdir()
+            .context("Failed to create temporary directory")?;
+
+        info!("Unpacking archive {}...", archive.display());
+        let mut child = Command::new("bsdtar")
+            .arg("-xf")
+            .arg(archive)
+            .arg("-C")
+            .arg(dir.path())
+            .args(["--no-same-owner", "--no-same-permissions"])
+            .spawn()
+            .context("Failed to run bsdtar")?;
+        let status = loop {
+            let status = child.try_wait().context("Failed to wait for bsdtar")?;
+            // checked once more after bsdtar exited, small archives finish before the first check
+            if unpacked_size(dir.path()) > max_size {
+                child.kill().ok();
+                child.wait().ok();
+                bail!("Archive unpacks to more than {} bytes", max_size);
+            }
+            if let Some(status) = status {
+                break status;
+            }
+            thread::sleep(SIZE_CHECK_INTERVAL);
+        };
+        if !status.success() {
+            bail!("bsdtar exited with {}", status);
+        }
+
+        Ok(Extracted {
+            archive: archive.to_path_buf(),
+            path: dir.path().to_path_buf(),
+            _dir: Some(dir),
+        })
+    }
+
+    /// An archive that another process unpacked into `path`, it's not removed by this one
+    #[must_use]
+    pub fn unpacked_in(archive: PathBuf, path: PathBuf) -> Extracted {
+        Extracted {
+            archive,
+            path,
+            _dir: None,
+        }
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Map a path in the temporary directory back to the member name inside the archive
+    #[must_use]
+    pub fn member<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        path.strip_prefix(&self.path).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_archive() {
+        assert!(is_archive(Path::new("/tmp/backup.tar.gz")));
+        assert!(is_archive(Path::new("/tmp/INSTALL.ZIP")));
+        assert!(is_archive(Path::new("disk.img")));
+        assert!(is_archive(Path::new("uBlock0@raymondhill.net.xpi")));
+        assert!(!is_archive(Path::new("/tmp/invoice.pdf")));
+        assert!(!is_archive(Path::new("/")));
+    }
+
+    #[test]
+    fn test_logical_path() {
+        let dir = Path::new("/tmp/libredefender-archive-abc");
+        let archive = Path::new("/home/user/backup.tar.gz");
+        assert_eq!(
+            logical_path(
+                dir,
+                archive,
+                Path::new("/tmp/libredefender-archive-abc/src/main.rs")
+            ),
+            Some(PathBuf::from("/home/user/backup.tar.gz/src/main.rs"))
+        );
+        assert_eq!(logical_path(dir, archive, dir), Some(archive.to_path_buf()));
+        assert_eq!(logical_path(dir, archive, Path::new("/tmp/other")), None);
+    }
+
+    #[test]
+    fn test_unpacked_size() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("a")).unwrap();
+        std::fs::write(dir.path().join("a/b"), [0; 300]).unwrap();
+        std::fs::write(dir.path().join("c"), [0; 12]).unwrap();
+        assert_eq!(unpacked_size(dir.path()), 312);
+    }
+}
@@ -1,6 +1,12 @@
+use crate::check::Age;
+use crate::config::HumanSize;
 use crate::errors::*;
-use clap::{ArgAction, CommandFactory, Parser};
+use crate::patterns::Pattern;
+use crate::syslog::LogTarget;
+use chrono::NaiveDate;
+use clap::{ArgAction, CommandFactory, Parser, ValueEnum};
 use clap_complete::Shell;
+use std::fs;
 use std::io::stdout;
 use std::path::PathBuf;
 
@@ -14,26 +20,127 @@ pub struct Args {
     pub verbose: u8,
     #[clap(short = 'C', long, global = true)]
     pub colors: bool,
+    /// Keep the database, quarantine and audit log in this directory instead of ~/.local/share
     #[clap(short = 'D', long, global = true)]
     pub data: Option<PathBuf>,
+    /// Load the config from this file instead of ~/.config/libredefender.toml
+    #[clap(short = 'c', long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Write a chrome trace of the spans to this file, open it in chrome://tracing or perfetto
+    #[clap(long, global = true)]
+    pub trace_output: Option<PathBuf>,
+    /// Where logs are written to, the scheduler defaults to `schedule.log_target`
+    #[clap(long, global = true, value_enum)]
+    pub log_target: Option<LogTarget>,
     #[clap(subcommand)]
     pub subcommand: Option<SubCommand>,
 }
 
 #[derive(Parser)]
 pub enum SubCommand {
+    /// Show the current status, this is the default if no subcommand is given
+    Status(Status),
     /// Scan directories for signature matches
     Scan(Scan),
+    /// Report the status with nagios/icinga plugin exit codes and perfdata
+    Check(Check),
+    /// Scan the layers of a container image
+    ScanImage(ScanImage),
     /// Run a background service that scans periodically
     Scheduler(Scheduler),
+    /// Watch directories and scan new files as soon as they're written
+    #[cfg(target_os = "linux")]
+    Watch(Watch),
     /// List threats that have been detected
     Infections(Infections),
-    /// Send a test notification
-    TestNotify,
+    /// List, inspect and restore quarantined files
+    #[clap(subcommand)]
+    Quarantine(Quarantine),
+    /// Generate a report of the last scan for compliance evidence
+    Report(Report),
+    /// Print the current status in the prometheus text format
+    Metrics(Metrics),
+    /// Add "Scan with libredefender" to the context menu of Dolphin and Nautilus
+    InstallFileManager(InstallFileManager),
+    /// Run the configured scans from systemd timers or cron instead of the scheduler
+    InstallTimer(InstallTimer),
+    /// Send a test notification, to check that every notification channel works
+    TestNotify(TestNotify),
+    /// Scan an EICAR test file to verify the scanner and notifications work
+    SelfTest(SelfTest),
+    /// Check the md5 and digital signature of the downloaded signature databases
+    VerifySignatures(VerifySignatures),
+    /// Show a notification in the current session (used to reach user sessions when running as root)
+    #[clap(hide = true)]
+    Notify(Notify),
+    /// Delete or quarantine files as root, started by `infections` through pkexec
+    #[clap(hide = true)]
+    HelperDelete(HelperDelete),
+    /// Scan in a separate process for `scan.user` and cgroup scopes, started by `scan`
+    #[cfg(target_os = "linux")]
+    #[clap(hide = true)]
+    ScanChild,
     /// Load the configuration and print it as json for debugging
-    DumpConfig,
+    DumpConfig(DumpConfig),
+    /// Validate the configuration
+    #[clap(subcommand)]
+    Config(Config),
+    /// Manage the excludes in the user config
+    #[clap(subcommand)]
+    Exclude(Exclude),
+    /// Show which rule causes a path to be skipped by scans
+    ExplainPath(ExplainPath),
+    /// Control a scan that was started with `scan --detach`
+    #[clap(subcommand)]
+    Ctl(Ctl),
+    /// Share the cache of known clean files between machines
+    #[clap(subcommand)]
+    Cache(Cache),
     /// Generate shell completions
     Completions(Completions),
+    /// Generate a man page for the command and every subcommand
+    Manpages(Manpages),
+    /// Print the version, with `--verbose` also libclamav, signature databases and build features
+    Version,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StatusFormat {
+    Text,
+    /// Everything the text output shows, for scripts and frontends
+    Json,
+    Waybar,
+    Template,
+}
+
+#[derive(Parser)]
+pub struct Status {
+    /// Output format, `waybar` emits a single json line for status bars
+    #[clap(long, value_enum, default_value_t = StatusFormat::Text)]
+    pub format: StatusFormat,
+    /// Used with `--format template`, supports {state}, {threats}, {last_scan}, {signatures} and {signatures_age}
+    #[clap(long, required_if_eq("format", "template"))]
+    pub template: Option<String>,
+    /// Shorthand for `--format json`
+    #[clap(long, conflicts_with = "format")]
+    pub json: bool,
+}
+
+#[derive(Parser)]
+pub struct Metrics {
+    /// Atomically replace this file instead of printing, e.g. in the textfile directory of node_exporter
+    #[clap(long)]
+    pub textfile: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+pub struct Check {
+    /// Warn if signatures are older than this, e.g. `3d` (defaults to `update.max_age_days`)
+    #[clap(long)]
+    pub max_sig_age: Option<Age>,
+    /// Warn if the last scan is older than this, e.g. `2d`
+    #[clap(long)]
+    pub max_scan_age: Option<Age>,
 }
 
 #[derive(Parser, Default)]
@@ -43,10 +150,106 @@ pub struct Scan {
     /// Configure the number of scanning threads, defaults to number of cpu cores
     #[clap(short = 'j', long)]
     pub concurrency: Option<usize>,
+    /// Only scan executables, scripts, documents and archives
+    #[clap(long)]
+    pub quick: bool,
+    /// Use the settings from a `[profile.<name>]` section of the config
+    #[clap(short, long)]
+    pub profile: Option<String>,
+    /// Also descend into fuse mounts, directories that were already visited are still skipped
+    #[clap(long)]
+    pub follow_mounts: bool,
+    /// Index all files first and scan the most recently modified ones first
+    #[clap(long)]
+    pub newest_first: bool,
+    /// Count all files first to show the percentage and remaining time, like `scan.estimate`
+    #[clap(long)]
+    pub estimate: bool,
+    /// Don't lower the cpu and io priority, for foreground scans that should finish quickly
+    #[clap(long, visible_alias = "fast")]
+    pub no_nice: bool,
+    /// List the files that couldn't be scanned after the scan finished
+    #[clap(long)]
+    pub show_skipped: bool,
+    /// Show the progress and results as desktop notifications, used by the file manager integration
+    #[clap(long)]
+    pub gui: bool,
+    /// Only report threats that weren't known before this scan, known ones don't notify, push, fire hooks or write audit entries
+    #[clap(long)]
+    pub diff: bool,
+    /// Skipped in addition to the configured excludes, set by `--mount-and-scan` for Windows partitions
+    #[clap(skip)]
+    pub excludes: Vec<Pattern>,
+    /// When running as root, switch to this user after loading the signatures and only keep read access to all files
+    #[clap(long)]
+    pub user: Option<String>,
+    /// Mount a block device or disk image read-only, scan it and unmount it again
+    #[clap(long, value_name = "DEVICE")]
+    pub mount_and_scan: Option<PathBuf>,
+    /// Scan the extensions, cached downloads and native messaging hosts of Firefox and Chromium profiles
+    #[clap(long, conflicts_with_all = &["paths", "mount_and_scan"])]
+    pub browser: bool,
+    /// Only traverse the paths and list what would be scanned or skipped, with the reason
+    #[clap(long)]
+    pub dry_run: bool,
+    /// Print the `--dry-run` listing as json
+    #[clap(long, requires = "dry_run")]
+    pub json: bool,
+    /// Fork to the background and write a pid file, for long scans over ssh
+    #[clap(long, conflicts_with_all = &["gui", "dry_run"])]
+    pub detach: bool,
+    /// Where a detached scan writes its output, defaults to scan.log in the data directory
+    #[clap(long, requires = "detach")]
+    pub log_file: Option<PathBuf>,
+    /// The scanned filesystem belongs to another system, ignore files in it are not honored
+    #[clap(skip)]
+    pub foreign: bool,
+}
+
+#[derive(Parser)]
+pub struct ScanImage {
+    /// Path to a docker-archive/oci-archive tarball, or docker://name to export from local storage
+    pub image: String,
+}
+
+#[derive(Parser)]
+pub struct Scheduler {
+    /// Run the scheduled scan once with all checks of the scheduler and exit, this is used by `install-timer`
+    #[clap(long)]
+    pub once: bool,
+    /// The `[[schedule.job]]` to run with --once
+    #[clap(long, requires = "once")]
+    pub job: Option<String>,
 }
 
 #[derive(Parser)]
-pub struct Scheduler {}
+pub struct InstallFileManager {
+    /// Remove the context menu entries instead
+    #[clap(long)]
+    pub uninstall: bool,
+}
+
+#[derive(Parser)]
+pub struct InstallTimer {
+    /// Install user units in ~/.config/systemd/user (default)
+    #[clap(long, group = "scope")]
+    pub user: bool,
+    /// Install system units in /etc/systemd/system that run the scans as root
+    #[clap(long, group = "scope")]
+    pub system: bool,
+    /// Write a crontab entry instead, for systems without systemd
+    #[clap(long)]
+    pub cron: bool,
+    /// Disable and remove the timers instead
+    #[clap(long)]
+    pub uninstall: bool,
+}
+
+#[derive(Parser)]
+pub struct Watch {
+    /// Directories that should be watched, defaults to the downloads folder
+    pub paths: Vec<PathBuf>,
+}
 
 #[derive(Parser)]
 pub struct Infections {
@@ -56,6 +259,190 @@ pub struct Infections {
     /// Delete all files without further confirmation (DANGER!)
     #[clap(long, group = "action")]
     pub delete_all: bool,
+    /// Move all files into the quarantine directory
+    #[clap(long, group = "action")]
+    pub quarantine: bool,
+    /// Export all threats with hashes to stdout
+    #[clap(long, value_enum, group = "action")]
+    pub export: Option<ExportFormat>,
+    /// Print the details of a false positive report for this file and open the ClamAV web form to submit it
+    #[clap(long, value_name = "PATH", group = "action")]
+    pub open_fp_form: Option<PathBuf>,
+    /// Scan all recorded threats again and remove the ones that aren't detected anymore
+    #[clap(long, group = "action")]
+    pub recheck: bool,
+}
+
+#[derive(Parser)]
+pub struct HelperDelete {
+    /// The database of the user, only files that are recorded as threats are removed
+    #[clap(long)]
+    pub database: PathBuf,
+    /// Move the files into this quarantine directory instead of deleting them
+    #[clap(long)]
+    pub move_to: Option<PathBuf>,
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Html,
+    Markdown,
+}
+
+#[derive(Parser)]
+pub struct Report {
+    /// Write the report to this file instead of stdout
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+    /// Defaults to html if the output file ends with .html, markdown otherwise
+    #[clap(long, value_enum)]
+    pub format: Option<ReportFormat>,
+    /// Only include threats first seen and scans run on or after this day, like 2024-01-01
+    #[clap(long)]
+    pub since: Option<NaiveDate>,
+    /// Only include threats first seen and scans run on or before this day, like 2024-03-31
+    #[clap(long)]
+    pub until: Option<NaiveDate>,
+}
+
+#[derive(Parser)]
+pub struct SelfTest {
+    /// Don't show a notification for the test detection
+    #[clap(long)]
+    pub no_notify: bool,
+}
+
+#[derive(Parser)]
+pub struct Notify {
+    #[clap(long)]
+    pub summary: String,
+    #[clap(long)]
+    pub body: String,
+}
+
+#[derive(Parser)]
+pub struct VerifySignatures {
+    /// Only check the md5 of the databases, skip the digital signature check with sigtool
+    #[clap(long)]
+    pub no_sigtool: bool,
+}
+
+#[derive(Parser)]
+pub struct DumpConfig {
+    /// Print every key with the source it was set by (default, system file, user file or env)
+    #[clap(long)]
+    pub origin: bool,
+}
+
+#[derive(Parser)]
+pub enum Config {
+    /// Load the config strictly and report unknown keys and invalid values
+    Check,
+}
+
+#[derive(Parser)]
+pub enum Ctl {
+    /// Show the progress of the running scan, or how the last one ended
+    Status,
+}
+
+#[derive(Parser)]
+pub enum Quarantine {
+    /// List the quarantined files with their id
+    List,
+    /// Show the original path, hash and timestamps of a quarantined file
+    Inspect { id: String },
+    /// Move a quarantined file back to its original path, existing files are never overwritten
+    Restore {
+        id: String,
+        /// Restore the file to this path instead
+        #[clap(long)]
+        to: Option<PathBuf>,
+    },
+    /// Delete the files beyond quarantine.retention_days and quarantine.max_size, like the scheduler
+    Prune {
+        /// Keep files for this many days instead of quarantine.retention_days
+        #[clap(long)]
+        retention_days: Option<u32>,
+        /// Limit the quarantine to this size instead of quarantine.max_size, e.g. "1GiB"
+        #[clap(long)]
+        max_size: Option<HumanSize>,
+    },
+}
+
+#[derive(Parser)]
+pub enum Cache {
+    /// Write the cache to a file, e.g. on a freshly scanned golden machine
+    Export { path: PathBuf },
+    /// Merge an exported cache, both machines need the same signature versions
+    Import { path: PathBuf },
+    /// Forget all known clean files
+    Clear,
+}
+
+#[derive(Parser)]
+pub enum Exclude {
+    /// Exclude a path or glob from scans, threats inside of it are removed from the database
+    Add {
+        /// e.g. ~/archive or */node_modules
+        pattern: String,
+    },
+    /// List the excludes that are in effect
+    List,
+    /// Remove an exclude from the user config
+    Remove { pattern: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum NotifyBackend {
+    /// Desktop notification, on the sessions of all logged in users if running as root
+    Dbus,
+    /// Print the notification, to preview how alerts look
+    Stdout,
+    Ntfy,
+    Matrix,
+    Telegram,
+}
+
+impl NotifyBackend {
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            NotifyBackend::Dbus => "dbus",
+            NotifyBackend::Stdout => "stdout",
+            NotifyBackend::Ntfy => "ntfy",
+            NotifyBackend::Matrix => "matrix",
+            NotifyBackend::Telegram => "telegram",
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct TestNotify {
+    /// Only use these backends, defaults to the desktop notification and all configured push services
+    #[clap(long = "backend", value_enum)]
+    pub backends: Vec<NotifyBackend>,
+    /// Defaults to the title of a detection of the EICAR test file
+    #[clap(long)]
+    pub summary: Option<String>,
+    #[clap(long)]
+    pub body: Option<String>,
+}
+
+#[derive(Parser)]
+pub struct ExplainPath {
+    /// The file or directory that wasn't scanned
+    pub path: PathBuf,
+    /// Use the settings from a `[profile.<name>]` section of the config
+    #[clap(short, long)]
+    pub profile: Option<String>,
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -74,3 +461,19 @@ impl Completions {
         Ok(())
     }
 }
+
+#[derive(Debug, Clone, Parser)]
+pub struct Manpages {
+    /// The pages are written to this directory, e.g. `libredefender-scan.1`
+    pub dir: PathBuf,
+}
+
+impl Manpages {
+    pub fn gen_manpages(&self) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| anyhow!("Failed to create {:?}", self.dir))?;
+        clap_mangen::generate_to(Args::command(), &self.dir)
+            .with_context(|| anyhow!("Failed to write man pages to {:?}", self.dir))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use crate::errors::*;
+use crate::privdrop;
+use crate::utils;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Detected,
+    Deleted,
+    Quarantined,
+    /// Moved out of the quarantine with `quarantine restore`
+    Restored,
+    /// A ransomware canary planted by `watch` was modified
+    CanaryTriggered,
+    /// Removed from the database without touching the file
+    Cleared,
+    /// Removed from the database because the path was added to the excludes
+    Allowlisted,
+}
+
+/// One line in the audit log, this file is only ever appended to
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    pub action: Action,
+    pub path: PathBuf,
+    pub names: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Entry {
+    #[must_use]
+    pub fn new(action: Action, path: &Path, names: &[String]) -> Entry {
+        Entry {
+            time: Utc::now(),
+            action,
+            path: path.to_path_buf(),
+            names: names.to_vec(),
+            detail: None,
+        }
+    }
+
+    #[must_use]
+    pub fn detail<I: Into<String>>(mut self, detail: I) -> Entry {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+pub fn path() -> Result<PathBuf> {
+    let path = utils::data_dir()?.join("audit.log");
+    Ok(path)
+}
+
+pub fn record(entry: &Entry) -> Result<()> {
+    let path = path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create audit log directory")?;
+    }
+
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+
+    // `watch` opens the log before dropping the privileges
+    let mut file = match privdrop::kept_open(&path) {
+        Some(file) => file?,
+        None => OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .with_context(|| anyhow!("Failed to open audit log: {:?}", path))?,
+    };
+    // write the line with a single syscall so concurrent writers don't interleave
+    file.write_all(line.as_bytes())
+        .context("Failed to write to audit log")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_entry() {
+        let mut entry = Entry::new(
+            Action::Quarantined,
+            Path::new("/tmp/eicar.txt"),
+            &["Win.Test.EICAR_HDB-1".to_string()],
+        )
+        .detail("0123456789abcdef");
+        entry.time = DateTime::parse_from_rfc3339("2021-05-09T11:08:36Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let json = serde_json::to_string(&entry).unwrap();
+        assert_eq!(
+            json,
+            r#"{"time":"2021-05-09T11:08:36Z","action":"quarantined","path":"/tmp/eicar.txt","names":["Win.Test.EICAR_HDB-1"],"detail":"0123456789abcdef"}"#
+        );
+    }
+}
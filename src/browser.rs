@@ -0,0 +1,228 @@
+use crate::args;
+use crate::errors::*;
+use crate::packages::{Manifest, Verdict};
+use crate::scan::{self, ScanSummary};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Firefox and its forks, relative to the home directory
+const FIREFOX: &[&str] = &[".mozilla/firefox", ".librewolf", ".waterfox"];
+/// Chromium based browsers, relative to `~/.config` and `~/.cache`
+const CHROMIUM: &[&str] = &[
+    "chromium",
+    "google-chrome",
+    "BraveSoftware/Brave-Browser",
+    "vivaldi",
+    "microsoft-edge",
+];
+/// Native messaging hosts installed for all users, by packages or by other software
+const SYSTEM_HOSTS: &[&str] = &[
+    "/usr/lib/mozilla/native-messaging-hosts",
+    "/usr/lib64/mozilla/native-messaging-hosts",
+    "/etc/chromium/native-messaging-hosts",
+    "/etc/opt/chrome/native-messaging-hosts",
+    "/etc/opt/edge/native-messaging-hosts",
+];
+
+fn glob_dirs(base: &Path, pattern: &str) -> Vec<PathBuf> {
+    let pattern = format!(
+        "{}/{}",
+        glob::Pattern::escape(&base.to_string_lossy()),
+        pattern
+    );
+    let Ok(paths) = glob::glob(&pattern) else {
+        return Vec::new();
+    };
+    let mut paths = paths.flatten().collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
+/// Directories that contain native messaging host manifests
+#[must_use]
+pub fn native_host_dirs(home: &Path, config: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![home.join(".mozilla/native-messaging-hosts")];
+    dirs.extend(
+        CHROMIUM
+            .iter()
+            .map(|name| config.join(name).join("NativeMessagingHosts")),
+    );
+    dirs.extend(SYSTEM_HOSTS.iter().map(PathBuf::from));
+    dirs.retain(|dir| dir.is_dir());
+    dirs
+}
+
+/// Extensions, cached downloads and native messaging hosts of all browser profiles that exist
+///
+/// Installed Firefox extensions are listed individually, so every `.xpi` is unpacked like an archive.
+#[must_use]
+pub fn paths(home: &Path, config: &Path, cache: &Path) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for firefox in FIREFOX {
+        paths.extend(glob_dirs(&home.join(firefox), "*/extensions/*"));
+        paths.extend(glob_dirs(
+            &cache.join(firefox.trim_start_matches('.')),
+            "*/cache2",
+        ));
+    }
+    for chromium in CHROMIUM {
+        paths.extend(glob_dirs(&config.join(chromium), "*/Extensions"));
+        paths.extend(glob_dirs(&config.join(chromium), "Webstore Downloads"));
+        paths.extend(glob_dirs(&cache.join(chromium), "*/Cache"));
+    }
+    paths.extend(native_host_dirs(home, config));
+    paths
+}
+
+/// A program that extensions are allowed to start, registered with a json manifest
+#[derive(Debug, PartialEq, Eq)]
+pub struct NativeHost {
+    pub manifest: PathBuf,
+    pub name: String,
+    pub binary: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct HostManifest {
+    name: String,
+    path: PathBuf,
+}
+
+/// Read the manifests in these directories, invalid ones are logged and skipped
+#[must_use]
+pub fn native_hosts(dirs: &[PathBuf]) -> Vec<NativeHost> {
+    let mut hosts = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        let mut manifests = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect::<Vec<_>>();
+        manifests.sort();
+        for path in manifests {
+            let manifest = fs::read(&path)
+                .map_err(Error::from)
+                .and_then(|buf| Ok(serde_json::from_slice::<HostManifest>(&buf)?));
+            match manifest {
+                Ok(manifest) => hosts.push(NativeHost {
+                    binary: dir.join(manifest.path),
+                    name: manifest.name,
+                    manifest: path,
+                }),
+                Err(err) => warn!("Failed to read native messaging host {:?}: {:#}", path, err),
+            }
+        }
+    }
+    hosts
+}
+
+/// Warn about native messaging hosts that run a program the package manager doesn't know about
+fn report_native_hosts(hosts: &[NativeHost]) {
+    if hosts.is_empty() {
+        return;
+    }
+    let manifest = match Manifest::load() {
+        Ok(Some(manifest)) => manifest,
+        Ok(None) => {
+            debug!("No package manager found, not checking native messaging hosts");
+            return;
+        }
+        Err(err) => {
+            warn!("Failed to read package manifests: {:#}", err);
+            return;
+        }
+    };
+    for host in hosts {
+        match manifest.verify(&host.binary) {
+            Ok(Verdict::Unmodified) => (),
+            Ok(Verdict::Unknown) => warn!(
+                "Native messaging host {:?} runs a binary that isn't installed by {}: {:?}",
+                host.name,
+                manifest.manager(),
+                host.binary
+            ),
+            Ok(Verdict::Modified) => warn!(
+                "Native messaging host {:?} runs a binary that was modified since it was installed: {:?}",
+                host.name, host.binary
+            ),
+            Err(err) => warn!(
+                "Failed to verify native messaging host {:?}: {:#}",
+                host.name, err
+            ),
+        }
+    }
+}
+
+/// Scan the browser profiles of the current user, the programs of native messaging hosts are scanned too
+pub fn scan(config_path: Option<&Path>, mut args: args::Scan) -> Result<ScanSummary> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let config = dirs::config_dir().context("Failed to find config directory")?;
+    let cache = dirs::cache_dir().context("Failed to find cache directory")?;
+
+    let paths = paths(&home, &config, &cache);
+    if paths.is_empty() {
+        bail!("No browser profiles found");
+    }
+    let hosts = native_hosts(&native_host_dirs(&home, &config));
+    report_native_hosts(&hosts);
+
+    args.paths.extend(paths);
+    args.paths.extend(
+        hosts
+            .into_iter()
+            .map(|host| host.binary)
+            .filter(|binary| binary.is_file()),
+    );
+    scan::run(config_path, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let home = dir.path().join("home");
+        let config = home.join(".config");
+        let cache = home.join(".cache");
+        let profile = home.join(".mozilla/firefox/abcd1234.default-release");
+        fs::create_dir_all(profile.join("extensions")).unwrap();
+        fs::write(profile.join("extensions/uBlock0@raymondhill.net.xpi"), b"").unwrap();
+        fs::create_dir_all(cache.join("mozilla/firefox/abcd1234.default-release/cache2")).unwrap();
+        fs::create_dir_all(config.join("chromium/Default/Extensions")).unwrap();
+        fs::create_dir_all(config.join("chromium/Profile 1")).unwrap();
+        let hosts = config.join("chromium/NativeMessagingHosts");
+        fs::create_dir_all(&hosts).unwrap();
+
+        let paths = paths(&home, &config, &cache);
+        assert_eq!(
+            &paths[..4],
+            &[
+                profile.join("extensions/uBlock0@raymondhill.net.xpi"),
+                cache.join("mozilla/firefox/abcd1234.default-release/cache2"),
+                config.join("chromium/Default/Extensions"),
+                hosts.clone(),
+            ]
+        );
+
+        fs::write(
+            hosts.join("com.example.helper.json"),
+            br#"{"name":"com.example.helper","description":"helper","path":"/opt/example/helper","type":"stdio"}"#,
+        )
+        .unwrap();
+        fs::write(hosts.join("broken.json"), b"{").unwrap();
+        assert_eq!(
+            native_hosts(std::slice::from_ref(&hosts)),
+            vec![NativeHost {
+                manifest: hosts.join("com.example.helper.json"),
+                name: "com.example.helper".to_string(),
+                binary: PathBuf::from("/opt/example/helper"),
+            }]
+        );
+    }
+}
@@ -0,0 +1,290 @@
+use crate::config::Config;
+use crate::errors::*;
+use crate::scan::EngineOptions;
+use crate::utils;
+use crate::verify;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tempfile::NamedTempFile;
+
+/// The on-disk format, also used for `cache export` and `cache import`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheFile {
+    signatures: String,
+    hashes: Vec<String>,
+}
+
+/// sha256 of files that were scanned without a detection, only valid for the signatures they were scanned with
+#[derive(Debug)]
+pub struct CleanCache {
+    signatures: String,
+    hashes: Mutex<HashSet<String>>,
+}
+
+/// `~/.local/share/libredefender/cache/clean.json`, in its own directory so it can be handed to `scan.user`
+pub fn path() -> Result<PathBuf> {
+    let path = utils::data_dir()?.join("cache").join("clean.json");
+    Ok(path)
+}
+
+/// Files that libclamav loads from a database directory
+const DATABASE_EXTENSIONS: &[&str] = &[
+    "cvd", "cld", "cud", "hdb", "hsb", "hdu", "hsu", "mdb", "msb", "mdu", "msu", "ndb", "ndu",
+    "ldb", "ldu", "idb", "cdb", "cbc", "pdb", "gdb", "wdb", "ftm", "cfg", "crb", "cat", "fp",
+    "sfp", "ign", "ign2", "imp", "pwdb", "yar", "yara", "info",
+];
+
+/// Every database file in these directories, or the paths themselves if they are files
+fn database_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_file() {
+            files.push(path.clone());
+            continue;
+        }
+        let entries = fs::read_dir(path)
+            .with_context(|| anyhow!("Failed to read database directory {:?}", path))?;
+        for entry in entries {
+            let path = entry?.path();
+            let is_database = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| DATABASE_EXTENSIONS.contains(&ext));
+            if is_database && path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Hash of the names and content of every loaded database file, covers custom and third-party signatures
+fn databases_digest(files: &[PathBuf]) -> Result<String> {
+    let mut hasher = Sha256::new();
+    for path in files {
+        hasher.update(path.as_os_str().as_bytes());
+        hasher.update([0]);
+        let mut file = File::open(path).with_context(|| anyhow!("Failed to open {:?}", path))?;
+        io::copy(&mut file, &mut hasher).with_context(|| anyhow!("Failed to read {:?}", path))?;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// The engine settings that change whether a file is detected
+fn options_version(options: &EngineOptions) -> String {
+    let max_scansize = options
+        .max_scansize
+        .map_or_else(|| "default".to_string(), |max| max.to_string());
+    format!(
+        "bytecode:{} heuristic-precedence:{} max-scansize:{}",
+        options.bytecode, options.heuristic_precedence, max_scansize
+    )
+}
+
+/// The versions of the official databases, e.g. `bytecode:335 daily:27432 main:62`, followed by
+/// a digest of all loaded database files and the engine options
+///
+/// Verdicts are only reused if the exact same signatures and settings are used.
+pub fn signature_version(config: &Config) -> Result<String> {
+    let mut versions = Vec::new();
+    for path in verify::databases(&config.update)? {
+        let name = path
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let info = verify::database_info(&path)?;
+        versions.push(format!("{}:{}", name, info.version));
+    }
+    if versions.is_empty() {
+        bail!("No signature databases found");
+    }
+    versions.sort();
+    // the official directory and `extra_databases`, like they are loaded by the scanner
+    let paths = std::iter::once(config.update.path.clone())
+        .chain(config.extra_databases())
+        .collect::<Vec<_>>();
+    let digest = databases_digest(&database_files(&paths)?)?;
+    let options = EngineOptions::from_config(&config.scan);
+    Ok(format!(
+        "{} databases:{} {}",
+        versions.join(" "),
+        &digest[..16],
+        options_version(&options)
+    ))
+}
+
+fn read(path: &Path) -> Result<Option<CacheFile>> {
+    let buf = match fs::read(path) {
+        Ok(buf) => buf,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| anyhow!("Failed to read {:?}", path)),
+    };
+    let file = serde_json::from_slice(&buf)
+        .with_context(|| anyhow!("Failed to parse clean cache {:?}", path))?;
+    Ok(Some(file))
+}
+
+impl CleanCache {
+    #[must_use]
+    pub fn new(signatures: String) -> CleanCache {
+        CleanCache {
+            signatures,
+            hashes: Mutex::default(),
+        }
+    }
+
+    /// Entries that were recorded with other signatures are dropped
+    pub fn load(path: &Path, signatures: String) -> Result<CleanCache> {
+        let cache = CleanCache::new(signatures);
+        match read(path)? {
+            Some(file) if file.signatures == cache.signatures => {
+                cache.extend(file.hashes);
+            }
+            Some(file) => info!(
+                "Signatures changed since the clean cache was written, dropping {} entries",
+                file.hashes.len()
+            ),
+            None => (),
+        }
+        Ok(cache)
+    }
+
+    #[must_use]
+    pub fn signatures(&self) -> &str {
+        &self.signatures
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashSet<String>> {
+        self.hashes.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    #[must_use]
+    pub fn contains(&self, hash: &str) -> bool {
+        self.lock().contains(hash)
+    }
+
+    pub fn insert(&self, hash: String) {
+        self.lock().insert(hash);
+    }
+
+    fn extend(&self, hashes: Vec<String>) {
+        self.lock().extend(hashes);
+    }
+
+    /// Replace the file atomically, this is also how the cache is exported
+    pub fn store(&self, path: &Path) -> Result<()> {
+        let mut hashes = self.lock().iter().cloned().collect::<Vec<_>>();
+        hashes.sort();
+        let file = CacheFile {
+            signatures: self.signatures.clone(),
+            hashes,
+        };
+        let dir = path
+            .parent()
+            .context("Cache path has no parent directory")?;
+        fs::create_dir_all(dir).with_context(|| anyhow!("Failed to create {:?}", dir))?;
+        let mut tmp = NamedTempFile::new_in(dir)?;
+        serde_json::to_writer(&mut tmp, &file)?;
+        tmp.write_all(b"\n")?;
+        tmp.persist(path)
+            .with_context(|| anyhow!("Failed to write {:?}", path))?;
+        Ok(())
+    }
+
+    /// Merge an exported cache, it has to be created with the same signatures, returns the number of new entries
+    pub fn import(&self, path: &Path) -> Result<usize> {
+        let file = read(path)?.with_context(|| anyhow!("Exported cache not found: {:?}", path))?;
+        if file.signatures != self.signatures {
+            bail!(
+                "The cache was created with other signatures ({}), the local signatures are {}, update both machines with freshclam first",
+                file.signatures,
+                self.signatures
+            );
+        }
+        let before = self.len();
+        self.extend(file.hashes);
+        Ok(self.len() - before)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clean-cache.json");
+        let cache = CleanCache::load(&path, "daily:1 main:1".to_string()).unwrap();
+        assert!(cache.is_empty());
+        cache.insert("a".repeat(64));
+        cache.store(&path).unwrap();
+
+        let cache = CleanCache::load(&path, "daily:1 main:1".to_string()).unwrap();
+        assert!(cache.contains(&"a".repeat(64)));
+
+        // verdicts of older signatures can't be trusted anymore
+        let cache = CleanCache::load(&path, "daily:2 main:1".to_string()).unwrap();
+        assert!(cache.is_empty());
+        assert!(cache.import(&path).is_err());
+
+        let exported = dir.path().join("golden.json");
+        let golden = CleanCache::new("daily:2 main:1".to_string());
+        golden.insert("b".repeat(64));
+        golden.store(&exported).unwrap();
+        assert_eq!(cache.import(&exported).unwrap(), 1);
+        assert_eq!(cache.import(&exported).unwrap(), 0);
+        assert!(cache.contains(&"b".repeat(64)));
+    }
+
+    #[test]
+    fn test_signature_version_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let official = dir.path().join("clamav");
+        fs::create_dir(&official).unwrap();
+        fs::write(official.join("daily.cvd"), b"daily").unwrap();
+        fs::write(official.join("freshclam.dat"), b"state").unwrap();
+        let paths = vec![official.clone()];
+        let files = database_files(&paths).unwrap();
+        assert_eq!(files, vec![official.join("daily.cvd")]);
+        let before = databases_digest(&files).unwrap();
+
+        // a custom signature makes previous verdicts invalid
+        let custom = dir.path().join("custom.ndb");
+        fs::write(&custom, b"Custom.Sig:0:*:41414141").unwrap();
+        let files = database_files(&[official, custom]).unwrap();
+        assert_ne!(databases_digest(&files).unwrap(), before);
+
+        let options = EngineOptions::default();
+        let without_bytecode = EngineOptions {
+            bytecode: false,
+            ..Default::default()
+        };
+        let limited = EngineOptions {
+            max_scansize: Some(1024),
+            ..Default::default()
+        };
+        assert_ne!(
+            options_version(&options),
+            options_version(&without_bytecode)
+        );
+        assert_ne!(options_version(&options), options_version(&limited));
+    }
+}
@@ -0,0 +1,264 @@
+use crate::audit::{self, Action};
+use crate::config::{CanaryConfig, NotifyConfig};
+use crate::errors::*;
+use crate::inuse;
+use crate::notify;
+use crate::push;
+use inotify::{Inotify, WatchDescriptor, WatchMask};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Sorts before regular documents, ransomware often encrypts directories in order
+pub const FILE_NAME: &str = "000-libredefender-canary.docx";
+const CONTENT: &[u8] = b"This file is monitored by libredefender to detect ransomware.\n\
+Please don't modify, rename or delete it, any change is reported as an attack.\n";
+
+/// The directories to plant canaries in, `canary.paths` or the documents and pictures directories
+#[must_use]
+pub fn dirs(config: &CanaryConfig) -> Vec<PathBuf> {
+    if !config.paths.is_empty() {
+        return config.paths.clone();
+    }
+    vec![dirs::document_dir(), dirs::picture_dir()]
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Write the canary into `dir`, an existing canary that was changed is replaced
+pub fn plant(dir: &Path) -> Result<PathBuf> {
+    let path = dir.join(FILE_NAME);
+    match fs::read(&path) {
+        Ok(content) if content == CONTENT => return Ok(path),
+        Ok(_) => warn!(
+            "Canary was modified while it wasn't watched, replacing it: {:?}",
+            path
+        ),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+        Err(err) => return Err(err).with_context(|| anyhow!("Failed to read {:?}", path)),
+    }
+    fs::write(&path, CONTENT).with_context(|| anyhow!("Failed to write canary {:?}", path))?;
+    Ok(path)
+}
+
+/// A change to the inode that doesn't alter the content, like `touch`, is not an attack
+#[must_use]
+pub fn is_intact(path: &Path) -> bool {
+    fs::read(path).is_ok_and(|content| content == CONTENT)
+}
+
+fn watch(inotify: &mut Inotify, path: &Path) -> Result<WatchDescriptor> {
+    let wd = inotify
+        .watches()
+        .add(
+            path,
+            WatchMask::MODIFY
+                | WatchMask::CLOSE_WRITE
+                | WatchMask::MOVE_SELF
+                | WatchMask::DELETE_SELF,
+        )
+        .with_context(|| anyhow!("Failed to watch canary {:?}", path))?;
+    Ok(wd)
+}
+
+/// A planted canary, the inode is kept to find the processes that still have it open after it was replaced
+struct Canary {
+    path: PathBuf,
+    inode: (u64, u64),
+}
+
+impl Canary {
+    fn watch(inotify: &mut Inotify, path: PathBuf) -> Result<(WatchDescriptor, Canary)> {
+        let md =
+            fs::metadata(&path).with_context(|| anyhow!("Failed to stat canary {:?}", path))?;
+        let wd = watch(inotify, &path)?;
+        let canary = Canary {
+            path,
+            inode: (md.dev(), md.ino()),
+        };
+        Ok((wd, canary))
+    }
+}
+
+/// Canaries that are watched by an inotify instance, a triggered canary is planted again after the alert
+pub struct Canaries {
+    watches: HashMap<WatchDescriptor, Canary>,
+    pause_processes: bool,
+    pause_allowlist: Vec<String>,
+}
+
+impl Canaries {
+    pub fn plant(config: &CanaryConfig, inotify: &mut Inotify) -> Result<Canaries> {
+        let mut watches = HashMap::new();
+        for dir in dirs(config) {
+            let (wd, canary) = Canary::watch(inotify, plant(&dir)?)?;
+            info!("Watching canary {}", canary.path.display());
+            watches.insert(wd, canary);
+        }
+        Ok(Canaries {
+            watches,
+            pause_processes: config.pause_processes,
+            pause_allowlist: config.pause_allowlist.clone(),
+        })
+    }
+
+    #[must_use]
+    pub fn contains(&self, wd: &WatchDescriptor) -> bool {
+        self.watches.contains_key(wd)
+    }
+
+    /// Check the canary after an inotify event, and alert if it was modified, renamed or deleted
+    pub fn check(&mut self, inotify: &mut Inotify, wd: WatchDescriptor, notify: &NotifyConfig) {
+        let Some(canary) = self.watches.get(&wd) else {
+            return;
+        };
+        if is_intact(&canary.path) {
+            return;
+        }
+        let Some(canary) = self.watches.remove(&wd) else {
+            return;
+        };
+        // the watch is already gone if the canary was deleted
+        inotify.watches().remove(wd).ok();
+        self.trigger(&canary, notify);
+
+        // keep watching the directory for the rest of the attack
+        let Some(dir) = canary.path.parent() else {
+            return;
+        };
+        match plant(dir).and_then(|path| Canary::watch(inotify, path)) {
+            Ok((wd, canary)) => {
+                info!("Planted canary again: {}", canary.path.display());
+                self.watches.insert(wd, canary);
+            }
+            Err(err) => warn!("Failed to plant canary again: {:#}", err),
+        }
+    }
+
+    fn trigger(&self, canary: &Canary, notify: &NotifyConfig) {
+        let path = &canary.path;
+        error!(
+            "Canary was modified, possible ransomware attack: {:?}",
+            path
+        );
+        let mut body = format!(
+            "The canary file {:?} was modified, renamed or deleted. This is a sign of ransomware encrypting your files.",
+            path
+        );
+        let mut detail = "modified".to_string();
+        if self.pause_processes {
+            let stopped = pause_openers(canary, &self.pause_allowlist);
+            if stopped.is_empty() {
+                body.push_str("\nNo process that has the canary open was found.");
+            } else {
+                let pids = stopped
+                    .iter()
+                    .map(|pid| pid.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                body.push_str(&format!(
+                    "\nStopped the processes that have the canary open: {}. Resume them with `kill -CONT {}` if this is a false alarm.",
+                    describe(&stopped),
+                    pids
+                ));
+                detail = format!("stopped pids {}", pids);
+            }
+        }
+
+        let entry = audit::Entry::new(Action::CanaryTriggered, path, &[]).detail(detail);
+        if let Err(err) = audit::record(&entry) {
+            warn!("Failed to write audit log: {:#}", err);
+        }
+        push::send(&notify.push, "Ransomware canary triggered", &body, true);
+        if let Err(err) = notify::alert("Ransomware canary triggered", &body) {
+            warn!("Failed to display notification: {:#}", err);
+        }
+    }
+}
+
+fn is_allowed(name: Option<&str>, allowlist: &[String]) -> bool {
+    name.is_some_and(|name| allowlist.iter().any(|allowed| allowed == name))
+}
+
+/// `1234 (name)`, for the log and the notification
+fn describe(pids: &[u32]) -> String {
+    pids.iter()
+        .map(|pid| {
+            let name = inuse::process_name(*pid).unwrap_or_else(|| "?".to_string());
+            format!("{} ({})", pid, name)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Send SIGSTOP to every process that has the canary open, except this one and `pause_allowlist`
+fn pause_openers(canary: &Canary, allowlist: &[String]) -> Vec<u32> {
+    let pids = match inuse::processes_with_open(canary.inode) {
+        Ok(pids) => pids,
+        Err(err) => {
+            warn!(
+                "Failed to find processes that have {:?} open: {:#}",
+                canary.path, err
+            );
+            return Vec::new();
+        }
+    };
+    let own = std::process::id();
+    pids.into_iter()
+        .filter(|pid| *pid != own)
+        .filter(|pid| {
+            let name = inuse::process_name(*pid);
+            if is_allowed(name.as_deref(), allowlist) {
+                info!(
+                    "Not stopping process {} ({:?}), it's in canary.pause_allowlist",
+                    pid, name
+                );
+                return false;
+            }
+            if unsafe { libc::kill(*pid as libc::pid_t, libc::SIGSTOP) } == 0 {
+                warn!(
+                    "Stopped process {} ({:?}) that has the canary {:?} open, resume it with `kill -CONT {}`",
+                    pid, name, canary.path, pid
+                );
+                true
+            } else {
+                let err = io::Error::last_os_error();
+                warn!("Failed to stop process {}: {:#}", pid, err);
+                false
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plant() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = plant(dir.path()).unwrap();
+        assert!(is_intact(&path));
+
+        fs::write(&path, b"encrypted").unwrap();
+        assert!(!is_intact(&path));
+        plant(dir.path()).unwrap();
+        assert!(is_intact(&path));
+
+        fs::remove_file(&path).unwrap();
+        assert!(!is_intact(&path));
+    }
+
+    #[test]
+    fn test_pause_allowlist() {
+        let allowlist = vec!["syncthing".to_string()];
+        assert!(is_allowed(Some("syncthing"), &allowlist));
+        assert!(!is_allowed(Some("syncthing-gtk"), &allowlist));
+        // processes that are already gone or hidden can't be matched
+        assert!(!is_allowed(None, &allowlist));
+        assert!(!is_allowed(Some("syncthing"), &[]));
+    }
+}
@@ -0,0 +1,74 @@
+use crate::config::{CgroupConfig, HumanSize};
+use crate::errors::*;
+use crate::utils;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::{OwnedObjectPath, Value};
+
+const DESTINATION: &str = "org.freedesktop.systemd1";
+const MANAGER_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+
+static IN_SCOPE: AtomicBool = AtomicBool::new(false);
+
+#[must_use]
+pub fn is_systemd() -> bool {
+    Path::new("/run/systemd/system").exists()
+}
+
+/// Move the current process into a transient systemd scope with the configured limits
+///
+/// Scans call this in the child process that runs them, so long running callers aren't
+/// kept under the limits of a scan.
+///
+/// `max_memory` is used if `memory_max` isn't configured explicitly.
+pub fn setup(config: &CgroupConfig, max_memory: Option<u64>) -> Result<()> {
+    if !is_systemd() {
+        warn!("System isn't running systemd, ignoring cgroup settings");
+        return Ok(());
+    }
+    // a process is only moved once, the scope lives as long as the process
+    if IN_SCOPE.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    let conn = if utils::is_root() {
+        Connection::system().context("Failed to connect to system bus")?
+    } else {
+        Connection::session().context("Failed to connect to session bus")?
+    };
+    let manager = Proxy::new(&conn, DESTINATION, MANAGER_PATH, MANAGER_INTERFACE)
+        .context("Failed to create proxy for systemd")?;
+
+    let pid = process::id();
+    let name = format!("libredefender-scan-{}.scope", pid);
+    let mut properties = vec![
+        ("Description", Value::from("libredefender scan")),
+        ("PIDs", Value::from(vec![pid])),
+    ];
+    if let Some(weight) = config.cpu_weight {
+        properties.push(("CPUWeight", Value::from(weight)));
+    }
+    if let Some(weight) = config.io_weight {
+        properties.push(("IOWeight", Value::from(weight)));
+    }
+    if let Some(max) = config
+        .memory_max
+        .as_ref()
+        .map(HumanSize::as_bytes)
+        .or(max_memory)
+    {
+        properties.push(("MemoryMax", Value::from(max)));
+    }
+    let aux: Vec<(&str, Vec<(&str, Value)>)> = Vec::new();
+
+    debug!("Starting transient unit {:?}", name);
+    let _job: OwnedObjectPath = manager
+        .call("StartTransientUnit", &(&name, "fail", properties, aux))
+        .with_context(|| anyhow!("Failed to start transient unit {:?}", name))?;
+    info!("Moved scan into cgroup {:?}", name);
+
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use crate::db::Data;
+use crate::errors::*;
+use chrono::{DateTime, Duration, Utc};
+use chrono_humanize::HumanTime;
+use std::fmt;
+use std::str::FromStr;
+
+/// A duration like `90m`, `12h`, `3d` or `2w`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Age(pub Duration);
+
+impl FromStr for Age {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (num, unit) = s.split_at(split);
+        let num = num
+            .parse::<i64>()
+            .with_context(|| anyhow!("Invalid age: {:?}", s))?;
+        let duration = match unit {
+            "s" => Duration::seconds(num),
+            "m" => Duration::minutes(num),
+            "h" => Duration::hours(num),
+            "" | "d" => Duration::days(num),
+            "w" => Duration::weeks(num),
+            _ => bail!("Unknown unit in age (expected s, m, h, d or w): {:?}", s),
+        };
+        Ok(Age(duration))
+    }
+}
+
+/// Plugin return codes of nagios and icinga
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Code {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl Code {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::Ok => "OK",
+            Code::Warning => "WARNING",
+            Code::Critical => "CRITICAL",
+            Code::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+/// The result of `libredefender check`, displayed as a single line with perfdata
+#[derive(Debug)]
+pub struct Check {
+    pub code: Code,
+    pub problems: Vec<String>,
+    pub perfdata: Vec<String>,
+}
+
+impl Check {
+    #[must_use]
+    pub fn unknown(err: &Error) -> Check {
+        Check {
+            code: Code::Unknown,
+            problems: vec![format!("{:#}", err)],
+            perfdata: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "LIBREDEFENDER {} - ", self.code.as_str())?;
+        if self.problems.is_empty() {
+            write!(w, "no threats found")?;
+        } else {
+            write!(w, "{}", self.problems.join(", "))?;
+        }
+        if !self.perfdata.is_empty() {
+            write!(w, " | {}", self.perfdata.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Seconds since `dt`, perfdata needs a plain number
+fn age_secs(dt: &Option<DateTime<Utc>>, now: DateTime<Utc>) -> Option<i64> {
+    dt.map(|dt| now.signed_duration_since(dt).num_seconds())
+}
+
+fn perf(label: &str, value: Option<i64>, uom: &str, warn: Option<Duration>) -> String {
+    // `U` means the value is unknown, it has no unit
+    let value = value.map_or_else(|| "U".to_string(), |v| format!("{}{}", v, uom));
+    let warn = warn.map_or_else(String::new, |d| d.num_seconds().to_string());
+    format!("{}={};{};;0;", label, value, warn)
+}
+
+#[must_use]
+pub fn check(data: &Data, max_sig_age: Duration, max_scan_age: Option<Duration>) -> Check {
+    let now = Utc::now();
+    let mut code = Code::Ok;
+    let mut problems = Vec::new();
+
+    if !data.threats.is_empty() {
+        code = Code::Critical;
+        problems.push(format!("{} threat(s) found", data.threats.len()));
+    }
+
+    let scan_age = age_secs(&data.last_scan, now);
+    match data.last_scan {
+        None => {
+            code = code.max(Code::Warning);
+            problems.push("never scanned".to_string());
+        }
+        Some(last_scan) if max_scan_age.is_some_and(|max| now - last_scan > max) => {
+            code = code.max(Code::Warning);
+            problems.push(format!("last scan {}", HumanTime::from(last_scan - now)));
+        }
+        Some(_) => (),
+    }
+
+    let sig_age = age_secs(&data.signatures_age, now);
+    if data.signature_count == 0 {
+        code = code.max(Code::Warning);
+        problems.push("no signatures loaded".to_string());
+    } else if let Some(updated) = data.signatures_age {
+        if now - updated > max_sig_age {
+            code = code.max(Code::Warning);
+            problems.push(format!(
+                "signatures updated {}",
+                HumanTime::from(updated - now)
+            ));
+        }
+    }
+
+    Check {
+        code,
+        problems,
+        perfdata: vec![
+            format!("threats={};;0;0;", data.threats.len()),
+            perf("signatures_age", sig_age, "s", Some(max_sig_age)),
+            perf("last_scan_age", scan_age, "s", max_scan_age),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Threat;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!("3d".parse::<Age>().unwrap(), Age(Duration::days(3)));
+        assert_eq!("12h".parse::<Age>().unwrap(), Age(Duration::hours(12)));
+        assert_eq!("2w".parse::<Age>().unwrap(), Age(Duration::days(14)));
+        assert_eq!("7".parse::<Age>().unwrap(), Age(Duration::days(7)));
+        assert!("3 days".parse::<Age>().is_err());
+        assert!("d".parse::<Age>().is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let mut data = Data {
+            last_scan: Some(Utc::now() - Duration::hours(5)),
+            signature_count: 1,
+            signatures_age: Some(Utc::now() - Duration::days(1)),
+            ..Default::default()
+        };
+        let c = check(&data, Duration::days(3), Some(Duration::days(2)));
+        assert_eq!(c.code, Code::Ok);
+        assert!(c.to_string().starts_with(
+            "LIBREDEFENDER OK - no threats found | threats=0;;0;0; signatures_age=86400s;259200;;0; last_scan_age=18000s;172800;;0;"
+        ));
+
+        data.signatures_age = Some(Utc::now() - Duration::days(4));
+        let c = check(&data, Duration::days(3), Some(Duration::days(2)));
+        assert_eq!(c.code, Code::Warning);
+        assert_eq!(c.problems, vec!["signatures updated 4 days ago"]);
+
+        data.threats
+            .insert(PathBuf::from("/a"), Threat::new(Utc::now()));
+        let c = check(&data, Duration::days(3), None);
+        assert_eq!(c.code, Code::Critical);
+        assert_eq!(c.problems.len(), 2);
+
+        let c = check(&Data::default(), Duration::days(3), None);
+        assert_eq!(c.code, Code::Warning);
+        assert!(c.to_string().contains("last_scan_age=U;;;0;"));
+    }
+}
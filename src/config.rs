@@ -1,9 +1,18 @@
 use crate::args;
 use crate::errors::*;
-use crate::patterns::Pattern;
+use crate::http;
+use crate::nice;
+use crate::patterns::{ExcludeFile, Pattern};
 use crate::schedule::PreferedHours;
+use crate::severity::Severity;
+use crate::syslog::LogTarget;
+use crate::utils;
 use human_size::{Byte, Size, SpecificSize};
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::mem;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
@@ -14,6 +23,34 @@ pub struct Config {
     pub update: UpdateConfig,
     #[serde(default)]
     pub schedule: ScheduleConfig,
+    #[serde(default)]
+    pub watch: WatchConfig,
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    pub reporting: Option<ReportingConfig>,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    #[serde(default)]
+    pub quarantine: QuarantineConfig,
+    #[serde(default)]
+    pub canary: CanaryConfig,
+    #[serde(default)]
+    pub profile: HashMap<String, ProfileConfig>,
+}
+
+impl Config {
+    /// Signature directories and files that are loaded in addition to `update.path`
+    #[must_use]
+    pub fn extra_databases(&self) -> Vec<PathBuf> {
+        self.update
+            .paths
+            .iter()
+            .chain(self.scan.extra_databases.iter())
+            .cloned()
+            .collect()
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -22,15 +59,386 @@ pub struct ScanConfig {
     pub paths: Vec<PathBuf>,
     pub concurrency: Option<usize>,
     #[serde(default)]
+    pub includes: Vec<Pattern>,
+    #[serde(default)]
     pub excludes: Vec<Pattern>,
+    /// Additional excludes in gitignore syntax
+    pub exclude_file: Option<PathBuf>,
+    #[serde(skip)]
+    pub exclude_matcher: Option<ExcludeFile>,
     #[serde(default)]
     pub skip_hidden: bool,
+    /// Skip directories with a `.nocheck` marker and honor `.libredefender-ignore` files, opt-in because
+    /// any process running as the user can create them
+    pub ignore_files: Option<bool>,
     pub skip_larger_than: Option<HumanSize>,
+    /// Archives that unpack to more than this are scanned as a single file, defaults to 4 GiB
+    pub max_unpacked_size: Option<HumanSize>,
+    /// Remember the sha256 of clean files and skip them until the signatures are updated
+    #[serde(default)]
+    pub clean_cache: bool,
+    /// Report double extensions, bidi overrides in names and executable scripts in downloads as low severity
+    #[serde(default)]
+    pub builtin_heuristics: bool,
+    /// Skip files that match the digest recorded by pacman, dpkg or rpm, modified package files are scanned
+    #[serde(default)]
+    pub skip_package_files: bool,
+    /// Skip files that are opened for writing by a running process, like VM images or downloads
+    #[serde(default)]
+    pub skip_in_use: bool,
+    /// Only files larger than this are skipped by `skip_in_use`, defaults to 64 MiB
+    pub skip_in_use_above: Option<HumanSize>,
+    /// File types that are skipped after sniffing the start of the file, e.g. `video/*`
+    #[serde(default)]
+    pub skip_mime: Vec<String>,
+    /// Only scan executables, scripts, documents and archives
+    #[serde(default)]
+    pub quick: bool,
+    /// Skip files that are owned by somebody other than the user running the scan
+    #[serde(default)]
+    pub skip_other_users: bool,
+    #[serde(default)]
+    pub skip_uids: Vec<u32>,
+    /// Descend into fuse mounts like sshfs or gvfs
+    #[serde(default)]
+    pub follow_mounts: bool,
+    pub max_depth: Option<usize>,
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    /// Scanned before everything else, defaults to downloads, /tmp, browser caches and mail
+    pub priority_paths: Option<Vec<PathBuf>>,
+    /// Index all files before scanning and start with the most recently modified ones
+    #[serde(default)]
+    pub newest_first: bool,
+    /// Count the files before scanning them, for the percentage and remaining time of the progress
+    #[serde(default)]
+    pub estimate: bool,
+    /// Log the scan progress every this many seconds, 0 disables it
+    pub progress_log_interval: Option<u64>,
+    /// Limit how many megabytes per second are read by all scan workers combined
+    pub max_read_mbps: Option<f64>,
+    pub files_per_second: Option<f64>,
+    /// Run scans in a transient systemd scope with these resource controls
+    pub cgroup: Option<CgroupConfig>,
+    /// Defaults to 19, the lowest priority
+    pub nice_level: Option<i32>,
+    #[serde(default)]
+    pub ionice_class: IoniceClass,
+    /// Only used for best-effort, 0 (highest) to 7 (lowest)
+    pub ionice_level: Option<u8>,
+    /// Pin the scan workers to these cpu cores, this also limits the default concurrency
+    pub cpuset: Option<Vec<usize>>,
+    /// Limit the memory of the scan, split between the workers for libclamav's per-file limits
+    pub max_memory: Option<HumanSize>,
+    #[serde(default)]
+    pub backend: Backend,
+    /// Additional signature files like `.hdb`, `.ndb` or `.yara`
+    #[serde(default)]
+    pub extra_databases: Vec<PathBuf>,
+    /// Load the bytecode signatures of the official databases, defaults to true
+    pub bytecode: Option<bool>,
+    /// Stop at the first heuristic match, faster but reports less specific names
+    #[serde(default)]
+    pub heuristic_precedence: bool,
+    /// When running as root, switch to this user after loading the signatures
+    pub user: Option<String>,
+    /// Concurrency and priority for the files below these paths, usually set by a
+    /// `[profile.x.paths."..."]` table
+    #[serde(default)]
+    pub path_overrides: BTreeMap<PathBuf, PathOverride>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl ScanConfig {
+    /// Number of scan workers, defaults to the number of (pinned) cpu cores
+    #[must_use]
+    pub fn workers(&self) -> usize {
+        self.concurrency.unwrap_or_else(|| {
+            self.cpuset
+                .as_ref()
+                .map_or_else(num_cpus::get, |cpuset| cpuset.len())
+        })
+    }
+
+    #[must_use]
+    pub fn ignore_files(&self) -> bool {
+        self.ignore_files.unwrap_or(false)
+    }
+
+    /// Smaller files are scanned even if they're in use, malware could keep itself open for writing
+    #[must_use]
+    pub fn skip_in_use_above(&self) -> u64 {
+        self.skip_in_use_above
+            .as_ref()
+            .map_or(64 * 1024 * 1024, HumanSize::as_bytes)
+    }
+
+    #[must_use]
+    pub fn max_unpacked_size(&self) -> u64 {
+        self.max_unpacked_size
+            .as_ref()
+            .map_or(4 * 1024 * 1024 * 1024, HumanSize::as_bytes)
+    }
+
+    #[must_use]
+    pub fn bytecode(&self) -> bool {
+        self.bytecode.unwrap_or(true)
+    }
+
+    /// Number of scan workers including the pools of `path_overrides`
+    #[must_use]
+    pub fn total_workers(&self) -> usize {
+        let workers = self.workers();
+        let overrides = self
+            .path_overrides
+            .values()
+            .map(|o| o.concurrency.unwrap_or(workers))
+            .sum::<usize>();
+        workers + overrides
+    }
+
+    /// The memory limit of a single worker, libclamav limits the data scanned per file to this
+    #[must_use]
+    pub fn max_scansize(&self) -> Option<u64> {
+        let max_memory = self.max_memory.as_ref()?.as_bytes();
+        Some(max_memory / self.total_workers().max(1) as u64)
+    }
+}
+
+/// Files below the path are scanned by their own pool of workers with these settings,
+/// e.g. a single thread for a slow usb disk
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PathOverride {
+    /// Defaults to the number of workers of the scan
+    pub concurrency: Option<usize>,
+    pub nice_level: Option<i32>,
+    pub ionice_class: Option<IoniceClass>,
+    pub ionice_level: Option<u8>,
+}
+
+/// The paths of a profile, a list or a table with the overrides of each path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ProfilePaths {
+    List(Vec<PathBuf>),
+    Table(BTreeMap<PathBuf, PathOverride>),
+}
+
+impl ProfilePaths {
+    #[must_use]
+    pub fn paths(&self) -> Vec<PathBuf> {
+        match self {
+            ProfilePaths::List(paths) => paths.clone(),
+            ProfilePaths::Table(paths) => paths.keys().cloned().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoniceClass {
+    #[default]
+    Idle,
+    BestEffort,
+    None,
+}
+
+/// What happens to a scheduled scan that is still running when the preferred hours end
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutsidePreferredHours {
+    #[default]
+    Continue,
+    /// Pause the scan until the preferred hours start again
+    Pause,
+    /// Stop the scan and start a new one during the next preferred hours
+    Abort,
+}
+
+/// Where in the preferred hours a scheduled scan starts
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Placement {
+    /// At the start of the window, delayed by up to `jitter`
+    Start,
+    /// Anywhere in the window
+    #[default]
+    Random,
+    /// Up to `jitter` before the window ends
+    End,
+}
+
+/// How files are handed to clamav
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    Libclamav,
+    /// Send file descriptors to a running clamd
+    Clamdscan,
+    /// Spawn clamscan for every file, very slow since signatures are loaded each time
+    Clamscan,
+}
+
+impl Default for Backend {
+    fn default() -> Backend {
+        if cfg!(feature = "libclamav") {
+            Backend::Libclamav
+        } else {
+            Backend::Clamdscan
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CgroupConfig {
+    pub cpu_weight: Option<u64>,
+    pub io_weight: Option<u64>,
+    pub memory_max: Option<HumanSize>,
+}
+
+/// Named set of scan settings, everything that is set replaces the value in `[scan]`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProfileConfig {
+    pub paths: Option<ProfilePaths>,
+    pub concurrency: Option<usize>,
+    pub includes: Option<Vec<Pattern>>,
+    pub excludes: Option<Vec<Pattern>>,
+    pub exclude_file: Option<PathBuf>,
+    pub skip_hidden: Option<bool>,
+    pub ignore_files: Option<bool>,
+    pub skip_larger_than: Option<HumanSize>,
+    pub skip_package_files: Option<bool>,
+    pub skip_in_use: Option<bool>,
+    pub skip_mime: Option<Vec<String>>,
+    pub quick: Option<bool>,
+    pub skip_other_users: Option<bool>,
+    pub skip_uids: Option<Vec<u32>>,
+    pub max_depth: Option<usize>,
+    pub follow_symlinks: Option<bool>,
+    pub priority_paths: Option<Vec<PathBuf>>,
+    pub newest_first: Option<bool>,
+    pub estimate: Option<bool>,
+    pub max_read_mbps: Option<f64>,
+    pub files_per_second: Option<f64>,
+}
+
+impl ProfileConfig {
+    pub fn apply(self, scan: &mut ScanConfig) {
+        if let Some(paths) = self.paths {
+            scan.paths = paths.paths();
+            if let ProfilePaths::Table(paths) = paths {
+                let overrides = paths
+                    .into_iter()
+                    .filter(|(_, o)| *o != PathOverride::default());
+                scan.path_overrides.extend(overrides);
+            }
+        }
+        if let Some(concurrency) = self.concurrency {
+            scan.concurrency = Some(concurrency);
+        }
+        if let Some(includes) = self.includes {
+            scan.includes = includes;
+        }
+        if let Some(excludes) = self.excludes {
+            scan.excludes = excludes;
+        }
+        if let Some(exclude_file) = self.exclude_file {
+            scan.exclude_file = Some(exclude_file);
+        }
+        if let Some(skip_hidden) = self.skip_hidden {
+            scan.skip_hidden = skip_hidden;
+        }
+        if let Some(ignore_files) = self.ignore_files {
+            scan.ignore_files = Some(ignore_files);
+        }
+        if let Some(skip_larger_than) = self.skip_larger_than {
+            scan.skip_larger_than = Some(skip_larger_than);
+        }
+        if let Some(skip_package_files) = self.skip_package_files {
+            scan.skip_package_files = skip_package_files;
+        }
+        if let Some(skip_in_use) = self.skip_in_use {
+            scan.skip_in_use = skip_in_use;
+        }
+        if let Some(skip_mime) = self.skip_mime {
+            scan.skip_mime = skip_mime;
+        }
+        if let Some(quick) = self.quick {
+            scan.quick = quick;
+        }
+        if let Some(skip_other_users) = self.skip_other_users {
+            scan.skip_other_users = skip_other_users;
+        }
+        if let Some(skip_uids) = self.skip_uids {
+            scan.skip_uids = skip_uids;
+        }
+        if let Some(max_depth) = self.max_depth {
+            scan.max_depth = Some(max_depth);
+        }
+        if let Some(follow_symlinks) = self.follow_symlinks {
+            scan.follow_symlinks = follow_symlinks;
+        }
+        if let Some(priority_paths) = self.priority_paths {
+            scan.priority_paths = Some(priority_paths);
+        }
+        if let Some(newest_first) = self.newest_first {
+            scan.newest_first = newest_first;
+        }
+        if let Some(estimate) = self.estimate {
+            scan.estimate = estimate;
+        }
+        if let Some(max_read_mbps) = self.max_read_mbps {
+            scan.max_read_mbps = Some(max_read_mbps);
+        }
+        if let Some(files_per_second) = self.files_per_second {
+            scan.files_per_second = Some(files_per_second);
+        }
+    }
+}
+
+pub const DEFAULT_MAX_AGE_DAYS: u32 = 7;
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_max_age_days() -> u32 {
+    DEFAULT_MAX_AGE_DAYS
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpdateConfig {
     pub path: PathBuf,
+    /// Additional database directories, e.g. third-party signatures
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+    /// Signatures older than this are reported as outdated
+    #[serde(default = "default_max_age_days")]
+    pub max_age_days: u32,
+    /// Run freshclam when the scheduler finds outdated signatures
+    #[serde(default)]
+    pub refresh_when_stale: bool,
+    /// Also download signatures on connections NetworkManager considers metered
+    #[serde(default)]
+    pub allow_metered: bool,
+    /// Proxy for freshclam and all http requests, e.g. `socks5h://127.0.0.1:9050` for Tor
+    pub proxy: Option<String>,
+    /// Reload the signatures during long running scans and in `watch` when freshclam updates them
+    #[serde(default = "default_true")]
+    pub reload_on_change: bool,
+    /// Refuse to scan if the md5 of a `.cvd` file doesn't match its header, otherwise only warn
+    #[serde(default = "default_true")]
+    pub refuse_tampered: bool,
+    /// Signature descriptions that are loaded in addition to the bundled ones
+    #[serde(default)]
+    pub descriptions: Vec<PathBuf>,
+}
+
+impl UpdateConfig {
+    #[must_use]
+    pub fn max_age(&self) -> chrono::Duration {
+        chrono::Duration::days(self.max_age_days.into())
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -38,7 +446,265 @@ pub struct ScheduleConfig {
     pub automatic_scans: Option<String>,
     pub preferred_hours: Option<PreferedHours>,
     #[serde(default)]
+    pub outside_preferred_hours: OutsidePreferredHours,
+    #[serde(default)]
+    pub placement: Placement,
+    /// Delay scheduled scans by a random number of seconds up to this value
+    #[serde(default)]
+    pub jitter: u32,
+    #[serde(default)]
     pub skip_on_battery: bool,
+    #[serde(default)]
+    pub scan_removable: bool,
+    /// Only alert about threats that weren't known before, like `scan --diff`
+    #[serde(default)]
+    pub diff: bool,
+    /// Profile used for scheduled scans
+    pub profile: Option<String>,
+    /// Run freshclam before every scheduled scan, scans are skipped if signatures are still outdated
+    #[serde(default)]
+    pub update_before_scan: bool,
+    /// Postpone scheduled scans while another program holds a blocking inhibitor for these, e.g. `idle`
+    #[serde(default)]
+    pub respect_inhibitors: Vec<String>,
+    /// Take an inhibitor lock for the duration of a scheduled scan, e.g. `sleep:shutdown`
+    pub inhibit: Option<String>,
+    /// Wake the machine from suspend for scheduled scans, if the alarm woke it up it is suspended again when idle
+    #[serde(default)]
+    pub use_rtc_wake: bool,
+    /// Scans with their own interval, replaces the scan configured by `preferred_hours` and `profile`
+    #[serde(default)]
+    pub job: Vec<JobConfig>,
+    /// Send the logs of the scheduler to syslog instead of stderr
+    #[serde(default)]
+    pub log_target: LogTarget,
+    /// Run as root and scan the home directory of every human user with their privileges
+    #[serde(default)]
+    pub system: bool,
+    /// Accounts with a lower uid are system users and aren't scanned in system mode, defaults to 1000
+    pub min_uid: Option<u32>,
+}
+
+impl ScheduleConfig {
+    #[must_use]
+    pub fn min_uid(&self) -> u32 {
+        self.min_uid.unwrap_or(1000)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobConfig {
+    pub name: String,
+    #[serde(default)]
+    pub interval: Interval,
+    pub preferred_hours: Option<PreferedHours>,
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Interval {
+    #[default]
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Interval {
+    #[must_use]
+    pub fn duration(self) -> chrono::Duration {
+        match self {
+            Interval::Daily => chrono::Duration::days(1),
+            Interval::Weekly => chrono::Duration::days(7),
+            Interval::Monthly => chrono::Duration::days(30),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WatchConfig {
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+}
+
+/// Files that `watch` plants in the directories ransomware goes after, any change to them raises an alert
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CanaryConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Defaults to the documents and pictures directories
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+    /// Stop the processes that have a triggered canary open with SIGSTOP
+    #[serde(default)]
+    pub pause_processes: bool,
+    /// Process names, as in `/proc/<pid>/comm`, that are never stopped
+    #[serde(default)]
+    pub pause_allowlist: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct NotifyConfig {
+    /// Detections below this severity are only logged
+    #[serde(default)]
+    pub min_severity: Severity,
+    #[serde(default)]
+    pub push: PushConfig,
+}
+
+/// Push services for detections and failed scheduled scans, for machines without a desktop session
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PushConfig {
+    pub ntfy: Option<NtfyConfig>,
+    pub matrix: Option<MatrixConfig>,
+    pub telegram: Option<TelegramConfig>,
+    /// Copied from `update.proxy` when the config is loaded
+    #[serde(skip)]
+    pub proxy: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NtfyConfig {
+    /// The topic url, e.g. `https://ntfy.sh/my-topic`
+    pub url: String,
+    /// Access token for protected topics
+    pub token: Option<String>,
+    pub token_file: Option<PathBuf>,
+}
+
+impl NtfyConfig {
+    pub fn token(&self) -> Result<Option<String>> {
+        secret(&self.token, &self.token_file)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatrixConfig {
+    /// e.g. `https://matrix.org`
+    pub homeserver: String,
+    /// The room id, e.g. `!abcdef:matrix.org`
+    pub room: String,
+    pub access_token: Option<String>,
+    pub access_token_file: Option<PathBuf>,
+}
+
+impl MatrixConfig {
+    pub fn access_token(&self) -> Result<String> {
+        secret(&self.access_token, &self.access_token_file)?
+            .context("Either access_token or access_token_file is required")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramConfig {
+    pub chat_id: String,
+    pub bot_token: Option<String>,
+    pub bot_token_file: Option<PathBuf>,
+}
+
+impl TelegramConfig {
+    pub fn bot_token(&self) -> Result<String> {
+        secret(&self.bot_token, &self.bot_token_file)?
+            .context("Either bot_token or bot_token_file is required")
+    }
+}
+
+/// Upload a summary of every scan to a fleet server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportingConfig {
+    /// Reports are sent with a POST request, only https is supported
+    pub url: String,
+    pub token: Option<String>,
+    /// Read the token from this file, so the config can be world-readable
+    pub token_file: Option<PathBuf>,
+    /// Key for the HMAC of the body, the signature header is only sent if this is set
+    pub signing_key: Option<String>,
+    pub signing_key_file: Option<PathBuf>,
+    /// How often an upload is retried with exponential backoff before the reports stay queued
+    #[serde(default = "default_report_retries")]
+    pub retries: u32,
+    /// Drop the oldest reports if more than this are waiting to be uploaded
+    #[serde(default = "default_max_queued")]
+    pub max_queued: usize,
+    /// Copied from `update.proxy` when the config is loaded
+    #[serde(skip)]
+    pub proxy: Option<String>,
+}
+
+/// A secret that's either set in the config or read from a file
+fn secret(value: &Option<String>, file: &Option<PathBuf>) -> Result<Option<String>> {
+    let secret = match (value, file) {
+        (Some(value), _) => value.clone(),
+        (None, Some(path)) => fs::read_to_string(path)
+            .with_context(|| anyhow!("Failed to read secret from {:?}", path))?
+            .trim()
+            .to_string(),
+        (None, None) => return Ok(None),
+    };
+    if secret.is_empty() || !secret.chars().all(|c| c.is_ascii_graphic()) {
+        bail!("Secret is empty or contains invalid characters");
+    }
+    Ok(Some(secret))
+}
+
+fn default_report_retries() -> u32 {
+    3
+}
+
+fn default_max_queued() -> usize {
+    100
+}
+
+impl ReportingConfig {
+    pub fn token(&self) -> Result<String> {
+        secret(&self.token, &self.token_file)?
+            .context("Either reporting.token or reporting.token_file is required")
+    }
+
+    pub fn signing_key(&self) -> Result<Option<String>> {
+        secret(&self.signing_key, &self.signing_key_file)
+    }
+}
+
+/// Commands that are run with `sh -c` on scan events, with the details in `LIBREDEFENDER_*` variables and as json on stdin
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct HooksConfig {
+    pub on_threat: Option<String>,
+    pub on_scan_start: Option<String>,
+    pub on_scan_end: Option<String>,
+    /// Hooks are killed after this many seconds
+    pub timeout: Option<u64>,
+}
+
+impl HooksConfig {
+    #[must_use]
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.timeout.unwrap_or(60))
+    }
+}
+
+/// A hundred years, longer retentions don't make sense and get close to the range of supported dates
+const MAX_RETENTION_DAYS: u32 = 36_500;
+
+/// Limits for the quarantine directory, the scheduler deletes the oldest files to enforce them
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct QuarantineConfig {
+    pub max_size: Option<HumanSize>,
+    pub retention_days: Option<u32>,
+}
+
+impl QuarantineConfig {
+    #[must_use]
+    pub fn retention(&self) -> Option<chrono::Duration> {
+        self.retention_days
+            .map(|days| chrono::Duration::days(days.into()))
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// Write prometheus metrics to this file after every scan, for the textfile collector of node_exporter
+    pub textfile: Option<PathBuf>,
 }
 
 // config::File::new expects &str instead of &Path
@@ -47,30 +713,532 @@ fn path_to_string(path: &Path) -> Result<String> {
     Ok(s.to_string())
 }
 
-pub fn load(args: Option<&args::Scan>) -> Result<Config> {
-    let mut settings = config::Config::builder().set_default("update.path", "/var/lib/clamav")?;
+/// What logind inhibitor locks can be taken for
+const INHIBITOR_OPERATIONS: &[&str] = &[
+    "shutdown",
+    "sleep",
+    "idle",
+    "handle-power-key",
+    "handle-suspend-key",
+    "handle-hibernate-key",
+    "handle-lid-switch",
+];
 
-    let config_dir = dirs::config_dir().context("Failed to find config dir")?;
-    let path = path_to_string(&config_dir.join("libredefender.toml"))?;
-    settings =
-        settings.add_source(config::File::new(&path, config::FileFormat::Toml).required(false));
+pub const SYSTEM_CONFIG: &str = "/etc/libredefender.toml";
+const DEFAULT_DATABASE_PATH: &str = "/var/lib/clamav";
 
-    if let Some(args) = args {
-        if let Some(concurrency) = args.concurrency {
-            settings = settings.set_override("scan.concurrency", concurrency as i64)?;
+/// Where a config value was set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    SystemFile(PathBuf),
+    UserFile(PathBuf),
+    /// `LIBREDEFENDER_SCAN__CONCURRENCY=4` sets `scan.concurrency`
+    Env,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Default => write!(w, "default"),
+            Origin::SystemFile(path) => write!(w, "system file {:?}", path),
+            Origin::UserFile(path) => write!(w, "user file {:?}", path),
+            Origin::Env => write!(w, "env"),
         }
     }
+}
+
+type Source = Vec<Box<dyn config::Source + Send + Sync>>;
+
+/// The file passed with `--config`, or ~/.config/libredefender.toml
+pub fn user_path(path: Option<&Path>) -> Result<PathBuf> {
+    if let Some(path) = path {
+        Ok(path.to_path_buf())
+    } else {
+        let config_dir = dirs::config_dir().context("Failed to find config dir")?;
+        Ok(config_dir.join("libredefender.toml"))
+    }
+}
 
-    let settings = settings.build().context("Failed to load configuration")?;
+/// The config sources, lowest precedence first
+///
+/// The user file is the one passed with `--config`, or ~/.config/libredefender.toml if it exists.
+fn sources(path: Option<&Path>) -> Result<Vec<(Origin, Source)>> {
+    let required = path.is_some();
+    let path = user_path(path)?;
 
+    let system = config::File::new(SYSTEM_CONFIG, config::FileFormat::Toml).required(false);
+    let user =
+        config::File::new(&path_to_string(&path)?, config::FileFormat::Toml).required(required);
+    let env = config::Environment::with_prefix("LIBREDEFENDER")
+        .prefix_separator("_")
+        .separator("__")
+        .try_parsing(true);
+
+    Ok(vec![
+        (
+            Origin::SystemFile(PathBuf::from(SYSTEM_CONFIG)),
+            vec![Box::new(system)],
+        ),
+        (Origin::UserFile(path), vec![Box::new(user)]),
+        (Origin::Env, vec![Box::new(env)]),
+    ])
+}
+
+fn settings(path: Option<&Path>) -> Result<config::Config> {
+    let mut settings =
+        config::Config::builder().set_default("update.path", DEFAULT_DATABASE_PATH)?;
+    for (_, source) in sources(path)? {
+        settings = settings.add_source(source);
+    }
+    settings.build().context("Failed to load configuration")
+}
+
+/// Collect keys that are set in `raw` but don't exist in `known`, e.g. `scan.exclude`
+fn unknown_keys(prefix: &str, raw: &serde_json::Value, known: &serde_json::Value) -> Vec<String> {
+    let (Some(raw), Some(known)) = (raw.as_object(), known.as_object()) else {
+        return Vec::new();
+    };
+
+    let mut unknown = Vec::new();
+    for (key, value) in raw {
+        let path = if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match known.get(key) {
+            Some(known) => unknown.extend(unknown_keys(&path, value, known)),
+            None => unknown.push(path),
+        }
+    }
+    unknown
+}
+
+/// Deserialize the config and return the keys that were ignored
+fn parse(settings: config::Config) -> Result<(Config, Vec<String>)> {
+    let raw = settings
+        .clone()
+        .try_deserialize::<serde_json::Value>()
+        .context("Failed to parse config")?;
     let config = settings
         .try_deserialize::<Config>()
         .context("Failed to parse config")?;
+    let known = serde_json::to_value(&config)?;
+    let unknown = unknown_keys("", &raw, &known);
+    Ok((config, unknown))
+}
+
+pub fn load(path: Option<&Path>, args: Option<&args::Scan>) -> Result<Config> {
+    let (mut config, unknown) = parse(settings(path)?)?;
+    for key in unknown {
+        warn!("Ignoring unknown key in config: {:?}", key);
+    }
+
+    if let Some(args) = args {
+        if let Some(name) = &args.profile {
+            let profile = config
+                .profile
+                .remove(name)
+                .with_context(|| anyhow!("Profile not found in config: {:?}", name))?;
+            profile.apply(&mut config.scan);
+        }
+        if let Some(concurrency) = args.concurrency {
+            config.scan.concurrency = Some(concurrency);
+        }
+        if args.quick {
+            config.scan.quick = true;
+        }
+        if args.follow_mounts {
+            config.scan.follow_mounts = true;
+        }
+        if args.newest_first {
+            config.scan.newest_first = true;
+        }
+        if args.estimate {
+            config.scan.estimate = true;
+        }
+        if let Some(user) = &args.user {
+            config.scan.user = Some(user.clone());
+        }
+        config.scan.excludes.extend(args.excludes.iter().cloned());
+        if args.foreign {
+            config.scan.ignore_files = Some(false);
+        }
+    }
+
+    // a system-wide config can refer to the home directory of the user that is scanning
+    config.scan.paths = config
+        .scan
+        .paths
+        .iter()
+        .map(|path| utils::expand_path(path))
+        .collect::<Result<_>>()
+        .context("Failed to expand scan.paths")?;
+    config.scan.path_overrides = mem::take(&mut config.scan.path_overrides)
+        .into_iter()
+        .map(|(path, o)| Ok((utils::expand_path(&path)?, o)))
+        .collect::<Result<_>>()
+        .context("Failed to expand scan.path_overrides")?;
+    config.scan.excludes = config
+        .scan
+        .excludes
+        .iter()
+        .map(Pattern::expand_vars)
+        .collect::<Result<_>>()
+        .context("Failed to expand scan.excludes")?;
+
+    load_exclude_file(&mut config.scan)?;
+    config.notify.push.proxy = config.update.proxy.clone();
+    if let Some(reporting) = &mut config.reporting {
+        reporting.proxy = config.update.proxy.clone();
+    }
 
     Ok(config)
 }
 
-#[derive(Debug)]
+/// `exclude_matcher` isn't serialized, this loads it again after the config was sent to another process
+pub fn load_exclude_file(scan: &mut ScanConfig) -> Result<()> {
+    if let Some(path) = &scan.exclude_file {
+        let path = utils::expand_path(path)?;
+        scan.exclude_matcher = Some(ExcludeFile::load(&path)?);
+    }
+    Ok(())
+}
+
+fn flatten(prefix: &str, value: &serde_json::Value, out: &mut Vec<(String, serde_json::Value)>) {
+    match value.as_object() {
+        Some(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value, out);
+            }
+        }
+        None => out.push((prefix.to_string(), value.clone())),
+    }
+}
+
+fn lookup<'a>(value: &'a serde_json::Value, key: &str) -> Option<&'a serde_json::Value> {
+    key.split('.').try_fold(value, |value, key| value.get(key))
+}
+
+/// The effective value of every key, annotated with the source that set it
+pub fn origins(path: Option<&Path>) -> Result<Vec<(String, serde_json::Value, Origin)>> {
+    let config = load(path, None)?;
+    let mut effective = Vec::new();
+    flatten("", &serde_json::to_value(&config)?, &mut effective);
+
+    let mut layers = Vec::new();
+    for (origin, source) in sources(path)? {
+        let raw = config::Config::builder()
+            .add_source(source)
+            .build()
+            .with_context(|| anyhow!("Failed to load {}", origin))?
+            .try_deserialize::<serde_json::Value>()
+            .with_context(|| anyhow!("Failed to parse {}", origin))?;
+        layers.push((origin, raw));
+    }
+
+    let origins = effective
+        .into_iter()
+        .map(|(key, value)| {
+            let origin = layers
+                .iter()
+                .rev()
+                .find(|(_, raw)| lookup(raw, &key).is_some())
+                .map_or(Origin::Default, |(origin, _)| origin.clone());
+            (key, value, origin)
+        })
+        .collect();
+    Ok(origins)
+}
+
+/// A problem found by `config check`, with the toml key at fault
+#[derive(Debug, PartialEq, Eq)]
+pub struct Issue {
+    pub key: String,
+    pub message: String,
+}
+
+impl Issue {
+    fn new<K: Into<String>, M: Into<String>>(key: K, message: M) -> Issue {
+        Issue {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+fn check_https(issues: &mut Vec<Issue>, key: &str, url: &str) {
+    if !url.starts_with("https://") {
+        issues.push(Issue::new(key, "Must be an https:// url"));
+    }
+}
+
+fn check_paths(issues: &mut Vec<Issue>, key: &str, paths: &[PathBuf]) {
+    for path in paths {
+        if !path.exists() {
+            issues.push(Issue::new(key, format!("Path does not exist: {:?}", path)));
+        }
+    }
+}
+
+fn check_positive(issues: &mut Vec<Issue>, key: &str, value: Option<f64>) {
+    if value.is_some_and(|v| v.is_nan() || v <= 0.0) {
+        issues.push(Issue::new(key, "Must be greater than 0"));
+    }
+}
+
+/// Check the values that deserialize fine but can't work at runtime
+#[must_use]
+pub fn validate(config: &Config) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let scan = &config.scan;
+
+    check_paths(&mut issues, "scan.paths", &scan.paths);
+    if scan.concurrency == Some(0) {
+        issues.push(Issue::new("scan.concurrency", "Must be at least 1"));
+    }
+    if let Some(path) = &scan.exclude_file {
+        match utils::expand_path(path).and_then(|path| ExcludeFile::load(&path)) {
+            Ok(_) => (),
+            Err(err) => issues.push(Issue::new("scan.exclude_file", format!("{:#}", err))),
+        }
+    }
+    for mime in &scan.skip_mime {
+        if !mime.contains('/') {
+            issues.push(Issue::new(
+                "scan.skip_mime",
+                format!("Expected a mime type like `video/*`: {:?}", mime),
+            ));
+        }
+    }
+    check_positive(&mut issues, "scan.max_read_mbps", scan.max_read_mbps);
+    check_positive(&mut issues, "scan.files_per_second", scan.files_per_second);
+    if scan.nice_level.is_some_and(|n| !(-20..=19).contains(&n)) {
+        issues.push(Issue::new("scan.nice_level", "Must be between -20 and 19"));
+    }
+    if scan.ionice_level.is_some_and(|n| n > 7) {
+        issues.push(Issue::new("scan.ionice_level", "Must be between 0 and 7"));
+    }
+    if let Some(cpuset) = &scan.cpuset {
+        let cpus = num_cpus::get();
+        if cpuset.is_empty() {
+            issues.push(Issue::new("scan.cpuset", "Must contain at least one core"));
+        } else if let Some(cpu) = cpuset.iter().find(|cpu| **cpu >= nice::MAX_CPUS) {
+            issues.push(Issue::new(
+                "scan.cpuset",
+                format!(
+                    "Core {} can't be pinned to, must be below {}",
+                    cpu,
+                    nice::MAX_CPUS
+                ),
+            ));
+        } else if let Some(cpu) = cpuset.iter().find(|cpu| **cpu >= cpus) {
+            issues.push(Issue::new(
+                "scan.cpuset",
+                format!("There's no core {}, this system has {} cores", cpu, cpus),
+            ));
+        }
+    }
+    if let Some(cgroup) = &scan.cgroup {
+        for (key, weight) in [
+            ("scan.cgroup.cpu_weight", cgroup.cpu_weight),
+            ("scan.cgroup.io_weight", cgroup.io_weight),
+        ] {
+            if weight.is_some_and(|w| !(1..=10000).contains(&w)) {
+                issues.push(Issue::new(key, "Must be between 1 and 10000"));
+            }
+        }
+    }
+
+    check_paths(
+        &mut issues,
+        "update.path",
+        std::slice::from_ref(&config.update.path),
+    );
+    check_paths(&mut issues, "update.paths", &config.update.paths);
+    if let Some(proxy) = &config.update.proxy {
+        if let Err(err) = http::check_proxy(proxy) {
+            issues.push(Issue::new("update.proxy", format!("{:#}", err)));
+        }
+    }
+    check_paths(&mut issues, "scan.extra_databases", &scan.extra_databases);
+
+    match config.schedule.automatic_scans.as_deref() {
+        Some("off") | Some("daily") | None => (),
+        Some(value) => issues.push(Issue::new(
+            "schedule.automatic_scans",
+            format!("Expected \"daily\" or \"off\": {:?}", value),
+        )),
+    }
+    if let Some(name) = &config.schedule.profile {
+        if !config.profile.contains_key(name) {
+            issues.push(Issue::new(
+                "schedule.profile",
+                format!("Profile not found in config: {:?}", name),
+            ));
+        }
+    }
+    let mut job_names = HashSet::new();
+    for job in &config.schedule.job {
+        if job.name.is_empty() {
+            issues.push(Issue::new("schedule.job.name", "Must not be empty"));
+        } else if !job_names.insert(&job.name) {
+            issues.push(Issue::new(
+                "schedule.job.name",
+                format!("Duplicate job name: {:?}", job.name),
+            ));
+        }
+        if let Some(name) = &job.profile {
+            if !config.profile.contains_key(name) {
+                issues.push(Issue::new(
+                    format!("schedule.job.{}.profile", job.name),
+                    format!("Profile not found in config: {:?}", name),
+                ));
+            }
+        }
+    }
+
+    let respect = config.schedule.respect_inhibitors.iter();
+    let respect = respect.map(|what| ("schedule.respect_inhibitors", what.as_str()));
+    let inhibit = config
+        .schedule
+        .inhibit
+        .iter()
+        .flat_map(|what| what.split(':'));
+    let inhibit = inhibit.map(|what| ("schedule.inhibit", what));
+    for (key, what) in respect.chain(inhibit) {
+        if !INHIBITOR_OPERATIONS.contains(&what) {
+            issues.push(Issue::new(
+                key,
+                format!("Unknown inhibitor operation: {:?}", what),
+            ));
+        }
+    }
+
+    check_paths(&mut issues, "watch.paths", &config.watch.paths);
+
+    let push = &config.notify.push;
+    if let Some(ntfy) = &push.ntfy {
+        check_https(&mut issues, "notify.push.ntfy.url", &ntfy.url);
+        if let Err(err) = ntfy.token() {
+            issues.push(Issue::new("notify.push.ntfy.token", format!("{:#}", err)));
+        }
+    }
+    if let Some(matrix) = &push.matrix {
+        check_https(
+            &mut issues,
+            "notify.push.matrix.homeserver",
+            &matrix.homeserver,
+        );
+        if let Err(err) = matrix.access_token() {
+            issues.push(Issue::new(
+                "notify.push.matrix.access_token",
+                format!("{:#}", err),
+            ));
+        }
+    }
+    if let Some(telegram) = &push.telegram {
+        if let Err(err) = telegram.bot_token() {
+            issues.push(Issue::new(
+                "notify.push.telegram.bot_token",
+                format!("{:#}", err),
+            ));
+        }
+    }
+
+    if let Some(reporting) = &config.reporting {
+        check_https(&mut issues, "reporting.url", &reporting.url);
+        if let Err(err) = reporting.token() {
+            issues.push(Issue::new("reporting.token", format!("{:#}", err)));
+        }
+        if let Err(err) = reporting.signing_key() {
+            issues.push(Issue::new("reporting.signing_key", format!("{:#}", err)));
+        }
+    }
+
+    let hooks = &config.hooks;
+    for (key, command) in [
+        ("hooks.on_threat", &hooks.on_threat),
+        ("hooks.on_scan_start", &hooks.on_scan_start),
+        ("hooks.on_scan_end", &hooks.on_scan_end),
+    ] {
+        if command.as_ref().is_some_and(|c| c.trim().is_empty()) {
+            issues.push(Issue::new(key, "Must not be empty"));
+        }
+    }
+    if hooks.timeout == Some(0) {
+        issues.push(Issue::new("hooks.timeout", "Must be at least 1"));
+    }
+
+    if config.canary.enabled {
+        check_paths(&mut issues, "canary.paths", &config.canary.paths);
+    }
+    match config.quarantine.retention_days {
+        Some(0) => issues.push(Issue::new(
+            "quarantine.retention_days",
+            "Must be at least 1",
+        )),
+        Some(days) if days > MAX_RETENTION_DAYS => issues.push(Issue::new(
+            "quarantine.retention_days",
+            format!("Must be at most {}", MAX_RETENTION_DAYS),
+        )),
+        _ => (),
+    }
+
+    let mut names = config.profile.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        let profile = &config.profile[name];
+        if let Some(paths) = &profile.paths {
+            check_paths(
+                &mut issues,
+                &format!("profile.{}.paths", name),
+                &paths.paths(),
+            );
+        }
+        if let Some(ProfilePaths::Table(paths)) = &profile.paths {
+            for (path, o) in paths {
+                let key = |field| format!("profile.{}.paths.{:?}.{}", name, path, field);
+                if o.concurrency == Some(0) {
+                    issues.push(Issue::new(key("concurrency"), "Must be at least 1"));
+                }
+                if o.nice_level.is_some_and(|n| !(-20..=19).contains(&n)) {
+                    issues.push(Issue::new(key("nice_level"), "Must be between -20 and 19"));
+                }
+                if o.ionice_level.is_some_and(|n| n > 7) {
+                    issues.push(Issue::new(key("ionice_level"), "Must be between 0 and 7"));
+                }
+            }
+        }
+        if profile.concurrency == Some(0) {
+            issues.push(Issue::new(
+                format!("profile.{}.concurrency", name),
+                "Must be at least 1",
+            ));
+        }
+    }
+
+    issues
+}
+
+/// Load the config strictly for `config check`, unknown keys are reported instead of ignored
+pub fn check(path: Option<&Path>) -> Result<Vec<Issue>> {
+    let (config, unknown) = parse(settings(path)?)?;
+    let mut issues = unknown
+        .into_iter()
+        .map(|key| Issue::new(key, "Unknown key"))
+        .collect::<Vec<_>>();
+    issues.extend(validate(&config));
+    Ok(issues)
+}
+
+#[derive(Debug, Clone)]
 pub struct HumanSize(SpecificSize);
 
 impl HumanSize {
@@ -108,3 +1276,173 @@ impl<'de> Deserialize<'de> for HumanSize {
         FromStr::from_str(&s).map_err(de::Error::custom)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_scansize_per_worker() {
+        let scan = ScanConfig {
+            cpuset: Some(vec![0, 1]),
+            max_memory: Some(HumanSize::from_str("2 GiB").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(scan.workers(), 2);
+        assert_eq!(scan.max_scansize(), Some(1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_profile_path_overrides() {
+        let profile = serde_json::from_value::<ProfileConfig>(serde_json::json!({
+            "paths": {
+                "/home/user": {},
+                "/mnt/usb": {"concurrency": 1, "ionice_class": "idle"},
+            },
+        }))
+        .unwrap();
+        let mut scan = ScanConfig {
+            concurrency: Some(8),
+            ..Default::default()
+        };
+        profile.apply(&mut scan);
+        assert_eq!(
+            scan.paths,
+            vec![PathBuf::from("/home/user"), PathBuf::from("/mnt/usb")]
+        );
+        assert_eq!(
+            scan.path_overrides.into_iter().collect::<Vec<_>>(),
+            vec![(
+                PathBuf::from("/mnt/usb"),
+                PathOverride {
+                    concurrency: Some(1),
+                    ionice_class: Some(IoniceClass::Idle),
+                    ..Default::default()
+                }
+            )]
+        );
+
+        let profile = serde_json::from_value::<ProfileConfig>(serde_json::json!({
+            "paths": ["/home/user"],
+        }))
+        .unwrap();
+        assert_eq!(
+            profile.paths,
+            Some(ProfilePaths::List(vec![PathBuf::from("/home/user")]))
+        );
+    }
+
+    #[test]
+    fn test_apply_profile() {
+        let mut scan = ScanConfig {
+            paths: vec![PathBuf::from("/home/user")],
+            skip_hidden: true,
+            ..Default::default()
+        };
+        let profile = ProfileConfig {
+            concurrency: Some(1),
+            quick: Some(true),
+            ..Default::default()
+        };
+        profile.apply(&mut scan);
+        assert_eq!(scan.paths, vec![PathBuf::from("/home/user")]);
+        assert!(scan.skip_hidden);
+        assert_eq!(scan.concurrency, Some(1));
+        assert!(scan.quick);
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let raw = serde_json::json!({
+            "scan": {"skip_hidden": true, "exclude": ["/tmp"]},
+            "update": {"path": "/var/lib/clamav"},
+            "sched": {},
+        });
+        let known = serde_json::json!({
+            "scan": {"skip_hidden": true, "excludes": []},
+            "update": {"path": "/var/lib/clamav"},
+        });
+        let mut unknown = unknown_keys("", &raw, &known);
+        unknown.sort();
+        assert_eq!(unknown, vec!["scan.exclude", "sched"]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = Config {
+            scan: ScanConfig {
+                concurrency: Some(0),
+                ionice_level: Some(9),
+                cpuset: Some(vec![0, 4096]),
+                ..Default::default()
+            },
+            update: UpdateConfig {
+                path: PathBuf::from("/"),
+                paths: Vec::new(),
+                max_age_days: DEFAULT_MAX_AGE_DAYS,
+                refresh_when_stale: false,
+                allow_metered: false,
+                proxy: Some("ftp://proxy.example.com".to_string()),
+                reload_on_change: true,
+                refuse_tampered: true,
+                descriptions: Vec::new(),
+            },
+            schedule: ScheduleConfig {
+                profile: Some("nightly".to_string()),
+                ..Default::default()
+            },
+            watch: WatchConfig::default(),
+            notify: NotifyConfig::default(),
+            metrics: MetricsConfig::default(),
+            reporting: None,
+            hooks: HooksConfig::default(),
+            quarantine: QuarantineConfig {
+                retention_days: Some(u32::MAX),
+                ..Default::default()
+            },
+            canary: CanaryConfig::default(),
+            profile: HashMap::new(),
+        };
+        let keys = validate(&config)
+            .into_iter()
+            .map(|issue| issue.key)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                "scan.concurrency",
+                "scan.ionice_level",
+                "scan.cpuset",
+                "update.proxy",
+                "schedule.profile",
+                "quarantine.retention_days"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flatten() {
+        let value = serde_json::json!({
+            "scan": {"concurrency": 2, "paths": ["/home"], "cgroup": null},
+            "profile": {"daily": {"quick": true}},
+        });
+        let mut flat = Vec::new();
+        flatten("", &value, &mut flat);
+        flat.sort_by(|a, b| a.0.cmp(&b.0));
+        let keys = flat.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                "profile.daily.quick",
+                "scan.cgroup",
+                "scan.concurrency",
+                "scan.paths"
+            ]
+        );
+        assert_eq!(
+            lookup(&value, "scan.concurrency"),
+            Some(&serde_json::json!(2))
+        );
+        assert_eq!(lookup(&value, "scan.quick"), None);
+    }
+}
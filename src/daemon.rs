@@ -0,0 +1,64 @@
+use crate::errors::*;
+use crate::pidfile::{self, PidFile};
+use crate::utils;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// `~/.local/share/libredefender/scan.log`
+pub fn default_log_file() -> Result<PathBuf> {
+    let path = utils::data_dir()?.join("scan.log");
+    Ok(path)
+}
+
+fn redirect(file: &File, fd: libc::c_int) -> Result<()> {
+    if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+        return Err(io::Error::last_os_error()).context("Failed to redirect output");
+    }
+    Ok(())
+}
+
+/// Fork to the background with stdout and stderr appended to `log_file`
+///
+/// Only the child returns, it keeps the scan pid file locked until it exits. This needs to be
+/// called before any threads are started, including the ones of the logging setup.
+pub fn detach(log_file: &Path) -> Result<PidFile> {
+    let mut pidfile = PidFile::acquire(&pidfile::scan_path())
+        .context("A detached scan is already running, see `libredefender ctl status`")?;
+
+    if let Some(dir) = log_file.parent() {
+        fs::create_dir_all(dir).with_context(|| anyhow!("Failed to create {:?}", dir))?;
+    }
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .with_context(|| anyhow!("Failed to open log file {:?}", log_file))?;
+    let null = File::open("/dev/null").context("Failed to open /dev/null")?;
+    io::stdout().flush()?;
+
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()).context("Failed to fork"),
+        0 => {
+            // a new session doesn't receive the SIGHUP when the ssh connection closes
+            if unsafe { libc::setsid() } == -1 {
+                return Err(io::Error::last_os_error()).context("Failed to create session");
+            }
+            redirect(&null, libc::STDIN_FILENO)?;
+            redirect(&log, libc::STDOUT_FILENO)?;
+            redirect(&log, libc::STDERR_FILENO)?;
+            Ok(pidfile)
+        }
+        pid => {
+            pidfile.set_pid(pid as u32)?;
+            println!(
+                "Scan is running in the background (pid {}), logging to {:?}",
+                pid, log_file
+            );
+            println!("Follow its progress with `libredefender ctl status`");
+            process::exit(0);
+        }
+    }
+}
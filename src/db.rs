@@ -1,17 +1,41 @@
 use crate::errors::*;
+use crate::privdrop;
+use crate::severity::{self, Severity};
+use crate::utils;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hash;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// Signature names that are kept for each path
+const MAX_NAMES: usize = 8;
 
 pub struct Database {
     path: PathBuf,
     data: Data,
+    /// The contents of the file when it was loaded, to find out what other processes changed since then
+    base: Option<Data>,
+}
+
+/// Lock the database file for a single read or write, the lock is released when the file is closed
+///
+/// Nothing is locked while a scan runs, scans can take hours and other commands write to the
+/// database in the meantime. `store` merges their changes with the ones made since `load` instead.
+fn lock(file: &File, operation: libc::c_int) -> Result<()> {
+    utils::flock(file, operation).context("Failed to lock database")
 }
 
 impl Database {
     pub fn path() -> Result<PathBuf> {
+        if let Some(dir) = utils::custom_data_dir() {
+            return Ok(dir.join("libredefender.db"));
+        }
+        // the default location is next to the data directory, not inside of it
         let data_dir = dirs::data_dir().context("Failed to find data directory")?;
         let path = data_dir.join("libredefender.db");
         Ok(path)
@@ -25,6 +49,7 @@ impl Database {
             Ok(Database {
                 path,
                 data: Data::default(),
+                base: None,
             })
         }
     }
@@ -44,17 +69,69 @@ impl Database {
     }
 
     pub fn load_from_existing(path: PathBuf) -> Result<Database> {
-        let buf = fs::read(&path).context("Failed to open database")?;
-        let data = serde_json::from_slice(&buf).context("Failed to read database")?;
-        Ok(Database { path, data })
+        let mut file = File::open(&path).context("Failed to open database")?;
+        // only protects against reading a partial write, it's released when this function returns
+        lock(&file, libc::LOCK_SH)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)
+            .context("Failed to read database")?;
+        let data: Data = serde_json::from_slice(&buf).context("Failed to read database")?;
+        Ok(Database {
+            path,
+            base: Some(data.clone()),
+            data,
+        })
     }
 
-    pub fn store(&self) -> Result<()> {
+    /// Write the database, changes other processes made since it was loaded are merged instead of overwritten
+    #[tracing::instrument(skip_all, fields(path = %self.path.display()))]
+    pub fn store(&mut self) -> Result<()> {
         if let Some(parent) = self.path.parent() {
             fs::create_dir_all(parent).context("Failed to create database directory")?;
         }
+        // the file is written in place, `watch` opens it before dropping the privileges
+        let mut file = match privdrop::kept_open(&self.path) {
+            Some(file) => file?,
+            None => OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&self.path)
+                .context("Failed to open database")?,
+        };
+        lock(&file, libc::LOCK_EX)?;
+        let ret = self.merge_and_write(&mut file);
+        // a kept descriptor holds the lock after this one is closed
+        lock(&file, libc::LOCK_UN)?;
+        ret
+    }
+
+    fn merge_and_write(&mut self, file: &mut File) -> Result<()> {
+        // kept descriptors share their offset with the previous write
+        file.seek(SeekFrom::Start(0))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)
+            .context("Failed to read database")?;
+        if !buf.is_empty() {
+            match serde_json::from_slice::<Data>(&buf) {
+                Ok(theirs) => {
+                    let base = self.base.take().unwrap_or_default();
+                    if theirs != base {
+                        debug!("Database was modified by another process, merging changes");
+                        let ours = mem::take(&mut self.data);
+                        self.data = Data::merge(&base, ours, theirs);
+                    }
+                }
+                Err(err) => warn!("Overwriting database that can't be read: {:#}", err),
+            }
+        }
+
         let buf = serde_json::to_vec(&self.data)?;
-        fs::write(&self.path, buf).context("Failed to write database")?;
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&buf).context("Failed to write database")?;
+        self.base = Some(self.data.clone());
         debug!("Wrote database to {}", self.path.display());
         Ok(())
     }
@@ -70,10 +147,560 @@ impl Database {
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// Take the value that was changed compared to `base`, `ours` if both were changed
+fn pick<T: PartialEq>(base: &T, ours: T, theirs: T) -> T {
+    if ours == *base {
+        theirs
+    } else {
+        ours
+    }
+}
+
+/// Three-way merge of each key, `resolve` is called for keys that were changed differently by both sides
+fn merge_map<K, V, F>(
+    base: &HashMap<K, V>,
+    mut ours: HashMap<K, V>,
+    mut theirs: HashMap<K, V>,
+    resolve: F,
+) -> HashMap<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: PartialEq,
+    F: Fn(&K, Option<V>, Option<V>) -> Option<V>,
+{
+    let keys = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .cloned()
+        .collect::<HashSet<_>>();
+    let mut merged = HashMap::new();
+    for key in keys {
+        let base = base.get(&key);
+        let ours = ours.remove(&key);
+        let theirs = theirs.remove(&key);
+        let value = if ours.as_ref() == base {
+            theirs
+        } else if theirs.as_ref() == base || ours == theirs {
+            ours
+        } else {
+            resolve(&key, ours, theirs)
+        };
+        if let Some(value) = value {
+            merged.insert(key, value);
+        }
+    }
+    merged
+}
+
+/// Both sides changed the threat, e.g. a scan found another signature while `infections --delete`
+/// removed the file, it's only kept if the file still exists
+fn resolve_threat(path: &Path, ours: Option<Threat>, theirs: Option<Threat>) -> Option<Threat> {
+    match (ours, theirs) {
+        (Some(mut ours), Some(theirs)) => {
+            for name in theirs.names {
+                ours.add_name(name);
+            }
+            for member in theirs.members {
+                ours.add_member(member);
+            }
+            ours.first_seen = match (ours.first_seen, theirs.first_seen) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            Some(ours)
+        }
+        (threat, None) | (None, threat) => threat.filter(|_| path.symlink_metadata().is_ok()),
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "ThreatRecord")]
+pub struct Threat {
+    pub names: Vec<String>,
+    pub first_seen: Option<DateTime<Utc>>,
+    pub severity: Severity,
+    /// The detected files inside of an archive, the threat is recorded on the archive
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<PathBuf>,
+}
+
+impl Threat {
+    #[must_use]
+    pub fn new(first_seen: DateTime<Utc>) -> Threat {
+        Threat {
+            names: Vec::new(),
+            first_seen: Some(first_seen),
+            severity: Severity::default(),
+            members: Vec::new(),
+        }
+    }
+
+    /// Record another detection, this also updates the severity
+    ///
+    /// Another revision of a signature that is already recorded replaces it, so repeated
+    /// scans with updated signatures don't grow the list.
+    pub fn add_name(&mut self, name: String) {
+        if self.names.contains(&name) {
+            return;
+        }
+        self.severity = self.severity.max(severity::classify(&name));
+        let family = signature_family(&name);
+        if let Some(existing) = self
+            .names
+            .iter_mut()
+            .find(|existing| signature_family(existing) == family)
+        {
+            *existing = name;
+            return;
+        }
+        self.names.push(name);
+        if self.names.len() > MAX_NAMES {
+            // the first detection is kept, it's what the threat is displayed as
+            self.names.remove(1);
+        }
+    }
+
+    /// Record a detected file inside of the archive
+    pub fn add_member(&mut self, member: PathBuf) {
+        if !self.members.contains(&member) {
+            self.members.push(member);
+        }
+    }
+
+    /// The name the threat was first detected as
+    #[must_use]
+    pub fn primary(&self) -> Option<&str> {
+        self.names.first().map(String::as_str)
+    }
+
+    /// Other signatures that matched the same file
+    #[must_use]
+    pub fn alternates(&self) -> &[String] {
+        self.names.get(1..).unwrap_or_default()
+    }
+}
+
+/// Strip the revision of `Win.Trojan.Agent-123456-0` and the `.UNOFFICIAL` suffix
+///
+/// Only the last number is a revision if it follows the signature id, a single trailing number is part
+/// of the name.
+#[must_use]
+pub fn signature_family(name: &str) -> &str {
+    fn split_number(name: &str) -> Option<&str> {
+        let (rest, number) = name.rsplit_once('-')?;
+        if rest.is_empty() || number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some(rest)
+    }
+
+    let name = name.strip_suffix(".UNOFFICIAL").unwrap_or(name);
+    match split_number(name) {
+        Some(rest) if split_number(rest).is_some() => rest,
+        _ => name,
+    }
+}
+
+/// Older databases stored a plain list of signature names for each path
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ThreatRecord {
+    Legacy(Vec<String>),
+    Current {
+        names: Vec<String>,
+        first_seen: Option<DateTime<Utc>>,
+        severity: Option<Severity>,
+        #[serde(default)]
+        members: Vec<PathBuf>,
+    },
+}
+
+impl From<ThreatRecord> for Threat {
+    fn from(record: ThreatRecord) -> Threat {
+        let (names, first_seen, severity, members) = match record {
+            ThreatRecord::Legacy(names) => (names, None, None, Vec::new()),
+            ThreatRecord::Current {
+                names,
+                first_seen,
+                severity,
+                members,
+            } => (names, first_seen, severity, members),
+        };
+        // older versions appended every new name, these are deduplicated when loading
+        let mut threat = Threat {
+            names: Vec::new(),
+            first_seen,
+            severity: Severity::default(),
+            members,
+        };
+        for name in names {
+            threat.add_name(name);
+        }
+        if let Some(severity) = severity {
+            threat.severity = severity;
+        }
+        threat
+    }
+}
+
+/// Why a file wasn't scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    PermissionDenied,
+    TooLarge,
+    Unreadable,
+    /// Opened for writing by another process, e.g. a download in progress
+    InUse,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SkipReason::PermissionDenied => write!(w, "permission denied"),
+            SkipReason::TooLarge => write!(w, "too large"),
+            SkipReason::Unreadable => write!(w, "unreadable"),
+            SkipReason::InUse => write!(w, "in use"),
+        }
+    }
+}
+
+/// Why scanning a file failed, counted to notice systemic problems like an unreadable subtree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCategory {
+    Permission,
+    Io,
+    /// libclamav or the external scanner failed
+    Engine,
+    Timeout,
+}
+
+impl ErrorCategory {
+    pub const ALL: [ErrorCategory; 4] = [
+        ErrorCategory::Permission,
+        ErrorCategory::Io,
+        ErrorCategory::Engine,
+        ErrorCategory::Timeout,
+    ];
+
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCategory::Permission => "permission",
+            ErrorCategory::Io => "io",
+            ErrorCategory::Engine => "engine",
+            ErrorCategory::Timeout => "timeout",
+        }
+    }
+
+    #[must_use]
+    pub fn skip_reason(&self) -> SkipReason {
+        match self {
+            ErrorCategory::Permission => SkipReason::PermissionDenied,
+            _ => SkipReason::Unreadable,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Data {
     pub last_scan: Option<DateTime<Utc>>,
-    pub threats: HashMap<PathBuf, Vec<String>>,
+    pub threats: HashMap<PathBuf, Threat>,
+    /// Files that couldn't be scanned
+    #[serde(default)]
+    pub skipped: HashMap<PathBuf, SkipReason>,
     pub signature_count: usize,
+    /// Signatures loaded from each database directory or file
+    #[serde(default)]
+    pub signature_sources: HashMap<PathBuf, usize>,
     pub signatures_age: Option<DateTime<Utc>>,
+    /// When each `[[schedule.job]]` last ran to completion
+    #[serde(default)]
+    pub job_runs: HashMap<String, DateTime<Utc>>,
+    #[serde(default)]
+    pub scheduler: SchedulerState,
+    /// Threats that a later scan didn't find anymore
+    #[serde(default)]
+    pub resolved: HashMap<PathBuf, Resolved>,
+    #[serde(default)]
+    pub last_scan_stats: Option<ScanStats>,
+}
+
+impl Data {
+    /// Reconcile the changes of two writers that both started from `base`
+    #[must_use]
+    pub fn merge(base: &Data, ours: Data, theirs: Data) -> Data {
+        let threats = merge_map(&base.threats, ours.threats, theirs.threats, |path, a, b| {
+            resolve_threat(path, a, b)
+        });
+        let mut resolved = merge_map(&base.resolved, ours.resolved, theirs.resolved, |_, a, _| a);
+        resolved.retain(|path, _| !threats.contains_key(path));
+        Data {
+            last_scan: pick(&base.last_scan, ours.last_scan, theirs.last_scan),
+            threats,
+            skipped: merge_map(&base.skipped, ours.skipped, theirs.skipped, |_, a, _| a),
+            signature_count: pick(
+                &base.signature_count,
+                ours.signature_count,
+                theirs.signature_count,
+            ),
+            signature_sources: pick(
+                &base.signature_sources,
+                ours.signature_sources,
+                theirs.signature_sources,
+            ),
+            signatures_age: pick(
+                &base.signatures_age,
+                ours.signatures_age,
+                theirs.signatures_age,
+            ),
+            job_runs: merge_map(&base.job_runs, ours.job_runs, theirs.job_runs, |_, a, b| {
+                a.max(b)
+            }),
+            scheduler: pick(&base.scheduler, ours.scheduler, theirs.scheduler),
+            resolved,
+            last_scan_stats: pick(
+                &base.last_scan_stats,
+                ours.last_scan_stats,
+                theirs.last_scan_stats,
+            ),
+        }
+    }
+}
+
+/// Numbers of the last scan that wasn't cancelled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScanStats {
+    pub files_scanned: usize,
+    pub bytes_scanned: u64,
+    pub duration_secs: f64,
+    pub errors: usize,
+    #[serde(default)]
+    pub error_categories: BTreeMap<ErrorCategory, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resolved {
+    pub names: Vec<String>,
+    pub first_seen: Option<DateTime<Utc>>,
+    pub resolved: DateTime<Utc>,
+}
+
+/// What `libredefender scheduler` is planning to do, for the status output
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchedulerState {
+    pub next_scan: Option<DateTime<Utc>>,
+    /// The `[[schedule.job]]` that runs next
+    pub next_job: Option<String>,
+    pub last_skip: Option<ScheduleSkip>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduleSkip {
+    pub time: DateTime<Utc>,
+    pub reason: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_legacy_threats() {
+        let data: Data = serde_json::from_str(
+            r#"{"last_scan":null,"threats":{"/a":["Win.Test.EICAR_HDB-1"]},"signature_count":0,"signatures_age":null}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            data.threats[&PathBuf::from("/a")],
+            Threat {
+                names: vec!["Win.Test.EICAR_HDB-1".to_string()],
+                first_seen: None,
+                severity: Severity::Low,
+                members: Vec::new(),
+            }
+        );
+    }
+
+    fn threat(names: &[&str]) -> Threat {
+        let mut threat = Threat::default();
+        for name in names {
+            threat.add_name(name.to_string());
+        }
+        threat
+    }
+
+    #[test]
+    fn test_merge_concurrent_delete() {
+        let mut base = Data::default();
+        base.threats
+            .insert(PathBuf::from("/nonexistent/a"), threat(&["Eicar-1"]));
+        base.threats
+            .insert(PathBuf::from("/nonexistent/b"), threat(&["Eicar-2"]));
+
+        // `infections --delete` removed a file while a scan was running
+        let mut theirs = base.clone();
+        theirs.threats.remove(Path::new("/nonexistent/a"));
+
+        // the scan finished with its old snapshot and found another threat
+        let mut ours = base.clone();
+        ours.threats
+            .insert(PathBuf::from("/nonexistent/c"), threat(&["Eicar-3"]));
+        ours.last_scan = Some(Utc::now());
+
+        let merged = Data::merge(&base, ours.clone(), theirs);
+        let mut paths = merged.threats.keys().collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [Path::new("/nonexistent/b"), Path::new("/nonexistent/c")]
+        );
+        assert_eq!(merged.last_scan, ours.last_scan);
+    }
+
+    #[test]
+    fn test_merge_conflicting_threats() {
+        let dir = tempfile::tempdir().unwrap();
+        let exists = dir.path().join("a");
+        fs::write(&exists, "").unwrap();
+        let gone = dir.path().join("b");
+
+        let mut base = Data::default();
+        base.threats.insert(exists.clone(), threat(&["Eicar-1"]));
+        base.threats.insert(gone.clone(), threat(&["Eicar-1"]));
+
+        let mut ours = base.clone();
+        ours.threats
+            .get_mut(&exists)
+            .unwrap()
+            .add_name("Eicar-2".to_string());
+        ours.threats
+            .get_mut(&gone)
+            .unwrap()
+            .add_name("Eicar-2".to_string());
+
+        let mut theirs = base.clone();
+        theirs
+            .threats
+            .get_mut(&exists)
+            .unwrap()
+            .add_name("Eicar-3".to_string());
+        theirs.threats.remove(&gone);
+
+        let merged = Data::merge(&base, ours, theirs);
+        assert_eq!(
+            merged.threats[&exists].names,
+            ["Eicar-1", "Eicar-2", "Eicar-3"]
+        );
+        assert!(!merged.threats.contains_key(&gone));
+    }
+
+    #[test]
+    fn test_store_merges() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("libredefender.db");
+        let mut db = Database {
+            path: path.clone(),
+            data: Data::default(),
+            base: None,
+        };
+        db.data_mut()
+            .threats
+            .insert(PathBuf::from("/nonexistent/a"), threat(&["Eicar-1"]));
+        db.store().unwrap();
+
+        let mut scan = Database::load_from_existing(path.clone()).unwrap();
+        let mut infections = Database::load_from_existing(path.clone()).unwrap();
+        infections
+            .data_mut()
+            .threats
+            .remove(Path::new("/nonexistent/a"));
+        infections.store().unwrap();
+
+        scan.data_mut().signature_count = 23;
+        scan.store().unwrap();
+
+        let db = Database::load_from_existing(path).unwrap();
+        assert!(db.data().threats.is_empty());
+        assert_eq!(db.data().signature_count, 23);
+    }
+
+    #[test]
+    fn test_signature_family() {
+        assert_eq!(
+            signature_family("Win.Trojan.Agent-123456-0"),
+            "Win.Trojan.Agent-123456"
+        );
+        assert_eq!(
+            signature_family("Win.Test.EICAR_HDB-1"),
+            "Win.Test.EICAR_HDB-1"
+        );
+        assert_eq!(
+            signature_family("Win.Malware.Foo-12-1.UNOFFICIAL"),
+            "Win.Malware.Foo-12"
+        );
+        assert_eq!(signature_family("Eicar-Signature"), "Eicar-Signature");
+        assert_eq!(signature_family("YARA.rule_1-"), "YARA.rule_1-");
+    }
+
+    #[test]
+    fn test_add_name_dedup() {
+        let mut threat = Threat::default();
+        threat.add_name("Win.Trojan.Agent-123-0".to_string());
+        threat.add_name("Win.Trojan.Agent-123-1".to_string());
+        threat.add_name("Win.Trojan.Agent-123-1".to_string());
+        threat.add_name("Win.Test.EICAR_HDB-1".to_string());
+        assert_eq!(threat.primary(), Some("Win.Trojan.Agent-123-1"));
+        assert_eq!(threat.alternates(), ["Win.Test.EICAR_HDB-1"]);
+
+        for i in 0..20 {
+            threat.add_name(format!("Heuristics.Test{}", i));
+        }
+        assert_eq!(threat.names.len(), MAX_NAMES);
+        assert_eq!(threat.primary(), Some("Win.Trojan.Agent-123-1"));
+        assert_eq!(threat.names.last().unwrap(), "Heuristics.Test19");
+    }
+
+    #[test]
+    fn test_add_name_keeps_other_ids() {
+        let mut threat = Threat::default();
+        threat.add_name("Win.Trojan.Agent-123-0".to_string());
+        threat.add_name("Win.Trojan.Agent-456-0".to_string());
+        threat.add_name("Win.Trojan.Agent-123-1".to_string());
+        assert_eq!(
+            threat.names,
+            ["Win.Trojan.Agent-123-1", "Win.Trojan.Agent-456-0"]
+        );
+    }
+
+    #[test]
+    fn test_dedup_on_load() {
+        let threat: Threat = serde_json::from_str(
+            r#"{"names":["Win.Trojan.Agent-1-0","Win.Trojan.Agent-1-1","Win.Trojan.Agent-1-2"],"first_seen":null,"severity":"high"}"#,
+        )
+        .unwrap();
+        assert_eq!(threat.names, ["Win.Trojan.Agent-1-2"]);
+        assert_eq!(threat.severity, Severity::High);
+    }
+
+    #[test]
+    fn test_threat_roundtrip() {
+        let threat = Threat {
+            names: vec!["Win.Test.EICAR_HDB-1".to_string()],
+            first_seen: Some(Utc::now()),
+            severity: Severity::Low,
+            members: vec![PathBuf::from("dist/setup.exe")],
+        };
+        let json = serde_json::to_string(&threat).unwrap();
+        let threat2 = serde_json::from_str::<Threat>(&json).unwrap();
+        assert_eq!(threat, threat2);
+    }
 }
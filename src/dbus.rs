@@ -0,0 +1,217 @@
+use crate::audit::{self, Action};
+use crate::db::Database;
+use crate::errors::*;
+use crate::quarantine::Quarantine;
+use crate::scan::{Control, Event};
+use crate::schedule::Command;
+use crate::status;
+use crate::utils;
+use crossbeam_channel::Sender;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use zbus::blocking::{Connection, ConnectionBuilder};
+use zbus::fdo;
+use zbus::zvariant::{OwnedValue, Value};
+use zbus::{dbus_interface, SignalContext};
+
+pub const NAME: &str = "org.libredefender1";
+pub const PATH: &str = "/org/libredefender1";
+
+pub struct Service {
+    control: Arc<Control>,
+    commands: Sender<Command>,
+    max_age: chrono::Duration,
+}
+
+fn db_error(err: &Error) -> fdo::Error {
+    fdo::Error::Failed(format!("{:#}", err))
+}
+
+#[dbus_interface(name = "org.libredefender1")]
+impl Service {
+    /// Start a scan immediately, or resume a paused one
+    fn start_scan(&self) -> fdo::Result<()> {
+        if self.control.is_running() {
+            self.control.resume();
+        } else {
+            self.commands
+                .send(Command::StartScan)
+                .map_err(|_| fdo::Error::Failed("Scheduler is shutting down".into()))?;
+        }
+        Ok(())
+    }
+
+    fn pause_scan(&self) -> fdo::Result<()> {
+        if !self.control.is_running() {
+            return Err(fdo::Error::Failed("No scan is running".into()));
+        }
+        self.control.pause();
+        Ok(())
+    }
+
+    fn resume_scan(&self) {
+        self.control.resume();
+    }
+
+    fn get_status(&self) -> fdo::Result<HashMap<String, OwnedValue>> {
+        let db = Database::load().map_err(|err| db_error(&err))?;
+        let data = db.data();
+
+        let state = if self.control.is_paused() {
+            "paused"
+        } else if self.control.is_running() {
+            "scanning"
+        } else {
+            "idle"
+        };
+
+        let mut status = HashMap::new();
+        status.insert("State".to_string(), OwnedValue::from(Value::from(state)));
+        status.insert(
+            "LastScan".to_string(),
+            OwnedValue::from(data.last_scan.map_or(0, |dt| dt.timestamp())),
+        );
+        status.insert(
+            "Threats".to_string(),
+            OwnedValue::from(data.threats.len() as u64),
+        );
+        status.insert(
+            "SignatureCount".to_string(),
+            OwnedValue::from(data.signature_count as u64),
+        );
+        status.insert(
+            "SignaturesAge".to_string(),
+            OwnedValue::from(data.signatures_age.map_or(0, |dt| dt.timestamp())),
+        );
+        Ok(status)
+    }
+
+    /// Everything `libredefender status --json` prints
+    fn get_status_json(&self) -> fdo::Result<String> {
+        let db = Database::load().map_err(|err| db_error(&err))?;
+        status::json(db.data(), self.max_age).map_err(|err| db_error(&err))
+    }
+
+    fn list_threats(&self) -> fdo::Result<Vec<(String, Vec<String>)>> {
+        let db = Database::load().map_err(|err| db_error(&err))?;
+        let threats = db
+            .data()
+            .threats
+            .iter()
+            .map(|(path, threat)| (path.to_string_lossy().into_owned(), threat.names.clone()))
+            .collect();
+        Ok(threats)
+    }
+
+    fn quarantine_threat(&self, path: &str) -> fdo::Result<()> {
+        let path = Path::new(path);
+        let mut db = Database::load().map_err(|err| db_error(&err))?;
+        let threat =
+            db.data_mut().threats.remove(path).ok_or_else(|| {
+                fdo::Error::InvalidArgs(format!("Not a known threat: {:?}", path))
+            })?;
+
+        let quarantine = Quarantine::open().map_err(|err| db_error(&err))?;
+        let entry = quarantine
+            .add(path, &threat.names)
+            .map_err(|err| db_error(&err))?;
+        info!("Moved {:?} at {:?} to quarantine", threat.names, path);
+
+        let entry = audit::Entry::new(Action::Quarantined, path, &threat.names).detail(entry.id);
+        if let Err(err) = audit::record(&entry) {
+            warn!("Failed to write audit log: {:#}", err);
+        }
+
+        db.store().map_err(|err| db_error(&err))?;
+        Ok(())
+    }
+
+    #[dbus_interface(signal)]
+    async fn threat_found(ctxt: &SignalContext<'_>, path: &str, name: &str) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn scan_progress(ctxt: &SignalContext<'_>, files_scanned: u64) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn scan_progress_estimate(
+        ctxt: &SignalContext<'_>,
+        files_scanned: u64,
+        files_total: u64,
+        percent: f64,
+    ) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn scan_finished(ctxt: &SignalContext<'_>, threats: u64) -> zbus::Result<()>;
+}
+
+pub struct Server {
+    conn: Connection,
+}
+
+impl Server {
+    /// Register the service on the system bus when running as root, the session bus otherwise
+    pub fn start(
+        control: Arc<Control>,
+        commands: Sender<Command>,
+        max_age: chrono::Duration,
+    ) -> Result<Server> {
+        let builder = if utils::is_root() {
+            ConnectionBuilder::system()
+        } else {
+            ConnectionBuilder::session()
+        }?;
+
+        let conn = builder
+            .name(NAME)?
+            .serve_at(
+                PATH,
+                Service {
+                    control,
+                    commands,
+                    max_age,
+                },
+            )?
+            .build()
+            .with_context(|| anyhow!("Failed to register {} on dbus", NAME))?;
+        info!("Registered dbus service {}", NAME);
+
+        Ok(Server { conn })
+    }
+
+    pub fn emit(&self, event: &Event) -> Result<()> {
+        let ctxt = SignalContext::new(self.conn.inner(), PATH)?;
+        zbus::block_on(async {
+            match event {
+                Event::Threat { path, name, .. } => {
+                    Service::threat_found(&ctxt, &path.to_string_lossy(), name).await
+                }
+                Event::Progress {
+                    files_scanned,
+                    bytes_scanned,
+                    estimate,
+                    ..
+                } => {
+                    let files_scanned = *files_scanned as u64;
+                    Service::scan_progress(&ctxt, files_scanned).await?;
+                    // only sent once the files were counted
+                    if let Some(estimate) = estimate {
+                        Service::scan_progress_estimate(
+                            &ctxt,
+                            files_scanned,
+                            estimate.files as u64,
+                            estimate.percent(*bytes_scanned),
+                        )
+                        .await?;
+                    }
+                    Ok(())
+                }
+                Event::Finished { threats, .. } => {
+                    Service::scan_finished(&ctxt, *threats as u64).await
+                }
+                Event::Error { .. } => Ok(()),
+            }
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use crate::config::UpdateConfig;
+use crate::errors::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const BUNDLED: &str = include_str!("../contrib/descriptions.toml");
+
+#[derive(Debug, Clone, Deserialize)]
+struct Category {
+    name: String,
+    description: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Mapping {
+    #[serde(default)]
+    platforms: HashMap<String, String>,
+    #[serde(default)]
+    categories: HashMap<String, Category>,
+    #[serde(default)]
+    signatures: HashMap<String, String>,
+}
+
+/// What a detection means, for people that don't know the ClamAV naming scheme
+#[derive(Debug, PartialEq, Eq)]
+pub struct Description {
+    pub category: Option<String>,
+    pub platform: Option<String>,
+    pub text: String,
+}
+
+impl fmt::Display for Description {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.category, &self.platform) {
+            (Some(category), Some(platform)) => write!(w, "{} for {}: ", category, platform)?,
+            (Some(category), None) => write!(w, "{}: ", category)?,
+            _ => (),
+        }
+        write!(w, "{}", self.text)
+    }
+}
+
+/// Offline mapping of signature names to descriptions
+#[derive(Debug)]
+pub struct Descriptions {
+    mapping: Mapping,
+}
+
+impl Descriptions {
+    /// The bundled mapping with the entries of `update.descriptions` on top
+    pub fn load(paths: &[PathBuf]) -> Result<Descriptions> {
+        let mut mapping = toml::from_str::<Mapping>(BUNDLED)?;
+        for path in paths {
+            let extra = Self::read(path)?;
+            for (key, value) in extra.platforms {
+                mapping.platforms.insert(key.to_ascii_lowercase(), value);
+            }
+            for (key, value) in extra.categories {
+                mapping.categories.insert(key.to_ascii_lowercase(), value);
+            }
+            mapping.signatures.extend(extra.signatures);
+        }
+        Ok(Descriptions { mapping })
+    }
+
+    fn read(path: &Path) -> Result<Mapping> {
+        let text = fs::read_to_string(path)
+            .with_context(|| anyhow!("Failed to read descriptions from {:?}", path))?;
+        toml::from_str(&text).with_context(|| anyhow!("Failed to parse descriptions {:?}", path))
+    }
+
+    /// Load the configured mappings, falls back to the bundled one if they can't be loaded
+    #[must_use]
+    pub fn from_config(config: &UpdateConfig) -> Descriptions {
+        Self::load(&config.descriptions).unwrap_or_else(|err| {
+            warn!("Failed to load signature descriptions: {:#}", err);
+            Self::bundled()
+        })
+    }
+
+    #[must_use]
+    pub fn bundled() -> Descriptions {
+        Descriptions {
+            mapping: toml::from_str(BUNDLED).unwrap_or_default(),
+        }
+    }
+
+    #[must_use]
+    pub fn describe(&self, name: &str) -> Option<Description> {
+        let mut platform = None;
+        let mut category = None;
+        for part in name.split('.') {
+            let part = part.to_ascii_lowercase();
+            if platform.is_none() {
+                platform = self.mapping.platforms.get(&part);
+            }
+            // names like `PUA.Win.Adware.Foo` get more specific towards the end
+            if let Some(c) = self.mapping.categories.get(&part) {
+                category = Some(c);
+            }
+        }
+
+        let text = match (self.mapping.signatures.get(name), category) {
+            (Some(text), _) => text.clone(),
+            (None, Some(category)) => category.description.clone(),
+            (None, None) => return None,
+        };
+        Some(Description {
+            category: category.map(|c| c.name.clone()),
+            platform: platform.cloned(),
+            text,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundled_mapping() {
+        let descriptions = Descriptions::load(&[]).unwrap();
+
+        let eicar = descriptions.describe("Win.Test.EICAR_HDB-1").unwrap();
+        assert_eq!(eicar.category.as_deref(), Some("Test file"));
+        assert_eq!(eicar.platform.as_deref(), Some("Windows"));
+        assert!(eicar.text.starts_with("The EICAR test file"));
+
+        let adware = descriptions
+            .describe("PUA.Win.Adware.Agent-6781912-0")
+            .unwrap();
+        assert_eq!(
+            adware.to_string(),
+            "Adware for Windows: Shows unwanted advertisements, often bundled with free software"
+        );
+
+        assert!(descriptions.describe("YARA.my_rule.UNOFFICIAL").is_none());
+    }
+
+    #[test]
+    fn test_extra_mapping() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("descriptions.toml");
+        fs::write(
+            &path,
+            "[signatures]\n\"YARA.my_rule.UNOFFICIAL\" = \"Internal rule for the 2023 incident\"\n",
+        )
+        .unwrap();
+        let descriptions = Descriptions::load(&[path]).unwrap();
+        assert_eq!(
+            descriptions
+                .describe("YARA.my_rule.UNOFFICIAL")
+                .unwrap()
+                .to_string(),
+            "Internal rule for the 2023 incident"
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use crate::args;
+use crate::errors::*;
+use crate::notify;
+use crate::scan::{self, Control, Event, ScanSummary};
+use notify_rust::{Notification, NotificationHandle, Timeout, Urgency};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const DOLPHIN_SERVICE_MENU: &str = include_str!("../contrib/libredefender-servicemenu.desktop");
+const NAUTILUS_SCRIPT: &str = include_str!("../contrib/nautilus-script");
+
+/// A notification that's updated while a scan started from the file manager is running
+struct Progress {
+    handle: Option<NotificationHandle>,
+    target: String,
+}
+
+impl Progress {
+    fn start(paths: &[PathBuf]) -> Progress {
+        let target = match paths {
+            [path] => format!("{:?}", path),
+            paths => format!("{} paths", paths.len()),
+        };
+        let handle = Notification::new()
+            .summary("Scanning with libredefender")
+            .body(&format!("Scanning {}...", target))
+            .icon("libredefender")
+            .timeout(Timeout::Never)
+            .show();
+        let handle = match handle {
+            Ok(handle) => Some(handle),
+            Err(err) => {
+                warn!("Failed to display notification: {:#}", err);
+                None
+            }
+        };
+        Progress { handle, target }
+    }
+
+    fn update(&mut self, files_scanned: usize, percent: Option<f64>) {
+        if let Some(handle) = &mut self.handle {
+            let body = match percent {
+                Some(percent) => format!(
+                    "Scanning {}, {:.0}% done ({} file(s))...",
+                    self.target, percent, files_scanned
+                ),
+                None => format!(
+                    "Scanning {}, {} file(s) scanned so far...",
+                    self.target, files_scanned
+                ),
+            };
+            handle.body(&body);
+            if let Err(err) = handle.update() {
+                debug!("Failed to update notification: {:#}", err);
+            }
+        }
+    }
+
+    fn close(self) {
+        if let Some(handle) = self.handle {
+            handle.close();
+        }
+    }
+}
+
+fn show_result(summary: &ScanSummary) -> Result<()> {
+    let (summary_line, urgency) = if summary.threats.is_empty() {
+        ("No threats found", Urgency::Normal)
+    } else {
+        ("Threats found", Urgency::Critical)
+    };
+    let mut body = format!("Scanned {} file(s)", summary.files_scanned);
+    for (path, name) in &summary.threats {
+        body.push_str(&format!("\n{} => {:?}", name, path));
+    }
+    if !summary.errors.is_empty() {
+        body.push_str(&format!(
+            "\n{} file(s) couldn't be scanned",
+            summary.errors.len()
+        ));
+    }
+    Notification::new()
+        .summary(summary_line)
+        .body(&v_htmlescape::escape(&body).to_string())
+        .icon("libredefender")
+        .urgency(urgency)
+        .show()?;
+    Ok(())
+}
+
+/// Scan with notifications instead of terminal output, for the file manager context menu
+pub fn scan_gui(config_path: Option<&Path>, args: args::Scan) -> Result<()> {
+    let mut progress = Progress::start(&args.paths);
+    let control = Arc::new(Control::default());
+    let ret = scan::run_with(config_path, args, &control, |event| {
+        if let Event::Progress {
+            files_scanned,
+            bytes_scanned,
+            estimate,
+            ..
+        } = event
+        {
+            progress.update(
+                files_scanned,
+                estimate.map(|estimate| estimate.percent(bytes_scanned)),
+            );
+        }
+    });
+    progress.close();
+
+    match ret {
+        Ok(summary) => show_result(&summary),
+        Err(err) => {
+            let body = format!("{:#}", err);
+            if let Err(err) = notify::send("Scan failed", &body) {
+                warn!("Failed to display notification: {:#}", err);
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Where the "Scan with libredefender" entries are installed for the current user
+fn menu_entries() -> Result<Vec<(PathBuf, &'static str)>> {
+    let data_dir = dirs::data_dir().context("Failed to find data directory")?;
+    Ok(vec![
+        (
+            data_dir.join("kio/servicemenus/libredefender.desktop"),
+            DOLPHIN_SERVICE_MENU,
+        ),
+        (
+            data_dir.join("nautilus/scripts/Scan with libredefender"),
+            NAUTILUS_SCRIPT,
+        ),
+    ])
+}
+
+/// Add "Scan with libredefender" to the context menu of Dolphin and Nautilus
+pub fn install() -> Result<()> {
+    for (path, content) in menu_entries()? {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| anyhow!("Failed to create directory {:?}", parent))?;
+        }
+        fs::write(&path, content).with_context(|| anyhow!("Failed to write {:?}", path))?;
+        // both file managers only run executable entries from the home directory
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+            .with_context(|| anyhow!("Failed to make {:?} executable", path))?;
+        info!("Installed {:?}", path);
+    }
+    Ok(())
+}
+
+pub fn uninstall() -> Result<()> {
+    for (path, _) in menu_entries()? {
+        match fs::remove_file(&path) {
+            Ok(()) => info!("Removed {:?}", path),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err).with_context(|| anyhow!("Failed to remove {:?}", path)),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use crate::args;
+use crate::config;
+use crate::db::SkipReason;
+use crate::errors::*;
+use crate::scan::{self, Visited};
+use colored::Colorize;
+use serde::Serialize;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A path that would be scanned, or the reason it would be skipped
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub scan: bool,
+    pub reason: Option<String>,
+    /// Only known for files
+    pub size: Option<u64>,
+}
+
+impl Entry {
+    fn scan(path: &Path, size: u64) -> Entry {
+        Entry {
+            path: path.to_path_buf(),
+            scan: true,
+            reason: None,
+            size: Some(size),
+        }
+    }
+
+    fn skip(path: PathBuf, reason: String) -> Entry {
+        Entry {
+            path,
+            scan: false,
+            reason: Some(reason),
+            size: None,
+        }
+    }
+}
+
+/// Traverse the paths like a scan would, without loading signatures or reading more than the start of files
+///
+/// Excludes apply to the paths themselves unless they were passed `explicit`ly.
+pub fn entries(cfg: &config::ScanConfig, paths: &[PathBuf], explicit: bool) -> Vec<Entry> {
+    let roots = paths
+        .iter()
+        .map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
+        .collect::<Vec<_>>();
+    let mut visited = Visited::new(cfg);
+    visited.set_roots(&roots);
+    if !explicit {
+        visited.filter_roots();
+    }
+    visited.record_filtered();
+
+    let mut entries = Vec::new();
+    for path in paths {
+        scan::walk_directory(cfg, &mut visited, path, |entry| {
+            let path = entry.path();
+            let size = entry.metadata().map(|md| md.len()).unwrap_or(0);
+            // the same checks the scan workers do before reading a file
+            let reason = if entry.depth() == 0 {
+                None
+            } else if cfg.quick && scan::skipped_by_quick(path) {
+                Some("not relevant for quick scan")
+            } else if scan::skipped_by_mime(cfg, path) {
+                Some("file type matches skip_mime")
+            } else {
+                None
+            };
+            entries.push(match reason {
+                Some(reason) => Entry::skip(path.to_path_buf(), reason.to_string()),
+                None => Entry::scan(path, size),
+            });
+            true
+        });
+    }
+
+    for (path, reason) in visited.take_filtered() {
+        entries.push(Entry::skip(path, reason));
+    }
+    // too large and in use are already part of the filtered paths
+    for (path, reason) in visited.take_skipped() {
+        if matches!(
+            reason,
+            SkipReason::PermissionDenied | SkipReason::Unreadable
+        ) {
+            entries.push(Entry::skip(path, reason.to_string()));
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    entries
+}
+
+fn print_text<W: Write>(mut w: W, entries: &[Entry]) -> Result<()> {
+    let mut scanned = 0;
+    let mut bytes = 0;
+    for entry in entries {
+        if entry.scan {
+            scanned += 1;
+            bytes += entry.size.unwrap_or(0);
+            writeln!(w, "{} {}", "scan".green(), entry.path.display())?;
+        } else {
+            let reason = entry.reason.as_deref().unwrap_or_default();
+            writeln!(
+                w,
+                "{} {} {}",
+                "skip".yellow(),
+                entry.path.display(),
+                format!("({})", reason).dimmed()
+            )?;
+        }
+    }
+    writeln!(
+        w,
+        "{} file(s) with {} bytes would be scanned, {} path(s) skipped",
+        scanned,
+        bytes,
+        entries.len() - scanned
+    )?;
+    Ok(())
+}
+
+/// `scan --dry-run`, list what a scan with these options would scan and skip
+pub fn run(config_path: Option<&Path>, args: args::Scan) -> Result<()> {
+    let json = args.json;
+    let config = config::load(config_path, Some(&args)).context("Failed to load config")?;
+    let explicit = !args.paths.is_empty();
+    let paths = scan::resolve_paths(&config.scan, args.paths)?;
+    let entries = entries(&config.scan, &paths, explicit);
+
+    let stdout = std::io::stdout();
+    if json {
+        let mut w = stdout.lock();
+        serde_json::to_writer_pretty(&mut w, &entries)?;
+        writeln!(w)?;
+    } else {
+        print_text(stdout.lock(), &entries)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ScanConfig;
+
+    #[test]
+    fn test_dry_run_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.exe"), b"MZ").unwrap();
+        fs::write(dir.path().join(".hidden"), b"x").unwrap();
+        fs::create_dir(dir.path().join("cache")).unwrap();
+        fs::write(dir.path().join("cache/b.exe"), b"MZ").unwrap();
+
+        let cfg = ScanConfig {
+            skip_hidden: true,
+            excludes: vec!["**/cache".parse().unwrap()],
+            ..Default::default()
+        };
+        let entries = entries(&cfg, &[dir.path().to_path_buf()], true);
+        let entries = entries
+            .iter()
+            .map(|e| {
+                let name = e.path.strip_prefix(dir.path()).unwrap();
+                (name.to_str().unwrap(), e.reason.as_deref())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                (".hidden", Some("name starts with dot")),
+                ("a.exe", None),
+                ("cache", Some("matches exclude (**/cache)")),
+            ]
+        );
+    }
+}
@@ -1,2 +1,2 @@
 pub use anyhow::{anyhow, bail, Context, Error, Result};
-pub use log::{debug, error, info, trace, warn};
+pub use tracing::{debug, error, info, trace, warn};
@@ -0,0 +1,172 @@
+use crate::audit::{self, Action};
+use crate::config;
+use crate::db::Database;
+use crate::errors::*;
+use crate::patterns::Pattern;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use toml_edit::{Array, DocumentMut, Item, Table};
+
+/// Turn a path or glob into a pattern that matches the absolute paths seen during a scan
+pub fn normalize(input: &str) -> Result<String> {
+    // ~ and ${VAR} are kept so the pattern is expanded for each user that loads the config
+    let is_glob = input.contains(['*', '?', '[']) || input.starts_with(['~', '$']);
+    let pattern = if is_glob {
+        let glob = input.strip_prefix("(?i)").unwrap_or(input);
+        if !glob.starts_with(['/', '*', '~', '$']) {
+            bail!(
+                "Patterns are matched against absolute paths and need to start with /, *, ~ or $: {:?}",
+                input
+            );
+        }
+        input.to_string()
+    } else {
+        let path = Path::new(input);
+        let path = fs::canonicalize(path).or_else(|_| std::path::absolute(path))?;
+        let path = path.to_str().context("Path contains invalid utf-8")?;
+        glob::Pattern::escape(path)
+    };
+    Pattern::from_str(&pattern).with_context(|| anyhow!("Invalid pattern: {:?}", pattern))?;
+    Ok(pattern)
+}
+
+fn read_document(path: &Path) -> Result<DocumentMut> {
+    match fs::read_to_string(path) {
+        Ok(text) => text
+            .parse()
+            .with_context(|| anyhow!("Failed to parse config file {:?}", path)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(DocumentMut::new()),
+        Err(err) => Err(err).with_context(|| anyhow!("Failed to read config file {:?}", path)),
+    }
+}
+
+fn write_document(path: &Path, doc: &DocumentMut) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create config directory")?;
+    }
+    fs::write(path, doc.to_string())
+        .with_context(|| anyhow!("Failed to write config file {:?}", path))
+}
+
+/// The `scan.excludes` array of the document, it's created if it doesn't exist yet
+fn excludes_mut(doc: &mut DocumentMut) -> Result<&mut Array> {
+    let scan = doc
+        .entry("scan")
+        .or_insert_with(|| Item::Table(Table::new()))
+        .as_table_like_mut()
+        .context("scan is not a table")?;
+    scan.entry("excludes")
+        .or_insert(Item::Value(Array::new().into()))
+        .as_array_mut()
+        .context("scan.excludes is not an array")
+}
+
+/// Add a pattern to the user config, comments and formatting of the file are kept
+pub fn add(config_path: Option<&Path>, input: &str) -> Result<()> {
+    let pattern = normalize(input)?;
+    let path = config::user_path(config_path)?;
+    let mut doc = read_document(&path)?;
+
+    let excludes = excludes_mut(&mut doc)?;
+    if excludes.iter().any(|e| e.as_str() == Some(&pattern)) {
+        info!("{:?} is already excluded", pattern);
+        return Ok(());
+    }
+    excludes.push(pattern.as_str());
+    write_document(&path, &doc)?;
+    info!("Added {:?} to excludes in {:?}", pattern, path);
+
+    allowlist_threats(&pattern)
+}
+
+/// Threats inside of an excluded path won't be scanned again, so they're removed from the database
+fn allowlist_threats(pattern: &str) -> Result<()> {
+    let pattern = Pattern::from_str(pattern)?.expand_vars()?;
+    let mut db = Database::load().context("Failed to load database")?;
+    let data = db.data_mut();
+
+    let allowlisted = data
+        .threats
+        .keys()
+        .filter(|path| path.ancestors().any(|p| pattern.matches(p)))
+        .cloned()
+        .collect::<Vec<_>>();
+    if allowlisted.is_empty() {
+        return Ok(());
+    }
+    for path in allowlisted {
+        let threat = data.threats.remove(&path).unwrap();
+        info!("Removing allowlisted threat {:?}", path);
+        let entry = audit::Entry::new(Action::Allowlisted, &path, &threat.names);
+        if let Err(err) = audit::record(&entry) {
+            warn!("Failed to write audit log: {:#}", err);
+        }
+    }
+    db.store().context("Failed to write database")
+}
+
+pub fn remove(config_path: Option<&Path>, input: &str) -> Result<()> {
+    let path = config::user_path(config_path)?;
+    let mut doc = read_document(&path)?;
+    let excludes = excludes_mut(&mut doc)?;
+
+    // the input may be the pattern exactly as it's listed, or a path that was normalized when it was added
+    let normalized = normalize(input).ok();
+    let before = excludes.len();
+    excludes.retain(|e| {
+        e.as_str()
+            .is_none_or(|e| e != input && Some(e) != normalized.as_deref())
+    });
+    if excludes.len() == before {
+        bail!("{:?} is not in the excludes of {:?}", input, path);
+    }
+    write_document(&path, &doc)?;
+    info!("Removed {:?} from excludes in {:?}", input, path);
+    Ok(())
+}
+
+/// The excludes that are in effect, this includes the system config
+pub fn list(config_path: Option<&Path>) -> Result<Vec<String>> {
+    let config = config::load(config_path, None).context("Failed to load config")?;
+    Ok(config
+        .scan
+        .excludes
+        .iter()
+        .map(ToString::to_string)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("*/node_modules").unwrap(), "*/node_modules");
+        assert_eq!(
+            normalize("/nonexistent/archive").unwrap(),
+            "/nonexistent/archive"
+        );
+        assert!(normalize("node_modules/*").is_err());
+        assert_eq!(normalize("(?i)*/Temp/*").unwrap(), "(?i)*/Temp/*");
+        assert_eq!(
+            normalize("~/VirtualBox VMs/**").unwrap(),
+            "~/VirtualBox VMs/**"
+        );
+        assert_eq!(normalize("~/archive").unwrap(), "~/archive");
+    }
+
+    #[test]
+    fn test_add_keeps_comments() {
+        let mut doc = "# my config\n[scan]\n# keep this\nskip_hidden = true\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+        excludes_mut(&mut doc).unwrap().push("/tmp");
+        assert_eq!(
+            doc.to_string(),
+            "# my config\n[scan]\n# keep this\nskip_hidden = true\nexcludes = [\"/tmp\"]\n"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use crate::config::ScanConfig;
+use crate::errors::*;
+use crate::patterns::DirIgnore;
+use crate::scan;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::{DirEntry, WalkDir};
+
+/// A rule that excludes the path, or a directory above it, from scans
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl Finding {
+    fn new(path: &Path, reason: String) -> Finding {
+        Finding {
+            path: path.to_path_buf(),
+            reason,
+        }
+    }
+}
+
+fn dir_entry(path: &Path) -> Result<DirEntry> {
+    WalkDir::new(path)
+        .max_depth(0)
+        .into_iter()
+        .next()
+        .context("walkdir returned no entry")?
+        .with_context(|| anyhow!("Failed to access {:?}", path))
+}
+
+/// Ignore files in the directories between the scanned path and `path`, like they're applied during traversal
+fn ignore_files(root: &Path, path: &Path, is_dir: bool) -> Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    for dir in path.ancestors().skip(1) {
+        if !dir.starts_with(root) {
+            break;
+        }
+        let ignore = match DirIgnore::load(dir)? {
+            // the scanned path itself is still traversed if it's marked as ignored
+            Some(DirIgnore::All) if dir == root => continue,
+            Some(ignore) => ignore,
+            None => continue,
+        };
+        let ignored = path
+            .ancestors()
+            .take_while(|p| *p != dir)
+            .any(|p| ignore.matches(p, if p == path { is_dir } else { true }));
+        if ignored {
+            let reason = match ignore {
+                DirIgnore::All => "directory is marked as ignored".to_string(),
+                DirIgnore::Patterns(_) => "matches ignore file".to_string(),
+            };
+            findings.push(Finding::new(dir, reason));
+        }
+    }
+    Ok(findings)
+}
+
+/// Evaluate the excludes, includes, limits and hidden rules against one path,
+/// returns every rule that causes it to be skipped
+pub fn explain(cfg: &ScanConfig, roots: &[PathBuf], path: &Path) -> Result<Vec<Finding>> {
+    let path = fs::canonicalize(path).with_context(|| anyhow!("Failed to access {:?}", path))?;
+    let root = roots
+        .iter()
+        .map(|root| fs::canonicalize(root).unwrap_or_else(|_| root.clone()))
+        .find(|root| path.starts_with(root));
+    let Some(root) = root else {
+        let reason = format!(
+            "not inside of the scanned paths {:?}, it's only scanned if passed explicitly",
+            roots
+        );
+        return Ok(vec![Finding::new(&path, reason)]);
+    };
+
+    let mut findings = Vec::new();
+    let depth = path.strip_prefix(&root)?.components().count();
+    if let Some(max_depth) = cfg.max_depth {
+        if depth > max_depth {
+            let reason = format!("deeper than max_depth ({} > {})", depth, max_depth);
+            findings.push(Finding::new(&path, reason));
+        }
+    }
+
+    // directories above the path are filtered during traversal too, top down, including
+    // the configured path itself
+    let mut parents = path
+        .ancestors()
+        .take_while(|p| p.starts_with(&root))
+        .collect::<Vec<_>>();
+    parents.reverse();
+    for p in parents {
+        let entry = dir_entry(p)?;
+        let reason = if p == root {
+            scan::exclude_reason(cfg, &entry, p)
+        } else {
+            scan::filter_reason(cfg, &entry)
+        };
+        if let Some(reason) = reason {
+            findings.push(Finding::new(p, reason.to_string()));
+        }
+    }
+
+    let is_dir = path.is_dir();
+    if cfg.ignore_files() {
+        findings.extend(ignore_files(&root, &path, is_dir)?);
+    }
+
+    if !is_dir && path != root {
+        if cfg.quick && scan::skipped_by_quick(&path) {
+            findings.push(Finding::new(
+                &path,
+                "not relevant for quick scan".to_string(),
+            ));
+        }
+        if scan::skipped_by_mime(cfg, &path) {
+            findings.push(Finding::new(
+                &path,
+                "file type matches skip_mime".to_string(),
+            ));
+        }
+    }
+
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(root.join(".cache/build")).unwrap();
+        fs::write(root.join(".cache/build/a.exe"), b"MZ").unwrap();
+        fs::write(root.join(".cache/.libredefender-ignore"), b"build/\n").unwrap();
+        fs::write(root.join("b.exe"), b"MZ").unwrap();
+
+        let cfg = ScanConfig {
+            skip_hidden: true,
+            ignore_files: Some(true),
+            ..Default::default()
+        };
+        let roots = [root.clone()];
+        assert_eq!(
+            explain(&cfg, &roots, &root.join(".cache/build/a.exe")).unwrap(),
+            vec![
+                Finding::new(&root.join(".cache"), "name starts with dot".to_string()),
+                Finding::new(&root.join(".cache"), "matches ignore file".to_string()),
+            ]
+        );
+        assert_eq!(explain(&cfg, &roots, &root.join("b.exe")).unwrap(), vec![]);
+
+        let other = tempfile::tempdir().unwrap();
+        let findings = explain(&cfg, &[other.path().to_path_buf()], &root.join("b.exe")).unwrap();
+        assert!(findings[0]
+            .reason
+            .starts_with("not inside of the scanned paths"));
+    }
+}
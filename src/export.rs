@@ -0,0 +1,118 @@
+use crate::db::{Data, Threat};
+use crate::errors::*;
+use crate::utils;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize)]
+pub struct Record {
+    pub path: PathBuf,
+    pub signatures: Vec<String>,
+    pub sha256: Option<String>,
+    pub size: Option<u64>,
+    pub first_seen: Option<DateTime<Utc>>,
+}
+
+impl Record {
+    #[must_use]
+    pub fn new(path: &Path, threat: &Threat) -> Record {
+        let sha256 = match utils::sha256_file(path) {
+            Ok(hash) => Some(hash),
+            Err(err) => {
+                warn!("Failed to hash {:?}: {:#}", path, err);
+                None
+            }
+        };
+        let size = fs::metadata(path).ok().map(|md| md.len());
+        Record {
+            path: path.to_path_buf(),
+            signatures: threat.names.clone(),
+            sha256,
+            size,
+            first_seen: threat.first_seen,
+        }
+    }
+}
+
+/// Collect all threats from the database, sorted by path
+#[must_use]
+pub fn records(data: &Data) -> Vec<Record> {
+    let mut records = data
+        .threats
+        .iter()
+        .map(|(path, threat)| Record::new(path, threat))
+        .collect::<Vec<_>>();
+    records.sort_by(|a, b| a.path.cmp(&b.path));
+    records
+}
+
+fn csv_field(s: &str) -> Cow<'_, str> {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+pub fn write_csv<W: Write>(mut w: W, records: &[Record]) -> Result<()> {
+    writeln!(w, "path,signatures,sha256,size,first_seen")?;
+    for record in records {
+        writeln!(
+            w,
+            "{},{},{},{},{}",
+            csv_field(&record.path.to_string_lossy()),
+            csv_field(&record.signatures.join(";")),
+            record.sha256.as_deref().unwrap_or_default(),
+            record.size.map(|s| s.to_string()).unwrap_or_default(),
+            record
+                .first_seen
+                .map(|dt| dt.to_rfc3339())
+                .unwrap_or_default(),
+        )?;
+    }
+    Ok(())
+}
+
+pub fn write_json<W: Write>(mut w: W, records: &[Record]) -> Result<()> {
+    serde_json::to_writer_pretty(&mut w, records)?;
+    writeln!(w)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_field_plain() {
+        assert_eq!(csv_field("/home/user/eicar.txt"), "/home/user/eicar.txt");
+    }
+
+    #[test]
+    fn test_csv_field_quoted() {
+        assert_eq!(csv_field("/a,b"), "\"/a,b\"");
+        assert_eq!(csv_field("/a\"b"), "\"/a\"\"b\"");
+        assert_eq!(csv_field("/a\nb"), "\"/a\nb\"");
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let records = vec![Record {
+            path: PathBuf::from("/tmp/x,y"),
+            signatures: vec!["A".to_string(), "B".to_string()],
+            sha256: None,
+            size: Some(68),
+            first_seen: None,
+        }];
+        let mut buf = Vec::new();
+        write_csv(&mut buf, &records).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "path,signatures,sha256,size,first_seen\n\"/tmp/x,y\",A;B,,68,\n"
+        );
+    }
+}
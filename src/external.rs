@@ -0,0 +1,146 @@
+use crate::config::Backend;
+use crate::errors::*;
+use crate::scan::{EngineError, EngineOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Scan files with the clamdscan or clamscan binaries instead of linking against libclamav
+pub struct External {
+    bin: &'static str,
+    databases: Vec<PathBuf>,
+    /// Extra arguments for clamscan
+    options: Vec<&'static str>,
+}
+
+impl External {
+    pub fn new(
+        backend: Backend,
+        databases: &[PathBuf],
+        options: &EngineOptions,
+    ) -> Result<External> {
+        let bin = match backend {
+            Backend::Clamdscan => "clamdscan",
+            Backend::Clamscan => "clamscan",
+            Backend::Libclamav => bail!("libclamav is not an external backend"),
+        };
+
+        let output = match Command::new(bin).arg("--version").output() {
+            Ok(output) => output,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                bail!("{} is not installed", bin)
+            }
+            Err(err) => return Err(err).with_context(|| anyhow!("Failed to run {}", bin)),
+        };
+        let version = String::from_utf8_lossy(&output.stdout);
+        info!("Using {} backend: {}", bin, version.trim());
+
+        let mut args = Vec::new();
+        if !options.bytecode {
+            args.push("--bytecode=no");
+        }
+        if options.heuristic_precedence {
+            args.push("--heuristic-scan-precedence=yes");
+        }
+
+        if bin == "clamscan" {
+            warn!("clamscan loads all signatures for every file, consider using clamdscan instead");
+        } else {
+            if databases.len() > 1 {
+                warn!("clamdscan uses the databases of clamd, additional databases need to be configured in clamd.conf");
+            }
+            if !args.is_empty() {
+                warn!("clamdscan uses the settings of clamd, configure Bytecode and HeuristicScanPrecedence in clamd.conf instead");
+                args.clear();
+            }
+        }
+
+        Ok(External {
+            bin,
+            databases: databases.to_vec(),
+            options: args,
+        })
+    }
+
+    pub fn scan_file(&self, path: &Path) -> Result<Option<String>> {
+        let mut cmd = Command::new(self.bin);
+        cmd.arg("--no-summary");
+        if self.bin == "clamdscan" {
+            // clamd usually runs as its own user and can't open our files
+            cmd.arg("--fdpass");
+        } else {
+            for database in &self.databases {
+                cmd.arg("--database").arg(database);
+            }
+            cmd.args(&self.options);
+        }
+        let output = cmd
+            .arg("--")
+            .arg(path)
+            .output()
+            .with_context(|| anyhow!("Failed to run {}", self.bin))?;
+
+        // 0 means clean, 1 means a virus was found, everything else is an error
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match output.status.code() {
+            Some(0) => Ok(None),
+            Some(1) => {
+                let name = parse_output(&stdout)
+                    .into_iter()
+                    .next()
+                    .map(|(_, name)| name)
+                    .context("Failed to find threat name in output")?;
+                Ok(Some(name))
+            }
+            _ => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let msg = if stderr.trim().is_empty() {
+                    stdout.trim()
+                } else {
+                    stderr.trim()
+                };
+                // errors are printed as `/path/to/file: message ERROR`
+                let prefix = format!("{}: ", path.display());
+                let msg = msg
+                    .lines()
+                    .map(|line| line.strip_prefix(&prefix).unwrap_or(line))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                Err(Error::new(EngineError(msg)))
+                    .with_context(|| anyhow!("{} exited with {}", self.bin, output.status))
+            }
+        }
+    }
+}
+
+/// Parse `/path/to/file: Win.Test.EICAR_HDB-1 FOUND` lines
+#[must_use]
+pub fn parse_output(output: &str) -> Vec<(PathBuf, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let line = line.strip_suffix(" FOUND")?;
+            let (path, name) = line.rsplit_once(": ")?;
+            Some((PathBuf::from(path), name.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        let results = parse_output(
+            "/tmp/a: b/eicar.txt: Win.Test.EICAR_HDB-1 FOUND\n/tmp/clean.txt: OK\n/tmp/x: Can't open file or directory ERROR\n",
+        );
+        assert_eq!(
+            results,
+            vec![(
+                PathBuf::from("/tmp/a: b/eicar.txt"),
+                "Win.Test.EICAR_HDB-1".to_string()
+            )]
+        );
+    }
+}
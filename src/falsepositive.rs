@@ -0,0 +1,68 @@
+use crate::db::{Data, Threat};
+use crate::errors::*;
+use crate::scan;
+use crate::utils;
+use chrono::{DateTime, Utc};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// ClamAV only accepts false positive reports through this web form
+pub const SUBMIT_URL: &str = "https://www.clamav.net/reports/fp";
+
+#[derive(Debug)]
+pub struct FpReport {
+    pub path: PathBuf,
+    pub names: Vec<String>,
+    pub sha256: String,
+    pub size: u64,
+    pub clamav_version: String,
+    pub signatures_age: Option<DateTime<Utc>>,
+}
+
+impl FpReport {
+    pub fn new(path: &Path, threat: &Threat, data: &Data) -> Result<FpReport> {
+        let md = fs::metadata(path).with_context(|| anyhow!("Failed to access {:?}", path))?;
+        Ok(FpReport {
+            path: path.to_path_buf(),
+            names: threat.names.clone(),
+            sha256: utils::sha256_file(path)?,
+            size: md.len(),
+            clamav_version: scan::clamav_version(),
+            signatures_age: data.signatures_age,
+        })
+    }
+
+    #[must_use]
+    pub fn to_text(&self) -> String {
+        let file_name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+
+        let mut out = String::new();
+        writeln!(out, "File name:          {}", file_name).unwrap();
+        writeln!(out, "Detected as:        {}", self.names.join(", ")).unwrap();
+        writeln!(out, "SHA256:             {}", self.sha256).unwrap();
+        writeln!(out, "Size:               {} bytes", self.size).unwrap();
+        writeln!(out, "libclamav version:  {}", self.clamav_version).unwrap();
+        if let Some(age) = &self.signatures_age {
+            writeln!(out, "Signatures from:    {}", age.to_rfc3339()).unwrap();
+        }
+        out
+    }
+}
+
+/// Open the form in the browser, the user attaches the file and pastes the report there
+pub fn open_submission_form() -> Result<()> {
+    let status = Command::new("xdg-open")
+        .arg(SUBMIT_URL)
+        .status()
+        .context("Failed to run xdg-open")?;
+    if !status.success() {
+        bail!("xdg-open exited with {}", status);
+    }
+    Ok(())
+}
@@ -0,0 +1,404 @@
+use crate::args;
+use crate::db::{Data, Database};
+use crate::errors::*;
+use crate::quarantine::{self, Quarantine};
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A file that was removed by the privileged helper
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Removed {
+    pub path: PathBuf,
+    /// The name in the quarantine directory, if it was moved there instead of deleted
+    pub id: Option<String>,
+}
+
+/// The user that ran pkexec, set by pkexec itself and can't be forged by the caller
+fn invoking_uid() -> Result<u32> {
+    let uid = env::var("PKEXEC_UID").context("The helper is only meant to be started by pkexec")?;
+    uid.parse()
+        .with_context(|| anyhow!("Invalid PKEXEC_UID: {:?}", uid))
+}
+
+/// Only regular files are removed, and only through their real path to avoid symlink tricks
+fn check_target(path: &Path) -> Result<fs::Metadata> {
+    if !path.is_absolute() {
+        bail!("Path needs to be absolute: {:?}", path);
+    }
+    let md = fs::symlink_metadata(path).with_context(|| anyhow!("Failed to access {:?}", path))?;
+    if !md.is_file() {
+        bail!("Not a regular file: {:?}", path);
+    }
+    let real = fs::canonicalize(path)?;
+    if real != path {
+        bail!("Path is not canonical, it resolves to {:?}", real);
+    }
+    Ok(md)
+}
+
+/// The threats in the database of the user that ran pkexec, only these are removed
+fn recorded_threats(path: &Path, uid: u32) -> Result<Data> {
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path)
+        .with_context(|| anyhow!("Failed to open database {:?}", path))?;
+    let md = file.metadata()?;
+    if !md.is_file() || md.uid() != uid {
+        bail!("Database is not owned by uid {}: {:?}", uid, path);
+    }
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| anyhow!("Failed to read database {:?}", path))
+}
+
+/// The quarantine directory of the user, opened once so it can't be swapped for a symlink later
+fn open_quarantine(path: &Path, uid: u32) -> Result<File> {
+    let dir = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECTORY | libc::O_NOFOLLOW)
+        .open(path)
+        .with_context(|| anyhow!("Failed to open quarantine directory {:?}", path))?;
+    // refuse to write into directories the user doesn't own
+    let md = dir.metadata()?;
+    if !md.is_dir() || md.uid() != uid {
+        bail!(
+            "Quarantine directory is not owned by uid {}: {:?}",
+            uid,
+            path
+        );
+    }
+    Ok(dir)
+}
+
+fn openat(dir: &File, name: &OsStr, flags: libc::c_int, mode: libc::mode_t) -> io::Result<File> {
+    let name = CString::new(name.as_bytes())?;
+    let fd = unsafe {
+        libc::openat(
+            dir.as_raw_fd(),
+            name.as_ptr(),
+            flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            libc::c_uint::from(mode),
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+fn renameat(dir: &File, from: &OsStr, to: &OsStr) -> io::Result<()> {
+    let from = CString::new(from.as_bytes())?;
+    let to = CString::new(to.as_bytes())?;
+    let fd = dir.as_raw_fd();
+    if unsafe { libc::renameat(fd, from.as_ptr(), fd, to.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn unlinkat(dir: &File, name: &OsStr) -> io::Result<()> {
+    let name = CString::new(name.as_bytes())?;
+    if unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Write the container next to its final name and rename it once it's complete
+///
+/// Everything refers to the opened directory and the file that was created in it, the user
+/// can rename things in their quarantine directory but can't redirect any of these writes.
+fn write_quarantined(dir: &File, target: &Target, path: &Path, uid: u32) -> Result<String> {
+    let id = quarantine::new_id();
+    let tmp = OsString::from(format!(".{}.tmp", id));
+    let mut out = openat(
+        dir,
+        &tmp,
+        libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL,
+        0o400,
+    )
+    .context("Failed to create quarantined file")?;
+    let ret = fill_quarantined(&mut out, target, path, uid).and_then(|()| {
+        renameat(dir, &tmp, OsStr::new(&id)).context("Failed to rename quarantined file")
+    });
+    if let Err(err) = ret {
+        if let Err(err) = unlinkat(dir, &tmp) {
+            warn!("Failed to remove incomplete quarantined file: {:#}", err);
+        }
+        return Err(err);
+    }
+    Ok(id)
+}
+
+fn fill_quarantined(out: &mut File, target: &Target, path: &Path, uid: u32) -> Result<()> {
+    // the quarantined file belongs to the user, like the ones they could move themselves
+    if unsafe { libc::fchown(out.as_raw_fd(), uid, libc::gid_t::MAX) } != 0 {
+        return Err(io::Error::last_os_error())
+            .context("Failed to change owner of quarantined file");
+    }
+    quarantine::write_container_to(target.file.try_clone()?, path, out)?;
+    Ok(())
+}
+
+/// A file that was opened through file descriptors of its parent directories, none of them is a symlink
+///
+/// The user can still rename things in their directories after `check_target`, but everything that
+/// happens afterwards refers to the directory and file that were actually opened.
+struct Target {
+    parent: File,
+    name: OsString,
+    file: File,
+}
+
+impl Target {
+    fn open(path: &Path, checked: &fs::Metadata) -> Result<Target> {
+        let name = path.file_name().context("Path has no file name")?;
+        let parent = path.parent().context("Path has no parent directory")?;
+        let mut dir = File::open("/").context("Failed to open /")?;
+        for component in parent.components() {
+            match component {
+                Component::RootDir => (),
+                Component::Normal(name) => {
+                    dir = openat(&dir, name, libc::O_RDONLY | libc::O_DIRECTORY, 0).with_context(
+                        || anyhow!("Failed to open directory {:?} of {:?}", name, path),
+                    )?;
+                }
+                _ => bail!("Path is not canonical: {:?}", path),
+            }
+        }
+        let file = openat(&dir, name, libc::O_RDONLY | libc::O_NONBLOCK, 0)
+            .with_context(|| anyhow!("Failed to open {:?}", path))?;
+        let target = Target {
+            parent: dir,
+            name: name.to_os_string(),
+            file,
+        };
+        let md = target.file.metadata()?;
+        if !md.is_file() || md.dev() != checked.dev() || md.ino() != checked.ino() {
+            bail!("File was replaced after it was checked: {:?}", path);
+        }
+        Ok(target)
+    }
+
+    /// Unlink the name in the opened directory, if it still refers to the opened file
+    fn unlink(&self) -> Result<()> {
+        let name = CString::new(self.name.as_bytes())?;
+        let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+        let ret = unsafe {
+            libc::fstatat(
+                self.parent.as_raw_fd(),
+                name.as_ptr(),
+                &mut st,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error()).context("Failed to access file");
+        }
+        let md = self.file.metadata()?;
+        if st.st_dev as u64 != md.dev() || st.st_ino as u64 != md.ino() {
+            bail!("File was replaced while it was removed");
+        }
+        if unsafe { libc::unlinkat(self.parent.as_raw_fd(), name.as_ptr(), 0) } != 0 {
+            return Err(io::Error::last_os_error()).context("Failed to delete file");
+        }
+        Ok(())
+    }
+}
+
+fn remove(path: &Path, quarantine: Option<&File>, uid: u32) -> Result<Removed> {
+    let checked = check_target(path)?;
+    let target = Target::open(path, &checked)?;
+    let id = if let Some(dir) = quarantine {
+        let id = write_quarantined(dir, &target, path, uid)?;
+        if let Err(err) = target.unlink() {
+            // don't leave a second copy behind if the original stays in place
+            if let Err(err) = unlinkat(dir, OsStr::new(&id)) {
+                warn!("Failed to remove quarantined copy {:?}: {:#}", id, err);
+            }
+            return Err(err).with_context(|| anyhow!("Failed to delete {:?}", path));
+        }
+        Some(id)
+    } else {
+        target
+            .unlink()
+            .with_context(|| anyhow!("Failed to delete {:?}", path))?;
+        None
+    };
+    Ok(Removed {
+        path: path.to_path_buf(),
+        id,
+    })
+}
+
+/// Entry point of `libredefender helper-delete`, runs as root
+pub fn run(args: args::HelperDelete) -> Result<()> {
+    if !utils::is_root() {
+        bail!("The helper needs to run as root");
+    }
+    let uid = invoking_uid()?;
+    let data = recorded_threats(&args.database, uid)?;
+    let quarantine = args
+        .move_to
+        .as_deref()
+        .map(|dir| open_quarantine(dir, uid))
+        .transpose()?;
+
+    let mut removed = Vec::new();
+    for path in &args.paths {
+        if !data.threats.contains_key(path) {
+            error!("Refusing to remove {:?}, it's not a recorded threat", path);
+            continue;
+        }
+        match remove(path, quarantine.as_ref(), uid) {
+            Ok(entry) => removed.push(entry),
+            Err(err) => error!("Failed to remove {:?}: {:#}", path, err),
+        }
+    }
+
+    let mut stdout = io::stdout();
+    serde_json::to_writer(&mut stdout, &removed)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// Delete or quarantine files through pkexec, this asks for the admin password once
+pub fn escalate(paths: &[PathBuf], quarantine: Option<&Quarantine>) -> Result<Vec<Removed>> {
+    let exe = env::current_exe().context("Failed to find libredefender executable")?;
+    let mut cmd = Command::new("pkexec");
+    cmd.arg(exe).arg("helper-delete");
+    // only threats recorded in the database of the user are removed
+    cmd.arg("--database").arg(Database::path()?);
+    if let Some(quarantine) = quarantine {
+        cmd.arg("--move-to").arg(quarantine.dir());
+    }
+    cmd.arg("--").args(paths);
+
+    // the text agent of pkexec asks for the password on the terminal if there's no graphical one
+    let output = cmd
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .context("Failed to run pkexec, is polkit installed?")?;
+    if !output.status.success() {
+        // 126 means the authentication dialog was dismissed
+        bail!("pkexec exited with {}", output.status);
+    }
+    let removed = serde_json::from_slice(&output.stdout)
+        .context("Failed to parse output of privileged helper")?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let file = root.join("malware.exe");
+        fs::write(&file, "a").unwrap();
+        let link = root.join("link");
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+
+        assert!(check_target(&file).is_ok());
+        assert!(check_target(&link).is_err());
+        assert!(check_target(&root).is_err());
+        assert!(check_target(Path::new("malware.exe")).is_err());
+        assert!(check_target(&root.join("x/../malware.exe")).is_err());
+    }
+
+    #[test]
+    fn test_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let sub = root.join("sub");
+        fs::create_dir(&sub).unwrap();
+        let file = sub.join("malware.exe");
+        fs::write(&file, "a").unwrap();
+
+        let checked = check_target(&file).unwrap();
+        let target = Target::open(&file, &checked).unwrap();
+        target.unlink().unwrap();
+        assert!(!file.exists());
+
+        // the parent directory is swapped for a symlink after the check
+        fs::write(&file, "a").unwrap();
+        let checked = check_target(&file).unwrap();
+        let other = root.join("other");
+        fs::create_dir(&other).unwrap();
+        fs::write(other.join("malware.exe"), "b").unwrap();
+        fs::rename(&sub, root.join("moved")).unwrap();
+        std::os::unix::fs::symlink(&other, &sub).unwrap();
+        assert!(Target::open(&file, &checked).is_err());
+        assert!(other.join("malware.exe").exists());
+
+        // the file is replaced with another one after it was opened
+        fs::remove_file(&sub).unwrap();
+        fs::rename(root.join("moved"), &sub).unwrap();
+        let target = Target::open(&file, &checked).unwrap();
+        fs::remove_file(&file).unwrap();
+        fs::write(&file, "c").unwrap();
+        assert!(target.unlink().is_err());
+        assert!(file.exists());
+    }
+
+    #[test]
+    fn test_remove_quarantine() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let file = root.join("malware.exe");
+        fs::write(&file, "a").unwrap();
+        let quarantine_dir = root.join("quarantine");
+        fs::create_dir(&quarantine_dir).unwrap();
+
+        let uid = utils::euid();
+        let quarantine = open_quarantine(&quarantine_dir, uid).unwrap();
+        let removed = remove(&file, Some(&quarantine), uid).unwrap();
+        assert!(!file.exists());
+        let dest = quarantine_dir.join(removed.id.unwrap());
+        let md = fs::symlink_metadata(&dest).unwrap();
+        assert!(md.is_file());
+        assert_eq!(md.uid(), uid);
+        let (header, _) = quarantine::open_container(&dest).unwrap().unwrap();
+        assert_eq!(header.original_path, file);
+        assert_eq!(fs::read_dir(&quarantine_dir).unwrap().count(), 1);
+
+        // the quarantine directory is swapped for a symlink
+        let link = root.join("link");
+        std::os::unix::fs::symlink(&quarantine_dir, &link).unwrap();
+        assert!(open_quarantine(&link, uid).is_err());
+        assert!(open_quarantine(&quarantine_dir, uid + 1).is_err());
+    }
+
+    #[test]
+    fn test_recorded_threats() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("libredefender.db");
+        let mut data = Data::default();
+        data.threats.insert(
+            PathBuf::from("/tmp/malware.exe"),
+            crate::db::Threat::new(chrono::Utc::now()),
+        );
+        fs::write(&path, serde_json::to_vec(&data).unwrap()).unwrap();
+
+        let uid = utils::euid();
+        let recorded = recorded_threats(&path, uid).unwrap();
+        assert!(recorded.threats.contains_key(Path::new("/tmp/malware.exe")));
+        assert!(recorded_threats(&path, uid + 1).is_err());
+
+        let link = dir.path().join("link.db");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        assert!(recorded_threats(&link, uid).is_err());
+    }
+}
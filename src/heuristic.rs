@@ -0,0 +1,120 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// Findings of the built-in heuristic start with this, they're classified as low severity
+pub const PREFIX: &str = "Libredefender.Suspicious.";
+
+/// Right-to-left and left-to-right overrides and isolates, used to disguise `exe.pdf` as `fdp.exe`
+const BIDI_CONTROLS: &[char] = &[
+    '\u{202a}', '\u{202b}', '\u{202d}', '\u{202e}', '\u{2066}', '\u{2067}', '\u{2068}',
+];
+
+/// Extensions that are run when the file is opened on Windows or by a desktop environment
+const EXECUTABLE: &[&str] = &[
+    "exe", "scr", "com", "pif", "bat", "cmd", "vbs", "vbe", "js", "jse", "wsf", "wsh", "hta",
+    "msi", "lnk", "ps1", "jar", "cpl", "desktop", "appimage",
+];
+
+/// Extensions people expect to be harmless
+const DOCUMENT: &[&str] = &[
+    "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "odt", "ods", "rtf", "txt", "csv", "jpg",
+    "jpeg", "png", "gif", "mp3", "mp4", "avi", "zip", "rar",
+];
+
+fn has_extension(list: &[&str], ext: &str) -> bool {
+    list.iter().any(|e| e.eq_ignore_ascii_case(ext))
+}
+
+/// Names like `invoice.pdf.exe`, also with whitespace to push the real extension out of view
+fn is_double_extension(name: &str) -> bool {
+    let mut parts = name.rsplit('.');
+    let (Some(last), Some(inner)) = (parts.next(), parts.next()) else {
+        return false;
+    };
+    // a name like `.exe` doesn't have an inner extension
+    if parts.next().is_none() {
+        return false;
+    }
+    has_extension(EXECUTABLE, last) && has_extension(DOCUMENT, inner.trim_end())
+}
+
+fn is_script(path: &Path) -> bool {
+    let is_desktop = path.extension().is_some_and(|ext| ext == "desktop");
+    let mut shebang = [0; 2];
+    let has_shebang = File::open(path)
+        .and_then(|mut file| file.read_exact(&mut shebang))
+        .is_ok_and(|()| &shebang == b"#!");
+    is_desktop || has_shebang
+}
+
+fn is_download(path: &Path) -> bool {
+    if let Some(dir) = dirs::download_dir() {
+        if path.starts_with(dir) {
+            return true;
+        }
+    }
+    // the downloads of other users, e.g. when scanning /home as root
+    path.components().any(|c| c.as_os_str() == "Downloads")
+}
+
+/// Check the name and permissions of a file, independent of the signatures
+#[must_use]
+pub fn check(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy();
+    let finding = if name.contains(BIDI_CONTROLS) {
+        "BidiOverride"
+    } else if is_double_extension(&name) {
+        "DoubleExtension"
+    } else if is_download(path)
+        && fs::metadata(path).is_ok_and(|md| md.is_file() && md.permissions().mode() & 0o111 != 0)
+        && is_script(path)
+    {
+        "ExecutableDownload"
+    } else {
+        return None;
+    };
+    Some(format!("{}{}", PREFIX, finding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        assert_eq!(
+            check(Path::new("/tmp/invoice.pdf.exe")).as_deref(),
+            Some("Libredefender.Suspicious.DoubleExtension")
+        );
+        assert!(check(Path::new("/tmp/Invoice.PDF     .Scr")).is_some());
+        assert!(check(Path::new("/tmp/setup.exe")).is_none());
+        assert!(check(Path::new("/tmp/backup.tar.gz")).is_none());
+        assert!(check(Path::new("/tmp/.exe")).is_none());
+        assert_eq!(
+            check(Path::new("/tmp/invoice\u{202e}fdp.exe")).as_deref(),
+            Some("Libredefender.Suspicious.BidiOverride")
+        );
+    }
+
+    #[test]
+    fn test_executable_download() {
+        let dir = tempfile::tempdir().unwrap();
+        let downloads = dir.path().join("Downloads");
+        fs::create_dir(&downloads).unwrap();
+        let script = downloads.join("install.sh");
+        fs::write(&script, b"#!/bin/sh\n").unwrap();
+        assert!(check(&script).is_none());
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(
+            check(&script).as_deref(),
+            Some("Libredefender.Suspicious.ExecutableDownload")
+        );
+
+        let elsewhere = dir.path().join("install.sh");
+        fs::write(&elsewhere, b"#!/bin/sh\n").unwrap();
+        fs::set_permissions(&elsewhere, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(check(&elsewhere).is_none());
+    }
+}
@@ -0,0 +1,268 @@
+use crate::config::HooksConfig;
+use crate::errors::*;
+use crate::scan::ScanSummary;
+use crossbeam_channel::Sender;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Passed to hooks as json on stdin
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum HookEvent<'a> {
+    ScanStart {
+        paths: &'a [PathBuf],
+    },
+    Threat {
+        path: &'a Path,
+        name: &'a str,
+    },
+    ScanEnd {
+        files_scanned: usize,
+        bytes_scanned: u64,
+        duration_secs: f64,
+        threats: &'a [(PathBuf, String)],
+        new_threats: &'a [(PathBuf, String)],
+        errors: usize,
+        skipped: usize,
+        cancelled: bool,
+    },
+}
+
+impl HookEvent<'_> {
+    #[must_use]
+    pub fn scan_end(summary: &ScanSummary) -> HookEvent<'_> {
+        HookEvent::ScanEnd {
+            files_scanned: summary.files_scanned,
+            bytes_scanned: summary.bytes_scanned,
+            duration_secs: summary.duration.as_secs_f64(),
+            threats: &summary.threats,
+            new_threats: &summary.new_threats,
+            errors: summary.errors.len(),
+            skipped: summary.skipped.len(),
+            cancelled: summary.cancelled,
+        }
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookEvent::ScanStart { .. } => "scan_start",
+            HookEvent::Threat { .. } => "threat",
+            HookEvent::ScanEnd { .. } => "scan_end",
+        }
+    }
+
+    /// The most useful fields as `LIBREDEFENDER_*` variables, for hooks that don't parse json
+    #[must_use]
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![("LIBREDEFENDER_EVENT", self.name().to_string())];
+        match self {
+            HookEvent::ScanStart { paths } => {
+                let paths = paths
+                    .iter()
+                    .map(|p| p.to_string_lossy())
+                    .collect::<Vec<_>>();
+                env.push(("LIBREDEFENDER_PATHS", paths.join(":")));
+            }
+            HookEvent::Threat { path, name } => {
+                env.push(("LIBREDEFENDER_PATH", path.to_string_lossy().into_owned()));
+                env.push(("LIBREDEFENDER_THREAT", name.to_string()));
+            }
+            HookEvent::ScanEnd {
+                files_scanned,
+                threats,
+                new_threats,
+                errors,
+                cancelled,
+                ..
+            } => {
+                env.push(("LIBREDEFENDER_FILES_SCANNED", files_scanned.to_string()));
+                env.push(("LIBREDEFENDER_THREATS", threats.len().to_string()));
+                env.push(("LIBREDEFENDER_NEW_THREATS", new_threats.len().to_string()));
+                env.push(("LIBREDEFENDER_ERRORS", errors.to_string()));
+                env.push(("LIBREDEFENDER_CANCELLED", cancelled.to_string()));
+            }
+        }
+        env
+    }
+}
+
+/// Start the hook, the event is written to stdin by a separate thread so a hook that doesn't
+/// read it can't block us past the timeout
+fn spawn(command: &str, event: &HookEvent) -> Result<(Child, JoinHandle<io::Result<()>>)> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(event.env())
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| anyhow!("Failed to start hook {:?}", command))?;
+
+    let mut json = serde_json::to_vec(event)?;
+    json.push(b'\n');
+    let mut stdin = child.stdin.take().context("Failed to open stdin of hook")?;
+    let writer = thread::spawn(move || match stdin.write_all(&json) {
+        // hooks that only look at the environment don't need to read stdin
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        ret => ret,
+    });
+    Ok((child, writer))
+}
+
+/// Run the command with `sh -c` and wait for it, it's killed if it takes longer than `timeout`
+pub fn run(command: &str, event: &HookEvent, timeout: Duration) -> Result<()> {
+    debug!("Running {} hook: {:?}", event.name(), command);
+    let (mut child, writer) = spawn(command, event)?;
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() > timeout {
+            child.kill()?;
+            child.wait()?;
+            // processes started by the hook may still hold the pipe open, the writer is left behind
+            bail!("Hook {:?} timed out after {:?}", command, timeout);
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    match writer.join() {
+        Ok(Ok(())) => (),
+        Ok(Err(err)) => warn!("Failed to write event to hook {:?}: {:#}", command, err),
+        Err(_) => warn!("Writing the event to hook {:?} panicked", command),
+    }
+    if !status.success() {
+        bail!("Hook {:?} exited with {}", command, status);
+    }
+    Ok(())
+}
+
+/// Run the hook that is configured for this event, failures are only logged
+pub fn fire(config: &HooksConfig, event: &HookEvent) {
+    let command = match event {
+        HookEvent::ScanStart { .. } => &config.on_scan_start,
+        HookEvent::Threat { .. } => &config.on_threat,
+        HookEvent::ScanEnd { .. } => &config.on_scan_end,
+    };
+    if let Some(command) = command {
+        if let Err(err) = run(command, event, config.timeout()) {
+            warn!("Failed to run {} hook: {:#}", event.name(), err);
+        }
+    }
+}
+
+/// Runs `on_threat` on a separate thread, one detection after another, so a slow hook doesn't hold up the scan
+pub struct ThreatHooks {
+    tx: Option<Sender<(PathBuf, String)>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ThreatHooks {
+    #[must_use]
+    pub fn spawn(config: &HooksConfig) -> ThreatHooks {
+        let Some(command) = config.on_threat.clone() else {
+            return ThreatHooks {
+                tx: None,
+                thread: None,
+            };
+        };
+        let timeout = config.timeout();
+        let (tx, rx) = crossbeam_channel::unbounded::<(PathBuf, String)>();
+        let thread = thread::spawn(move || {
+            for (path, name) in rx {
+                let event = HookEvent::Threat {
+                    path: &path,
+                    name: &name,
+                };
+                if let Err(err) = run(&command, &event, timeout) {
+                    warn!("Failed to run {} hook: {:#}", event.name(), err);
+                }
+            }
+        });
+        ThreatHooks {
+            tx: Some(tx),
+            thread: Some(thread),
+        }
+    }
+
+    /// Queue the hook for this detection, it's a no-op if `on_threat` isn't configured
+    pub fn fire(&self, path: &Path, name: &str) {
+        if let Some(tx) = &self.tx {
+            tx.send((path.to_path_buf(), name.to_string())).ok();
+        }
+    }
+
+    /// Wait for the queued hooks to finish
+    pub fn finish(mut self) {
+        self.tx.take();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_threat_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let command = format!(
+            "echo \"$LIBREDEFENDER_EVENT $LIBREDEFENDER_THREAT\" > {0}; cat >> {0}",
+            out.display()
+        );
+        let event = HookEvent::Threat {
+            path: Path::new("/home/user/a.exe"),
+            name: "Win.Test.EICAR_HDB-1",
+        };
+        run(&command, &event, Duration::from_secs(10)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            "threat Win.Test.EICAR_HDB-1\n{\"event\":\"threat\",\"path\":\"/home/user/a.exe\",\"name\":\"Win.Test.EICAR_HDB-1\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_hook_failure() {
+        let event = HookEvent::ScanStart { paths: &[] };
+        assert!(run("exit 3", &event, Duration::from_secs(10)).is_err());
+        assert!(run("sleep 10", &event, Duration::from_millis(100)).is_err());
+        assert!(run("true", &event, Duration::from_secs(10)).is_ok());
+    }
+
+    #[test]
+    fn test_hook_ignores_stdin() {
+        // more than a pipe buffer, the hook is still killed in time
+        let paths = (0..10_000)
+            .map(|i| PathBuf::from(format!("/home/user/{:08}", i)))
+            .collect::<Vec<_>>();
+        let event = HookEvent::ScanStart { paths: &paths };
+        let started = Instant::now();
+        assert!(run("sleep 10; true", &event, Duration::from_millis(100)).is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_threat_hooks() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let config = HooksConfig {
+            on_threat: Some(format!("echo \"$LIBREDEFENDER_PATH\" >> {}", out.display())),
+            ..Default::default()
+        };
+        let hooks = ThreatHooks::spawn(&config);
+        hooks.fire(Path::new("/a"), "Win.Test.EICAR_HDB-1");
+        hooks.fire(Path::new("/b"), "Win.Test.EICAR_HDB-1");
+        hooks.finish();
+        assert_eq!(fs::read_to_string(&out).unwrap(), "/a\n/b\n");
+    }
+}
@@ -0,0 +1,124 @@
+use crate::errors::*;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Schemes understood by curl, `socks5h` also resolves host names through the proxy
+const PROXY_SCHEMES: &[&str] = &["http", "https", "socks4", "socks4a", "socks5", "socks5h"];
+
+pub fn check_proxy(proxy: &str) -> Result<()> {
+    let (scheme, host) = proxy
+        .split_once("://")
+        .context("Expected a url like socks5h://127.0.0.1:9050")?;
+    if !PROXY_SCHEMES.contains(&scheme) {
+        bail!(
+            "Unsupported proxy scheme {:?}, expected one of {}",
+            scheme,
+            PROXY_SCHEMES.join(", ")
+        );
+    }
+    if host.is_empty() {
+        bail!("Proxy url has no host");
+    }
+    Ok(())
+}
+
+/// Quote a value for the config file of curl, line breaks would start a new option
+fn quote(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\r' | '\n' => out.push(' '),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Send a request with curl, only https is allowed
+///
+/// The url and headers are passed on stdin, arguments are visible to every user in /proc
+/// and both may contain tokens.
+pub fn send(
+    method: &str,
+    url: &str,
+    headers: &[String],
+    body: &[u8],
+    proxy: Option<&str>,
+) -> Result<()> {
+    let mut curl_config = format!("url = {}\nrequest = {}\n", quote(url), quote(method));
+    if let Some(proxy) = proxy {
+        curl_config.push_str(&format!("proxy = {}\n", quote(proxy)));
+    }
+    for header in headers {
+        curl_config.push_str(&format!("header = {}\n", quote(header)));
+    }
+    let mut body_file = tempfile::NamedTempFile::new()?;
+    body_file.write_all(body)?;
+    let data = format!("@{}", body_file.path().display());
+    curl_config.push_str(&format!("data-binary = {}\n", quote(&data)));
+
+    let mut child = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--proto", "=https"])
+        .args(["--max-time", "60", "--config", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run curl")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(curl_config.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "Request failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Percent-encode everything except unreserved characters, for a single path segment
+#[must_use]
+pub fn encode_path_segment(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            out.push(char::from(b));
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("Title: a \"b\""), r#""Title: a \"b\"""#);
+        assert_eq!(quote("a\\b\nurl = x"), r#""a\\b url = x""#);
+    }
+
+    #[test]
+    fn test_check_proxy() {
+        assert!(check_proxy("socks5h://127.0.0.1:9050").is_ok());
+        assert!(check_proxy("http://proxy.corp.example:3128").is_ok());
+        assert!(check_proxy("127.0.0.1:9050").is_err());
+        assert!(check_proxy("ftp://proxy.corp.example").is_err());
+        assert!(check_proxy("socks5://").is_err());
+    }
+
+    #[test]
+    fn test_encode_path_segment() {
+        assert_eq!(
+            encode_path_segment("!abc:matrix.org"),
+            "%21abc%3Amatrix.org"
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use crate::args;
+use crate::config::{self, ScanConfig};
+use crate::errors::*;
+use crate::scan::{self, Scanner};
+use crate::utils;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::thread;
+use walkdir::DirEntry;
+
+const DOCKER_PREFIX: &str = "docker://";
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Layer {
+    pub digest: Option<String>,
+    pub path: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct Finding {
+    pub layer: String,
+    pub path: PathBuf,
+    pub name: String,
+}
+
+/// manifest.json written by `docker save`
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerManifest {
+    layers: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct OciIndex {
+    manifests: Vec<OciDescriptor>,
+}
+
+#[derive(Deserialize)]
+struct OciManifest {
+    layers: Vec<OciDescriptor>,
+}
+
+#[derive(Deserialize)]
+struct OciDescriptor {
+    digest: String,
+}
+
+fn blob_path(digest: &str) -> Result<PathBuf> {
+    let (algo, hex) = digest
+        .split_once(':')
+        .with_context(|| anyhow!("Invalid digest: {:?}", digest))?;
+    if algo.contains('/') || hex.contains('/') {
+        bail!("Invalid digest: {:?}", digest);
+    }
+    Ok(Path::new("blobs").join(algo).join(hex))
+}
+
+fn parse_docker_manifest(buf: &[u8]) -> Result<Vec<Layer>> {
+    let manifests = serde_json::from_slice::<Vec<DockerManifest>>(buf)
+        .context("Failed to parse manifest.json")?;
+    let manifest = manifests
+        .into_iter()
+        .next()
+        .context("Image archive contains no images")?;
+    manifest
+        .layers
+        .into_iter()
+        .map(|path| {
+            let path = PathBuf::from(path);
+            if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+                bail!("Invalid layer path: {:?}", path);
+            }
+            Ok(Layer { digest: None, path })
+        })
+        .collect()
+}
+
+fn parse_oci_manifest(buf: &[u8]) -> Result<Vec<Layer>> {
+    let manifest =
+        serde_json::from_slice::<OciManifest>(buf).context("Failed to parse oci manifest")?;
+    manifest
+        .layers
+        .into_iter()
+        .map(|layer| {
+            Ok(Layer {
+                path: blob_path(&layer.digest)?,
+                digest: Some(layer.digest),
+            })
+        })
+        .collect()
+}
+
+/// Find the layers of an unpacked docker-archive or oci-archive
+pub fn layers(dir: &Path) -> Result<Vec<Layer>> {
+    let docker_manifest = dir.join("manifest.json");
+    if docker_manifest.exists() {
+        let buf = fs::read(&docker_manifest).context("Failed to read manifest.json")?;
+        return parse_docker_manifest(&buf);
+    }
+
+    let buf = fs::read(dir.join("index.json"))
+        .context("Archive contains neither manifest.json nor index.json")?;
+    let index = serde_json::from_slice::<OciIndex>(&buf).context("Failed to parse index.json")?;
+    let manifest = index
+        .manifests
+        .first()
+        .context("Image archive contains no images")?;
+    let buf =
+        fs::read(dir.join(blob_path(&manifest.digest)?)).context("Failed to read oci manifest")?;
+    parse_oci_manifest(&buf)
+}
+
+fn run_tar(archive: &Path, dest: &Path) -> Result<()> {
+    fs::create_dir_all(dest)?;
+    let status = Command::new("tar")
+        .arg("-xf")
+        .arg(archive)
+        .arg("-C")
+        .arg(dest)
+        // don't recreate device nodes or ownership from the image
+        .args([
+            "--no-same-owner",
+            "--no-same-permissions",
+            "--exclude=dev/*",
+        ])
+        .status()
+        .context("Failed to run tar")?;
+    if !status.success() {
+        bail!("tar exited with {}", status);
+    }
+    Ok(())
+}
+
+/// Export an image from the local docker (or podman) storage
+fn save_image(name: &str, dest: &Path) -> Result<()> {
+    for bin in ["docker", "podman"] {
+        info!("Exporting image {:?} with {}...", name, bin);
+        let status = match Command::new(bin)
+            .arg("save")
+            .arg("-o")
+            .arg(dest)
+            .arg(name)
+            .status()
+        {
+            Ok(status) => status,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| anyhow!("Failed to run {}", bin)),
+        };
+        if !status.success() {
+            bail!("{} save exited with {}", bin, status);
+        }
+        return Ok(());
+    }
+    bail!("Neither docker nor podman are installed");
+}
+
+fn scan_directory(
+    scanner: &Arc<Scanner>,
+    cfg: &Arc<ScanConfig>,
+    dir: &Path,
+    concurrency: usize,
+) -> Vec<(PathBuf, String)> {
+    let (results_tx, results_rx) = crossbeam_channel::unbounded();
+    let (fs_tx, fs_rx) = crossbeam_channel::bounded::<DirEntry>(128);
+
+    for _ in 0..concurrency {
+        let results_tx = results_tx.clone();
+        let fs_rx = fs_rx.clone();
+        let scanner = scanner.clone();
+        thread::spawn(move || {
+            for entry in fs_rx {
+                let path = entry.path();
+                if let Err(err) = scan::catch_panic(path, || scanner.scan_file(path, &results_tx)) {
+                    error!("{:#}", err);
+                }
+            }
+        });
+    }
+    mem::drop(results_tx);
+
+    let cfg = cfg.clone();
+    let dir = dir.to_path_buf();
+    thread::spawn(move || scan::ingest_directory(&cfg, &fs_tx, &dir));
+
+    results_rx.into_iter().collect()
+}
+
+pub fn run(config_path: Option<&Path>, args: args::ScanImage) -> Result<Vec<Finding>> {
+    let config = config::load(config_path, None).context("Failed to load config")?;
+    let concurrency = config.scan.concurrency.unwrap_or_else(num_cpus::get);
+
+    let tmp = tempfile::Builder::new()
+        .prefix("libredefender-image-")
+        .tempdir()
+        .context("Failed to create temporary directory")?;
+
+    let archive = if let Some(name) = args.image.strip_prefix(DOCKER_PREFIX) {
+        let archive = tmp.path().join("image.tar");
+        save_image(name, &archive)?;
+        archive
+    } else {
+        PathBuf::from(&args.image)
+    };
+
+    info!("Unpacking image archive {:?}...", archive);
+    let image_dir = tmp.path().join("image");
+    run_tar(&archive, &image_dir)?;
+
+    let scanner = Arc::new(Scanner::from_config(&config)?);
+    // excludes are meant for the local filesystem and don't apply to images,
+    // ignore files inside of an image are not trusted either
+    let cfg = Arc::new(ScanConfig {
+        skip_larger_than: config.scan.skip_larger_than,
+        ignore_files: Some(false),
+        ..Default::default()
+    });
+
+    let mut findings = Vec::new();
+    for (i, layer) in layers(&image_dir)?.into_iter().enumerate() {
+        let layer_path = image_dir.join(&layer.path);
+        let digest = match layer.digest {
+            Some(digest) => digest,
+            None => format!("sha256:{}", utils::sha256_file(&layer_path)?),
+        };
+
+        info!("Scanning layer {}...", digest);
+        let layer_dir = tmp.path().join("layers").join(i.to_string());
+        run_tar(&layer_path, &layer_dir)?;
+
+        for (path, name) in scan_directory(&scanner, &cfg, &layer_dir, concurrency) {
+            let path = path
+                .strip_prefix(&layer_dir)
+                .map(|p| Path::new("/").join(p))
+                .unwrap_or(path);
+            warn!(
+                "Found threat in layer {}: {} ({:?})",
+                digest,
+                path.display(),
+                name
+            );
+            findings.push(Finding {
+                layer: digest.clone(),
+                path,
+                name,
+            });
+        }
+
+        // free up disk space before the next layer is unpacked
+        if let Err(err) = fs::remove_dir_all(&layer_dir) {
+            warn!("Failed to remove {:?}: {:#}", layer_dir, err);
+        }
+    }
+
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_docker_manifest() {
+        let layers = parse_docker_manifest(br#"[{"Config":"abc.json","RepoTags":["alpine:latest"],"Layers":["1234/layer.tar","5678/layer.tar"]}]"#).unwrap();
+        assert_eq!(
+            layers,
+            vec![
+                Layer {
+                    digest: None,
+                    path: PathBuf::from("1234/layer.tar"),
+                },
+                Layer {
+                    digest: None,
+                    path: PathBuf::from("5678/layer.tar"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_oci_manifest() {
+        let layers = parse_oci_manifest(br#"{"schemaVersion":2,"config":{"mediaType":"application/vnd.oci.image.config.v1+json","digest":"sha256:aaaa","size":1},"layers":[{"mediaType":"application/vnd.oci.image.layer.v1.tar+gzip","digest":"sha256:bbbb","size":2}]}"#).unwrap();
+        assert_eq!(
+            layers,
+            vec![Layer {
+                digest: Some("sha256:bbbb".to_string()),
+                path: PathBuf::from("blobs/sha256/bbbb"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_blob_path_traversal() {
+        assert!(blob_path("sha256:../../etc/passwd").is_err());
+        assert!(blob_path("sha256").is_err());
+        assert!(parse_docker_manifest(br#"[{"Layers":["../layer.tar"]}]"#).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::errors::*;
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Files that are written to are rare, but a scan can take hours
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+const O_ACCMODE: u32 = 0o3;
+
+/// Parse the octal `flags:` line of `/proc/<pid>/fdinfo/<fd>` and check for write access
+fn is_writable(fdinfo: &str) -> bool {
+    fdinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("flags:"))
+        .and_then(|flags| u32::from_str_radix(flags.trim(), 8).ok())
+        .is_some_and(|flags| flags & O_ACCMODE != 0)
+}
+
+fn process_files(pid: &Path, files: &mut HashSet<(u64, u64)>) -> Result<()> {
+    for entry in fs::read_dir(pid.join("fd"))? {
+        let entry = entry?;
+        let Ok(fdinfo) = fs::read_to_string(pid.join("fdinfo").join(entry.file_name())) else {
+            continue;
+        };
+        if !is_writable(&fdinfo) {
+            continue;
+        }
+        // follows the magic link to the opened file, even if it was deleted or renamed
+        let Ok(md) = fs::metadata(entry.path()) else {
+            continue;
+        };
+        if md.is_file() {
+            files.insert((md.dev(), md.ino()));
+        }
+    }
+    Ok(())
+}
+
+/// Device and inode of all regular files that are currently opened for writing
+///
+/// Processes of other users are only visible to root.
+pub fn files_open_for_writing() -> Result<HashSet<(u64, u64)>> {
+    let mut files = HashSet::new();
+    for entry in fs::read_dir("/proc").context("Failed to list processes")? {
+        let entry = entry?;
+        let is_pid = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit()));
+        if !is_pid {
+            continue;
+        }
+        // processes may exit at any time or belong to other users
+        if let Err(err) = process_files(&entry.path(), &mut files) {
+            trace!("Failed to read open files of {:?}: {:#}", entry.path(), err);
+        }
+    }
+    Ok(files)
+}
+
+fn process_has_open(pid: &Path, file: (u64, u64)) -> Result<bool> {
+    for entry in fs::read_dir(pid.join("fd"))? {
+        // follows the magic link, so this also matches a file that was deleted or renamed since
+        let Ok(md) = fs::metadata(entry?.path()) else {
+            continue;
+        };
+        if (md.dev(), md.ino()) == file {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Processes that have the file with this device and inode open, for reading or writing
+///
+/// Processes of other users are only visible to root.
+pub fn processes_with_open(file: (u64, u64)) -> Result<Vec<u32>> {
+    let mut pids = Vec::new();
+    for entry in fs::read_dir("/proc").context("Failed to list processes")? {
+        let entry = entry?;
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        else {
+            continue;
+        };
+        match process_has_open(&entry.path(), file) {
+            Ok(true) => pids.push(pid),
+            Ok(false) => (),
+            Err(err) => trace!("Failed to read open files of {:?}: {:#}", entry.path(), err),
+        }
+    }
+    Ok(pids)
+}
+
+/// The name of a process as in `/proc/<pid>/comm`
+pub fn process_name(pid: u32) -> Option<String> {
+    let name = fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    Some(name.trim_end().to_string())
+}
+
+/// Snapshot of the files opened for writing, refreshed while the scan is running
+#[derive(Debug)]
+pub struct InUse {
+    files: HashSet<(u64, u64)>,
+    updated: Instant,
+}
+
+impl InUse {
+    pub fn new() -> Result<InUse> {
+        Ok(InUse {
+            files: files_open_for_writing()?,
+            updated: Instant::now(),
+        })
+    }
+
+    pub fn contains(&mut self, md: &fs::Metadata) -> bool {
+        if self.updated.elapsed() >= REFRESH_INTERVAL {
+            match files_open_for_writing() {
+                Ok(files) => self.files = files,
+                Err(err) => warn!("Failed to list files in use: {:#}", err),
+            }
+            self.updated = Instant::now();
+        }
+        self.files.contains(&(md.dev(), md.ino()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{File, OpenOptions};
+
+    #[test]
+    fn test_is_writable() {
+        assert!(!is_writable("pos:\t0\nflags:\t0100000\nmnt_id:\t25\n"));
+        assert!(is_writable("pos:\t0\nflags:\t0100001\nmnt_id:\t25\n"));
+        assert!(is_writable("pos:\t0\nflags:\t02100002\nmnt_id:\t25\n"));
+        assert!(!is_writable("pos:\t0\n"));
+    }
+
+    #[test]
+    fn test_files_open_for_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let written = dir.path().join("written");
+        let read = dir.path().join("read");
+        let _w = File::create(&written).unwrap();
+        fs::write(&read, "a").unwrap();
+        let _r = OpenOptions::new().read(true).open(&read).unwrap();
+
+        let files = files_open_for_writing().unwrap();
+        let md = fs::metadata(&written).unwrap();
+        assert!(files.contains(&(md.dev(), md.ino())));
+        let md = fs::metadata(&read).unwrap();
+        assert!(!files.contains(&(md.dev(), md.ino())));
+
+        // only the file itself counts, not other files in the same directory
+        let md = fs::metadata(&read).unwrap();
+        let pids = processes_with_open((md.dev(), md.ino())).unwrap();
+        assert_eq!(pids, vec![std::process::id()]);
+        let other = dir.path().join("other");
+        fs::write(&other, "b").unwrap();
+        let md = fs::metadata(&other).unwrap();
+        assert!(processes_with_open((md.dev(), md.ino()))
+            .unwrap()
+            .is_empty());
+        assert!(process_name(std::process::id()).is_some());
+    }
+}
@@ -7,13 +7,73 @@
     clippy::module_name_repetitions
 )]
 
+pub mod archive;
 pub mod args;
+pub mod audit;
+pub mod browser;
+pub mod cache;
+#[cfg(target_os = "linux")]
+pub mod canary;
+pub mod cgroup;
+pub mod check;
 pub mod config;
+pub mod daemon;
 pub mod db;
+pub mod dbus;
+pub mod describe;
+pub mod desktop;
+pub mod dryrun;
 pub mod errors;
+pub mod exclude;
+pub mod explain;
+pub mod export;
+pub mod external;
+pub mod falsepositive;
+pub mod helper;
+pub mod heuristic;
+pub mod hooks;
+pub mod http;
+pub mod image;
+pub mod inuse;
+pub mod logind;
+pub mod magic;
+pub mod metrics;
+pub mod mounts;
+pub mod multiuser;
+pub mod networkmanager;
 pub mod nice;
 pub mod notify;
+pub mod packages;
+pub mod partition;
 pub mod patterns;
+pub mod pidfile;
+pub mod privdrop;
+#[cfg(target_os = "linux")]
+pub mod privsep;
+pub mod push;
+pub mod quarantine;
+pub mod recheck;
+pub mod reload;
+#[cfg(target_os = "linux")]
+pub mod removable;
+pub mod report;
+pub mod reporting;
+pub mod rtc;
 pub mod scan;
 pub mod schedule;
+pub mod selftest;
+pub mod severity;
+pub mod status;
+pub mod statusfile;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod syslog;
+pub mod throttle;
+pub mod timer;
+pub mod udisks;
+pub mod update;
 pub mod utils;
+pub mod verify;
+pub mod version;
+#[cfg(target_os = "linux")]
+pub mod watch;
@@ -0,0 +1,193 @@
+use crate::errors::*;
+use std::path::PathBuf;
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::{OwnedFd, OwnedObjectPath};
+
+const DESTINATION: &str = "org.freedesktop.login1";
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+const USER_INTERFACE: &str = "org.freedesktop.login1.User";
+
+#[derive(Debug)]
+pub struct Session {
+    pub id: String,
+    pub uid: u32,
+    pub user: String,
+    pub class: String,
+    pub kind: String,
+    pub state: String,
+    pub remote: bool,
+}
+
+impl Session {
+    #[must_use]
+    pub fn is_graphical(&self) -> bool {
+        matches!(self.kind.as_str(), "x11" | "wayland" | "mir")
+    }
+
+    #[must_use]
+    pub fn is_active_user_session(&self) -> bool {
+        self.class == "user" && !self.remote && self.state != "closing"
+    }
+}
+
+#[derive(Debug)]
+pub struct User {
+    pub uid: u32,
+    pub gid: u32,
+    pub name: String,
+    pub runtime_path: PathBuf,
+}
+
+/// A lock that delays or blocks shutdown, sleep or the idle action
+#[derive(Debug)]
+pub struct Inhibitor {
+    pub what: String,
+    pub who: String,
+    pub why: String,
+    pub mode: String,
+    pub uid: u32,
+    pub pid: u32,
+}
+
+impl Inhibitor {
+    /// Check if this lock blocks any of the given operations, e.g. `idle`
+    #[must_use]
+    pub fn blocks(&self, operations: &[String]) -> bool {
+        self.mode == "block"
+            && self
+                .what
+                .split(':')
+                .any(|what| operations.iter().any(|op| op == what))
+    }
+}
+
+pub struct Logind {
+    conn: Connection,
+}
+
+impl Logind {
+    pub fn connect() -> Result<Logind> {
+        let conn = Connection::system().context("Failed to connect to system bus")?;
+        Ok(Logind { conn })
+    }
+
+    fn proxy<'a>(&self, path: &'a str, interface: &'a str) -> Result<Proxy<'a>> {
+        let proxy = Proxy::new(&self.conn, DESTINATION, path, interface)
+            .with_context(|| anyhow!("Failed to create proxy for {}", path))?;
+        Ok(proxy)
+    }
+
+    pub fn list_sessions(&self) -> Result<Vec<Session>> {
+        let manager = self.proxy(MANAGER_PATH, MANAGER_INTERFACE)?;
+        let list: Vec<(String, u32, String, String, OwnedObjectPath)> = manager
+            .call("ListSessions", &())
+            .context("Failed to list logind sessions")?;
+
+        let mut sessions = Vec::new();
+        for (id, uid, user, _seat, path) in list {
+            let session = self.proxy(path.as_str(), SESSION_INTERFACE)?;
+            let session = Session {
+                id,
+                uid,
+                user,
+                class: session.get_property("Class")?,
+                kind: session.get_property("Type")?,
+                state: session.get_property("State")?,
+                remote: session.get_property("Remote")?,
+            };
+            debug!("Found logind session: {:?}", session);
+            sessions.push(session);
+        }
+
+        Ok(sessions)
+    }
+
+    pub fn list_inhibitors(&self) -> Result<Vec<Inhibitor>> {
+        let manager = self.proxy(MANAGER_PATH, MANAGER_INTERFACE)?;
+        let list: Vec<(String, String, String, String, u32, u32)> = manager
+            .call("ListInhibitors", &())
+            .context("Failed to list logind inhibitors")?;
+
+        let inhibitors = list
+            .into_iter()
+            .map(|(what, who, why, mode, uid, pid)| Inhibitor {
+                what,
+                who,
+                why,
+                mode,
+                uid,
+                pid,
+            })
+            .collect();
+        Ok(inhibitors)
+    }
+
+    /// Take a blocking inhibitor lock, it's released when the returned fd is dropped
+    pub fn inhibit(&self, what: &str, why: &str) -> Result<OwnedFd> {
+        let manager = self.proxy(MANAGER_PATH, MANAGER_INTERFACE)?;
+        let fd = manager
+            .call("Inhibit", &(what, "libredefender", why, "block"))
+            .with_context(|| anyhow!("Failed to take inhibitor lock for {:?}", what))?;
+        Ok(fd)
+    }
+
+    /// All sessions are idle, e.g. because the screen is locked or nobody is logged in
+    pub fn is_idle(&self) -> Result<bool> {
+        let manager = self.proxy(MANAGER_PATH, MANAGER_INTERFACE)?;
+        let idle = manager
+            .get_property("IdleHint")
+            .context("Failed to read logind idle hint")?;
+        Ok(idle)
+    }
+
+    pub fn suspend(&self) -> Result<()> {
+        let manager = self.proxy(MANAGER_PATH, MANAGER_INTERFACE)?;
+        manager
+            .call::<_, _, ()>("Suspend", &(false,))
+            .context("Failed to suspend")?;
+        Ok(())
+    }
+
+    pub fn user(&self, uid: u32) -> Result<User> {
+        let manager = self.proxy(MANAGER_PATH, MANAGER_INTERFACE)?;
+        let path: OwnedObjectPath = manager
+            .call("GetUser", &(uid,))
+            .with_context(|| anyhow!("Failed to lookup logind user {}", uid))?;
+
+        let user = self.proxy(path.as_str(), USER_INTERFACE)?;
+        let runtime_path: String = user.get_property("RuntimePath")?;
+        Ok(User {
+            uid,
+            gid: user.get_property("GID")?,
+            name: user.get_property("Name")?,
+            runtime_path: PathBuf::from(runtime_path),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inhibitor_blocks() {
+        let inhibitor = Inhibitor {
+            what: "sleep:idle".to_string(),
+            who: "borg".to_string(),
+            why: "Backup in progress".to_string(),
+            mode: "block".to_string(),
+            uid: 1000,
+            pid: 1234,
+        };
+        assert!(inhibitor.blocks(&["idle".to_string()]));
+        assert!(!inhibitor.blocks(&["shutdown".to_string()]));
+
+        let delay = Inhibitor {
+            mode: "delay".to_string(),
+            ..inhibitor
+        };
+        assert!(!delay.blocks(&["idle".to_string()]));
+    }
+}
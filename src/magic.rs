@@ -0,0 +1,181 @@
+use crate::errors::*;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// (offset, magic bytes, mime type)
+const SIGNATURES: &[(usize, &[u8], &str)] = &[
+    (4, b"ftypqt", "video/quicktime"),
+    (4, b"ftyp", "video/mp4"),
+    (0, b"\x1a\x45\xdf\xa3", "video/x-matroska"),
+    (0, b"\x00\x00\x01\xba", "video/mpeg"),
+    (0, b"FLV\x01", "video/x-flv"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"\xff\xfb", "audio/mpeg"),
+    (0, b"fLaC", "audio/flac"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"GIF8", "image/gif"),
+    (0, b"QFI\xfb", "application/x-qemu-disk"),
+    (0, b"conectix", "application/x-vhd"),
+    (0, b"vhdxfile", "application/x-vhdx"),
+    (0, b"KDMV", "application/x-vmdk"),
+];
+
+/// RIFF containers carry their actual type at offset 8
+const RIFF_TYPES: &[(&[u8], &str)] = &[
+    (b"AVI ", "video/x-msvideo"),
+    (b"WAVE", "audio/x-wav"),
+    (b"WEBP", "image/webp"),
+];
+
+const ISO9660_OFFSET: u64 = 0x8001;
+
+/// Headers of executables, scripts, documents and archives that are scanned with `--quick`
+const DANGEROUS: &[&[u8]] = &[
+    b"\x7fELF",
+    b"MZ",
+    b"#!",
+    b"%PDF",
+    b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", // ole2, legacy office documents and msi
+    b"{\\rtf",
+    b"PK\x03\x04", // zip, office documents, jar and apk
+    b"\x1f\x8b",
+    b"BZh",
+    b"\xfd7zXZ\x00",
+    b"\x28\xb5\x2f\xfd",
+    b"7z\xbc\xaf\x27\x1c",
+    b"Rar!\x1a\x07",
+    b"\xca\xfe\xba\xbe", // java class and mach-o universal binaries
+    b"\xcf\xfa\xed\xfe",
+    b"L\x00\x00\x00\x01\x14\x02\x00", // windows shortcut
+];
+
+/// Scripts without a shebang can only be recognized by their extension
+const SCRIPT_EXTENSIONS: &[&str] = &[
+    "js", "jse", "vbs", "vbe", "wsf", "ps1", "bat", "cmd", "hta", "html", "htm", "svg", "py", "sh",
+];
+
+#[must_use]
+pub fn sniff(buf: &[u8]) -> Option<&'static str> {
+    if buf.starts_with(b"RIFF") {
+        let kind = buf.get(8..12)?;
+        return RIFF_TYPES
+            .iter()
+            .find(|(magic, _)| *magic == kind)
+            .map(|(_, mime)| *mime);
+    }
+
+    SIGNATURES
+        .iter()
+        .find(|(offset, magic, _)| buf.get(*offset..).is_some_and(|b| b.starts_with(magic)))
+        .map(|(_, _, mime)| *mime)
+}
+
+#[must_use]
+pub fn is_dangerous(buf: &[u8]) -> bool {
+    DANGEROUS.iter().any(|magic| buf.starts_with(magic))
+}
+
+/// Cheap check if a file could contain something executable, used by quick scans
+pub fn is_dangerous_file(path: &Path) -> Result<bool> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    if ext.is_some_and(|ext| SCRIPT_EXTENSIONS.contains(&ext.as_str())) {
+        return Ok(true);
+    }
+
+    let mut file = File::open(path).with_context(|| anyhow!("Failed to open {:?}", path))?;
+    let mode = file.metadata()?.permissions().mode();
+    if mode & 0o111 != 0 {
+        return Ok(true);
+    }
+
+    let mut buf = [0; 8];
+    let n = read_up_to(&mut file, &mut buf)?;
+    Ok(is_dangerous(&buf[..n]))
+}
+
+/// Read the first bytes of a file and detect its type
+pub fn sniff_file(path: &Path) -> Result<Option<&'static str>> {
+    let mut file = File::open(path).with_context(|| anyhow!("Failed to open {:?}", path))?;
+    let mut buf = [0; 64];
+    let n = read_up_to(&mut file, &mut buf)?;
+    if let Some(mime) = sniff(&buf[..n]) {
+        return Ok(Some(mime));
+    }
+
+    // iso images don't have a header at the start of the file
+    if file.seek(SeekFrom::Start(ISO9660_OFFSET)).is_ok() {
+        let mut buf = [0; 5];
+        if read_up_to(&mut file, &mut buf)? == buf.len() && &buf == b"CD001" {
+            return Ok(Some("application/x-iso9660-image"));
+        }
+    }
+
+    Ok(None)
+}
+
+fn read_up_to<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(len) => n += len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(n)
+}
+
+/// Match a mime type against a pattern like `video/mp4` or `video/*`
+#[must_use]
+pub fn mime_matches(pattern: &str, mime: &str) -> bool {
+    if let Some(prefix) = pattern.strip_suffix("/*") {
+        mime.split_once('/').is_some_and(|(kind, _)| kind == prefix)
+    } else {
+        pattern == mime
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(
+            sniff(b"\x00\x00\x00\x20ftypisom\x00\x00\x02\x00"),
+            Some("video/mp4")
+        );
+        assert_eq!(sniff(b"RIFF\x24\x00\x00\x00WAVEfmt "), Some("audio/x-wav"));
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\x00"), Some("image/png"));
+        assert_eq!(sniff(b"RIFF"), None);
+        assert_eq!(sniff(b"MZ\x90\x00"), None);
+        assert_eq!(sniff(b""), None);
+    }
+
+    #[test]
+    fn test_is_dangerous() {
+        assert!(is_dangerous(b"\x7fELF\x02\x01\x01"));
+        assert!(is_dangerous(b"MZ\x90\x00"));
+        assert!(is_dangerous(b"#!/bin/sh\n"));
+        assert!(is_dangerous(b"PK\x03\x04\x14\x00"));
+        assert!(!is_dangerous(b"\x89PNG\r\n\x1a\n"));
+        assert!(!is_dangerous(b"hello world"));
+        assert!(!is_dangerous(b""));
+    }
+
+    #[test]
+    fn test_mime_matches() {
+        assert!(mime_matches("video/*", "video/mp4"));
+        assert!(mime_matches("audio/flac", "audio/flac"));
+        assert!(!mime_matches("video/*", "audio/mpeg"));
+        assert!(!mime_matches("video/mp4", "video/x-matroska"));
+    }
+}
@@ -5,22 +5,57 @@
 )]
 
 use chrono::{DateTime, Local, Utc};
-use chrono_humanize::HumanTime;
+use chrono_humanize::{Accuracy, HumanTime, Tense};
 use clap::Parser;
 use colored::{Color, ColoredString, Colorize};
-use env_logger::Env;
-use libredefender::args::{Args, SubCommand};
+use libredefender::args::{self, Args, ExportFormat, ReportFormat, StatusFormat, SubCommand};
+use libredefender::audit::{self, Action};
+use libredefender::browser;
+use libredefender::cache::{self, CleanCache};
+use libredefender::check::{self, Check};
 use libredefender::config;
-use libredefender::db::Database;
+use libredefender::daemon;
+use libredefender::db::{Data, Database, SkipReason};
+use libredefender::describe::Descriptions;
+use libredefender::desktop;
+use libredefender::dryrun;
 use libredefender::errors::*;
+use libredefender::exclude;
+use libredefender::explain;
+use libredefender::export;
+use libredefender::falsepositive::{self, FpReport};
+use libredefender::helper;
+use libredefender::image;
+use libredefender::metrics;
 use libredefender::nice;
 use libredefender::notify;
-use libredefender::scan;
+use libredefender::partition;
+use libredefender::pidfile;
+use libredefender::push;
+use libredefender::quarantine::{self, Quarantine};
+use libredefender::recheck;
+use libredefender::report::{self, Period, Report};
+use libredefender::reporting;
+use libredefender::scan::{self, ScanJob, ScanSummary};
 use libredefender::schedule;
+use libredefender::selftest;
+use libredefender::status::{self, Dashboard};
+use libredefender::statusfile::{self, LiveStatus, State as StatusState};
+use libredefender::syslog::{Facility, LogTarget, SyslogLayer};
+use libredefender::timer;
 use libredefender::utils;
+use libredefender::verify;
+use libredefender::version;
+#[cfg(target_os = "linux")]
+use libredefender::watch;
 use num_format::{Locale, ToFormattedString};
 use std::borrow::Cow;
+use std::fs;
+use std::io::IsTerminal;
 use std::path::Path;
+use tracing_chrome::ChromeLayerBuilder;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
 
 fn format_num(num: usize, zero_is_bad: bool) -> ColoredString {
     let color = if zero_is_bad ^ (num != 0) {
@@ -52,9 +87,244 @@ fn print_line(line: &str, good: bool) {
     }
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+/// The status should still be shown if the config is broken
+fn max_signature_age(config_path: Option<&Path>) -> chrono::Duration {
+    match config::load(config_path, None) {
+        Ok(config) => config.update.max_age(),
+        Err(err) => {
+            debug!(
+                "Failed to load config, using default signature age: {:#}",
+                err
+            );
+            chrono::Duration::days(config::DEFAULT_MAX_AGE_DAYS.into())
+        }
+    }
+}
+
+/// Only shown if `libredefender scheduler` recorded its state
+fn print_scheduler(data: &Data) {
+    let state = &data.scheduler;
+    if let Some(next_scan) = &state.next_scan {
+        let job = state
+            .next_job
+            .as_ref()
+            .map(|name| format!(" [{}]", name))
+            .unwrap_or_default();
+        print_line(
+            &format!(
+                "Next scan                 {}{}",
+                format_datetime(&Some(*next_scan)),
+                job
+            ),
+            true,
+        );
+    }
+    // an older skip was followed by a successful scan
+    let skip = state
+        .last_skip
+        .as_ref()
+        .filter(|skip| data.last_scan.is_none_or(|last_scan| skip.time > last_scan));
+    if let Some(skip) = skip {
+        print_line(
+            &format!(
+                "Last scan skipped         {}",
+                format_datetime(&Some(skip.time))
+            ),
+            false,
+        );
+        println!("     {}", skip.reason.dimmed());
+    }
+}
+
+fn print_status(data: &Data, max_age: chrono::Duration) {
+    print_line(
+        &format!(
+            "Last scan                 {}",
+            format_datetime(&data.last_scan)
+        ),
+        data.last_scan.is_some(),
+    );
+    print_scheduler(data);
+    print_line(
+        &format!(
+            "Threats present           {}",
+            format_num(data.threats.len(), false)
+        ),
+        data.threats.is_empty(),
+    );
+
+    if !data.skipped.is_empty() {
+        // skipping large files and files in use is configured explicitly and not an error
+        let errors = data
+            .skipped
+            .values()
+            .any(|reason| !matches!(reason, SkipReason::TooLarge | SkipReason::InUse));
+        print_line(
+            &format!(
+                "Files not scanned         {}",
+                data.skipped.len().to_formatted_string(&Locale::en).bold()
+            ),
+            !errors,
+        );
+    }
+
+    print_line(
+        &format!(
+            "Signatures                {}",
+            format_num(data.signature_count, true)
+        ),
+        data.signature_count > 0,
+    );
+    if data.signature_sources.len() > 1 {
+        let mut sources = data.signature_sources.iter().collect::<Vec<_>>();
+        sources.sort();
+        for (path, count) in sources {
+            println!(
+                "     {:<22} {}",
+                path.display().to_string().dimmed(),
+                format_num(*count, true)
+            );
+        }
+    }
+    print_line(
+        &format!(
+            "Signatures updated        {}",
+            format_datetime(&data.signatures_age)
+        ),
+        data.signatures_age.is_some() && !status::is_outdated(data, max_age),
+    );
+
+    let dashboard = Dashboard::new(data, max_age);
+    let scheduler = &dashboard.scheduler;
+    let scheduler_line = match scheduler.pid {
+        Some(pid) if scheduler.system => format!("running for all users (pid {})", pid),
+        Some(pid) => format!("running (pid {})", pid),
+        None => "not running".to_string(),
+    };
+    print_line(
+        &format!("Scheduler                 {}", scheduler_line),
+        scheduler.running,
+    );
+    if let Some(quarantined) = dashboard.quarantined {
+        print_line(
+            &format!(
+                "Quarantined files         {}",
+                format_num(quarantined, true)
+            ),
+            true,
+        );
+    }
+    if let Some(size) = dashboard.database_size {
+        print_line(
+            &format!(
+                "Database size             {} bytes",
+                format_num(size as usize, true)
+            ),
+            true,
+        );
+    }
+
+    println!();
+    if dashboard.problems.is_empty() {
+        println!(
+            "{}",
+            "Start a scan with `libredefender scan` or run `libredefender help`".green()
+        );
+    } else {
+        println!("{}", "Problems detected".red().bold());
+        for problem in &dashboard.problems {
+            println!("  - {}", problem.message);
+            println!("    {} {}", "fix:".dimmed(), problem.fix.bold());
+        }
+    }
+}
+
+/// `ctl status`, the progress of a detached scan from the status file
+fn print_live_status(status: Option<&LiveStatus>, pid: Option<u32>) {
+    let scan = match pid {
+        Some(pid) => format!("running (pid {})", pid),
+        None => "not running".to_string(),
+    };
+    print_line(
+        &format!("Detached scan             {}", scan),
+        pid.is_some(),
+    );
+    let Some(status) = status else {
+        println!();
+        println!("{}", "No scan was started yet".yellow());
+        return;
+    };
+
+    print_line(
+        &format!("State                     {}", status.state.as_str()),
+        status.state != StatusState::Failed && status.state != StatusState::Cancelled,
+    );
+    print_line(
+        &format!(
+            "Started                   {}",
+            format_datetime(&Some(status.started))
+        ),
+        true,
+    );
+    print_line(
+        &format!(
+            "Files scanned             {} ({} bytes)",
+            format_num(status.files_scanned, false).normal(),
+            status.bytes_scanned.to_formatted_string(&Locale::en)
+        ),
+        true,
+    );
+    if let Some(percent) = status.percent {
+        let eta = status
+            .eta_secs
+            .map(|secs| chrono::Duration::seconds(secs as i64))
+            .map(|eta| {
+                format!(
+                    ", {} left",
+                    HumanTime::from(eta).to_text_en(Accuracy::Rough, Tense::Present)
+                )
+            })
+            .unwrap_or_default();
+        print_line(
+            &format!("Progress                  {:.1}%{}", percent, eta),
+            true,
+        );
+    }
+    print_line(
+        &format!(
+            "Threats found             {}",
+            format_num(status.threats, false)
+        ),
+        status.threats == 0,
+    );
+    print_line(
+        &format!(
+            "Scan errors               {}",
+            format_num(status.errors, false)
+        ),
+        status.errors == 0,
+    );
+    if let Some(path) = &status.current_path {
+        println!("     {}", path.display().to_string().dimmed());
+    }
+}
 
+/// The scheduler logs to the target in its config unless `--log-target` is given
+fn log_target(args: &Args) -> LogTarget {
+    if let Some(target) = args.log_target {
+        return target;
+    }
+    if !matches!(args.subcommand, Some(SubCommand::Scheduler(_))) {
+        return LogTarget::Stderr;
+    }
+    // logging isn't setup yet, errors are reported again once the scheduler loads the config
+    config::load(args.config.as_deref(), None)
+        .map(|config| config.schedule.log_target)
+        .unwrap_or_default()
+}
+
+/// The returned guard writes the trace file when it's dropped
+fn setup_logging(args: &Args) -> Result<Option<tracing_chrome::FlushGuard>> {
     let logging = match (args.quiet, args.verbose) {
         (true, _) => "warn",
         (false, 0) => "info",
@@ -62,71 +332,352 @@ fn main() -> Result<()> {
         (false, 2) => "debug",
         (false, _) => "debug,libredefender=trace",
     };
-    env_logger::init_from_env(Env::default().default_filter_or(logging));
+    let filter = || EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(logging));
+    let (fmt, syslog) = match log_target(args) {
+        LogTarget::Stderr => {
+            let layer = tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                // a detached scan logs to a file
+                .with_ansi(std::io::stderr().is_terminal())
+                .with_filter(filter());
+            (Some(layer), None)
+        }
+        LogTarget::Syslog => {
+            let facility = if matches!(args.subcommand, Some(SubCommand::Scheduler(_))) {
+                Facility::Daemon
+            } else {
+                Facility::User
+            };
+            let layer = SyslogLayer::new(facility)?.with_filter(filter());
+            (None, Some(layer))
+        }
+    };
+
+    let (chrome, guard) = match &args.trace_output {
+        Some(path) => {
+            let (layer, guard) = ChromeLayerBuilder::new().file(path).build();
+            // per-file spans are recorded at debug level
+            let layer = layer.with_filter(EnvFilter::new("info,libredefender=debug"));
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(fmt)
+        .with(syslog)
+        .with(chrome)
+        .init();
+    Ok(guard)
+}
+
+/// With `diff` only the threats that weren't known before the scan are listed
+fn print_summary(summary: &ScanSummary, diff: bool) {
+    println!();
+    let duration =
+        chrono::Duration::from_std(summary.duration).unwrap_or_else(|_| chrono::Duration::zero());
+    print_line(
+        &format!(
+            "Files scanned             {} (in {})",
+            format_num(summary.files_scanned, true),
+            HumanTime::from(duration).to_text_en(Accuracy::Rough, Tense::Present)
+        ),
+        !summary.cancelled,
+    );
+    if summary.files_cached > 0 {
+        println!(
+            "     {}",
+            format!(
+                "{} known clean file(s) skipped by the clean cache",
+                summary.files_cached.to_formatted_string(&Locale::en)
+            )
+            .dimmed()
+        );
+    }
+    if summary.files_unmodified > 0 {
+        println!(
+            "     {}",
+            format!(
+                "{} unmodified package file(s) skipped",
+                summary.files_unmodified.to_formatted_string(&Locale::en)
+            )
+            .dimmed()
+        );
+    }
+    print_line(
+        &format!(
+            "Threats found             {}",
+            format_num(summary.threats.len(), false)
+        ),
+        summary.threats.is_empty(),
+    );
+    let threats = if diff {
+        &summary.new_threats
+    } else {
+        &summary.threats
+    };
+    for (path, name) in threats {
+        println!("     {} => {}", name.red().bold(), path.display());
+    }
+    let known = summary.threats.len() - threats.len();
+    if known > 0 {
+        println!(
+            "     {}",
+            format!("{} known threat(s) not shown", known).dimmed()
+        );
+    }
+    if !summary.errors.is_empty() {
+        print_line(
+            &format!(
+                "Scan errors               {}",
+                format_num(summary.errors.len(), false)
+            ),
+            false,
+        );
+        let categories = scan::count_errors(&summary.errors)
+            .into_iter()
+            .map(|(category, n)| format!("{} {}", n, category))
+            .collect::<Vec<_>>();
+        println!("     {}", categories.join(", ").dimmed());
+    }
+    if !summary.modified.is_empty() {
+        print_line(
+            &format!(
+                "Modified package files    {}",
+                format_num(summary.modified.len(), false)
+            ),
+            false,
+        );
+        for path in &summary.modified {
+            println!("     {}", path.display());
+        }
+    }
+    if summary.cancelled {
+        println!("{}", "The scan was cancelled before it finished".yellow());
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    if let Some(data) = &args.data {
+        utils::set_data_dir(data.clone());
+    }
+    // the logging setup starts threads, forking has to happen before it
+    let _pidfile = match &args.subcommand {
+        Some(SubCommand::Scan(scan)) if scan.detach => {
+            let log_file = match &scan.log_file {
+                Some(path) => path.clone(),
+                None => daemon::default_log_file()?,
+            };
+            Some(daemon::detach(&log_file)?)
+        }
+        _ => None,
+    };
+
+    let _trace_guard = setup_logging(&args)?;
 
     if args.colors {
         colored::control::set_override(true);
     }
 
+    let config_path = args.config.as_deref();
     match args.subcommand {
         None => {
+            let db = Database::load().context("Failed to load database")?;
+            print_status(db.data(), max_signature_age(config_path));
+        }
+        Some(SubCommand::Status(args)) => {
             let db = Database::load().context("Failed to load database")?;
             let data = db.data();
-
-            print_line(
-                &format!(
-                    "Last scan                 {}",
-                    format_datetime(&data.last_scan)
-                ),
-                data.last_scan.is_some(),
-            );
-            print_line(
-                &format!(
-                    "Threats present           {}",
-                    format_num(data.threats.len(), false)
-                ),
-                data.threats.is_empty(),
-            );
-
-            print_line(
-                &format!(
-                    "Signatures                {}",
-                    format_num(data.signature_count, true)
-                ),
-                data.signature_count > 0,
-            );
-            print_line(
-                &format!(
-                    "Signatures updated        {}",
-                    format_datetime(&data.signatures_age)
-                ),
-                data.signatures_age.is_some(),
-            );
-
-            println!();
-            println!(
-                "{}",
-                "Start a scan with `libredefender scan` or run `libredefender help`".green()
-            );
+            let max_age = max_signature_age(config_path);
+            match args.format {
+                _ if args.json => println!("{}", status::json(data, max_age)?),
+                StatusFormat::Text => print_status(data, max_age),
+                StatusFormat::Json => println!("{}", status::json(data, max_age)?),
+                StatusFormat::Waybar => println!("{}", status::waybar(data, max_age)?),
+                StatusFormat::Template => {
+                    let template = args.template.as_deref().unwrap_or_default();
+                    println!("{}", status::render_template(template, data, max_age));
+                }
+            }
+        }
+        Some(SubCommand::Metrics(args)) => {
+            let db = Database::load().context("Failed to load database")?;
+            if let Some(path) = &args.textfile {
+                metrics::write_textfile(path, db.data())?;
+            } else {
+                print!("{}", metrics::render(db.data()));
+            }
+        }
+        Some(SubCommand::Check(args)) => {
+            let check = match Database::load() {
+                Ok(db) => {
+                    let max_sig_age = args
+                        .max_sig_age
+                        .map_or_else(|| max_signature_age(config_path), |age| age.0);
+                    check::check(db.data(), max_sig_age, args.max_scan_age.map(|age| age.0))
+                }
+                Err(err) => Check::unknown(&err.context("Failed to load database")),
+            };
+            println!("{}", check);
+            std::process::exit(check.code as i32);
         }
         Some(SubCommand::Scan(args)) => {
-            nice::setup()?;
+            if args.dry_run {
+                return dryrun::run(config_path, args);
+            }
+            if !args.no_nice {
+                nice::setup_from_config(config_path, Some(&args))?;
+            }
             scan::init()?;
-            scan::run(args)?;
+            if args.gui {
+                return desktop::scan_gui(config_path, args);
+            }
+            let show_skipped = args.show_skipped;
+            let diff = args.diff;
+            let summary = if let Some(device) = args.mount_and_scan.clone() {
+                partition::mount_and_scan(config_path, &device, args)?
+            } else if args.browser {
+                browser::scan(config_path, args)?
+            } else {
+                scan::run(config_path, args)?
+            };
+            print_summary(&summary, diff);
+            if show_skipped {
+                let db = Database::load().context("Failed to load database")?;
+                let mut skipped = db.data().skipped.iter().collect::<Vec<_>>();
+                skipped.sort_by_key(|(path, _)| *path);
+                for (path, reason) in skipped {
+                    println!("{} => {}", reason.to_string().yellow(), path.display());
+                }
+            }
+        }
+        Some(SubCommand::ScanImage(args)) => {
+            nice::setup_from_config(config_path, None)?;
+            scan::init()?;
+            let findings = image::run(config_path, args)?;
+            for finding in &findings {
+                println!(
+                    "{} => {} {}",
+                    finding.name.red().bold(),
+                    finding.layer,
+                    format!("{:?}", finding.path).yellow(),
+                );
+            }
+            if !findings.is_empty() {
+                bail!("Found {} threat(s) in image", findings.len());
+            }
         }
         Some(SubCommand::Scheduler(args)) => {
-            nice::setup()?;
+            nice::setup_from_config(config_path, None)?;
+            scan::init()?;
+            if args.once {
+                schedule::run_once(config_path, args.job.as_deref())?;
+            } else {
+                schedule::run(config_path, &args)?;
+            }
+        }
+        #[cfg(target_os = "linux")]
+        Some(SubCommand::Watch(args)) => {
+            nice::setup_from_config(config_path, None)?;
             scan::init()?;
-            schedule::run(&args)?;
+            watch::run(config_path, args)?;
         }
         Some(SubCommand::Infections(args)) => {
             let mut db = Database::load().context("Failed to load database")?;
             let data = db.data_mut();
 
+            if let Some(format) = args.export {
+                let records = export::records(data);
+                let stdout = std::io::stdout();
+                match format {
+                    ExportFormat::Csv => export::write_csv(stdout.lock(), &records)?,
+                    ExportFormat::Json => export::write_json(stdout.lock(), &records)?,
+                }
+                return Ok(());
+            }
+
+            if args.recheck {
+                let config = config::load(config_path, None).context("Failed to load config")?;
+                let recheck = recheck::run(&config, data)?;
+                db.store().context("Failed to write database")?;
+
+                for path in &recheck.cleared {
+                    println!("{} {:?}", "not detected anymore:".green(), path);
+                }
+                for path in &recheck.missing {
+                    println!("{} {:?}", "file is gone:".green(), path);
+                }
+                for (path, err) in &recheck.failed {
+                    println!("{} {:?}: {:#}", "failed to scan:".red(), path, err);
+                }
+                println!(
+                    "{} threat(s) before, {} after ({} cleared, {} gone, {} could not be scanned)",
+                    recheck.before(),
+                    recheck.after(),
+                    recheck.cleared.len(),
+                    recheck.missing.len(),
+                    recheck.failed.len()
+                );
+                return Ok(());
+            }
+
+            if let Some(path) = &args.open_fp_form {
+                let path = fs::canonicalize(path)
+                    .with_context(|| anyhow!("Failed to canonicalize path {:?}", path))?;
+                let threat = data.threats.get(&path).with_context(|| {
+                    anyhow!(
+                        "File is not a known threat, only detections can be reported: {:?}",
+                        path
+                    )
+                })?;
+
+                let report = FpReport::new(&path, threat, data)?;
+                println!("{}", report.to_text());
+
+                if utils::ask_confirmation(&format!(
+                    "Open the ClamAV false positive form at {}?",
+                    falsepositive::SUBMIT_URL
+                ))? {
+                    falsepositive::open_submission_form()?;
+                    println!("Attach {:?} to the form and paste the details above", path);
+                }
+                return Ok(());
+            }
+
+            let descriptions = config::load(config_path, None)
+                .map(|config| Descriptions::from_config(&config.update))
+                .unwrap_or_else(|_| Descriptions::bundled());
             let mut deleted = Vec::new();
+            let quarantine = if args.quarantine {
+                Some(Quarantine::open()?)
+            } else {
+                None
+            };
 
-            for (path, names) in &data.threats {
-                if args.delete || args.delete_all {
+            // files of other users or in system directories, these can be retried with pkexec
+            let mut denied = Vec::new();
+            for (path, threat) in &data.threats {
+                let names = &threat.names;
+                if let Some(quarantine) = &quarantine {
+                    info!("Moving {:?} at {:?} to quarantine", names, path);
+                    match quarantine.add(path, names) {
+                        Ok(entry) => {
+                            let entry = audit::Entry::new(Action::Quarantined, path, names)
+                                .detail(entry.id);
+                            if let Err(err) = audit::record(&entry) {
+                                warn!("Failed to write audit log: {:#}", err);
+                            }
+                            deleted.push(path.clone());
+                        }
+                        Err(err) if utils::is_permission_denied(&err) && !utils::is_root() => {
+                            warn!("Failed to quarantine {:?}: {:#}", path, err);
+                            denied.push(path.clone());
+                        }
+                        Err(err) => error!("Failed to quarantine {:?}: {:#}", path, err),
+                    }
+                } else if args.delete || args.delete_all {
                     let should_delete = if args.delete_all {
                         true
                     } else {
@@ -135,20 +686,75 @@ fn main() -> Result<()> {
 
                     if should_delete {
                         info!("Deleting {:?} at {:?}", names, path);
-                        if let Err(err) = utils::ensure_deleted(path) {
-                            error!("Failed to delete {:?}: {:#}", path, err);
-                        } else {
-                            deleted.push(path.clone());
+                        match utils::ensure_deleted(path) {
+                            Ok(()) => {
+                                let entry = audit::Entry::new(Action::Deleted, path, names);
+                                if let Err(err) = audit::record(&entry) {
+                                    warn!("Failed to write audit log: {:#}", err);
+                                }
+                                deleted.push(path.clone());
+                            }
+                            Err(err) if utils::is_permission_denied(&err) && !utils::is_root() => {
+                                warn!("Failed to delete {:?}: {:#}", path, err);
+                                denied.push(path.clone());
+                            }
+                            Err(err) => error!("Failed to delete {:?}: {:#}", path, err),
                         }
                     }
-                } else {
-                    for name in names {
+                } else if let Some(primary) = threat.primary() {
+                    println!(
+                        "{} ({}) => {}",
+                        primary.red().bold(),
+                        threat.severity,
+                        format!("{:?}", path).yellow(),
+                    );
+                    if !threat.alternates().is_empty() {
                         println!(
-                            "{} => {}",
-                            name.red().bold(),
-                            format!("{:?}", path).yellow(),
+                            "    {}",
+                            format!("also detected as {}", threat.alternates().join(", ")).dimmed()
                         );
                     }
+                    for member in &threat.members {
+                        println!("    {}", format!("in {:?}", member).dimmed());
+                    }
+                    if let Some(description) =
+                        names.iter().find_map(|name| descriptions.describe(name))
+                    {
+                        println!("    {}", description.to_string().dimmed());
+                    }
+                }
+            }
+
+            if !denied.is_empty()
+                && utils::ask_confirmation(&format!(
+                    "{} file(s) can only be removed as root, retry them with pkexec?",
+                    denied.len()
+                ))?
+            {
+                for removed in helper::escalate(&denied, quarantine.as_ref())? {
+                    let names = data
+                        .threats
+                        .get(&removed.path)
+                        .map(|threat| threat.names.clone())
+                        .unwrap_or_default();
+                    let entry = match (&quarantine, removed.id) {
+                        (Some(quarantine), Some(id)) => {
+                            // the file is already gone, keep going so the database is updated
+                            if let Err(err) = quarantine.record(id.clone(), &removed.path, &names) {
+                                error!(
+                                    "Failed to record quarantined file {:?} ({}): {:#}",
+                                    removed.path, id, err
+                                );
+                            }
+                            audit::Entry::new(Action::Quarantined, &removed.path, &names).detail(id)
+                        }
+                        _ => audit::Entry::new(Action::Deleted, &removed.path, &names)
+                            .detail("pkexec"),
+                    };
+                    if let Err(err) = audit::record(&entry) {
+                        warn!("Failed to write audit log: {:#}", err);
+                    }
+                    deleted.push(removed.path);
                 }
             }
 
@@ -159,15 +765,289 @@ fn main() -> Result<()> {
                 db.store().context("Failed to write database")?;
             }
         }
-        Some(SubCommand::TestNotify) => notify::show(Path::new("/just/a/test"), "just/testing")?,
-        Some(SubCommand::DumpConfig) => {
-            let config = config::load(None).context("Failed to load config")?;
+        Some(SubCommand::HelperDelete(args)) => helper::run(args)?,
+        #[cfg(target_os = "linux")]
+        Some(SubCommand::ScanChild) => ScanJob::run_child(),
+        Some(SubCommand::Report(args)) => {
+            let config = config::load(config_path, None).context("Failed to load config")?;
+            let db = Database::load().context("Failed to load database")?;
+            let period = Period {
+                since: args.since,
+                until: args.until,
+            };
+            let report = Report::new(db.data(), &config, period)?;
+
+            let format = args.format.unwrap_or_else(|| match &args.output {
+                Some(path) if report::is_html_path(path) => ReportFormat::Html,
+                _ => ReportFormat::Markdown,
+            });
+            let out = match format {
+                ReportFormat::Html => report.to_html(),
+                ReportFormat::Markdown => report.to_markdown(),
+            };
 
-            serde_json::to_writer_pretty(std::io::stdout(), &config)?;
-            println!();
+            if let Some(path) = &args.output {
+                fs::write(path, out)
+                    .with_context(|| anyhow!("Failed to write report to {:?}", path))?;
+                info!("Wrote report to {:?}", path);
+            } else {
+                print!("{}", out);
+            }
+        }
+        Some(SubCommand::InstallFileManager(args)) => {
+            if args.uninstall {
+                desktop::uninstall()?;
+            } else {
+                desktop::install()?;
+            }
+        }
+        Some(SubCommand::InstallTimer(args)) => {
+            let scope = if args.system {
+                timer::Scope::System
+            } else {
+                timer::Scope::User
+            };
+            if args.uninstall {
+                if args.cron {
+                    timer::uninstall_cron(scope)?;
+                } else {
+                    timer::uninstall_systemd_timers(scope)?;
+                }
+            } else {
+                let config = config::load(config_path, None).context("Failed to load config")?;
+                let timers = timer::timers(&config, config_path, scope)?;
+                if args.cron {
+                    timer::install_cron(scope, &timers)?;
+                } else {
+                    timer::install_systemd(scope, &timers)?;
+                }
+                println!("Installed {} scheduled scan(s)", timers.len());
+            }
+        }
+        Some(SubCommand::TestNotify(args)) => {
+            let config = config::load(config_path, None).context("Failed to load config")?;
+            let descriptions = Descriptions::from_config(&config.update);
+            let mut failed = 0;
+            for (backend, result) in notify::test(&config.notify, &descriptions, &args) {
+                match result {
+                    Ok(()) => eprintln!("{} {}", "ok".green(), backend),
+                    Err(err) => {
+                        eprintln!("{} {}: {:#}", "failed".red(), backend, err);
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0 {
+                bail!("{} notification backend(s) failed", failed);
+            }
+        }
+        Some(SubCommand::SelfTest(args)) => {
+            scan::init()?;
+            selftest::run(config_path, &args)?;
+            println!("{}", "Self-test passed".green().bold());
+        }
+        Some(SubCommand::VerifySignatures(args)) => {
+            let config = config::load(config_path, None).context("Failed to load config")?;
+            let verifications = verify::verify(&config.update, !args.no_sigtool)?;
+            if verifications.is_empty() {
+                bail!("No signature databases found in {:?}", config.update.path);
+            }
+            let mut tampered = 0;
+            for v in &verifications {
+                print_line(
+                    &format!(
+                        "{}: md5 {}, signature {}",
+                        v.path.display(),
+                        v.md5,
+                        v.signature
+                    ),
+                    !v.is_tampered(),
+                );
+                if v.is_tampered() {
+                    tampered += 1;
+                }
+            }
+            if tampered > 0 {
+                bail!("Found {} tampered database(s)", tampered);
+            }
+        }
+        Some(SubCommand::Notify(args)) => notify::send(&args.summary, &args.body)?,
+        Some(SubCommand::DumpConfig(args)) => {
+            if args.origin {
+                for (key, value, origin) in config::origins(config_path)? {
+                    println!("{} = {} {}", key, value, format!("# {}", origin).dimmed());
+                }
+            } else {
+                let config = config::load(config_path, None).context("Failed to load config")?;
+                serde_json::to_writer_pretty(std::io::stdout(), &config)?;
+                println!();
+            }
+        }
+        Some(SubCommand::Config(args::Config::Check)) => {
+            let issues = config::check(config_path)?;
+            for issue in &issues {
+                println!("{}: {}", issue.key.yellow().bold(), issue.message);
+            }
+            if !issues.is_empty() {
+                bail!("Found {} problem(s) in config", issues.len());
+            }
+            println!("{}", "Config is valid".green().bold());
+        }
+        Some(SubCommand::Exclude(args::Exclude::Add { pattern })) => {
+            exclude::add(config_path, &pattern)?;
+        }
+        Some(SubCommand::Exclude(args::Exclude::List)) => {
+            for pattern in exclude::list(config_path)? {
+                println!("{}", pattern);
+            }
+        }
+        Some(SubCommand::Exclude(args::Exclude::Remove { pattern })) => {
+            exclude::remove(config_path, &pattern)?;
+        }
+        Some(SubCommand::ExplainPath(args)) => {
+            let scan_args = args::Scan {
+                profile: args.profile,
+                ..Default::default()
+            };
+            let config =
+                config::load(config_path, Some(&scan_args)).context("Failed to load config")?;
+            let roots = scan::resolve_paths(&config.scan, Vec::new())?;
+            let findings = explain::explain(&config.scan, &roots, &args.path)?;
+            if findings.is_empty() {
+                println!("{} {}", "scan".green(), args.path.display());
+            }
+            for finding in findings {
+                println!(
+                    "{} {} {}",
+                    "skip".yellow(),
+                    finding.path.display(),
+                    format!("({})", finding.reason).dimmed()
+                );
+            }
+        }
+        Some(SubCommand::Ctl(args::Ctl::Status)) => {
+            let status = statusfile::read(&statusfile::path())?;
+            let pid = pidfile::running(&pidfile::scan_path());
+            print_live_status(status.as_ref(), pid);
+        }
+        Some(SubCommand::Quarantine(args)) => match args {
+            args::Quarantine::List => {
+                let mut entries = Quarantine::list()?;
+                entries.sort_by_key(|entry| entry.quarantined_at);
+                for entry in entries {
+                    println!(
+                        "{} {} {:?} {}",
+                        entry.id.bold(),
+                        entry
+                            .quarantined_at
+                            .with_timezone(&Local)
+                            .format("%Y-%m-%d %H:%M"),
+                        entry.original_path,
+                        entry.names.join(", ").red()
+                    );
+                }
+            }
+            args::Quarantine::Inspect { id } => {
+                let quarantine = Quarantine::open()?;
+                let (path, entry) = quarantine.get(&id)?;
+                println!("Original path             {:?}", entry.original_path);
+                println!("Threats                   {}", entry.names.join(", ").red());
+                println!(
+                    "Quarantined               {}",
+                    format_datetime(&Some(entry.quarantined_at))
+                );
+                match quarantine::open_container(&path)? {
+                    Some((header, _)) => {
+                        println!("Sha256                    {}", header.sha256);
+                        println!("Size                      {} bytes", header.size);
+                        println!("Mode                      {:o}", header.mode);
+                        println!(
+                            "Modified                  {}",
+                            format_datetime(&header.modified)
+                        );
+                    }
+                    None => println!(
+                        "{}",
+                        "Quarantined by an older version, the file is stored as it is".yellow()
+                    ),
+                }
+            }
+            args::Quarantine::Restore { id, to } => {
+                let quarantine = Quarantine::open()?;
+                let (dest, entry) = quarantine.restore(&id, to.as_deref())?;
+                let audit_entry =
+                    audit::Entry::new(Action::Restored, &dest, &entry.names).detail(id);
+                if let Err(err) = audit::record(&audit_entry) {
+                    warn!("Failed to write audit log: {:#}", err);
+                }
+                println!("Restored {:?}", dest);
+            }
+            args::Quarantine::Prune {
+                retention_days,
+                max_size,
+            } => {
+                let mut config =
+                    config::load(config_path, None).context("Failed to load config")?;
+                if retention_days.is_some() {
+                    config.quarantine.retention_days = retention_days;
+                }
+                if max_size.is_some() {
+                    config.quarantine.max_size = max_size;
+                }
+                schedule::prune_quarantine(&config);
+            }
+        },
+        Some(SubCommand::Cache(args)) => {
+            let config = config::load(config_path, None).context("Failed to load config")?;
+            let signatures = cache::signature_version(&config)?;
+            let path = cache::path()?;
+            match args {
+                args::Cache::Export { path: export } => {
+                    let cache = CleanCache::load(&path, signatures)?;
+                    cache.store(&export)?;
+                    println!(
+                        "Exported {} known clean file(s) for signatures {}",
+                        cache.len(),
+                        cache.signatures()
+                    );
+                }
+                args::Cache::Import { path: import } => {
+                    let cache = CleanCache::load(&path, signatures)?;
+                    let added = cache.import(&import)?;
+                    cache.store(&path)?;
+                    println!(
+                        "Imported {} new clean file(s), {} in total",
+                        added,
+                        cache.len()
+                    );
+                }
+                args::Cache::Clear => {
+                    CleanCache::new(signatures).store(&path)?;
+                    println!("Cleared the clean cache");
+                }
+            }
         }
         Some(SubCommand::Completions(args)) => args.gen_completions()?,
+        Some(SubCommand::Manpages(args)) => args.gen_manpages()?,
+        Some(SubCommand::Version) if args.verbose == 0 => {
+            println!("libredefender {}", env!("CARGO_PKG_VERSION"));
+        }
+        Some(SubCommand::Version) => {
+            let config = match config::load(config_path, None) {
+                Ok(config) => Some(config),
+                Err(err) => {
+                    warn!("Failed to load config: {:#}", err);
+                    None
+                }
+            };
+            for (key, value) in version::verbose(config.as_ref()) {
+                println!("{:<18} {}", format!("{}:", key), value);
+            }
+        }
     }
 
+    // notifications about detections and scan reports are sent in the background
+    push::wait();
+    reporting::wait();
     Ok(())
 }
@@ -0,0 +1,187 @@
+use crate::db::{Data, ErrorCategory};
+use crate::errors::*;
+use crate::severity::Severity;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tempfile::NamedTempFile;
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, f64)]) {
+    writeln!(out, "# HELP libredefender_{} {}", name, help).ok();
+    writeln!(out, "# TYPE libredefender_{} {}", name, kind).ok();
+    for (labels, value) in samples {
+        writeln!(out, "libredefender_{}{} {}", name, labels, value).ok();
+    }
+}
+
+/// The current status in the prometheus text format
+#[must_use]
+pub fn render(data: &Data) -> String {
+    let mut out = String::new();
+
+    // every severity is listed, even if there are no threats with it
+    let labels = [
+        Severity::Low,
+        Severity::Medium,
+        Severity::High,
+        Severity::Critical,
+    ]
+    .map(|severity| {
+        let count = data
+            .threats
+            .values()
+            .filter(|threat| threat.severity == severity)
+            .count();
+        (format!("{{severity=\"{}\"}}", severity), count as f64)
+    });
+    let samples = labels
+        .iter()
+        .map(|(labels, count)| (labels.as_str(), *count))
+        .collect::<Vec<_>>();
+    metric(
+        &mut out,
+        "threats",
+        "gauge",
+        "Threats found and not resolved yet",
+        &samples,
+    );
+
+    metric(
+        &mut out,
+        "skipped_files",
+        "gauge",
+        "Files that could not be scanned",
+        &[("", data.skipped.len() as f64)],
+    );
+    metric(
+        &mut out,
+        "signatures",
+        "gauge",
+        "Signatures loaded during the last scan",
+        &[("", data.signature_count as f64)],
+    );
+    if let Some(age) = data.signatures_age {
+        metric(
+            &mut out,
+            "signatures_updated_timestamp_seconds",
+            "gauge",
+            "When the signatures were built",
+            &[("", age.timestamp() as f64)],
+        );
+    }
+    if let Some(last_scan) = data.last_scan {
+        metric(
+            &mut out,
+            "last_scan_timestamp_seconds",
+            "gauge",
+            "When the last complete scan finished",
+            &[("", last_scan.timestamp() as f64)],
+        );
+    }
+    if let Some(stats) = &data.last_scan_stats {
+        metric(
+            &mut out,
+            "last_scan_files",
+            "gauge",
+            "Files scanned by the last complete scan",
+            &[("", stats.files_scanned as f64)],
+        );
+        metric(
+            &mut out,
+            "last_scan_bytes",
+            "gauge",
+            "Bytes scanned by the last complete scan",
+            &[("", stats.bytes_scanned as f64)],
+        );
+        metric(
+            &mut out,
+            "last_scan_duration_seconds",
+            "gauge",
+            "How long the last complete scan took",
+            &[("", stats.duration_secs)],
+        );
+        metric(
+            &mut out,
+            "last_scan_errors",
+            "gauge",
+            "Files that failed to scan during the last complete scan",
+            &[("", stats.errors as f64)],
+        );
+        let labels = ErrorCategory::ALL.map(|category| {
+            let count = stats.error_categories.get(&category).copied().unwrap_or(0);
+            (format!("{{category=\"{}\"}}", category), count as f64)
+        });
+        let samples = labels
+            .iter()
+            .map(|(labels, count)| (labels.as_str(), *count))
+            .collect::<Vec<_>>();
+        metric(
+            &mut out,
+            "last_scan_errors_by_category",
+            "gauge",
+            "Errors of the last complete scan by category",
+            &samples,
+        );
+    }
+    out
+}
+
+/// Replace the file atomically, node_exporter must never read a partially written file
+pub fn write_textfile(path: &Path, data: &Data) -> Result<()> {
+    let dir = path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let mut tmp = NamedTempFile::new_in(dir)
+        .with_context(|| anyhow!("Failed to create temporary file in {:?}", dir))?;
+    tmp.write_all(render(data).as_bytes())?;
+    // the tempfile is only readable by the owner, node_exporter usually runs as its own user
+    fs::set_permissions(tmp.path(), fs::Permissions::from_mode(0o644))?;
+    tmp.persist(path)
+        .with_context(|| anyhow!("Failed to write metrics to {:?}", path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ScanStats, Threat};
+    use chrono::{TimeZone, Utc};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_render() {
+        let mut threat = Threat::new(Utc::now());
+        threat.add_name("Win.Test.EICAR_HDB-1".to_string());
+        let mut data = Data {
+            last_scan: Some(Utc.timestamp_opt(1_700_000_000, 0).unwrap()),
+            signature_count: 1337,
+            last_scan_stats: Some(ScanStats {
+                files_scanned: 42,
+                bytes_scanned: 4096,
+                duration_secs: 1.5,
+                errors: 3,
+                error_categories: [(ErrorCategory::Permission, 3)].into(),
+            }),
+            ..Default::default()
+        };
+        data.threats.insert(PathBuf::from("/a"), threat);
+
+        let txt = render(&data);
+        assert!(txt.contains("# TYPE libredefender_threats gauge\n"));
+        assert!(txt.contains("libredefender_threats{severity=\"low\"} 1\n"));
+        assert!(txt.contains("libredefender_threats{severity=\"critical\"} 0\n"));
+        assert!(txt.contains("libredefender_signatures 1337\n"));
+        assert!(txt.contains("libredefender_last_scan_timestamp_seconds 1700000000\n"));
+        assert!(txt.contains("libredefender_last_scan_duration_seconds 1.5\n"));
+        assert!(
+            txt.contains("libredefender_last_scan_errors_by_category{category=\"permission\"} 3\n")
+        );
+        assert!(
+            txt.contains("libredefender_last_scan_errors_by_category{category=\"timeout\"} 0\n")
+        );
+        assert!(!txt.contains("signatures_updated_timestamp_seconds"));
+    }
+}
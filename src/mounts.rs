@@ -0,0 +1,120 @@
+use crate::errors::*;
+use std::fs;
+use std::path::PathBuf;
+
+pub const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mount {
+    pub major: u32,
+    pub minor: u32,
+    pub mountpoint: PathBuf,
+    pub fstype: String,
+}
+
+impl Mount {
+    /// Userspace filesystems like sshfs or gvfs, this excludes fuseblk for local disks
+    #[must_use]
+    pub fn is_fuse(&self) -> bool {
+        self.fstype == "fuse" || self.fstype.starts_with("fuse.")
+    }
+}
+
+/// Undo the octal escaping the kernel applies to spaces, tabs, newlines and backslashes
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let code = chars.by_ref().take(3).collect::<String>();
+            if let Some(c) = u8::from_str_radix(&code, 8).ok().map(char::from) {
+                out.push(c);
+            } else {
+                out.push('\\');
+                out.push_str(&code);
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn parse_mountinfo_line(line: &str) -> Option<Mount> {
+    let mut fields = line.split(' ');
+    let dev = fields.nth(2)?;
+    let mountpoint = fields.nth(1)?;
+    // the optional fields are terminated by a single hyphen
+    let fstype = fields.skip_while(|f| *f != "-").nth(1)?;
+
+    let (major, minor) = dev.split_once(':')?;
+    Some(Mount {
+        major: major.parse().ok()?,
+        minor: minor.parse().ok()?,
+        mountpoint: PathBuf::from(unescape(mountpoint)),
+        fstype: fstype.to_string(),
+    })
+}
+
+pub fn mounts() -> Result<Vec<Mount>> {
+    // other platforms don't have mountinfo, mount based features are skipped there
+    if !cfg!(target_os = "linux") {
+        return Ok(Vec::new());
+    }
+    let buf = fs::read_to_string(MOUNTINFO).context("Failed to read mountinfo")?;
+    let mounts = buf.lines().filter_map(parse_mountinfo_line).collect();
+    Ok(mounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mountinfo() {
+        let mount = parse_mountinfo_line(
+            "36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue",
+        )
+        .unwrap();
+        assert_eq!(
+            mount,
+            Mount {
+                major: 98,
+                minor: 0,
+                mountpoint: PathBuf::from("/mnt2"),
+                fstype: "ext3".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_mountinfo_escaped() {
+        let mount = parse_mountinfo_line("531 29 8:17 / /run/media/user/USB\\040STICK rw,nosuid,nodev,relatime shared:283 - vfat /dev/sdb1 rw").unwrap();
+        assert_eq!(
+            mount,
+            Mount {
+                major: 8,
+                minor: 17,
+                mountpoint: PathBuf::from("/run/media/user/USB STICK"),
+                fstype: "vfat".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_mountinfo_invalid() {
+        assert_eq!(parse_mountinfo_line("36 35"), None);
+        assert_eq!(parse_mountinfo_line("36 35 x / /mnt rw"), None);
+    }
+
+    #[test]
+    fn test_is_fuse() {
+        let mount = parse_mountinfo_line("98 29 0:52 / /run/user/1000/gvfs rw,nosuid,nodev,relatime shared:50 - fuse.gvfsd-fuse gvfsd-fuse rw,user_id=1000,group_id=1000").unwrap();
+        assert!(mount.is_fuse());
+        let mount = parse_mountinfo_line(
+            "99 29 8:3 / /mnt/windows rw,relatime shared:51 - fuseblk /dev/sda3 rw,user_id=0",
+        )
+        .unwrap();
+        assert!(!mount.is_fuse());
+    }
+}
@@ -0,0 +1,247 @@
+use crate::args;
+use crate::config::{Config, HumanSize, ScheduleConfig};
+use crate::errors::*;
+use crate::scan::Control;
+use crate::utils;
+use std::env;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// Login shells of system accounts
+const NO_LOGIN_SHELLS: &[&str] = &["nologin", "false", "sync", "halt", "shutdown"];
+/// Users above this are usually `nobody` or allocated to containers
+const MAX_UID: u32 = 60000;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HumanUser {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+}
+
+/// Parse /etc/passwd and keep the accounts that can log in and have a uid of at least `min_uid`
+#[must_use]
+pub fn parse_passwd(passwd: &str, min_uid: u32) -> Vec<HumanUser> {
+    let mut users = Vec::new();
+    for line in passwd.lines() {
+        let fields = line.split(':').collect::<Vec<_>>();
+        let [name, _, uid, gid, _, home, shell] = fields[..] else {
+            continue;
+        };
+        let (Ok(uid), Ok(gid)) = (uid.parse::<u32>(), gid.parse::<u32>()) else {
+            continue;
+        };
+        if uid < min_uid || uid > MAX_UID {
+            continue;
+        }
+        let shell = Path::new(shell).file_name().and_then(|s| s.to_str());
+        if shell.is_none_or(|shell| NO_LOGIN_SHELLS.contains(&shell)) {
+            continue;
+        }
+        users.push(HumanUser {
+            name: name.to_string(),
+            uid,
+            gid,
+            home: PathBuf::from(home),
+        });
+    }
+    users
+}
+
+pub fn human_users(min_uid: u32) -> Result<Vec<HumanUser>> {
+    let passwd = fs::read_to_string("/etc/passwd").context("Failed to read /etc/passwd")?;
+    let users = parse_passwd(&passwd, min_uid)
+        .into_iter()
+        .filter(|user| user.home.is_dir())
+        .collect();
+    Ok(users)
+}
+
+/// Run libredefender with the privileges and environment of the user
+///
+/// The results are written to the database in their home directory and notifications
+/// are shown on their session bus, like a command they started themselves.
+fn user_command(user: &HumanUser) -> Result<Command> {
+    let exe = env::current_exe().context("Failed to find libredefender executable")?;
+    let mut cmd = Command::new(exe);
+    cmd.env_clear()
+        .env("HOME", &user.home)
+        .env("USER", &user.name)
+        .env("LOGNAME", &user.name)
+        .env("PATH", "/usr/local/bin:/usr/bin:/bin");
+    if let Ok(filter) = env::var("RUST_LOG") {
+        cmd.env("RUST_LOG", filter);
+    }
+    let runtime_dir = PathBuf::from(format!("/run/user/{}", user.uid));
+    if runtime_dir.is_dir() {
+        let bus = runtime_dir.join("bus");
+        if bus.exists() {
+            cmd.env(
+                "DBUS_SESSION_BUS_ADDRESS",
+                format!("unix:path={}", bus.display()),
+            );
+        }
+        cmd.env("XDG_RUNTIME_DIR", runtime_dir);
+    }
+
+    // std clears the supplementary groups when switching from root
+    cmd.uid(user.uid)
+        .gid(user.gid)
+        .current_dir(&user.home)
+        .stdin(Stdio::null());
+    Ok(cmd)
+}
+
+/// Start `libredefender scan` for the home directory of the user
+fn spawn_scan(user: &HumanUser, args: &args::Scan) -> Result<Child> {
+    let mut cmd = user_command(user)?;
+    cmd.arg("scan");
+    if let Some(profile) = &args.profile {
+        cmd.arg("--profile").arg(profile);
+    }
+    if args.diff {
+        cmd.arg("--diff");
+    }
+    cmd.arg("--").arg(&user.home);
+    let child = cmd
+        .spawn()
+        .with_context(|| anyhow!("Failed to start scan for {:?}", user.name))?;
+    Ok(child)
+}
+
+/// Enforce our quarantine limits on the quarantine of every human user, as the user
+pub fn prune_users(config: &Config) -> Result<()> {
+    let retention_days = config.quarantine.retention_days;
+    let max_size = config.quarantine.max_size.as_ref().map(HumanSize::as_bytes);
+    if retention_days.is_none() && max_size.is_none() {
+        return Ok(());
+    }
+    if !utils::is_root() {
+        bail!("Pruning the quarantine of all users needs to run as root");
+    }
+    for user in human_users(config.schedule.min_uid())? {
+        let mut cmd = user_command(&user)?;
+        cmd.args(["quarantine", "prune"]);
+        if let Some(days) = retention_days {
+            cmd.arg("--retention-days").arg(days.to_string());
+        }
+        if let Some(max_size) = max_size {
+            cmd.arg("--max-size").arg(format!("{} B", max_size));
+        }
+        match cmd.status() {
+            Ok(status) if status.success() => (),
+            Ok(status) => warn!(
+                "Pruning the quarantine of {:?} exited with {}",
+                user.name, status
+            ),
+            Err(err) => warn!(
+                "Failed to prune the quarantine of {:?}: {:#}",
+                user.name, err
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn signal(child: &Child, sig: libc::c_int) {
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, sig);
+    }
+}
+
+/// Wait for the scan of one user, forwarding pause and cancel requests to the process
+fn wait(mut child: Child, control: &Control) -> Result<bool> {
+    let mut stopped = false;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                bail!("Scan exited with {}", status);
+            }
+            return Ok(true);
+        }
+        if control.cancellation_token().is_cancelled() {
+            // a stopped process only handles the signal after it was continued
+            if stopped {
+                signal(&child, libc::SIGCONT);
+            }
+            signal(&child, libc::SIGTERM);
+            child.wait()?;
+            return Ok(false);
+        }
+        match (control.is_paused(), stopped) {
+            (true, false) => signal(&child, libc::SIGSTOP),
+            (false, true) => signal(&child, libc::SIGCONT),
+            _ => (),
+        }
+        stopped = control.is_paused();
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Scan the home directory of every human user with their own privileges, one after another,
+/// returns `false` if it was cancelled before all users were scanned
+pub fn scan_users(config: &ScheduleConfig, args: &args::Scan, control: &Control) -> Result<bool> {
+    if !utils::is_root() {
+        bail!("Scanning all users needs to run as root");
+    }
+    let users = human_users(config.min_uid())?;
+    info!("Scanning the home directories of {} user(s)", users.len());
+    control.run_while(|| {
+        for user in &users {
+            if control.cancellation_token().is_cancelled() {
+                return Ok(false);
+            }
+            info!("Scanning {:?} as {:?}", user.home, user.name);
+            let ret = spawn_scan(user, args).and_then(|child| wait(child, control));
+            match ret {
+                Ok(true) => info!("Finished scan of {:?}", user.name),
+                Ok(false) => {
+                    info!("Scan of {:?} was cancelled", user.name);
+                    return Ok(false);
+                }
+                Err(err) => error!("Scan of {:?} failed: {:#}", user.name, err),
+            }
+        }
+        Ok(true)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_passwd() {
+        let passwd = "root:x:0:0::/root:/bin/bash
+bin:x:1:1::/:/usr/bin/nologin
+alice:x:1000:1000:Alice:/home/alice:/bin/zsh
+bob:x:1001:100::/home/bob:/bin/bash
+build:x:1002:1002::/var/lib/build:/usr/sbin/nologin
+nobody:x:65534:65534:Nobody:/:/usr/bin/nologin
+broken line
+";
+        assert_eq!(
+            parse_passwd(passwd, 1000),
+            vec![
+                HumanUser {
+                    name: "alice".to_string(),
+                    uid: 1000,
+                    gid: 1000,
+                    home: PathBuf::from("/home/alice"),
+                },
+                HumanUser {
+                    name: "bob".to_string(),
+                    uid: 1001,
+                    gid: 100,
+                    home: PathBuf::from("/home/bob"),
+                },
+            ]
+        );
+    }
+}
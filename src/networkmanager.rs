@@ -0,0 +1,61 @@
+use crate::errors::*;
+use zbus::blocking::{Connection, Proxy};
+
+const DESTINATION: &str = "org.freedesktop.NetworkManager";
+const PATH: &str = "/org/freedesktop/NetworkManager";
+const INTERFACE: &str = "org.freedesktop.NetworkManager";
+
+/// `NMMetered`, NetworkManager guesses e.g. for mobile broadband and phone hotspots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metered {
+    Unknown,
+    Yes,
+    No,
+    GuessYes,
+    GuessNo,
+}
+
+impl Metered {
+    #[must_use]
+    pub fn from_u32(value: u32) -> Metered {
+        match value {
+            1 => Metered::Yes,
+            2 => Metered::No,
+            3 => Metered::GuessYes,
+            4 => Metered::GuessNo,
+            _ => Metered::Unknown,
+        }
+    }
+
+    #[must_use]
+    pub fn is_metered(self) -> bool {
+        matches!(self, Metered::Yes | Metered::GuessYes)
+    }
+}
+
+/// The metered state of the primary connection
+pub fn metered() -> Result<Metered> {
+    let conn = Connection::system().context("Failed to connect to system bus")?;
+    let proxy = Proxy::new(&conn, DESTINATION, PATH, INTERFACE)
+        .context("Failed to create proxy for NetworkManager")?;
+    let metered = proxy
+        .get_property::<u32>("Metered")
+        .context("Failed to read metered state from NetworkManager")?;
+    Ok(Metered::from_u32(metered))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metered() {
+        assert!(Metered::from_u32(1).is_metered());
+        assert!(Metered::from_u32(3).is_metered());
+        assert!(!Metered::from_u32(2).is_metered());
+        assert!(!Metered::from_u32(4).is_metered());
+        // no NetworkManager connection or an unknown value
+        assert!(!Metered::from_u32(0).is_metered());
+        assert!(!Metered::from_u32(23).is_metered());
+    }
+}
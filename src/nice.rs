@@ -1,29 +1,171 @@
+use crate::args;
+use crate::config::{self, IoniceClass, PathOverride, ScanConfig};
 use crate::errors::*;
-use ioprio::Pid;
+use std::path::Path;
 
-pub fn setup() -> Result<()> {
-    if let Err(err) = ionice() {
+const DEFAULT_NICE_LEVEL: i32 = 19;
+/// The number of cores that fit into a `cpu_set_t`, higher cores can't be pinned to
+pub const MAX_CPUS: usize = 1024;
+
+/// Load the priority settings from the config, using the defaults if it can't be loaded
+pub fn setup_from_config(config_path: Option<&Path>, args: Option<&args::Scan>) -> Result<()> {
+    match config::load(config_path, args) {
+        Ok(config) => setup(&config.scan),
+        Err(err) => {
+            warn!("Failed to load config, using default priority: {:#}", err);
+            setup(&ScanConfig::default())
+        }
+    }
+}
+
+pub fn setup(config: &ScanConfig) -> Result<()> {
+    if let Err(err) = ionice(config.ionice_class, config.ionice_level) {
         warn!("{}", err);
     }
-    if let Err(err) = nice() {
+    if let Err(err) = nice(config.nice_level.unwrap_or(DEFAULT_NICE_LEVEL)) {
         warn!("{}", err);
     }
     Ok(())
 }
 
-pub fn nice() -> Result<()> {
-    debug!("Calling nice(2) for idle priority");
-    let err = unsafe { libc::nice(19) };
+/// Apply the io priority and cpu affinity to the current scan worker thread
+///
+/// Some kernels only honor the io priority of the thread, not the one of the process group.
+/// Workers of a `path_overrides` pool use the priority of the override where it is set.
+pub fn setup_worker(
+    config: &ScanConfig,
+    path_override: Option<&PathOverride>,
+    lower_priority: bool,
+) {
+    let path_override = path_override.cloned().unwrap_or_default();
+    let class = path_override.ionice_class.unwrap_or(config.ionice_class);
+    let level = path_override.ionice_level.or(config.ionice_level);
+    if lower_priority && class != IoniceClass::None {
+        if let Err(err) = platform::ionice_thread(class, level) {
+            debug!("Failed to ionice scan worker: {:#}", err);
+        }
+    }
+    if let Some(level) = path_override.nice_level.filter(|_| lower_priority) {
+        if let Err(err) = platform::nice_thread(level) {
+            warn!("{:#}", err);
+        }
+    }
+    if let Some(cpuset) = &config.cpuset {
+        if let Err(err) = platform::pin_thread(cpuset) {
+            warn!("{:#}", err);
+        }
+    }
+}
+
+pub fn nice(level: i32) -> Result<()> {
+    debug!("Calling setpriority(2) for nice level {}", level);
+    let err = unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, level) };
     if err == -1 {
         bail!("Failed to set process priority");
     }
     Ok(())
 }
 
-pub fn ionice() -> Result<()> {
-    let target = ioprio::Target::ProcessGroup(Pid::from_raw(0));
-    let priority = ioprio::Priority::new(ioprio::Class::Idle);
-    debug!("Calling ioprio_set for idle priority");
-    ioprio::set_priority(target, priority).context("Failed to ionice process group")?;
-    Ok(())
+pub fn ionice(class: IoniceClass, level: Option<u8>) -> Result<()> {
+    if class == IoniceClass::None {
+        return Ok(());
+    }
+    debug!("Setting io priority to {:?}", class);
+    platform::ionice(class, level)
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use crate::config::IoniceClass;
+    use crate::errors::*;
+    use ioprio::Pid;
+    use std::io;
+    use std::mem;
+
+    pub fn ionice(class: IoniceClass, level: Option<u8>) -> Result<()> {
+        let target = ioprio::Target::ProcessGroup(Pid::from_raw(0));
+        set_priority(target, class, level).context("Failed to ionice process group")
+    }
+
+    /// ioprio_set treats a thread id as a single thread
+    pub fn ionice_thread(class: IoniceClass, level: Option<u8>) -> Result<()> {
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) } as i32;
+        let target = ioprio::Target::Process(Pid::from_raw(tid));
+        set_priority(target, class, level).context("Failed to ionice thread")
+    }
+
+    fn set_priority(target: ioprio::Target, class: IoniceClass, level: Option<u8>) -> Result<()> {
+        let class = match class {
+            IoniceClass::Idle => ioprio::Class::Idle,
+            IoniceClass::BestEffort => {
+                let level = level.unwrap_or(7);
+                let level = ioprio::BePriorityLevel::from_level(level)
+                    .with_context(|| anyhow!("Invalid best-effort ionice level: {}", level))?;
+                ioprio::Class::BestEffort(level)
+            }
+            IoniceClass::None => return Ok(()),
+        };
+        let priority = ioprio::Priority::new(class);
+        match ioprio::set_priority(target, priority) {
+            Ok(()) => Ok(()),
+            // seccomp filters in containers commonly deny this syscall
+            Err(err) if matches!(err.raw_os_error(), Some(libc::ENOSYS | libc::EPERM)) => {
+                bail!("ioprio_set is not available, continuing with cpu priority only")
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// On linux the nice level is a property of the thread, not of the whole process
+    pub fn nice_thread(level: i32) -> Result<()> {
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) } as libc::id_t;
+        let ret = unsafe { libc::setpriority(libc::PRIO_PROCESS as _, tid, level) };
+        if ret == -1 {
+            return Err(io::Error::last_os_error())
+                .with_context(|| anyhow!("Failed to set nice level {} of scan worker", level));
+        }
+        Ok(())
+    }
+
+    pub fn pin_thread(cpus: &[usize]) -> Result<()> {
+        if let Some(cpu) = cpus.iter().find(|cpu| **cpu >= super::MAX_CPUS) {
+            bail!(
+                "Failed to pin scan worker to core {}, only cores below {} are supported",
+                cpu,
+                super::MAX_CPUS
+            );
+        }
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        for cpu in cpus {
+            unsafe { libc::CPU_SET(*cpu, &mut set) };
+        }
+        let ret = unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error())
+                .with_context(|| anyhow!("Failed to pin scan worker to cores {:?}", cpus));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use crate::config::IoniceClass;
+    use crate::errors::*;
+
+    pub fn ionice(_class: IoniceClass, _level: Option<u8>) -> Result<()> {
+        bail!("Setting the io priority is not supported on this platform, continuing with cpu priority only")
+    }
+
+    pub fn ionice_thread(_class: IoniceClass, _level: Option<u8>) -> Result<()> {
+        bail!("Setting the io priority is not supported on this platform")
+    }
+
+    pub fn nice_thread(_level: i32) -> Result<()> {
+        bail!("Setting the priority of scan workers is only supported on linux")
+    }
+
+    pub fn pin_thread(_cpus: &[usize]) -> Result<()> {
+        bail!("Pinning scan workers to cpu cores is only supported on linux")
+    }
 }
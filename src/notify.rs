@@ -1,14 +1,126 @@
+use crate::args::{self, NotifyBackend};
+use crate::config::NotifyConfig;
+use crate::describe::{Description, Descriptions};
 use crate::errors::*;
+use crate::logind::Logind;
+use crate::push;
+use crate::severity;
+use crate::utils;
+use clap::ValueEnum;
 use notify_rust::{Hint, Notification, Timeout, Urgency};
+use std::collections::HashSet;
+use std::env;
+use std::os::unix::process::CommandExt;
 use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
 use v_htmlescape::escape;
 
-pub fn show(path: &Path, detected_as: &str) -> Result<()> {
+const SKIP_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn threat_message(
+    path: &Path,
+    detected_as: &str,
+    description: Option<&Description>,
+) -> (String, String) {
     let title = format!("Infection found: {:?}", detected_as);
-    let body = format!("libredefender found an infected file:\n{:?}\nRun `libredefender infections -h` to take action.", path);
+    let mut body = format!("libredefender found an infected file:\n{:?}\n", path);
+    if let Some(description) = description {
+        body.push_str(&format!("{}\n", description));
+    }
+    body.push_str("Run `libredefender infections -h` to take action.");
+    (title, body)
+}
+
+pub fn show(path: &Path, detected_as: &str, description: Option<&Description>) -> Result<()> {
+    let (title, body) = threat_message(path, detected_as, description);
+    alert(&title, &body)
+}
+
+/// `test-notify`, deliver a notification with every selected backend and return the result of each
+pub fn test(
+    config: &NotifyConfig,
+    descriptions: &Descriptions,
+    args: &args::TestNotify,
+) -> Vec<(&'static str, Result<()>)> {
+    // preview a real alert unless the content was provided
+    let name = "Win.Test.EICAR_HDB-1";
+    let (title, body) = threat_message(
+        Path::new("/just/a/test"),
+        name,
+        descriptions.describe(name).as_ref(),
+    );
+    let title = args.summary.as_deref().unwrap_or(&title);
+    let body = args.body.as_deref().unwrap_or(&body);
+
+    let mut selected = args.backends.clone();
+    if selected.is_empty() {
+        selected.push(NotifyBackend::Dbus);
+        selected.extend(
+            push::backends(&config.push)
+                .iter()
+                .filter_map(|backend| NotifyBackend::from_str(backend.name(), true).ok()),
+        );
+    }
+
+    let push_backends = push::backends(&config.push);
+    let mut results = Vec::new();
+    for backend in selected {
+        let result = match backend {
+            NotifyBackend::Dbus => alert(title, body),
+            NotifyBackend::Stdout => {
+                println!("{}\n{}", title, body);
+                Ok(())
+            }
+            _ => match push_backends.iter().find(|b| b.name() == backend.name()) {
+                Some(push) => push::send_with(&config.push, *push, title, body, true),
+                None => Err(anyhow!(
+                    "Not configured, add a [notify.push.{}] section to the config",
+                    backend.name()
+                )),
+            },
+        };
+        results.push((backend.name(), result));
+    }
+    results
+}
+
+/// Show a notification for a detection if it's severe enough, otherwise only log it
+pub fn threat(
+    config: &NotifyConfig,
+    descriptions: &Descriptions,
+    path: &Path,
+    detected_as: &str,
+) -> Result<()> {
+    let severity = severity::classify(detected_as);
+    if severity < config.min_severity {
+        info!(
+            "Not showing notification for {:?} at {:?}, severity {} is below {}",
+            detected_as, path, severity, config.min_severity
+        );
+        return Ok(());
+    }
+    let description = descriptions.describe(detected_as);
+    let (title, body) = threat_message(path, detected_as, description.as_ref());
+    push::send(&config.push, &title, &body, true);
+    alert(&title, &body)
+}
+
+/// Show a notification to the user, or to all logged in users if running as root
+pub fn alert(summary: &str, body: &str) -> Result<()> {
+    if utils::is_root() {
+        send_to_sessions(summary, body)
+    } else {
+        send(summary, body)
+    }
+}
+
+/// Display a notification on the session bus of the current process
+pub fn send(summary: &str, body: &str) -> Result<()> {
     Notification::new()
-        .summary(&title)
-        .body(&escape(&body).to_string())
+        .summary(summary)
+        .body(&escape(body).to_string())
         .icon("libredefender")
         .urgency(Urgency::Critical)
         .hint(Hint::Resident(true)) // this is not supported by all implementations
@@ -16,3 +128,92 @@ pub fn show(path: &Path, detected_as: &str) -> Result<()> {
         .show()?;
     Ok(())
 }
+
+/// Announce the scan of newly mounted removable media, returns `true` if the user clicked skip
+#[must_use]
+pub fn ask_skip_removable(mountpoint: &Path) -> bool {
+    let summary = "Scanning removable media";
+    let body = format!("libredefender is going to scan {:?}", mountpoint);
+
+    if utils::is_root() {
+        // there's no way to receive the response from other sessions
+        if let Err(err) = send_to_sessions(summary, &body) {
+            warn!("Failed to display notification: {:#}", err);
+        }
+        return false;
+    }
+
+    let (tx, rx) = crossbeam_channel::bounded(1);
+    thread::spawn(move || {
+        let handle = Notification::new()
+            .summary(summary)
+            .body(&escape(&body).to_string())
+            .icon("libredefender")
+            .action("skip", "Skip scan")
+            .timeout(Timeout::Milliseconds(SKIP_TIMEOUT.as_millis() as u32))
+            .show();
+        match handle {
+            Ok(handle) => handle.wait_for_action(|action| {
+                tx.send(action == "skip").ok();
+            }),
+            Err(err) => warn!("Failed to display notification: {:#}", err),
+        }
+    });
+
+    // not all notification servers honor the timeout, don't wait forever
+    rx.recv_timeout(SKIP_TIMEOUT).unwrap_or(false)
+}
+
+/// root doesn't have a desktop session, deliver the notification to every
+/// user that is logged into a graphical session instead
+pub fn send_to_sessions(summary: &str, body: &str) -> Result<()> {
+    let logind = Logind::connect()?;
+
+    let mut notified = HashSet::new();
+    for session in logind.list_sessions()? {
+        if !session.is_graphical() || !session.is_active_user_session() {
+            continue;
+        }
+        // all sessions of a user share the same session bus
+        if !notified.insert(session.uid) {
+            continue;
+        }
+
+        if let Err(err) = send_as_user(&logind, session.uid, summary, body) {
+            warn!(
+                "Failed to notify session {} of user {:?}: {:#}",
+                session.id, session.user, err
+            );
+        }
+    }
+
+    if notified.is_empty() {
+        debug!("No graphical sessions found, nobody to notify");
+    }
+
+    Ok(())
+}
+
+fn send_as_user(logind: &Logind, uid: u32, summary: &str, body: &str) -> Result<()> {
+    let user = logind.user(uid)?;
+    debug!("Sending notification to user {:?}", user.name);
+
+    let exe = env::current_exe().context("Failed to locate own executable")?;
+    let status = Command::new(exe)
+        .args(["-q", "notify", "--summary", summary, "--body", body])
+        .env_clear()
+        .env(
+            "DBUS_SESSION_BUS_ADDRESS",
+            format!("unix:path={}", user.runtime_path.join("bus").display()),
+        )
+        .env("XDG_RUNTIME_DIR", &user.runtime_path)
+        .uid(user.uid)
+        .gid(user.gid)
+        .status()
+        .context("Failed to spawn notification process")?;
+
+    if !status.success() {
+        bail!("Notification process exited with {}", status);
+    }
+    Ok(())
+}
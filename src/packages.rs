@@ -0,0 +1,294 @@
+use crate::errors::*;
+use crate::utils;
+use flate2::read::GzDecoder;
+use md5::{Digest as _, Md5};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const PACMAN_DB: &str = "/var/lib/pacman/local";
+const DPKG_INFO: &str = "/var/lib/dpkg/info";
+const RPM_DB: &str = "/var/lib/rpm";
+
+/// rpm numbers its digest algorithms like the `PGPHASHALGO_*` constants
+const RPM_MD5: &str = "1";
+const RPM_SHA256: &str = "8";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Digest {
+    Md5(String),
+    Sha256(String),
+}
+
+impl Digest {
+    fn matches(&self, path: &Path) -> Result<bool> {
+        let actual = match self {
+            Digest::Md5(_) => md5_file(path)?,
+            Digest::Sha256(_) => utils::sha256_file(path)?,
+        };
+        let (Digest::Md5(expected) | Digest::Sha256(expected)) = self;
+        Ok(actual.eq_ignore_ascii_case(expected))
+    }
+}
+
+fn md5_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).with_context(|| anyhow!("Failed to open {:?}", path))?;
+    let mut hasher = Md5::new();
+    io::copy(&mut file, &mut hasher).with_context(|| anyhow!("Failed to read {:?}", path))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Not installed by a package, or the package manager doesn't record a digest for it
+    Unknown,
+    Unmodified,
+    /// Changed since it was installed, e.g. a config file or a tampered binary
+    Modified,
+}
+
+/// The digests of all files installed by the distro package manager
+#[derive(Debug, Default)]
+pub struct Manifest {
+    manager: &'static str,
+    files: HashMap<PathBuf, Digest>,
+}
+
+impl Manifest {
+    /// Read the manifests of pacman, dpkg or rpm, whichever is in use on this system
+    pub fn load() -> Result<Option<Manifest>> {
+        let (manager, files) = if Path::new(PACMAN_DB).is_dir() {
+            ("pacman", load_pacman(Path::new(PACMAN_DB))?)
+        } else if Path::new(DPKG_INFO).is_dir() {
+            ("dpkg", load_dpkg(Path::new(DPKG_INFO))?)
+        } else if Path::new(RPM_DB).is_dir() {
+            ("rpm", load_rpm()?)
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(Manifest { manager, files }))
+    }
+
+    #[must_use]
+    pub fn manager(&self) -> &'static str {
+        self.manager
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Hash the file and compare it with the digest of its package
+    pub fn verify(&self, path: &Path) -> Result<Verdict> {
+        let Some(digest) = self.files.get(path) else {
+            return Ok(Verdict::Unknown);
+        };
+        if digest.matches(path)? {
+            Ok(Verdict::Unmodified)
+        } else {
+            Ok(Verdict::Modified)
+        }
+    }
+}
+
+/// Decode the octal escapes of mtree, e.g. `\040` for a space
+fn unescape_mtree(s: &str) -> Option<PathBuf> {
+    let mut out = Vec::new();
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'\\' {
+            let digits = [bytes.next()?, bytes.next()?, bytes.next()?];
+            let digits = std::str::from_utf8(&digits).ok()?;
+            out.push(u8::from_str_radix(digits, 8).ok()?);
+        } else {
+            out.push(b);
+        }
+    }
+    Some(PathBuf::from(OsString::from_vec(out)))
+}
+
+/// The `mtree` of a pacman package, only regular files have a digest
+pub fn parse_mtree(text: &str) -> Vec<(PathBuf, Digest)> {
+    let mut files = Vec::new();
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(path) = fields.next().and_then(|path| path.strip_prefix("./")) else {
+            continue;
+        };
+        // metadata of the package itself, like .PKGINFO and .MTREE
+        if path.starts_with('.') {
+            continue;
+        }
+        let mut digest = None;
+        for field in fields {
+            if let Some(hash) = field.strip_prefix("sha256digest=") {
+                digest = Some(Digest::Sha256(hash.to_string()));
+            } else if let Some(hash) = field.strip_prefix("md5digest=") {
+                // sha256 is preferred if both are listed
+                digest = digest.or_else(|| Some(Digest::Md5(hash.to_string())));
+            }
+        }
+        if let (Some(path), Some(digest)) = (unescape_mtree(path), digest) {
+            files.push((Path::new("/").join(path), digest));
+        }
+    }
+    files
+}
+
+fn load_pacman(db: &Path) -> Result<HashMap<PathBuf, Digest>> {
+    let mut files = HashMap::new();
+    for entry in fs::read_dir(db).with_context(|| anyhow!("Failed to read {:?}", db))? {
+        let path = entry?.path().join("mtree");
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| anyhow!("Failed to open {:?}", path)),
+        };
+        let mut text = String::new();
+        GzDecoder::new(file)
+            .read_to_string(&mut text)
+            .with_context(|| anyhow!("Failed to decompress {:?}", path))?;
+        files.extend(parse_mtree(&text));
+    }
+    Ok(files)
+}
+
+/// The `*.md5sums` files of dpkg, with paths relative to `/`
+pub fn parse_md5sums(text: &str) -> Vec<(PathBuf, Digest)> {
+    text.lines()
+        .filter_map(|line| {
+            let (hash, path) = line.split_once("  ")?;
+            Some((Path::new("/").join(path), Digest::Md5(hash.to_string())))
+        })
+        .collect()
+}
+
+fn load_dpkg(info: &Path) -> Result<HashMap<PathBuf, Digest>> {
+    let mut files = HashMap::new();
+    for entry in fs::read_dir(info).with_context(|| anyhow!("Failed to read {:?}", info))? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("md5sums") {
+            continue;
+        }
+        let text =
+            fs::read_to_string(&path).with_context(|| anyhow!("Failed to read {:?}", path))?;
+        files.extend(parse_md5sums(&text));
+    }
+    Ok(files)
+}
+
+/// One file per line, formatted as `<algorithm> <digest> <path>`
+pub fn parse_rpm(text: &str) -> Vec<(PathBuf, Digest)> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let (algo, hash, path) = (fields.next()?, fields.next()?, fields.next()?);
+            // directories and symlinks don't have a digest
+            if hash.is_empty() {
+                return None;
+            }
+            let digest = match algo {
+                RPM_MD5 => Digest::Md5(hash.to_string()),
+                RPM_SHA256 => Digest::Sha256(hash.to_string()),
+                _ => return None,
+            };
+            Some((PathBuf::from(path), digest))
+        })
+        .collect()
+}
+
+fn load_rpm() -> Result<HashMap<PathBuf, Digest>> {
+    let output = Command::new("rpm")
+        .args([
+            "-qa",
+            "--qf",
+            "[%{=FILEDIGESTALGO} %{FILEDIGESTS} %{FILENAMES}\\n]",
+        ])
+        .output()
+        .context("Failed to run rpm")?;
+    if !output.status.success() {
+        bail!("rpm exited with {}", output.status);
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    Ok(parse_rpm(&text).into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mtree() {
+        let text = "#mtree
+/set type=file uid=0 gid=0 mode=644
+./.BUILDINFO time=1700000000.0 size=4896 sha256digest=2e0a
+./usr time=1700000000.0 mode=755 type=dir
+./usr/bin/ls time=1700000000.0 mode=755 size=138208 md5digest=7e5a sha256digest=4f2c
+./usr/share/doc/a\\040b time=1700000000.0 size=12 sha256digest=9d1e
+./usr/lib/libfoo.so time=1700000000.0 type=link link=libfoo.so.1
+";
+        assert_eq!(
+            parse_mtree(text),
+            vec![
+                (
+                    PathBuf::from("/usr/bin/ls"),
+                    Digest::Sha256("4f2c".to_string())
+                ),
+                (
+                    PathBuf::from("/usr/share/doc/a b"),
+                    Digest::Sha256("9d1e".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_md5sums_and_rpm() {
+        let md5sums = "3c1a0f0b4e1a1f1c1d1e1f1a1b1c1d1e  usr/bin/ls\n";
+        assert_eq!(
+            parse_md5sums(md5sums),
+            vec![(
+                PathBuf::from("/usr/bin/ls"),
+                Digest::Md5("3c1a0f0b4e1a1f1c1d1e1f1a1b1c1d1e".to_string())
+            )]
+        );
+
+        let rpm = "8  /usr/share/doc/coreutils\n8 4f2c /usr/bin/ls\n2 da39 /usr/bin/old\n";
+        assert_eq!(
+            parse_rpm(rpm),
+            vec![(
+                PathBuf::from("/usr/bin/ls"),
+                Digest::Sha256("4f2c".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ls");
+        fs::write(&path, b"hello world\n").unwrap();
+        let mut manifest = Manifest::default();
+        manifest.files.insert(
+            path.clone(),
+            Digest::Md5("6f5902ac237024bdd0c176cb93063dc4".to_string()),
+        );
+        assert_eq!(manifest.verify(&path).unwrap(), Verdict::Unmodified);
+        fs::write(&path, b"hello world!\n").unwrap();
+        assert_eq!(manifest.verify(&path).unwrap(), Verdict::Modified);
+        assert_eq!(
+            manifest.verify(&dir.path().join("other")).unwrap(),
+            Verdict::Unknown
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use crate::args;
+use crate::errors::*;
+use crate::mounts;
+use crate::patterns::Pattern;
+use crate::scan::{self, CancellationToken, Control, ScanSummary};
+use crate::udisks::Udisks;
+use crate::utils;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::ptr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Where filesystems are mounted when running as root, the paths in the database stay the same between scans
+const MOUNT_DIR: &str = "/run/libredefender/mount";
+
+/// Paths on Windows partitions that are large, locked or regenerated by the system
+const WINDOWS_EXCLUDES: &[&str] = &[
+    "pagefile.sys",
+    "hiberfil.sys",
+    "swapfile.sys",
+    "System Volume Information",
+    "Windows/WinSxS",
+    "Windows/SoftwareDistribution",
+];
+
+const WINDOWS_FSTYPES: &[&str] = &["ntfs", "ntfs3", "fuseblk"];
+
+/// Partitions of a block device like `sda` or `loop0`, or the device itself if it's not partitioned
+fn partitions(name: &str) -> Result<Vec<String>> {
+    let sys = Path::new("/sys/class/block").join(name);
+    let mut partitions = Vec::new();
+    for entry in fs::read_dir(&sys).with_context(|| anyhow!("Failed to read {:?}", sys))? {
+        let entry = entry?;
+        if entry.path().join("partition").exists() {
+            if let Some(name) = entry.file_name().to_str() {
+                partitions.push(name.to_string());
+            }
+        }
+    }
+    if partitions.is_empty() {
+        partitions.push(name.to_string());
+    }
+    partitions.sort();
+    Ok(partitions)
+}
+
+/// The `major:minor` of a block device, to find it in the mount table
+fn device_number(name: &str) -> Option<(u32, u32)> {
+    let dev = fs::read_to_string(Path::new("/sys/class/block").join(name).join("dev")).ok()?;
+    let (major, minor) = dev.trim().split_once(':')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Read-only isn't enough for journaling filesystems, mounting them would still replay the journal
+fn mount_options(fstype: Option<&str>) -> String {
+    let mut options = "ro,nosuid,nodev,noexec".to_string();
+    match fstype {
+        Some("ext3" | "ext4") => options.push_str(",noload"),
+        Some("xfs") => options.push_str(",norecovery"),
+        _ => (),
+    }
+    options
+}
+
+/// Set by SIGINT and SIGTERM, the scan is cancelled so everything is unmounted again
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn interrupted(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Cancel the scan on the first ctrl-c, a second one exits right away
+fn cancel_on_interrupt(token: CancellationToken) {
+    unsafe {
+        let mut action = mem::zeroed::<libc::sigaction>();
+        action.sa_sigaction = interrupted as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESETHAND;
+        libc::sigaction(libc::SIGINT, &action, ptr::null_mut());
+        libc::sigaction(libc::SIGTERM, &action, ptr::null_mut());
+    }
+    thread::spawn(move || {
+        while !INTERRUPTED.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+        }
+        warn!("Interrupted, stopping the scan");
+        token.cancel();
+    });
+}
+
+fn run(cmd: &mut Command) -> Result<String> {
+    let output = cmd
+        .output()
+        .with_context(|| anyhow!("Failed to run {:?}", cmd))?;
+    if !output.status.success() {
+        bail!(
+            "{:?} exited with {}: {}",
+            cmd,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+enum Backend {
+    /// Running as root, `losetup` and `mount` are used directly
+    Root,
+    /// Unprivileged, mounting is done by udisks2 after checking polkit
+    Udisks(Udisks),
+}
+
+#[derive(Debug)]
+struct Mounted {
+    name: String,
+    mountpoint: PathBuf,
+    fstype: Option<String>,
+    /// The filesystem was already mounted and is left alone afterwards
+    existing: bool,
+}
+
+/// A block device or disk image that is mounted read-only, everything is unmounted again on drop
+pub struct Attached {
+    backend: Backend,
+    loop_device: Option<String>,
+    mounted: Vec<Mounted>,
+}
+
+impl Attached {
+    pub fn attach(path: &Path) -> Result<Attached> {
+        let backend = if utils::is_root() {
+            Backend::Root
+        } else {
+            Backend::Udisks(Udisks::connect()?)
+        };
+        let mut attached = Attached {
+            backend,
+            loop_device: None,
+            mounted: Vec::new(),
+        };
+
+        let path =
+            fs::canonicalize(path).with_context(|| anyhow!("Failed to access {:?}", path))?;
+        let md = fs::metadata(&path)?;
+        let name = if md.is_file() {
+            let name = attached.loop_setup(&path)?;
+            info!("Attached {:?} to loop device {:?}", path, name);
+            attached.loop_device = Some(name.clone());
+            name
+        } else {
+            let name = path.strip_prefix("/dev").ok().and_then(|p| p.to_str());
+            name.with_context(|| anyhow!("Not a block device or image file: {:?}", path))?
+                .to_string()
+        };
+
+        let existing = mounts::mounts()?;
+        for name in partitions(&name)? {
+            let already = device_number(&name).and_then(|(major, minor)| {
+                existing
+                    .iter()
+                    .find(|m| m.major == major && m.minor == minor)
+            });
+            if let Some(mount) = already {
+                info!("{:?} is already mounted at {:?}", name, mount.mountpoint);
+                attached.mounted.push(Mounted {
+                    name,
+                    mountpoint: mount.mountpoint.clone(),
+                    fstype: Some(mount.fstype.clone()),
+                    existing: true,
+                });
+                continue;
+            }
+            // partition tables also contain swap, efi reserved and empty partitions
+            match attached.mount(&name) {
+                Ok(mountpoint) => {
+                    info!("Mounted {:?} read-only at {:?}", name, mountpoint);
+                    let fstype = mounts::mounts()?
+                        .into_iter()
+                        .find(|m| m.mountpoint == mountpoint)
+                        .map(|m| m.fstype);
+                    attached.mounted.push(Mounted {
+                        name,
+                        mountpoint,
+                        fstype,
+                        existing: false,
+                    });
+                }
+                Err(err) => warn!("Skipping {:?}: {:#}", name, err),
+            }
+        }
+
+        if attached.mounted.is_empty() {
+            bail!("Failed to mount any filesystem of {:?}", path);
+        }
+        Ok(attached)
+    }
+
+    fn loop_setup(&self, image: &Path) -> Result<String> {
+        match &self.backend {
+            Backend::Root => {
+                let device = run(Command::new("losetup")
+                    .args(["--find", "--show", "--read-only", "--partscan"])
+                    .arg(image))?;
+                let name = device
+                    .strip_prefix("/dev/")
+                    .with_context(|| anyhow!("Unexpected output from losetup: {:?}", device))?;
+                Ok(name.to_string())
+            }
+            Backend::Udisks(udisks) => udisks.loop_setup(image),
+        }
+    }
+
+    fn fstype(&self, name: &str) -> Option<String> {
+        let fstype = match &self.backend {
+            Backend::Root => run(Command::new("blkid")
+                .args(["-o", "value", "-s", "TYPE"])
+                .arg(Path::new("/dev").join(name))),
+            Backend::Udisks(udisks) => udisks.fstype(name),
+        };
+        match fstype {
+            Ok(fstype) => Some(fstype),
+            Err(err) => {
+                debug!("Failed to detect filesystem of {:?}: {:#}", name, err);
+                None
+            }
+        }
+    }
+
+    fn mount(&self, name: &str) -> Result<PathBuf> {
+        let options = mount_options(self.fstype(name).as_deref());
+        match &self.backend {
+            Backend::Root => {
+                let mountpoint = Path::new(MOUNT_DIR).join(name);
+                fs::create_dir_all(&mountpoint)
+                    .with_context(|| anyhow!("Failed to create mountpoint {:?}", mountpoint))?;
+                let ret = run(Command::new("mount")
+                    .arg("-o")
+                    .arg(&options)
+                    .arg(Path::new("/dev").join(name))
+                    .arg(&mountpoint));
+                if let Err(err) = ret {
+                    fs::remove_dir(&mountpoint).ok();
+                    return Err(err);
+                }
+                Ok(mountpoint)
+            }
+            Backend::Udisks(udisks) => udisks.mount(name, &options),
+        }
+    }
+
+    fn unmount(&self, mounted: &Mounted) -> Result<()> {
+        match &self.backend {
+            Backend::Root => {
+                run(Command::new("umount").arg(&mounted.mountpoint))?;
+                fs::remove_dir(&mounted.mountpoint).ok();
+                Ok(())
+            }
+            Backend::Udisks(udisks) => udisks.unmount(&mounted.name),
+        }
+    }
+
+    fn loop_delete(&self, name: &str) -> Result<()> {
+        match &self.backend {
+            Backend::Root => {
+                run(Command::new("losetup")
+                    .arg("-d")
+                    .arg(Path::new("/dev").join(name)))?;
+                Ok(())
+            }
+            Backend::Udisks(udisks) => udisks.loop_delete(name),
+        }
+    }
+
+    #[must_use]
+    pub fn mountpoints(&self) -> Vec<PathBuf> {
+        self.mounted.iter().map(|m| m.mountpoint.clone()).collect()
+    }
+
+    /// Skip the page file, hibernation image and system component store of Windows partitions
+    pub fn excludes(&self) -> Result<Vec<Pattern>> {
+        let mut excludes = Vec::new();
+        for mounted in &self.mounted {
+            let is_windows = mounted
+                .fstype
+                .as_deref()
+                .is_some_and(|fstype| WINDOWS_FSTYPES.contains(&fstype));
+            if !is_windows {
+                continue;
+            }
+            let mountpoint = mounted
+                .mountpoint
+                .to_str()
+                .context("Mountpoint contains invalid utf-8")?;
+            for exclude in WINDOWS_EXCLUDES {
+                let pattern = format!("{}/{}", glob::Pattern::escape(mountpoint), exclude);
+                // NTFS and FAT are case-insensitive, Windows doesn't keep the case consistent
+                excludes.push(Pattern::from_str(&pattern)?.case_insensitive(true));
+            }
+        }
+        Ok(excludes)
+    }
+}
+
+impl Drop for Attached {
+    fn drop(&mut self) {
+        for mounted in &self.mounted {
+            if mounted.existing {
+                continue;
+            }
+            match self.unmount(mounted) {
+                Ok(()) => info!("Unmounted {:?}", mounted.mountpoint),
+                Err(err) => error!("{:#}", err),
+            }
+        }
+        if let Some(name) = &self.loop_device {
+            match self.loop_delete(name) {
+                Ok(()) => info!("Detached loop device {:?}", name),
+                Err(err) => error!("{:#}", err),
+            }
+        }
+    }
+}
+
+/// Mount a block device or disk image read-only, scan it and unmount it again
+pub fn mount_and_scan(
+    config_path: Option<&Path>,
+    device: &Path,
+    mut args: args::Scan,
+) -> Result<ScanSummary> {
+    let control = Arc::new(Control::default());
+    // installed first, so an interrupted mount is undone too
+    cancel_on_interrupt(control.cancellation_token());
+    let attached = Attached::attach(device)?;
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        bail!("Interrupted before the scan started");
+    }
+    args.paths.extend(attached.mountpoints());
+    args.excludes.extend(attached.excludes()?);
+    // ignore files on a foreign filesystem could be used to hide malware
+    args.foreign = true;
+    scan::run_with(config_path, args, &control, |_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mount_options() {
+        assert_eq!(mount_options(Some("ext4")), "ro,nosuid,nodev,noexec,noload");
+        assert_eq!(
+            mount_options(Some("xfs")),
+            "ro,nosuid,nodev,noexec,norecovery"
+        );
+        assert_eq!(mount_options(Some("ntfs")), "ro,nosuid,nodev,noexec");
+        assert_eq!(mount_options(None), "ro,nosuid,nodev,noexec");
+    }
+
+    #[test]
+    fn test_windows_excludes() {
+        let attached = Attached {
+            backend: Backend::Root,
+            loop_device: None,
+            mounted: vec![
+                Mounted {
+                    name: "sda3".to_string(),
+                    mountpoint: PathBuf::from("/run/libredefender/mount/sda3"),
+                    fstype: Some("ntfs3".to_string()),
+                    existing: true,
+                },
+                Mounted {
+                    name: "sda4".to_string(),
+                    mountpoint: PathBuf::from("/run/libredefender/mount/sda4"),
+                    fstype: Some("ext4".to_string()),
+                    existing: true,
+                },
+            ],
+        };
+        let excludes = attached.excludes().unwrap();
+        assert_eq!(excludes.len(), WINDOWS_EXCLUDES.len());
+        assert!(excludes[0].matches(Path::new("/run/libredefender/mount/sda3/pagefile.sys")));
+        assert!(excludes
+            .iter()
+            .any(|e| e.matches(Path::new("/run/libredefender/mount/sda3/Windows/WinSxS"))));
+        assert!(!excludes
+            .iter()
+            .any(|e| e.matches(Path::new("/run/libredefender/mount/sda4/pagefile.sys"))));
+    }
+}
@@ -1,24 +1,126 @@
 use crate::errors::*;
+use crate::utils;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
 use std::str::FromStr;
 
-#[derive(Debug)]
-pub struct Pattern(glob::Pattern);
+/// Prefix of patterns that ignore case, e.g. for FAT and NTFS mounts
+const CASE_INSENSITIVE_PREFIX: &str = "(?i)";
+/// Patterns like `{a,{b,c}}{1,2}` can expand to many globs, stop before this gets out of hand
+const MAX_BRACE_EXPANSIONS: usize = 256;
+
+/// A glob that is matched against absolute paths
+///
+/// In the config it's either a string (with an optional `(?i)` prefix) or a table like
+/// `{ pattern = "*/{Temp,Cache}/*", case_insensitive = true, brace_expansion = true }`.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    /// As it was written, without the `(?i)` prefix
+    source: String,
+    globs: Vec<glob::Pattern>,
+    case_insensitive: bool,
+    brace_expansion: bool,
+}
 
 impl Pattern {
+    pub fn new(source: &str, case_insensitive: bool, brace_expansion: bool) -> Result<Pattern> {
+        let globs = if brace_expansion {
+            expand_braces(source)?
+        } else {
+            vec![source.to_string()]
+        };
+        let globs = globs
+            .iter()
+            .map(|glob| glob::Pattern::from_str(glob))
+            .collect::<Result<_, _>>()?;
+        Ok(Pattern {
+            source: source.to_string(),
+            globs,
+            case_insensitive,
+            brace_expansion,
+        })
+    }
+
+    /// Expand `~` and environment variables, the values are matched literally
+    pub fn expand_vars(&self) -> Result<Pattern> {
+        let source = utils::expand_vars(&self.source, glob::Pattern::escape)?;
+        Pattern::new(&source, self.case_insensitive, self.brace_expansion)
+    }
+
+    /// Ignore case when matching, e.g. for paths on FAT and NTFS filesystems
+    #[must_use]
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Pattern {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
     #[inline]
     #[must_use]
     pub fn matches(&self, path: &Path) -> bool {
-        self.0.matches_path(path)
+        let options = glob::MatchOptions {
+            case_sensitive: !self.case_insensitive,
+            ..Default::default()
+        };
+        self.globs
+            .iter()
+            .any(|glob| glob.matches_path_with(path, options))
+    }
+}
+
+/// Expand `{a,b}` alternatives, nested braces are supported
+fn expand_braces(s: &str) -> Result<Vec<String>> {
+    let Some(start) = s.find('{') else {
+        return Ok(vec![s.to_string()]);
+    };
+    let mut depth = 0;
+    let mut alternatives = Vec::new();
+    let mut last = start + 1;
+    let mut end = None;
+    for (i, c) in s.char_indices().skip_while(|(i, _)| *i <= start) {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => {
+                alternatives.push(&s[last..i]);
+                end = Some(i);
+                break;
+            }
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                alternatives.push(&s[last..i]);
+                last = i + 1;
+            }
+            _ => (),
+        }
     }
+    let end = end.with_context(|| anyhow!("Unclosed brace in pattern: {:?}", s))?;
+
+    let (prefix, suffix) = (&s[..start], &s[end + 1..]);
+    let mut expanded = Vec::new();
+    for alternative in alternatives {
+        for alternative in expand_braces(alternative)? {
+            for suffix in expand_braces(suffix)? {
+                expanded.push(format!("{}{}{}", prefix, alternative, suffix));
+                if expanded.len() > MAX_BRACE_EXPANSIONS {
+                    bail!("Pattern expands to too many alternatives: {:?}", s);
+                }
+            }
+        }
+    }
+    Ok(expanded)
 }
 
 impl fmt::Display for Pattern {
     #[inline]
     fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
-        self.0.fmt(w)
+        if self.case_insensitive {
+            w.write_str(CASE_INSENSITIVE_PREFIX)?;
+        }
+        w.write_str(&self.source)
     }
 }
 
@@ -26,18 +128,45 @@ impl FromStr for Pattern {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let pattern = glob::Pattern::from_str(s)?;
-        Ok(Pattern(pattern))
+        match s.strip_prefix(CASE_INSENSITIVE_PREFIX) {
+            Some(s) => Pattern::new(s, true, false),
+            None => Pattern::new(s, false, false),
+        }
     }
 }
 
+/// The table form of a pattern in the config
+#[derive(Serialize, Deserialize)]
+struct PatternTable {
+    pattern: String,
+    #[serde(default)]
+    case_insensitive: bool,
+    #[serde(default)]
+    brace_expansion: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PatternConfig {
+    String(String),
+    Table(PatternTable),
+}
+
 impl Serialize for Pattern {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let pattern = self.0.to_string();
-        serializer.serialize_str(&pattern)
+        if self.brace_expansion {
+            PatternTable {
+                pattern: self.source.clone(),
+                case_insensitive: self.case_insensitive,
+                brace_expansion: self.brace_expansion,
+            }
+            .serialize(serializer)
+        } else {
+            serializer.serialize_str(&self.to_string())
+        }
     }
 }
 
@@ -46,14 +175,139 @@ impl<'de> Deserialize<'de> for Pattern {
     where
         D: Deserializer<'de>,
     {
-        let s = String::deserialize(deserializer)?;
-        FromStr::from_str(&s).map_err(de::Error::custom)
+        match PatternConfig::deserialize(deserializer)? {
+            PatternConfig::String(s) => FromStr::from_str(&s).map_err(de::Error::custom),
+            PatternConfig::Table(t) => {
+                Pattern::new(&t.pattern, t.case_insensitive, t.brace_expansion)
+                    .map_err(de::Error::custom)
+            }
+        }
+    }
+}
+
+/// Exclude file with gitignore semantics, anchored patterns are relative to `/`
+#[derive(Debug)]
+pub struct ExcludeFile(Gitignore);
+
+impl ExcludeFile {
+    pub fn load(path: &Path) -> Result<ExcludeFile> {
+        let mut builder = GitignoreBuilder::new("/");
+        if let Some(err) = builder.add(path) {
+            return Err(err).with_context(|| anyhow!("Failed to load exclude file {:?}", path));
+        }
+        let gitignore = builder
+            .build()
+            .with_context(|| anyhow!("Failed to load exclude file {:?}", path))?;
+        Ok(ExcludeFile(gitignore))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        self.0.matched(path, is_dir).is_ignore()
+    }
+}
+
+/// Marker file that excludes the directory it's in, like `.nobackup`
+pub const NOCHECK_MARKER: &str = ".nocheck";
+/// Patterns in gitignore syntax relative to the directory it's in
+pub const IGNORE_FILE: &str = ".libredefender-ignore";
+
+/// What a `.nocheck` or `.libredefender-ignore` file in a directory excludes
+#[derive(Debug)]
+pub enum DirIgnore {
+    All,
+    Patterns(Gitignore),
+}
+
+/// Anybody can write files into /tmp, only files of the user running the scan or root are trusted
+fn is_trusted(path: &Path) -> io::Result<bool> {
+    let md = fs::symlink_metadata(path)?;
+    let uid = md.uid();
+    if uid == 0 || uid == utils::euid() {
+        Ok(true)
+    } else {
+        warn!(
+            "Ignoring {:?}: owned by uid {} instead of the scanning user",
+            path, uid
+        );
+        Ok(false)
+    }
+}
+
+impl DirIgnore {
+    pub fn load(dir: &Path) -> Result<Option<DirIgnore>> {
+        let marker = dir.join(NOCHECK_MARKER);
+        match is_trusted(&marker) {
+            Ok(true) => return Ok(Some(DirIgnore::All)),
+            Ok(false) => (),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => {
+                return Err(err).with_context(|| anyhow!("Failed to access {:?}", marker));
+            }
+        }
+
+        let path = dir.join(IGNORE_FILE);
+        match is_trusted(&path) {
+            Ok(true) => (),
+            Ok(false) => return Ok(None),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).with_context(|| anyhow!("Failed to access {:?}", path)),
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        if let Some(err) = builder.add(&path) {
+            return Err(err).with_context(|| anyhow!("Failed to load ignore file {:?}", path));
+        }
+        let gitignore = builder
+            .build()
+            .with_context(|| anyhow!("Failed to load ignore file {:?}", path))?;
+        if gitignore.is_empty() {
+            // `.nocheck` is the only way to exclude a whole directory
+            debug!("Ignore file has no patterns: {:?}", path);
+            Ok(None)
+        } else {
+            Ok(Some(DirIgnore::Patterns(gitignore)))
+        }
+    }
+
+    /// Check a path inside of the directory
+    #[must_use]
+    pub fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        match self {
+            DirIgnore::All => true,
+            DirIgnore::Patterns(gitignore) => gitignore.matched(path, is_dir).is_ignore(),
+        }
     }
 }
 
+/// Check the ignore files of all parent directories, for paths that are not reached by walking a directory
+#[must_use]
+pub fn is_ignored_by_parents(path: &Path, is_dir: bool) -> bool {
+    for dir in path.ancestors().skip(1) {
+        let ignore = match DirIgnore::load(dir) {
+            Ok(Some(ignore)) => ignore,
+            Ok(None) => continue,
+            Err(err) => {
+                debug!("{:#}", err);
+                continue;
+            }
+        };
+        // a pattern may match any directory between the ignore file and the path
+        let ignored = path
+            .ancestors()
+            .take_while(|p| *p != dir)
+            .any(|p| ignore.matches(p, if p == path { is_dir } else { true }));
+        if ignored {
+            return true;
+        }
+    }
+    false
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
 
     #[test]
     fn test_serialize_glob() {
@@ -62,4 +316,113 @@ mod tests {
         let json = serde_json::to_string(&p).unwrap();
         assert_eq!(json, "\"foo/**/{a,b}*\"");
     }
+
+    #[test]
+    fn test_case_insensitive() {
+        let p = Pattern::from_str("(?i)/mnt/*/Windows/Temp").unwrap();
+        assert!(p.matches(Path::new("/mnt/c/WINDOWS/temp")));
+        assert_eq!(p.to_string(), "(?i)/mnt/*/Windows/Temp");
+        assert_eq!(
+            serde_json::to_string(&p).unwrap(),
+            "\"(?i)/mnt/*/Windows/Temp\""
+        );
+
+        let p = Pattern::from_str("/mnt/*/Windows/Temp").unwrap();
+        assert!(!p.matches(Path::new("/mnt/c/WINDOWS/temp")));
+    }
+
+    #[test]
+    fn test_brace_expansion() {
+        assert_eq!(
+            expand_braces("*/{a,b{1,2}}/{x,y}").unwrap(),
+            vec!["*/a/x", "*/a/y", "*/b1/x", "*/b1/y", "*/b2/x", "*/b2/y"]
+        );
+        assert!(expand_braces("*/{a,b").is_err());
+        assert!(expand_braces(&"{a,b,c,d}".repeat(5)).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_table() {
+        #[derive(Deserialize)]
+        struct Config {
+            excludes: Vec<Pattern>,
+        }
+        let config = toml::from_str::<Config>(
+            r#"excludes = [
+                "/tmp/*",
+                { pattern = "*/{Temp,Cache}/*", case_insensitive = true, brace_expansion = true },
+            ]"#,
+        )
+        .unwrap();
+        let [tmp, cache] = &config.excludes[..] else {
+            panic!("expected two patterns");
+        };
+        assert!(tmp.matches(Path::new("/tmp/a.exe")));
+        assert!(cache.matches(Path::new("/home/user/temp/a.exe")));
+        assert!(cache.matches(Path::new("/home/user/CACHE/a.exe")));
+        assert!(!cache.matches(Path::new("/home/user/{Temp,Cache}/a.exe")));
+
+        let json = serde_json::to_value(cache).unwrap();
+        assert_eq!(json["pattern"], "*/{Temp,Cache}/*");
+        assert_eq!(json["brace_expansion"], true);
+    }
+
+    #[test]
+    fn test_exclude_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ignore");
+        fs::write(
+            &path,
+            "# comment\n*.iso\n!keep.iso\nnode_modules/\n/home/user/.cache\n",
+        )
+        .unwrap();
+        let exclude = ExcludeFile::load(&path).unwrap();
+
+        assert!(exclude.matches(Path::new("/home/user/Downloads/debian.iso"), false));
+        assert!(!exclude.matches(Path::new("/home/user/Downloads/keep.iso"), false));
+        assert!(exclude.matches(Path::new("/home/user/repos/x/node_modules"), true));
+        assert!(!exclude.matches(Path::new("/home/user/repos/x/node_modules"), false));
+        assert!(exclude.matches(Path::new("/home/user/.cache"), true));
+        assert!(!exclude.matches(Path::new("/home/other/user/.cache"), true));
+        assert!(!exclude.matches(Path::new("/home/user/invoice.pdf"), false));
+    }
+
+    #[test]
+    fn test_dir_ignore() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("research/samples")).unwrap();
+        fs::create_dir_all(root.join("vms/images")).unwrap();
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("research/.nocheck"), "").unwrap();
+        fs::write(root.join("vms/.libredefender-ignore"), "*.qcow2\nimages/\n").unwrap();
+        fs::write(
+            root.join("docs/.libredefender-ignore"),
+            "# only a comment\n",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            DirIgnore::load(&root.join("research")).unwrap(),
+            Some(DirIgnore::All)
+        ));
+        // comments aren't patterns, nothing is excluded
+        assert!(DirIgnore::load(&root.join("docs")).unwrap().is_none());
+        assert!(DirIgnore::load(root).unwrap().is_none());
+
+        let vms = DirIgnore::load(&root.join("vms")).unwrap().unwrap();
+        assert!(vms.matches(&root.join("vms/win10.qcow2"), false));
+        assert!(vms.matches(&root.join("vms/images"), true));
+        assert!(!vms.matches(&root.join("vms/notes.txt"), false));
+
+        assert!(is_ignored_by_parents(
+            &root.join("research/samples/a.exe"),
+            false
+        ));
+        assert!(is_ignored_by_parents(
+            &root.join("vms/images/disk.raw"),
+            false
+        ));
+        assert!(!is_ignored_by_parents(&root.join("vms/notes.txt"), false));
+    }
 }
@@ -0,0 +1,109 @@
+use crate::errors::*;
+use crate::utils;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// The scheduler that runs as root for all users, see `schedule.system`
+pub const SYSTEM_SCHEDULER: &str = "/run/libredefender/scheduler.pid";
+
+fn runtime_path(name: &str) -> PathBuf {
+    match dirs::runtime_dir() {
+        Some(dir) if !utils::is_root() => dir.join("libredefender").join(name),
+        _ => Path::new("/run/libredefender").join(name),
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/libredefender/scheduler.pid`, or the system path for root
+#[must_use]
+pub fn scheduler_path() -> PathBuf {
+    runtime_path("scheduler.pid")
+}
+
+/// The scan that was started with `scan --detach`
+#[must_use]
+pub fn scan_path() -> PathBuf {
+    runtime_path("scan.pid")
+}
+
+/// Contains the pid of the process and stays locked while it's running
+pub struct PidFile {
+    file: File,
+}
+
+impl PidFile {
+    /// Fails if another process holds the lock
+    pub fn acquire(path: &Path) -> Result<PidFile> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| anyhow!("Failed to create {:?}", dir))?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| anyhow!("Failed to open {:?}", path))?;
+        if let Err(err) = utils::flock(&file, libc::LOCK_EX | libc::LOCK_NB) {
+            if err.kind() == io::ErrorKind::WouldBlock {
+                let pid =
+                    read_pid(&mut file).map_or_else(String::new, |pid| format!(" (pid {})", pid));
+                bail!("Already running{}", pid);
+            }
+            return Err(err).with_context(|| anyhow!("Failed to lock {:?}", path));
+        }
+        // only truncate once the lock is held, the pid of the running process must stay readable
+        let mut pidfile = PidFile { file };
+        pidfile.set_pid(process::id())?;
+        Ok(pidfile)
+    }
+
+    /// After a fork the lock is shared with the child, which should be listed instead
+    pub fn set_pid(&mut self, pid: u32) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.rewind()?;
+        writeln!(self.file, "{}", pid)?;
+        Ok(())
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut buf = String::new();
+    file.read_to_string(&mut buf).ok()?;
+    buf.trim().parse().ok()
+}
+
+/// The pid of the process holding the lock, `None` if it isn't running
+#[must_use]
+pub fn running(path: &Path) -> Option<u32> {
+    let mut file = File::open(path).ok()?;
+    match utils::flock(&file, libc::LOCK_SH | libc::LOCK_NB) {
+        Ok(()) => None,
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => read_pid(&mut file),
+        Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pidfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scheduler.pid");
+        assert_eq!(running(&path), None);
+
+        let mut pidfile = PidFile::acquire(&path).unwrap();
+        assert_eq!(running(&path), Some(process::id()));
+        // flock is per open file description, a second open file conflicts
+        assert!(PidFile::acquire(&path).is_err());
+
+        pidfile.set_pid(1).unwrap();
+        assert_eq!(running(&path), Some(1));
+
+        drop(pidfile);
+        assert_eq!(running(&path), None);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::audit;
+use crate::db::Database;
+use crate::errors::*;
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// The scheduler needs root for notifications, rtc alarms and cgroups after every scan
+static STAY_PRIVILEGED: AtomicBool = AtomicBool::new(false);
+
+/// Files of root that are opened before the privileges are dropped, they stay owned by root
+static KEPT_OPEN: Mutex<Vec<(PathBuf, File)>> = Mutex::new(Vec::new());
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct User {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+pub fn lookup_user(name: &str) -> Result<User> {
+    let c_name = CString::new(name).context("User name contains a null byte")?;
+    let mut pwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut result = ptr::null_mut();
+    let ret = unsafe {
+        libc::getpwnam_r(
+            c_name.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret))
+            .with_context(|| anyhow!("Failed to lookup user {:?}", name));
+    }
+    if result.is_null() {
+        bail!("User does not exist: {:?}", name);
+    }
+    Ok(User {
+        uid: pwd.pw_uid,
+        gid: pwd.pw_gid,
+    })
+}
+
+/// Keep the current privileges even if `scan.user` is configured
+pub fn stay_privileged() {
+    STAY_PRIVILEGED.store(true, Ordering::SeqCst);
+}
+
+/// Open a file before the privileges are dropped, it's written through `kept_open` afterwards
+pub fn keep_open(path: &Path, options: &OpenOptions) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| anyhow!("Failed to create {:?}", parent))?;
+    }
+    let file = options
+        .open(path)
+        .with_context(|| anyhow!("Failed to open {:?}", path))?;
+    let mut kept = KEPT_OPEN.lock().unwrap_or_else(|err| err.into_inner());
+    kept.push((path.to_path_buf(), file));
+    Ok(())
+}
+
+/// A new descriptor of a file that was opened with `keep_open`
+///
+/// The descriptors share their flock, it has to be released explicitly.
+pub fn kept_open(path: &Path) -> Option<Result<File>> {
+    let kept = KEPT_OPEN.lock().unwrap_or_else(|err| err.into_inner());
+    let (_, file) = kept.iter().find(|(kept, _)| kept == path)?;
+    Some(
+        file.try_clone()
+            .with_context(|| anyhow!("Failed to duplicate descriptor of {:?}", path)),
+    )
+}
+
+pub use self::platform::drop_privileges;
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::User;
+    use crate::errors::*;
+    use std::io;
+    use std::ptr;
+
+    const CAP_DAC_READ_SEARCH: u32 = 2;
+    const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+    #[repr(C)]
+    struct CapHeader {
+        version: u32,
+        pid: libc::c_int,
+    }
+
+    #[repr(C)]
+    #[derive(Default, Clone, Copy)]
+    struct CapData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+
+    fn check(ret: libc::c_int, what: &str) -> Result<()> {
+        if ret != 0 {
+            return Err(io::Error::last_os_error()).with_context(|| anyhow!("Failed to {}", what));
+        }
+        Ok(())
+    }
+
+    /// Switch to an unprivileged user and only keep the capability to read all files
+    ///
+    /// This needs to happen before threads are spawned, capabilities are per thread.
+    pub fn drop_privileges(user: &User) -> Result<()> {
+        // everything except CAP_DAC_READ_SEARCH is removed from the bounding set for good
+        for cap in 0..64 {
+            if cap == CAP_DAC_READ_SEARCH {
+                continue;
+            }
+            let ret =
+                unsafe { libc::prctl(libc::PR_CAPBSET_DROP, libc::c_ulong::from(cap), 0, 0, 0) };
+            if ret != 0 && io::Error::last_os_error().raw_os_error() != Some(libc::EINVAL) {
+                return Err(io::Error::last_os_error())
+                    .with_context(|| anyhow!("Failed to drop capability {}", cap));
+            }
+        }
+
+        check(
+            unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1 as libc::c_ulong, 0, 0, 0) },
+            "keep capabilities",
+        )?;
+        check(unsafe { libc::setgroups(0, ptr::null()) }, "clear groups")?;
+        check(
+            unsafe { libc::setresgid(user.gid, user.gid, user.gid) },
+            "set gid",
+        )?;
+        check(
+            unsafe { libc::setresuid(user.uid, user.uid, user.uid) },
+            "set uid",
+        )?;
+
+        let header = CapHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let mut data = [CapData::default(); 2];
+        data[0].effective = 1 << CAP_DAC_READ_SEARCH;
+        data[0].permitted = 1 << CAP_DAC_READ_SEARCH;
+        let ret = unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) };
+        check(ret as libc::c_int, "set capabilities")?;
+
+        check(
+            unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1 as libc::c_ulong, 0, 0, 0) },
+            "set no_new_privs",
+        )?;
+        info!(
+            "Dropped privileges to uid={} gid={}, keeping CAP_DAC_READ_SEARCH",
+            user.uid, user.gid
+        );
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use super::User;
+    use crate::errors::*;
+
+    pub fn drop_privileges(_user: &User) -> Result<()> {
+        bail!("Dropping privileges with scan.user is only supported on linux")
+    }
+}
+
+/// The user a scan should switch to, `None` if the privileges are kept
+pub fn scan_user(user: Option<&str>) -> Result<Option<User>> {
+    let Some(name) = user else {
+        return Ok(None);
+    };
+    if !utils::is_root() {
+        debug!("Not running as root, ignoring scan.user");
+        return Ok(None);
+    }
+    if STAY_PRIVILEGED.load(Ordering::SeqCst) {
+        debug!("Keeping privileges, scan.user is ignored by the scheduler");
+        return Ok(None);
+    }
+    if !cfg!(target_os = "linux") {
+        bail!("Dropping privileges with scan.user is only supported on linux");
+    }
+    lookup_user(name).map(Some)
+}
+
+/// Apply `scan.user` in this process after the engine was loaded, only used by `watch`
+///
+/// The database and audit log are opened beforehand, files of root are never handed to the user.
+pub fn apply(user: Option<&str>) -> Result<()> {
+    let Some(user) = scan_user(user)? else {
+        return Ok(());
+    };
+    let db = Database::path()?;
+    if !db.exists() {
+        // an empty file isn't a valid database
+        Database::load()?.store()?;
+    }
+    keep_open(&db, OpenOptions::new().read(true).write(true))?;
+    keep_open(
+        &audit::path()?,
+        OpenOptions::new().append(true).create(true),
+    )?;
+    drop_privileges(&user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_user() {
+        assert_eq!(lookup_user("root").unwrap(), User { uid: 0, gid: 0 });
+        assert!(lookup_user("libredefender-does-not-exist").is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use crate::errors::*;
+use crate::scan::{Control, Event, ScanResults};
+use crate::utils;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Sent from the scan process to the parent, one json object per line
+#[derive(Debug, Serialize, Deserialize)]
+enum Message {
+    Event(Event),
+    Results(ScanResults),
+    Failed(String),
+}
+
+/// Set by SIGUSR1, the parent forwards `Control::cancel` this way
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn cancelled(_: libc::c_int) {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+fn cancel_on_signal(control: &Control) {
+    unsafe {
+        libc::signal(
+            libc::SIGUSR1,
+            cancelled as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+    let token = control.cancellation_token();
+    thread::spawn(move || {
+        while !CANCELLED.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+        }
+        token.cancel();
+    });
+}
+
+/// Don't keep scanning if the parent is gone, changing the credentials clears this again
+pub fn die_with_parent() {
+    unsafe {
+        libc::prctl(
+            libc::PR_SET_PDEATHSIG,
+            libc::SIGKILL as libc::c_ulong,
+            0,
+            0,
+            0,
+        );
+    }
+}
+
+fn send(mut pipe: &File, msg: &Message) -> Result<()> {
+    let mut line = serde_json::to_vec(msg)?;
+    line.push(b'\n');
+    pipe.write_all(&line)
+        .context("Failed to send to parent process")
+}
+
+/// Stdout is used to talk to the parent, anything else that's printed goes to stderr instead
+fn take_stdout() -> Result<File> {
+    let fd = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error()).context("Failed to duplicate stdout");
+    }
+    let pipe = unsafe { File::from_raw_fd(fd) };
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        return Err(io::Error::last_os_error()).context("Failed to redirect stdout");
+    }
+    Ok(pipe)
+}
+
+/// Entry point of the scan process, reads the job from stdin and sends the results to stdout
+pub fn child<J, F>(scan: F) -> !
+where
+    J: DeserializeOwned,
+    F: FnOnce(J, Arc<Control>, &mut dyn FnMut(Event)) -> Result<ScanResults>,
+{
+    die_with_parent();
+    let control = Arc::new(Control::default());
+    cancel_on_signal(&control);
+
+    let pipe = match take_stdout() {
+        Ok(pipe) => pipe,
+        Err(err) => {
+            error!("{:#}", err);
+            process::exit(1)
+        }
+    };
+    let mut buf = Vec::new();
+    let ret = io::stdin()
+        .read_to_end(&mut buf)
+        .context("Failed to read scan job")
+        .and_then(|_| serde_json::from_slice(&buf).context("Failed to parse scan job"))
+        .and_then(|job| {
+            scan(job, control, &mut |event| {
+                if let Err(err) = send(&pipe, &Message::Event(event)) {
+                    warn!("{:#}", err);
+                }
+            })
+        })
+        .and_then(|results| send(&pipe, &Message::Results(results)));
+    match ret {
+        Ok(()) => process::exit(0),
+        Err(err) => {
+            send(&pipe, &Message::Failed(format!("{:#}", err))).ok();
+            process::exit(1)
+        }
+    }
+}
+
+/// Pause, resume and cancel the child like the scan in this process would be
+fn forward_control(pid: libc::pid_t, control: Arc<Control>, done: Arc<AtomicBool>) {
+    let token = control.cancellation_token();
+    let mut paused = false;
+    let mut cancelled = false;
+    while !done.load(Ordering::SeqCst) {
+        if !cancelled && token.is_cancelled() {
+            unsafe { libc::kill(pid, libc::SIGUSR1) };
+            cancelled = true;
+        }
+        let pause = control.is_paused() && !cancelled;
+        if pause != paused {
+            let signal = if pause { libc::SIGSTOP } else { libc::SIGCONT };
+            unsafe { libc::kill(pid, signal) };
+            paused = pause;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Run `job` in a new `libredefender scan-child` process, the results are sent back to this process
+///
+/// The child moves itself into its cgroup scope, loads the engine and drops its privileges
+/// before it scans. Only the parent keeps the privileges, the database, audit log and everything else
+/// that's written after the scan is never accessible to the user libclamav runs as.
+pub fn run<J: Serialize>(
+    job: &J,
+    control: &Arc<Control>,
+    on_event: &mut dyn FnMut(Event),
+) -> Result<ScanResults> {
+    let exe = env::current_exe().context("Failed to find libredefender executable")?;
+    let mut cmd = Command::new(exe);
+    if tracing::level_enabled!(tracing::Level::DEBUG) {
+        cmd.arg("-v");
+    }
+    if let Some(dir) = utils::custom_data_dir() {
+        cmd.arg("--data").arg(dir);
+    }
+    let mut child = cmd
+        .arg("scan-child")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .context("Failed to start scan process")?;
+    let pid = child.id() as libc::pid_t;
+    debug!("Scanning in process {}", pid);
+
+    let done = Arc::new(AtomicBool::new(false));
+    let forward = {
+        let control = control.clone();
+        let done = done.clone();
+        thread::spawn(move || forward_control(pid, control, done))
+    };
+
+    // closing stdin tells the child the job is complete
+    if let Some(mut stdin) = child.stdin.take() {
+        let ret = serde_json::to_writer(&mut stdin, job).context("Failed to send scan job");
+        if let Err(err) = ret {
+            warn!("{:#}", err);
+        }
+    }
+
+    let mut results = None;
+    let mut failed = None;
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    warn!("Failed to read from scan process: {:#}", err);
+                    break;
+                }
+            };
+            let msg = match serde_json::from_str(&line) {
+                Ok(msg) => msg,
+                Err(err) => {
+                    warn!("Invalid message from scan process: {:#}", err);
+                    continue;
+                }
+            };
+            match msg {
+                Message::Event(event) => on_event(event),
+                Message::Results(msg) => results = Some(msg),
+                Message::Failed(msg) => failed = Some(msg),
+            }
+        }
+    }
+    done.store(true, Ordering::SeqCst);
+    forward.join().ok();
+    let status = child.wait().context("Failed to wait for scan process")?;
+
+    if let Some(msg) = failed {
+        bail!("{}", msg);
+    }
+    results.with_context(|| anyhow!("Scan process exited without results ({})", status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_message_roundtrip() {
+        let msg = Message::Event(Event::Threat {
+            path: PathBuf::from("/tmp/eicar.zip"),
+            name: "Win.Test.EICAR_HDB-1".to_string(),
+            member: Some(PathBuf::from("eicar.txt")),
+        });
+        let line = serde_json::to_string(&msg).unwrap();
+        assert!(!line.contains('\n'));
+        let Message::Event(Event::Threat { path, name, member }) =
+            serde_json::from_str(&line).unwrap()
+        else {
+            panic!("Unexpected message: {:?}", line);
+        };
+        assert_eq!(path, PathBuf::from("/tmp/eicar.zip"));
+        assert_eq!(name, "Win.Test.EICAR_HDB-1");
+        assert_eq!(member, Some(PathBuf::from("eicar.txt")));
+    }
+}
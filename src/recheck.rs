@@ -0,0 +1,119 @@
+use crate::archive::{self, Extracted};
+use crate::audit::{self, Action};
+use crate::config::Config;
+use crate::db::{Data, Threat};
+use crate::errors::*;
+use crate::scan::{self, Scanner};
+use std::mem;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// What happened to the recorded threats after scanning them again
+#[derive(Debug, Default)]
+pub struct Recheck {
+    /// Still detected with the current signatures
+    pub confirmed: Vec<PathBuf>,
+    /// Not detected anymore, e.g. because a false positive was fixed upstream
+    pub cleared: Vec<PathBuf>,
+    /// The file doesn't exist anymore
+    pub missing: Vec<PathBuf>,
+    /// The file couldn't be scanned, the entry is kept
+    pub failed: Vec<(PathBuf, Error)>,
+}
+
+impl Recheck {
+    #[must_use]
+    pub fn before(&self) -> usize {
+        self.confirmed.len() + self.cleared.len() + self.missing.len() + self.failed.len()
+    }
+
+    #[must_use]
+    pub fn after(&self) -> usize {
+        self.confirmed.len() + self.failed.len()
+    }
+}
+
+/// Archives are unpacked like in a regular scan, detected members are returned with the signature name
+fn scan_path(
+    scanner: &Scanner,
+    path: &Path,
+    max_unpacked_size: u64,
+) -> Result<Vec<(String, Option<PathBuf>)>> {
+    let (results_tx, results_rx) = crossbeam_channel::unbounded();
+    if archive::is_archive(path) {
+        let extracted = Extracted::new(path, max_unpacked_size)?;
+        for entry in WalkDir::new(extracted.path()) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                scanner.scan_file(entry.path(), &results_tx)?;
+            }
+        }
+        let extracted = [extracted];
+        Ok(results_rx
+            .try_iter()
+            .map(|(path, name)| (name, scan::report_on_archive(&extracted, path).1))
+            .collect())
+    } else {
+        scanner.scan_file(path, &results_tx)?;
+        Ok(results_rx
+            .try_iter()
+            .map(|(_, name)| (name, None))
+            .collect())
+    }
+}
+
+fn record_cleared(path: &Path, threat: &Threat, reason: &str) {
+    let entry = audit::Entry::new(Action::Cleared, path, &threat.names).detail(reason);
+    if let Err(err) = audit::record(&entry) {
+        warn!("Failed to write audit log: {:#}", err);
+    }
+}
+
+/// Scan every recorded threat again and drop the ones that are gone or not detected anymore
+pub fn run(config: &Config, data: &mut Data) -> Result<Recheck> {
+    let scanner = Scanner::from_config(config)?;
+
+    let mut recheck = Recheck::default();
+    let mut threats = mem::take(&mut data.threats).into_iter().collect::<Vec<_>>();
+    threats.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (path, threat) in threats {
+        if !path.exists() {
+            info!("File is gone, removing {:?}", path);
+            record_cleared(&path, &threat, "file is gone");
+            recheck.missing.push(path);
+            continue;
+        }
+
+        let names = match scan_path(&scanner, &path, config.scan.max_unpacked_size()) {
+            Ok(names) => names,
+            Err(err) => {
+                error!("Failed to scan {:?}: {:#}", path, err);
+                data.threats.insert(path.clone(), threat);
+                recheck.failed.push((path, err));
+                continue;
+            }
+        };
+        if names.is_empty() {
+            info!("Not detected anymore, removing {:?}", path);
+            record_cleared(&path, &threat, "not detected anymore");
+            recheck.cleared.push(path);
+        } else {
+            // signatures may have been renamed, only keep the current ones
+            let mut current = Threat {
+                first_seen: threat.first_seen,
+                ..Default::default()
+            };
+            for (name, member) in names {
+                current.add_name(name);
+                if let Some(member) = member {
+                    current.add_member(member);
+                }
+            }
+            data.threats.insert(path.clone(), current);
+            recheck.confirmed.push(path);
+        }
+    }
+
+    Ok(recheck)
+}
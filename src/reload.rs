@@ -0,0 +1,110 @@
+use crate::config::Config;
+use crate::errors::*;
+use crate::scan::{EngineOptions, Scanner};
+use std::sync::{Arc, RwLock};
+
+/// A scanner that can be replaced while files are being scanned
+pub struct SharedScanner {
+    current: RwLock<Arc<Scanner>>,
+}
+
+impl SharedScanner {
+    #[must_use]
+    pub fn new(scanner: Scanner) -> Arc<SharedScanner> {
+        Arc::new(SharedScanner {
+            current: RwLock::new(Arc::new(scanner)),
+        })
+    }
+
+    /// Get the current scanner, this is called for every file so reloads take effect between files
+    #[must_use]
+    pub fn get(&self) -> Arc<Scanner> {
+        self.current.read().unwrap().clone()
+    }
+
+    fn replace(&self, scanner: Scanner) {
+        *self.current.write().unwrap() = Arc::new(scanner);
+    }
+}
+
+/// freshclam writes multiple files, the reload waits until it's done
+#[cfg(target_os = "linux")]
+const SETTLE_TIME: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Reload the signatures in the background when freshclam updates the database directory
+///
+/// The watcher stops on the next update after the scanner was dropped.
+#[cfg(target_os = "linux")]
+pub fn watch(shared: &Arc<SharedScanner>, config: &Config) -> Result<()> {
+    use crate::verify;
+    use inotify::{Inotify, WatchMask};
+    use std::thread;
+
+    let backend = config.scan.backend;
+    let update = config.update.clone();
+    let path = config.update.path.clone();
+    let extra = config.extra_databases();
+    let options = EngineOptions::from_config(&config.scan);
+
+    let mut inotify = Inotify::init().context("Failed to initialize inotify")?;
+    inotify
+        .watches()
+        .add(&path, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+        .with_context(|| anyhow!("Failed to watch database directory {:?}", path))?;
+    debug!("Watching {:?} for signature updates", path);
+
+    let shared = Arc::downgrade(shared);
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        loop {
+            let changed = match inotify.read_events_blocking(&mut buf) {
+                Ok(events) => events.into_iter().any(|event| {
+                    event
+                        .name
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.ends_with(".cld") || name.ends_with(".cvd"))
+                }),
+                Err(err) => {
+                    error!("Failed to read inotify events: {:#}", err);
+                    return;
+                }
+            };
+            if !changed {
+                continue;
+            }
+            if shared.strong_count() == 0 {
+                return;
+            }
+
+            thread::sleep(SETTLE_TIME);
+            while inotify
+                .read_events(&mut buf)
+                .is_ok_and(|mut events| events.next().is_some())
+            {}
+
+            info!("Signature database was updated, reloading...");
+            let scanner = verify::before_load(&update)
+                .and_then(|_| Scanner::with_options(backend, &path, &extra, &options));
+            match scanner {
+                Ok(scanner) => {
+                    let Some(shared) = shared.upgrade() else {
+                        return;
+                    };
+                    shared.replace(scanner);
+                    info!("Reloaded signatures");
+                }
+                Err(err) => warn!(
+                    "Failed to reload signatures, keeping the previous ones: {:#}",
+                    err
+                ),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn watch(_shared: &Arc<SharedScanner>, _config: &Config) -> Result<()> {
+    bail!("Reloading signatures on change is only supported on linux")
+}
@@ -0,0 +1,90 @@
+use crate::errors::*;
+use crate::mounts::{self, MOUNTINFO};
+use crate::schedule::Command;
+use crossbeam_channel::Sender;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Check sysfs if the block device is removable or attached over usb
+#[must_use]
+pub fn is_removable(major: u32, minor: u32) -> bool {
+    // pseudo filesystems like tmpfs and proc don't have a backing device
+    if major == 0 {
+        return false;
+    }
+
+    let path = format!("/sys/dev/block/{}:{}", major, minor);
+    let path = match fs::canonicalize(&path) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+
+    let device: &Path = if path.join("partition").exists() {
+        path.parent().unwrap_or(&path)
+    } else {
+        &path
+    };
+
+    let removable = fs::read_to_string(device.join("removable"))
+        .map(|s| s.trim() == "1")
+        .unwrap_or(false);
+    removable || device.to_string_lossy().contains("/usb")
+}
+
+fn removable_mounts() -> Result<HashSet<PathBuf>> {
+    let mounts = mounts::mounts()?
+        .into_iter()
+        .filter(|m| is_removable(m.major, m.minor))
+        .map(|m| m.mountpoint)
+        .collect();
+    Ok(mounts)
+}
+
+/// Wait for new removable media to be mounted and request a scan from the scheduler
+pub fn watch(commands: &Sender<Command>) -> Result<()> {
+    let mut known = removable_mounts()?;
+    let file = File::open(MOUNTINFO).context("Failed to open mountinfo")?;
+
+    info!("Watching for removable media");
+    loop {
+        let mut fds = libc::pollfd {
+            fd: file.as_raw_fd(),
+            events: libc::POLLPRI,
+            revents: 0,
+        };
+        // the kernel signals POLLPRI|POLLERR whenever the mount table changes
+        let ret = unsafe { libc::poll(&mut fds, 1, -1) };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err).context("Failed to poll mountinfo");
+        }
+
+        let current = removable_mounts()?;
+        for mountpoint in current.difference(&known) {
+            info!("Removable media was mounted: {:?}", mountpoint);
+            if commands
+                .send(Command::ScanRemovable(mountpoint.clone()))
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+        known = current;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pseudo_fs_is_not_removable() {
+        assert!(!is_removable(0, 22));
+    }
+}
@@ -0,0 +1,368 @@
+use crate::config::Config;
+use crate::db::{Data, SkipReason};
+use crate::errors::*;
+use crate::export::{self, Record};
+use crate::scan;
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use v_htmlescape::escape;
+
+/// The days a report covers in the local timezone, both ends are included
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Period {
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+impl Period {
+    #[must_use]
+    pub fn is_unbounded(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+
+    #[must_use]
+    pub fn contains(&self, time: &DateTime<Utc>) -> bool {
+        let day = time.with_timezone(&Local).date_naive();
+        self.since.is_none_or(|since| day >= since) && self.until.is_none_or(|until| day <= until)
+    }
+
+    /// Without a period everything is included, even if the time isn't known
+    fn includes(&self, time: &Option<DateTime<Utc>>) -> bool {
+        match time {
+            Some(time) => self.contains(time),
+            None => self.is_unbounded(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub generated: DateTime<Utc>,
+    pub period: Period,
+    pub last_scan: Option<DateTime<Utc>>,
+    pub signature_count: usize,
+    pub signatures_age: Option<DateTime<Utc>>,
+    pub clamav_version: String,
+    /// Threats that were first seen in the period
+    pub threats: Vec<Record>,
+    /// Files the last scan couldn't scan, empty if it didn't run in the period
+    pub skipped: Vec<(PathBuf, SkipReason)>,
+    pub config: String,
+}
+
+fn format_datetime(dt: &Option<DateTime<Utc>>) -> String {
+    if let Some(dt) = dt {
+        dt.with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S %Z")
+            .to_string()
+    } else {
+        "-".to_string()
+    }
+}
+
+fn format_opt<T: ToString>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map_or_else(|| "-".to_string(), ToString::to_string)
+}
+
+fn escape_markdown(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace('`', "\\`")
+        .replace('\n', " ")
+}
+
+/// Skipped files are only recorded for the last scan, sorted by path
+fn skipped_files(data: &Data, period: &Period) -> Vec<(PathBuf, SkipReason)> {
+    if !period.includes(&data.last_scan) {
+        return Vec::new();
+    }
+    let mut skipped = data
+        .skipped
+        .iter()
+        .map(|(path, reason)| (path.clone(), *reason))
+        .collect::<Vec<_>>();
+    skipped.sort_by(|(a, _), (b, _)| a.cmp(b));
+    skipped
+}
+
+impl Report {
+    pub fn new(data: &Data, config: &Config, period: Period) -> Result<Report> {
+        let threats = export::records(data)
+            .into_iter()
+            .filter(|threat| period.includes(&threat.first_seen))
+            .collect();
+        let last_scan = data.last_scan.filter(|time| period.contains(time));
+
+        Ok(Report {
+            generated: Utc::now(),
+            period,
+            last_scan,
+            signature_count: data.signature_count,
+            signatures_age: data.signatures_age,
+            clamav_version: scan::clamav_version(),
+            threats,
+            skipped: skipped_files(data, &period),
+            config: serde_json::to_string_pretty(config)?,
+        })
+    }
+
+    fn metadata(&self) -> Vec<(&'static str, String)> {
+        let mut metadata = vec![("Report generated", format_datetime(&Some(self.generated)))];
+        if !self.period.is_unbounded() {
+            metadata.push((
+                "Period",
+                format!(
+                    "{} to {}",
+                    format_opt(&self.period.since),
+                    format_opt(&self.period.until)
+                ),
+            ));
+        }
+        metadata.extend([
+            ("Last scan", format_datetime(&self.last_scan)),
+            ("Threats present", self.threats.len().to_string()),
+            ("Files skipped", self.skipped.len().to_string()),
+            ("Signatures", self.signature_count.to_string()),
+            ("Signatures updated", format_datetime(&self.signatures_age)),
+            ("libclamav version", self.clamav_version.clone()),
+            (
+                "libredefender version",
+                env!("CARGO_PKG_VERSION").to_string(),
+            ),
+        ]);
+        metadata
+    }
+
+    #[must_use]
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# libredefender scan report\n\n");
+
+        out.push_str("| | |\n|---|---|\n");
+        for (key, value) in self.metadata() {
+            writeln!(out, "| {} | {} |", key, escape_markdown(&value)).unwrap();
+        }
+
+        out.push_str("\n## Threats\n\n");
+        if self.threats.is_empty() {
+            out.push_str("No threats have been detected.\n");
+        } else {
+            out.push_str(
+                "| Path | Signatures | SHA256 | Size | First seen |\n|---|---|---|---|---|\n",
+            );
+            for threat in &self.threats {
+                writeln!(
+                    out,
+                    "| {} | {} | {} | {} | {} |",
+                    escape_markdown(&threat.path.to_string_lossy()),
+                    escape_markdown(&threat.signatures.join(", ")),
+                    format_opt(&threat.sha256),
+                    format_opt(&threat.size),
+                    format_datetime(&threat.first_seen),
+                )
+                .unwrap();
+            }
+        }
+
+        out.push_str("\n## Skipped files\n\n");
+        if self.skipped.is_empty() {
+            out.push_str("No files were skipped.\n");
+        } else {
+            out.push_str("| Path | Reason |\n|---|---|\n");
+            for (path, reason) in &self.skipped {
+                writeln!(
+                    out,
+                    "| {} | {} |",
+                    escape_markdown(&path.to_string_lossy()),
+                    reason
+                )
+                .unwrap();
+            }
+        }
+
+        out.push_str("\n## Configuration\n\n```json\n");
+        out.push_str(&self.config);
+        out.push_str("\n```\n");
+        out
+    }
+
+    #[must_use]
+    pub fn to_html(&self) -> String {
+        let mut out = String::new();
+        out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        out.push_str("<title>libredefender scan report</title>\n<style>\n");
+        out.push_str("body { font-family: sans-serif; margin: 2em; }\n");
+        out.push_str("table { border-collapse: collapse; margin-bottom: 1em; }\n");
+        out.push_str("th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }\n");
+        out.push_str("td.hash { font-family: monospace; }\n");
+        out.push_str("</style>\n</head>\n<body>\n<h1>libredefender scan report</h1>\n");
+
+        out.push_str("<table>\n");
+        for (key, value) in self.metadata() {
+            writeln!(
+                out,
+                "<tr><th>{}</th><td>{}</td></tr>",
+                escape(key),
+                escape(&value)
+            )
+            .unwrap();
+        }
+        out.push_str("</table>\n");
+
+        out.push_str("<h2>Threats</h2>\n");
+        if self.threats.is_empty() {
+            out.push_str("<p>No threats have been detected.</p>\n");
+        } else {
+            out.push_str(
+                "<table>\n<tr><th>Path</th><th>Signatures</th><th>SHA256</th><th>Size</th><th>First seen</th></tr>\n",
+            );
+            for threat in &self.threats {
+                writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{}</td><td class=\"hash\">{}</td><td>{}</td><td>{}</td></tr>",
+                    escape(&threat.path.to_string_lossy()),
+                    escape(&threat.signatures.join(", ")),
+                    escape(&format_opt(&threat.sha256)),
+                    escape(&format_opt(&threat.size)),
+                    escape(&format_datetime(&threat.first_seen)),
+                )
+                .unwrap();
+            }
+            out.push_str("</table>\n");
+        }
+
+        out.push_str("<h2>Skipped files</h2>\n");
+        if self.skipped.is_empty() {
+            out.push_str("<p>No files were skipped.</p>\n");
+        } else {
+            out.push_str("<table>\n<tr><th>Path</th><th>Reason</th></tr>\n");
+            for (path, reason) in &self.skipped {
+                writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{}</td></tr>",
+                    escape(&path.to_string_lossy()),
+                    escape(&reason.to_string()),
+                )
+                .unwrap();
+            }
+            out.push_str("</table>\n");
+        }
+
+        out.push_str("<h2>Configuration</h2>\n<pre>");
+        write!(out, "{}", escape(&self.config)).unwrap();
+        out.push_str("</pre>\n</body>\n</html>\n");
+        out
+    }
+}
+
+/// Guess the report format from the file extension of the output path
+#[must_use]
+pub fn is_html_path(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("html" | "htm")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_escape_markdown() {
+        let s = escape_markdown("/home/user/a|b`c\nd");
+        assert_eq!(s, "/home/user/a\\|b\\`c d");
+    }
+
+    #[test]
+    fn test_period() {
+        let time = Local
+            .with_ymd_and_hms(2024, 3, 31, 23, 30, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        let day = |d| NaiveDate::from_ymd_opt(2024, 3, d);
+        assert!(Period::default().contains(&time));
+        assert!(Period {
+            since: day(31),
+            until: day(31),
+        }
+        .contains(&time));
+        assert!(!Period {
+            since: None,
+            until: day(30),
+        }
+        .contains(&time));
+        assert!(!Period {
+            since: NaiveDate::from_ymd_opt(2024, 4, 1),
+            until: None,
+        }
+        .contains(&time));
+        // threats from before first_seen was recorded are only listed without a period
+        assert!(Period::default().includes(&None));
+        assert!(!Period {
+            since: day(1),
+            until: None,
+        }
+        .includes(&None));
+    }
+
+    #[test]
+    fn test_skipped_files() {
+        let mut data = Data {
+            last_scan: Some(Utc::now()),
+            ..Data::default()
+        };
+        data.skipped
+            .insert(PathBuf::from("/home/user/b.iso"), SkipReason::TooLarge);
+        data.skipped.insert(
+            PathBuf::from("/home/user/a.db"),
+            SkipReason::PermissionDenied,
+        );
+        let skipped = skipped_files(&data, &Period::default());
+        assert_eq!(
+            skipped,
+            [
+                (
+                    PathBuf::from("/home/user/a.db"),
+                    SkipReason::PermissionDenied
+                ),
+                (PathBuf::from("/home/user/b.iso"), SkipReason::TooLarge),
+            ]
+        );
+
+        // the last scan wasn't in the period
+        let period = Period {
+            since: None,
+            until: NaiveDate::from_ymd_opt(2000, 1, 1),
+        };
+        assert!(skipped_files(&data, &period).is_empty());
+
+        let report = Report {
+            generated: Utc::now(),
+            period: Period::default(),
+            last_scan: data.last_scan,
+            signature_count: 0,
+            signatures_age: None,
+            clamav_version: String::new(),
+            threats: Vec::new(),
+            skipped,
+            config: String::new(),
+        };
+        assert!(report
+            .to_markdown()
+            .contains("| /home/user/b.iso | too large |"));
+        assert!(report.to_html().contains("<td>permission denied</td>"));
+    }
+
+    #[test]
+    fn test_is_html_path() {
+        assert!(is_html_path(Path::new("report.html")));
+        assert!(is_html_path(Path::new("/tmp/report.htm")));
+        assert!(!is_html_path(Path::new("report.md")));
+        assert!(!is_html_path(Path::new("report")));
+    }
+}
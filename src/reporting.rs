@@ -0,0 +1,252 @@
+use crate::config::ReportingConfig;
+use crate::db::{Data, ErrorCategory};
+use crate::errors::*;
+use crate::export::{self, Record};
+use crate::http;
+use crate::scan::{self, ScanSummary};
+use crate::utils;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const SIGNATURE_HEADER: &str = "X-Libredefender-Signature";
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// What is uploaded to the fleet server after every scan
+#[derive(Debug, Serialize)]
+pub struct ScanReport {
+    pub hostname: String,
+    /// From /etc/machine-id, stays the same if the machine is renamed
+    pub machine_id: Option<String>,
+    pub version: &'static str,
+    pub time: DateTime<Utc>,
+    pub files_scanned: usize,
+    pub bytes_scanned: u64,
+    /// Files that weren't scanned again because the clean cache knew them
+    pub files_cached: usize,
+    /// Package files that weren't scanned because they match their package
+    pub files_unmodified: usize,
+    pub duration_secs: f64,
+    pub errors: usize,
+    pub error_categories: BTreeMap<ErrorCategory, usize>,
+    pub skipped: usize,
+    pub cancelled: bool,
+    pub signature_count: usize,
+    pub signatures_age: Option<DateTime<Utc>>,
+    /// Every threat that is currently recorded, not only the ones found by this scan
+    pub threats: Vec<Record>,
+    /// Detections of this scan that weren't recorded before
+    pub new_threats: Vec<(PathBuf, String)>,
+}
+
+impl ScanReport {
+    #[must_use]
+    pub fn new(summary: &ScanSummary, data: &Data) -> ScanReport {
+        let machine_id = fs::read_to_string("/etc/machine-id")
+            .ok()
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty());
+        ScanReport {
+            hostname: utils::hostname(),
+            machine_id,
+            version: env!("CARGO_PKG_VERSION"),
+            time: Utc::now(),
+            files_scanned: summary.files_scanned,
+            bytes_scanned: summary.bytes_scanned,
+            files_cached: summary.files_cached,
+            files_unmodified: summary.files_unmodified,
+            duration_secs: summary.duration.as_secs_f64(),
+            errors: summary.errors.len(),
+            error_categories: scan::count_errors(&summary.errors),
+            skipped: summary.skipped.len(),
+            cancelled: summary.cancelled,
+            signature_count: data.signature_count,
+            signatures_age: data.signatures_age,
+            threats: export::records(data),
+            new_threats: summary.new_threats.clone(),
+        }
+    }
+}
+
+/// HMAC-SHA256 (RFC 2104), the server verifies the body wasn't modified with the shared token
+#[must_use]
+pub fn sign(key: &[u8], msg: &[u8]) -> String {
+    // any key length is valid for hmac, longer keys are hashed first
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts keys of any length");
+    mac.update(msg);
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+/// Reports that couldn't be uploaded yet, they are sent in order with the next scan
+pub fn queue_dir() -> Result<PathBuf> {
+    let path = utils::data_dir()?.join("reports");
+    Ok(path)
+}
+
+fn queued(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut queued = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| anyhow!("Failed to read {:?}", dir))? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            queued.push(path);
+        }
+    }
+    // file names start with the time they were queued at
+    queued.sort();
+    Ok(queued)
+}
+
+fn enqueue(dir: &Path, report: &ScanReport, max_queued: usize) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| anyhow!("Failed to create {:?}", dir))?;
+    let name = format!(
+        "{:020}-{:016x}.json",
+        report.time.timestamp_millis(),
+        rand::random::<u64>()
+    );
+    let buf = serde_json::to_vec(report)?;
+    fs::write(dir.join(name), buf).context("Failed to queue report")?;
+
+    let queued = queued(dir)?;
+    let excess = queued.len().saturating_sub(max_queued);
+    for path in &queued[..excess] {
+        warn!("Report queue is full, dropping {:?}", path);
+        utils::ensure_deleted(path)?;
+    }
+    Ok(())
+}
+
+/// The credentials of the fleet server, the token authenticates and the signing key signs the body
+struct Keys {
+    token: String,
+    signing_key: Option<String>,
+}
+
+fn upload(config: &ReportingConfig, keys: &Keys, body: &[u8]) -> Result<()> {
+    let mut headers = vec![
+        "Content-Type: application/json".to_string(),
+        format!("Authorization: Bearer {}", keys.token),
+    ];
+    if let Some(key) = &keys.signing_key {
+        headers.push(format!(
+            "{}: sha256={}",
+            SIGNATURE_HEADER,
+            sign(key.as_bytes(), body)
+        ));
+    }
+    http::send("POST", &config.url, &headers, body, config.proxy.as_deref())
+        .with_context(|| anyhow!("Upload to {:?} failed", config.url))
+}
+
+fn upload_with_retries(config: &ReportingConfig, keys: &Keys, body: &[u8]) -> Result<()> {
+    let mut backoff = Duration::from_secs(1);
+    let mut attempt = 0;
+    loop {
+        match upload(config, keys, body) {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt < config.retries => {
+                debug!("{:#}, retrying in {:?}", err, backoff);
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Upload queued reports, oldest first, and stop at the first one that can't be delivered
+pub fn flush(config: &ReportingConfig) -> Result<usize> {
+    // the scheduler could start the next upload before the previous one is done
+    static FLUSHING: Mutex<()> = Mutex::new(());
+    let _lock = FLUSHING.lock();
+
+    let dir = queue_dir()?;
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let keys = Keys {
+        token: config.token()?,
+        signing_key: config.signing_key()?,
+    };
+    let mut sent = 0;
+    for path in queued(&dir)? {
+        let body = fs::read(&path).with_context(|| anyhow!("Failed to read {:?}", path))?;
+        upload_with_retries(config, &keys, &body)?;
+        utils::ensure_deleted(&path)?;
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+/// Uploads that were started by `submit`, joined by `wait` before the process exits
+static UPLOADS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// Queue the report of a scan and upload everything that is queued in the background
+///
+/// Retries don't delay the end of the scan, reports that weren't sent after the last
+/// retry stay queued for the next scan.
+pub fn submit(config: &ReportingConfig, report: &ScanReport) -> Result<()> {
+    enqueue(&queue_dir()?, report, config.max_queued)?;
+    let config = config.clone();
+    let thread = thread::spawn(move || match flush(&config) {
+        Ok(sent) => debug!("Uploaded {} report(s) to {:?}", sent, config.url),
+        Err(err) => warn!(
+            "Failed to upload scan report, reports are kept queued for the next scan: {:#}",
+            err
+        ),
+    });
+    let mut uploads = UPLOADS.lock().unwrap_or_else(|err| err.into_inner());
+    // the scheduler keeps running, don't collect the uploads of every scan
+    uploads.retain(|thread| !thread.is_finished());
+    uploads.push(thread);
+    Ok(())
+}
+
+/// Wait for the uploads to finish, before the process exits
+pub fn wait() {
+    let uploads = mem::take(&mut *UPLOADS.lock().unwrap_or_else(|err| err.into_inner()));
+    for thread in uploads {
+        thread.join().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // keys longer than the block size are hashed first, test case 6
+        assert_eq!(
+            sign(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            ),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn test_queue_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let summary = ScanSummary::default();
+        let data = Data::default();
+        for _ in 0..5 {
+            enqueue(dir.path(), &ScanReport::new(&summary, &data), 3).unwrap();
+        }
+        assert_eq!(queued(dir.path()).unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::errors::*;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+/// How late the scan may start after the alarm to still count as woken up by it
+const WAKE_TOLERANCE: i64 = 5 * 60;
+
+/// A wake alarm we programmed, remembers the alarm it replaced
+#[derive(Debug)]
+pub struct WakeAlarm {
+    at: DateTime<Utc>,
+    previous: Option<DateTime<Utc>>,
+    suspended: Duration,
+    resumed: bool,
+}
+
+impl WakeAlarm {
+    /// Record if the machine was suspended since the last call, to be called while waiting for the alarm
+    pub fn tick(&mut self) {
+        let suspended = platform::suspended_time();
+        // both clocks are read separately, ignore the jitter between them
+        self.resumed = suspended - self.suspended > Duration::seconds(1);
+        self.suspended = suspended;
+    }
+
+    /// If the machine resumed from suspend right before the alarm was due
+    #[must_use]
+    pub fn woke_us(&self) -> bool {
+        let now = Utc::now();
+        self.resumed && now >= self.at && now - self.at < Duration::seconds(WAKE_TOLERANCE)
+    }
+
+    /// Put back the alarm we replaced, or clear ours if it didn't fire yet
+    pub fn restore(self) -> Result<()> {
+        match self.previous {
+            Some(previous) if previous > Utc::now() => {
+                platform::set_wake_alarm(previous.timestamp())?;
+                debug!("Restored previous rtc wake alarm for {}", previous);
+            }
+            _ if self.at > Utc::now() => platform::clear_wake_alarm()?,
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// Program the real time clock to wake the machine from suspend at the given time
+pub fn set_wake_alarm(at: DateTime<Utc>) -> Result<WakeAlarm> {
+    let previous = platform::read_wake_alarm()
+        .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+        .filter(|previous| *previous > Utc::now());
+    if let Some(previous) = previous {
+        debug!("Replacing existing rtc wake alarm for {}", previous);
+    }
+    platform::set_wake_alarm(at.timestamp())?;
+    debug!("Programmed rtc wake alarm for {}", at);
+    Ok(WakeAlarm {
+        at,
+        previous,
+        suspended: platform::suspended_time(),
+        resumed: false,
+    })
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use crate::errors::*;
+    use chrono::Duration;
+    use std::fs;
+    use std::process::Command;
+
+    const WAKEALARM: &str = "/sys/class/rtc/rtc0/wakealarm";
+
+    /// The currently programmed alarm, the file is empty if there is none
+    pub fn read_wake_alarm() -> Option<i64> {
+        fs::read_to_string(WAKEALARM).ok()?.trim().parse().ok()
+    }
+
+    pub fn set_wake_alarm(timestamp: i64) -> Result<()> {
+        // an existing alarm has to be cleared before a new one can be set
+        let ret =
+            fs::write(WAKEALARM, "0").and_then(|_| fs::write(WAKEALARM, timestamp.to_string()));
+        if let Err(err) = ret {
+            debug!(
+                "Failed to write {}, trying rtcwake instead: {:#}",
+                WAKEALARM, err
+            );
+            rtcwake(&["-m", "no", "-t", &timestamp.to_string()])?;
+        }
+        Ok(())
+    }
+
+    pub fn clear_wake_alarm() -> Result<()> {
+        if let Err(err) = fs::write(WAKEALARM, "0") {
+            debug!(
+                "Failed to write {}, trying rtcwake instead: {:#}",
+                WAKEALARM, err
+            );
+            rtcwake(&["-m", "disable"])?;
+        }
+        Ok(())
+    }
+
+    fn rtcwake(args: &[&str]) -> Result<()> {
+        let status = Command::new("rtcwake")
+            .args(args)
+            .status()
+            .context("Failed to run rtcwake")?;
+        if !status.success() {
+            bail!("rtcwake exited with {}", status);
+        }
+        Ok(())
+    }
+
+    fn clock(id: libc::clockid_t) -> Duration {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { libc::clock_gettime(id, &mut ts) };
+        Duration::seconds(ts.tv_sec) + Duration::nanoseconds(ts.tv_nsec)
+    }
+
+    /// The time spent in suspend since boot, the monotonic clock doesn't advance during suspend
+    pub fn suspended_time() -> Duration {
+        clock(libc::CLOCK_BOOTTIME) - clock(libc::CLOCK_MONOTONIC)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use crate::errors::*;
+    use chrono::Duration;
+
+    pub fn read_wake_alarm() -> Option<i64> {
+        None
+    }
+
+    pub fn set_wake_alarm(_timestamp: i64) -> Result<()> {
+        bail!("Waking up for scans with schedule.use_rtc_wake is only supported on linux")
+    }
+
+    pub fn clear_wake_alarm() -> Result<()> {
+        Ok(())
+    }
+
+    pub fn suspended_time() -> Duration {
+        Duration::zero()
+    }
+}
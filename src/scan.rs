@@ -1,31 +1,95 @@
+use crate::archive::{self, Extracted};
 use crate::args;
-use crate::config::{self, ScanConfig};
-use crate::db::Database;
+use crate::audit::{self, Action};
+use crate::cache::{self, CleanCache};
+use crate::cgroup;
+use crate::config::{self, Backend, CgroupConfig, Config, HumanSize, PathOverride, ScanConfig};
+use crate::db::{Data, Database, ErrorCategory, Resolved, ScanStats, SkipReason, Threat};
+use crate::describe::Descriptions;
 use crate::errors::*;
+use crate::external::External;
+use crate::heuristic;
+use crate::hooks::{self, HookEvent, ThreatHooks};
+use crate::inuse::InUse;
+use crate::magic;
+use crate::metrics;
+use crate::mounts;
+use crate::nice;
 use crate::notify;
+use crate::packages::{Manifest, Verdict};
+use crate::patterns::{self, DirIgnore};
+use crate::privdrop;
+#[cfg(target_os = "linux")]
+use crate::privsep;
+use crate::reload::{self, SharedScanner};
+use crate::reporting::{self, ScanReport};
+use crate::statusfile::{self, State as StatusState, StatusFile};
+#[cfg(feature = "async")]
+pub use crate::stream::run_async;
+use crate::throttle::Throttle;
+use crate::utils;
+use crate::verify;
 use chrono::TimeZone;
 use chrono::{DateTime, Utc};
-use clamav_rs::engine::{Engine, ScanResult};
-use clamav_rs::scan_settings::ScanSettings;
-use crossbeam_channel::Sender;
+#[cfg(feature = "libclamav")]
+use clamav_rs::engine::{BytecodeMode, Engine, ScanResult};
+#[cfg(feature = "libclamav")]
+use clamav_rs::scan_settings::{ScanSettings, ScanSettingsBuilder};
+use crossbeam_channel::{Receiver, Select, Sender, TrySendError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::ffi::OsStr;
+use std::fmt;
 use std::fs::{self, File, FileType};
-use std::io::Read;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::iter;
 use std::mem;
-use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+#[cfg(feature = "libclamav")]
+use std::os::unix::io::AsRawFd;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread;
+use std::time::{Duration, Instant};
 use walkdir::{DirEntry, WalkDir};
 
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_PROGRESS_LOG_INTERVAL: u64 = 60;
+
+#[cfg(feature = "libclamav")]
 pub fn init() -> Result<()> {
     info!("Initializing with libclamav {}", clamav_rs::version());
     clamav_rs::initialize().map_err(|e| anyhow!("Failed to init clamav: {:#}", e))?;
     Ok(())
 }
 
+#[cfg(not(feature = "libclamav"))]
+pub fn init() -> Result<()> {
+    debug!("Built without libclamav, using external backends");
+    Ok(())
+}
+
+#[cfg(feature = "libclamav")]
+#[must_use]
+pub fn clamav_version() -> String {
+    clamav_rs::version().to_string()
+}
+
+#[cfg(not(feature = "libclamav"))]
+#[must_use]
+pub fn clamav_version() -> String {
+    "external".to_string()
+}
+
 // clamav_rs::engine::Engine::scan_file expects &str instead of &Path
+#[cfg(feature = "libclamav")]
 fn path_to_string(path: &Path) -> Result<String> {
     let s = path.to_str().context("Path contains invalid utf-8")?;
     Ok(s.to_string())
@@ -34,46 +98,277 @@ fn path_to_string(path: &Path) -> Result<String> {
 fn is_hidden(entry: &OsStr) -> bool {
     entry
         .to_str()
-        .map_or(false, |s| s != "." && s != ".." && s.starts_with('.'))
+        .is_some_and(|s| s != "." && s != ".." && s.starts_with('.'))
+}
+
+/// If includes are configured, only files matching at least one of them are scanned
+#[must_use]
+pub fn is_included(config: &ScanConfig, path: &Path) -> bool {
+    config.includes.is_empty() || config.includes.iter().any(|i| i.matches(path))
+}
+
+/// Check the owner of a file against `skip_other_users` and `skip_uids`
+#[must_use]
+pub fn is_skipped_owner(config: &ScanConfig, uid: u32) -> bool {
+    (config.skip_other_users && uid != utils::euid()) || config.skip_uids.contains(&uid)
 }
 
 #[must_use]
 pub fn matches(config: &ScanConfig, e: &DirEntry) -> bool {
-    let path = e.path();
+    match filter_reason(config, e) {
+        Some(reason) => {
+            debug!("Skipping path {}: {}", e.path().display(), reason);
+            false
+        }
+        None => true,
+    }
+}
+
+/// Why the config excludes a path from scans
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExcludeReason {
+    Hidden,
+    Exclude(String),
+    NotIncluded,
+    ExcludeFile,
+    Owner(u32),
+    TooLarge,
+}
+
+impl fmt::Display for ExcludeReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExcludeReason::Hidden => f.write_str("name starts with dot"),
+            ExcludeReason::Exclude(exclude) => write!(f, "matches exclude ({})", exclude),
+            ExcludeReason::NotIncluded => f.write_str("doesn't match any include"),
+            ExcludeReason::ExcludeFile => f.write_str("matches exclude file"),
+            ExcludeReason::Owner(uid) => write!(f, "owned by uid {}", uid),
+            ExcludeReason::TooLarge => f.write_str("size exceeds limit"),
+        }
+    }
+}
 
+/// Why the config excludes a path from scans, `None` if it's scanned
+#[must_use]
+pub fn filter_reason(config: &ScanConfig, e: &DirEntry) -> Option<ExcludeReason> {
     if config.skip_hidden && is_hidden(e.file_name()) {
-        debug!("Skipping path {}: name starts with dot", path.display());
-        return false;
+        return Some(ExcludeReason::Hidden);
     }
+    exclude_reason(config, e, e.path())
+}
 
+/// The excludes, includes and limits matched against `path`, these also apply to configured scan paths
+/// while `skip_hidden` only applies to their contents
+#[must_use]
+pub fn exclude_reason(config: &ScanConfig, e: &DirEntry, path: &Path) -> Option<ExcludeReason> {
     for exclude in &config.excludes {
-        if exclude.matches(e.path()) {
-            debug!(
-                "Skipping path {}: matches exclude ({})",
-                path.display(),
-                exclude
-            );
-            return false;
+        if exclude.matches(path) {
+            return Some(ExcludeReason::Exclude(exclude.to_string()));
         }
     }
 
-    if let Some(skip_larger_than) = &config.skip_larger_than {
-        if e.file_type().is_file() {
-            if let Ok(md) = e.metadata() {
-                let size = md.len();
-                if size > skip_larger_than.as_bytes() {
-                    debug!(
-                        "Skipping path {}: size exceeds limit ({})",
-                        path.display(),
-                        size
-                    );
-                    return false;
+    if !e.file_type().is_dir() && !is_included(config, path) {
+        return Some(ExcludeReason::NotIncluded);
+    }
+
+    if let Some(exclude_file) = &config.exclude_matcher {
+        if exclude_file.matches(path, e.file_type().is_dir()) {
+            return Some(ExcludeReason::ExcludeFile);
+        }
+    }
+
+    // directories are still traversed, they may contain files of other owners
+    if e.file_type().is_file() && (config.skip_other_users || !config.skip_uids.is_empty()) {
+        if let Ok(md) = e.metadata() {
+            if is_skipped_owner(config, md.uid()) {
+                return Some(ExcludeReason::Owner(md.uid()));
+            }
+        }
+    }
+
+    if is_too_large(config, e) {
+        return Some(ExcludeReason::TooLarge);
+    }
+
+    None
+}
+
+/// libclamav doesn't look at files larger than `max_scansize`, these are skipped instead of reported as clean
+fn size_limit(config: &ScanConfig) -> Option<u64> {
+    let skip_larger_than = config.skip_larger_than.as_ref().map(HumanSize::as_bytes);
+    let max_scansize = config
+        .max_scansize()
+        .filter(|_| config.backend == Backend::Libclamav);
+    match (skip_larger_than, max_scansize) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn is_too_large(config: &ScanConfig, e: &DirEntry) -> bool {
+    let Some(limit) = size_limit(config) else {
+        return false;
+    };
+    e.file_type().is_file() && e.metadata().is_ok_and(|md| md.len() > limit)
+}
+
+/// Every filter of a regular scan applied to a single file that shows up after the traversal,
+/// like a new download in `watch`, `None` if the file should be scanned
+#[must_use]
+pub fn file_filter_reason(cfg: &ScanConfig, path: &Path) -> Option<String> {
+    let entry = WalkDir::new(path).max_depth(0).into_iter().next()?;
+    let e = match entry {
+        Ok(e) => e,
+        Err(err) => return Some(format!("failed to access: {:#}", err)),
+    };
+    if !e.file_type().is_file() {
+        return Some("not a regular file".to_string());
+    }
+    if let Some(reason) = filter_reason(cfg, &e) {
+        return Some(reason.to_string());
+    }
+    if cfg.ignore_files() && patterns::is_ignored_by_parents(path, false) {
+        return Some("matches ignore file".to_string());
+    }
+    if skipped_by_mime(cfg, path) {
+        return Some("file type matches skip_mime".to_string());
+    }
+    if cfg.skip_in_use {
+        let in_use = e
+            .metadata()
+            .ok()
+            .filter(|md| md.len() > cfg.skip_in_use_above());
+        if let Some(md) = in_use {
+            match InUse::new() {
+                Ok(mut in_use) => {
+                    if in_use.contains(&md) {
+                        return Some("opened for writing by another process".to_string());
+                    }
                 }
+                Err(err) => warn!("Failed to list files in use: {:#}", err),
+            }
+        }
+    }
+    None
+}
+
+/// A file or directory that couldn't be scanned
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub category: ErrorCategory,
+    pub message: String,
+}
+
+impl ScanError {
+    #[must_use]
+    pub fn new(path: PathBuf, err: &Error) -> ScanError {
+        ScanError {
+            path,
+            category: error_category(err),
+            message: format!("{:#}", err),
+        }
+    }
+}
+
+/// The message of libclamav or an external scanner, without the path of the file
+#[derive(Debug)]
+pub struct EngineError(pub String);
+
+impl EngineError {
+    #[must_use]
+    pub fn is_timeout(&self) -> bool {
+        let msg = self.0.to_lowercase();
+        msg.contains("timeout") || msg.contains("time limit")
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+/// Classify why a file couldn't be scanned
+#[must_use]
+pub fn error_category(err: &Error) -> ErrorCategory {
+    if utils::is_permission_denied(err) {
+        return ErrorCategory::Permission;
+    }
+    let io_kind = err
+        .chain()
+        .find_map(|err| err.downcast_ref::<io::Error>())
+        .map(io::Error::kind);
+    // libclamav reports its time limit as "CL_ETIMEOUT" or "Time limit reached", only the
+    // message of the engine is looked at, the path could contain anything
+    let engine_timeout = err
+        .chain()
+        .find_map(|err| err.downcast_ref::<EngineError>())
+        .is_some_and(EngineError::is_timeout);
+    if io_kind == Some(io::ErrorKind::TimedOut) || engine_timeout {
+        ErrorCategory::Timeout
+    } else if io_kind.is_some() {
+        ErrorCategory::Io
+    } else {
+        ErrorCategory::Engine
+    }
+}
+
+/// Number of errors in each category
+#[must_use]
+pub fn count_errors(errors: &[ScanError]) -> BTreeMap<ErrorCategory, usize> {
+    let mut counts = BTreeMap::new();
+    for error in errors {
+        *counts.entry(error.category).or_default() += 1;
+    }
+    counts
+}
+
+/// File type prefilter, this needs to read the start of the file so it runs in the scan workers
+#[must_use]
+pub fn skipped_by_mime(config: &ScanConfig, path: &Path) -> bool {
+    if config.skip_mime.is_empty() {
+        return false;
+    }
+
+    match magic::sniff_file(path) {
+        Ok(Some(mime)) => {
+            let skip = config
+                .skip_mime
+                .iter()
+                .any(|pattern| magic::mime_matches(pattern, mime));
+            if skip {
+                debug!("Skipping path {}: file type is {}", path.display(), mime);
             }
+            skip
+        }
+        Ok(None) => false,
+        Err(err) => {
+            debug!("Failed to detect file type: {:#}", err);
+            false
         }
     }
+}
 
-    true
+/// Quick scans only look at executables, scripts, documents and archives
+#[must_use]
+pub fn skipped_by_quick(path: &Path) -> bool {
+    match magic::is_dangerous_file(path) {
+        Ok(true) => false,
+        Ok(false) => {
+            debug!(
+                "Skipping path {}: not relevant for quick scan",
+                path.display()
+            );
+            true
+        }
+        Err(err) => {
+            debug!("Failed to detect file type: {:#}", err);
+            false
+        }
+    }
 }
 
 pub fn should_be_skipped(ft: &FileType) -> Option<&'static str> {
@@ -94,13 +389,314 @@ pub fn should_be_skipped(ft: &FileType) -> Option<&'static str> {
     }
 }
 
+/// Locations where infections are most likely to show up
+fn default_priority_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(download_dir) = dirs::download_dir() {
+        paths.push(download_dir);
+    }
+    paths.push(PathBuf::from("/tmp"));
+    if let Some(cache_dir) = dirs::cache_dir() {
+        for browser in ["mozilla", "chromium", "google-chrome"] {
+            paths.push(cache_dir.join(browser));
+        }
+    }
+    if let Some(home_dir) = dirs::home_dir() {
+        paths.push(home_dir.join(".thunderbird"));
+    }
+    paths
+}
+
+/// Priority paths that are inside of the scanned roots and not filtered by the config
+#[must_use]
+pub fn priority_paths(cfg: &ScanConfig, roots: &[PathBuf]) -> Vec<PathBuf> {
+    let candidates = match &cfg.priority_paths {
+        Some(paths) => paths
+            .iter()
+            .filter_map(|path| utils::expand_path(path).ok())
+            .collect(),
+        None => default_priority_paths(),
+    };
+
+    candidates
+        .into_iter()
+        .filter_map(|path| fs::canonicalize(path).ok())
+        .filter(|path| path.is_dir())
+        .filter(|path| {
+            roots.iter().any(|root| {
+                path.strip_prefix(root).is_ok_and(|rel| {
+                    let hidden = cfg.skip_hidden && rel.iter().any(is_hidden);
+                    !rel.as_os_str().is_empty() && !hidden
+                })
+            })
+        })
+        .filter(|path| {
+            !path.ancestors().any(|p| {
+                cfg.excludes.iter().any(|e| e.matches(p))
+                    || cfg
+                        .exclude_matcher
+                        .as_ref()
+                        .is_some_and(|e| e.matches(p, true))
+            })
+        })
+        .filter(|path| {
+            let ignored = patterns::is_ignored_by_parents(path, true)
+                || matches!(DirIgnore::load(path), Ok(Some(DirIgnore::All)));
+            !(cfg.ignore_files() && ignored)
+        })
+        .collect()
+}
+
+/// Traversal state shared by all paths of a scan, protects against bind mount loops
+#[derive(Debug, Default)]
+pub struct Visited {
+    dirs: HashSet<(u64, u64)>,
+    skip_mounts: HashSet<PathBuf>,
+    skipped: Vec<(PathBuf, SkipReason)>,
+    /// Canonical scan roots, symlinks pointing into them are scanned through their real path
+    roots: Vec<PathBuf>,
+    symlink_targets: HashSet<PathBuf>,
+    /// The first path of every file with multiple hardlinks
+    hardlinks: HashMap<(u64, u64), PathBuf>,
+    /// Additional links that weren't scanned, with the path that was scanned instead
+    links: Vec<(PathBuf, PathBuf)>,
+    in_use: Option<InUse>,
+    in_use_above: u64,
+    filtered: Option<Vec<(PathBuf, String)>>,
+    /// The paths weren't passed explicitly, the filters apply to them too
+    filter_roots: bool,
+    /// Temporary directories of unpacked archives, with the archive they belong to
+    archives: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Visited {
+    #[must_use]
+    pub fn new(cfg: &ScanConfig) -> Visited {
+        let mut visited = Visited::default();
+        if !cfg.follow_mounts {
+            match mounts::mounts() {
+                Ok(mounts) => {
+                    visited.skip_mounts = mounts
+                        .into_iter()
+                        .filter(|m| m.is_fuse())
+                        .map(|m| m.mountpoint)
+                        .collect();
+                }
+                Err(err) => warn!("Failed to list mounts: {:#}", err),
+            }
+        }
+        if cfg.skip_in_use {
+            match InUse::new() {
+                Ok(in_use) => {
+                    visited.in_use = Some(in_use);
+                    visited.in_use_above = cfg.skip_in_use_above();
+                }
+                Err(err) => warn!("Failed to list files in use: {:#}", err),
+            }
+        }
+        visited
+    }
+
+    pub fn set_roots(&mut self, roots: &[PathBuf]) {
+        self.roots = roots.to_vec();
+    }
+
+    /// Apply excludes and includes to the scanned paths themselves, for paths from the config
+    pub fn filter_roots(&mut self) {
+        self.filter_roots = true;
+    }
+
+    /// Match the members of an unpacked archive as if the archive was a directory
+    pub fn add_archive(&mut self, dir: &Path, archive: &Path) {
+        self.archives
+            .push((dir.to_path_buf(), archive.to_path_buf()));
+    }
+
+    fn logical_path<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        self.archives
+            .iter()
+            .find_map(|(dir, archive)| archive::logical_path(dir, archive, path))
+            .map_or(Cow::Borrowed(path), Cow::Owned)
+    }
+
+    /// Hardlinks that were skipped, as `(scanned path, skipped path)`
+    pub fn take_hardlinks(&mut self) -> Vec<(PathBuf, PathBuf)> {
+        mem::take(&mut self.links)
+    }
+
+    /// Files and directories that couldn't be traversed
+    pub fn take_skipped(&mut self) -> Vec<(PathBuf, SkipReason)> {
+        mem::take(&mut self.skipped)
+    }
+
+    /// Record why paths were filtered during traversal, for `scan --dry-run`
+    pub fn record_filtered(&mut self) {
+        self.filtered = Some(Vec::new());
+    }
+
+    /// Paths that were filtered with the reason, if `record_filtered` was called
+    pub fn take_filtered(&mut self) -> Vec<(PathBuf, String)> {
+        self.filtered.take().unwrap_or_default()
+    }
+
+    fn filter(&mut self, e: &DirEntry, reason: String) {
+        debug!("Skipping path {}: {}", e.path().display(), reason);
+        if let Some(filtered) = &mut self.filtered {
+            filtered.push((e.path().to_path_buf(), reason));
+        }
+    }
+
+    /// Returns the reason if the directory was already traversed or should not be entered
+    fn enter(&mut self, e: &DirEntry) -> Option<String> {
+        if !e.file_type().is_dir() {
+            return self.enter_file(e);
+        }
+
+        if self.skip_mounts.contains(e.path()) {
+            return Some("fuse mount (use --follow-mounts to scan)".to_string());
+        }
+
+        let md = e.metadata().ok()?;
+        if !self.dirs.insert((md.dev(), md.ino())) {
+            return Some("directory was already visited".to_string());
+        }
+
+        None
+    }
+
+    /// Returns the reason for file symlinks and hardlinks whose content is scanned anyway
+    fn enter_file(&mut self, e: &DirEntry) -> Option<String> {
+        if let Ok(md) = e.metadata() {
+            if let Some(in_use) = &mut self.in_use {
+                if md.is_file() && md.len() > self.in_use_above && in_use.contains(&md) {
+                    self.skipped
+                        .push((e.path().to_path_buf(), SkipReason::InUse));
+                    return Some("opened for writing by another process".to_string());
+                }
+            }
+            if md.is_file() && md.nlink() > 1 {
+                match self.hardlinks.entry((md.dev(), md.ino())) {
+                    Entry::Occupied(first) => {
+                        self.links
+                            .push((first.get().clone(), e.path().to_path_buf()));
+                        return Some(format!("hardlink of {}", first.get().display()));
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(e.path().to_path_buf());
+                    }
+                }
+            }
+        }
+
+        if e.depth() == 0 || !e.path_is_symlink() {
+            return None;
+        }
+        let target = fs::canonicalize(e.path()).ok()?;
+        if self.roots.iter().any(|root| target.starts_with(root)) {
+            return Some("symlink target is inside of the scanned paths".to_string());
+        }
+        if !self.symlink_targets.insert(target) {
+            return Some("symlink target was already scanned".to_string());
+        }
+        None
+    }
+}
+
 pub fn ingest_directory(cfg: &ScanConfig, tx: &Sender<DirEntry>, path: &Path) {
-    let walker = WalkDir::new(path).into_iter();
-    for entry in walker.filter_entry(|e| matches(cfg, e)) {
+    ingest_directory_with(cfg, &mut Visited::default(), tx, path);
+}
+
+pub fn ingest_directory_with(
+    cfg: &ScanConfig,
+    visited: &mut Visited,
+    tx: &Sender<DirEntry>,
+    path: &Path,
+) {
+    walk_directory(cfg, visited, path, |entry| tx.send(entry).is_ok());
+}
+
+/// Traverse a directory and pass every file that should be scanned to `f`, stops if `f` returns false
+#[tracing::instrument(name = "traverse", skip_all, fields(path = %path.display()))]
+pub fn walk_directory<F: FnMut(DirEntry) -> bool>(
+    cfg: &ScanConfig,
+    visited: &mut Visited,
+    path: &Path,
+    mut f: F,
+) {
+    let mut walker = WalkDir::new(path).follow_links(cfg.follow_symlinks);
+    if let Some(max_depth) = cfg.max_depth {
+        walker = walker.max_depth(max_depth);
+    }
+    // walkdir reports symlink loops as errors, directories reached through
+    // multiple symlinks are only visited once
+    let walker = walker.into_iter();
+    let mut errors = Vec::new();
+    // symlinks, sockets and devices, these aren't filtered by the config
+    let mut special = Vec::new();
+    // ignore files of the directories above the current entry, by depth
+    let mut ignores = Vec::<(usize, DirIgnore)>::new();
+    // paths that were requested explicitly are not subject to filters, members of unpacked
+    // archives are matched as if the archive was a directory
+    let filter = |e: &DirEntry| {
+        let reason = if e.depth() > 0 && cfg.skip_hidden && is_hidden(e.file_name()) {
+            Some(ExcludeReason::Hidden)
+        } else if e.depth() > 0 || visited.filter_roots {
+            exclude_reason(cfg, e, &visited.logical_path(e.path()))
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            if reason == ExcludeReason::TooLarge {
+                visited
+                    .skipped
+                    .push((e.path().to_path_buf(), SkipReason::TooLarge));
+            }
+            visited.filter(e, reason.to_string());
+            return false;
+        }
+        if cfg.ignore_files() {
+            // entries are visited depth first, everything that isn't shallower is done
+            ignores.retain(|(depth, _)| *depth < e.depth());
+            if ignores
+                .iter()
+                .any(|(_, i)| i.matches(e.path(), e.file_type().is_dir()))
+            {
+                visited.filter(e, "matches ignore file".to_string());
+                return false;
+            }
+        }
+        if let Some(reason) = visited.enter(e) {
+            visited.filter(e, reason);
+            return false;
+        }
+        if cfg.ignore_files() && e.file_type().is_dir() {
+            match DirIgnore::load(e.path()) {
+                Ok(Some(DirIgnore::All)) if e.depth() > 0 => {
+                    visited.filter(e, "directory is marked as ignored".to_string());
+                    return false;
+                }
+                Ok(Some(DirIgnore::All)) => {
+                    info!("Scanning {:?} even though it's marked as ignored", e.path());
+                }
+                Ok(Some(ignore)) => ignores.push((e.depth(), ignore)),
+                Ok(None) => (),
+                Err(err) => warn!("{:#}", err),
+            }
+        }
+        true
+    };
+    for entry in walker.filter_entry(filter) {
         let entry = match entry {
             Ok(entry) => entry,
             Err(err) => {
                 warn!("Failed to scan directory: {:#}", err);
+                if let (Some(path), None) = (err.path(), err.loop_ancestor()) {
+                    let reason = match err.io_error().map(|err| err.kind()) {
+                        Some(io::ErrorKind::PermissionDenied) => SkipReason::PermissionDenied,
+                        _ => SkipReason::Unreadable,
+                    };
+                    errors.push((path.to_path_buf(), reason));
+                }
                 continue;
             }
         };
@@ -112,170 +708,1848 @@ pub fn ingest_directory(cfg: &ScanConfig, tx: &Sender<DirEntry>, path: &Path) {
 
         if let Some(reason) = should_be_skipped(&ft) {
             debug!("{}: {}", reason, path.display());
+            if !ft.is_dir() {
+                special.push((path.to_path_buf(), reason.to_lowercase()));
+            }
             continue;
         }
 
-        if tx.send(entry).is_err() {
+        if !f(entry) {
             break;
         }
     }
+    visited.skipped.extend(errors);
+    if let Some(filtered) = &mut visited.filtered {
+        filtered.extend(special);
+    }
+}
+
+/// Run the work for a single file, a panic is returned as error so the worker keeps going
+pub fn catch_panic<T, F: FnOnce() -> Result<T>>(path: &Path, f: F) -> Result<T> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(ret) => ret,
+        Err(payload) => {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown cause");
+            bail!("Scan of {:?} panicked: {}", path, msg)
+        }
+    }
+}
+
+/// Number of files and bytes a scan is going to read, counted before they are scanned
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Estimate {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl Estimate {
+    /// Progress in percent, by bytes since large files take longer to scan
+    #[must_use]
+    pub fn percent(&self, bytes_scanned: u64) -> f64 {
+        if self.bytes == 0 {
+            return 100.0;
+        }
+        (bytes_scanned as f64 / self.bytes as f64 * 100.0).min(100.0)
+    }
+
+    /// The remaining time if the scan continues at its average speed so far
+    #[must_use]
+    pub fn eta(&self, bytes_scanned: u64, elapsed: Duration) -> Option<Duration> {
+        let secs = elapsed.as_secs_f64();
+        if bytes_scanned == 0 || secs <= 0.0 {
+            return None;
+        }
+        let remaining = self.bytes.saturating_sub(bytes_scanned) as f64;
+        Some(Duration::from_secs_f64(
+            remaining * secs / bytes_scanned as f64,
+        ))
+    }
+}
+
+/// Count the files that would be scanned, returns `None` if `stop` was set before counting finished
+///
+/// Quick scans read the first bytes of each file to detect executables, files skipped
+/// by `skip_mime` are still counted.
+pub fn estimate(
+    cfg: &ScanConfig,
+    visited: &mut Visited,
+    paths: &[PathBuf],
+    stop: &AtomicBool,
+) -> Option<Estimate> {
+    let mut estimate = Estimate::default();
+    for path in paths {
+        walk_directory(cfg, visited, path, |entry| {
+            if !(cfg.quick && entry.depth() > 0 && skipped_by_quick(entry.path())) {
+                estimate.files += 1;
+                estimate.bytes += entry.metadata().map(|md| md.len()).unwrap_or(0);
+            }
+            !stop.load(Ordering::Relaxed)
+        });
+    }
+    (!stop.load(Ordering::Relaxed)).then_some(estimate)
+}
+
+/// Collect all files first and sort them by modification time, newest first
+fn index_newest_first(cfg: &ScanConfig, visited: &mut Visited, paths: &[PathBuf]) -> Vec<DirEntry> {
+    let mut entries = Vec::new();
+    for path in paths {
+        info!("Indexing directory {}...", path.display());
+        walk_directory(cfg, visited, path, |entry| {
+            entries.push(entry);
+            true
+        });
+    }
+    info!("Indexed {} files, scanning newest first", entries.len());
+    entries.sort_by_cached_key(|e| {
+        let modified = e.metadata().ok().and_then(|md| md.modified().ok());
+        Reverse(modified)
+    });
+    entries
+}
+
+enum ScanEngine {
+    #[cfg(feature = "libclamav")]
+    Libclamav(Engine),
+    External(External),
+}
+
+/// Settings of the scan engine that are applied when the signatures are loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineOptions {
+    /// libclamav doesn't scan more than this many bytes of a single file
+    pub max_scansize: Option<u64>,
+    /// Load signatures written in ClamAV bytecode, these find more but are slower
+    pub bytecode: bool,
+    /// Report the first heuristic match instead of continuing to look for a signature match
+    pub heuristic_precedence: bool,
+}
+
+impl Default for EngineOptions {
+    fn default() -> EngineOptions {
+        EngineOptions {
+            max_scansize: None,
+            bytecode: true,
+            heuristic_precedence: false,
+        }
+    }
+}
+
+impl EngineOptions {
+    #[must_use]
+    pub fn from_config(config: &ScanConfig) -> EngineOptions {
+        EngineOptions {
+            max_scansize: config.max_scansize(),
+            bytecode: config.bytecode(),
+            heuristic_precedence: config.heuristic_precedence,
+        }
+    }
 }
 
 pub struct Scanner {
-    engine: Engine,
-    signature_count: u32,
+    engine: ScanEngine,
+    sources: Vec<(PathBuf, u32)>,
     signatures_age: DateTime<Utc>,
+    #[cfg_attr(not(feature = "libclamav"), allow(dead_code))]
+    heuristic_precedence: bool,
 }
 
 impl Scanner {
     pub fn new(path: &Path) -> Result<Scanner> {
-        let scanner = Engine::new();
-        info!("Loading database from {}...", path.display());
+        Self::with_backend(Backend::default(), path)
+    }
+
+    /// Verify and load all databases of the config with the configured backend
+    pub fn from_config(config: &Config) -> Result<Scanner> {
+        verify::before_load(&config.update)?;
+        Self::with_options(
+            config.scan.backend,
+            &config.update.path,
+            &config.extra_databases(),
+            &EngineOptions::from_config(&config.scan),
+        )
+    }
+
+    pub fn with_backend(backend: Backend, path: &Path) -> Result<Scanner> {
+        Self::with_databases(backend, path, &[])
+    }
+
+    /// Load the official database directory and additional directories or signature files
+    pub fn with_databases(backend: Backend, path: &Path, extra: &[PathBuf]) -> Result<Scanner> {
+        Self::with_options(backend, path, extra, &EngineOptions::default())
+    }
+
+    /// Like `with_databases`, with limits and detection settings for the engine
+    #[tracing::instrument(skip_all, fields(backend = ?backend))]
+    pub fn with_options(
+        backend: Backend,
+        path: &Path,
+        extra: &[PathBuf],
+        options: &EngineOptions,
+    ) -> Result<Scanner> {
+        let databases = std::iter::once(path.to_path_buf())
+            .chain(extra.iter().cloned())
+            .collect::<Vec<_>>();
 
-        let path_str = path_to_string(path)?;
-        let stats = scanner
-            .load_databases(&path_str)
-            .map_err(|e| anyhow!("Failed to load clamav database: {:#}", e))?;
+        let (engine, sources) = match backend {
+            #[cfg(feature = "libclamav")]
+            Backend::Libclamav => {
+                let scanner = Engine::new();
+                if !options.bytecode {
+                    debug!("Disabling bytecode signatures");
+                    scanner
+                        .set_bytecode_mode(BytecodeMode::Off)
+                        .map_err(|e| anyhow!("Failed to disable bytecode signatures: {:#}", e))?;
+                }
+                let mut sources = Vec::new();
+                for path in databases {
+                    info!("Loading database from {}...", path.display());
+                    let path_str = path_to_string(&path)?;
+                    let stats = scanner.load_databases(&path_str).map_err(|e| {
+                        anyhow!("Failed to load clamav database {:?}: {:#}", path, e)
+                    })?;
+                    sources.push((path, stats.signature_count));
+                }
+
+                if let Some(max) = options.max_scansize {
+                    debug!("Limiting libclamav to {} bytes per file", max);
+                    scanner
+                        .set_max_scansize(max)
+                        .and_then(|_| scanner.set_max_filesize(max))
+                        .map_err(|e| anyhow!("Failed to configure clamav limits: {:#}", e))?;
+                }
+
+                info!("Compiling clamav rules...");
+                scanner
+                    .compile()
+                    .map_err(|e| anyhow!("Failed to compile clamav rules: {:#}", e))?;
+
+                (ScanEngine::Libclamav(scanner), sources)
+            }
+            #[cfg(not(feature = "libclamav"))]
+            Backend::Libclamav => bail!("libredefender was built without libclamav support"),
+            Backend::Clamdscan | Backend::Clamscan => {
+                if options.max_scansize.is_some() {
+                    warn!("scan.max_memory only limits libclamav, configure MaxScanSize for {:?} instead", backend);
+                }
+                let external = External::new(backend, &databases, options)?;
+                let sources = databases
+                    .into_iter()
+                    .map(|path| {
+                        let count = count_signatures(&path);
+                        (path, count)
+                    })
+                    .collect();
+                (ScanEngine::External(external), sources)
+            }
+        };
 
         info!("Checking database age...");
-        let daily_path = Self::find_daily_db_path(path)?;
+        let signatures_age = database_age(path)?;
 
-        let mut buf = [0; 512];
-        read_clamav_header(&daily_path, &mut buf)?;
-        let signatures_age = parse_database_age(&buf)?;
+        Ok(Scanner {
+            engine,
+            sources,
+            signatures_age,
+            heuristic_precedence: options.heuristic_precedence,
+        })
+    }
 
-        info!("Compiling clamav rules...");
-        scanner
-            .compile()
-            .map_err(|e| anyhow!("Failed to compile clamav rules: {:#}", e))?;
+    #[cfg(feature = "libclamav")]
+    fn scan_settings(&self) -> ScanSettings {
+        let mut builder = ScanSettingsBuilder::new();
+        if self.heuristic_precedence {
+            builder.enable_heuristic_precedence();
+        }
+        builder.build()
+    }
+
+    #[must_use]
+    pub fn signature_count(&self) -> usize {
+        self.sources.iter().map(|(_, n)| *n as usize).sum()
+    }
+
+    /// Number of signatures loaded from each database directory or file
+    #[must_use]
+    pub fn signature_sources(&self) -> &[(PathBuf, u32)] {
+        &self.sources
+    }
+
+    #[must_use]
+    pub fn signatures_age(&self) -> DateTime<Utc> {
+        self.signatures_age
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(path = %path.display()))]
+    /// Returns true if a threat was found, it's also sent to `results_tx`
+    pub fn scan_file(&self, path: &Path, results_tx: &Sender<(PathBuf, String)>) -> Result<bool> {
+        debug!("Scanning file {}...", path.display());
+
+        let hit = match &self.engine {
+            #[cfg(feature = "libclamav")]
+            ScanEngine::Libclamav(engine) => {
+                let path_str = path_to_string(path)?;
+                let mut settings = self.scan_settings();
+                let hit = engine
+                    .scan_file(&path_str, &mut settings)
+                    .map_err(|e| Error::new(EngineError(e.to_string())))
+                    .with_context(|| anyhow!("Failed to scan file {:?}", path))?;
+                match hit {
+                    ScanResult::Virus(name) => Some(name),
+                    ScanResult::Clean | ScanResult::Whitelisted => None,
+                }
+            }
+            ScanEngine::External(external) => external
+                .scan_file(path)
+                .with_context(|| anyhow!("Failed to scan file {:?}", path))?,
+        };
+        Ok(self.report(path, hit, results_tx))
+    }
+
+    /// Scan a file that was already opened, e.g. after hashing it, so the bytes that are scanned can't be swapped
+    ///
+    /// Only libclamav scans file descriptors, see `supports_descriptors`.
+    #[tracing::instrument(level = "debug", skip_all, fields(path = %path.display()))]
+    pub fn scan_descriptor(
+        &self,
+        file: &mut File,
+        path: &Path,
+        results_tx: &Sender<(PathBuf, String)>,
+    ) -> Result<bool> {
+        debug!("Scanning file {} by descriptor...", path.display());
+        file.seek(SeekFrom::Start(0))?;
+        let hit = match &self.engine {
+            #[cfg(feature = "libclamav")]
+            ScanEngine::Libclamav(engine) => {
+                let mut settings = self.scan_settings();
+                let hit = engine
+                    .scan_descriptor(file.as_raw_fd(), &mut settings)
+                    .map_err(|e| Error::new(EngineError(e.to_string())))
+                    .with_context(|| anyhow!("Failed to scan file {:?}", path))?;
+                match hit {
+                    ScanResult::Virus(name) => Some(name),
+                    ScanResult::Clean | ScanResult::Whitelisted => None,
+                }
+            }
+            ScanEngine::External(_) => bail!("External scanners can only scan by path"),
+        };
+        Ok(self.report(path, hit, results_tx))
+    }
+
+    /// Whether `scan_descriptor` works with this engine
+    #[must_use]
+    pub fn supports_descriptors(&self) -> bool {
+        !matches!(self.engine, ScanEngine::External(_))
+    }
+
+    fn report(
+        &self,
+        path: &Path,
+        hit: Option<String>,
+        results_tx: &Sender<(PathBuf, String)>,
+    ) -> bool {
+        let found = hit.is_some();
+        if let Some(name) = hit {
+            warn!(
+                detection = true,
+                "Found threat: {} ({:?})",
+                path.display(),
+                name
+            );
+            results_tx.send((path.to_path_buf(), name)).ok();
+        }
+
+        debug!("Finished scanning file {}", path.display());
+
+        found
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Event {
+    Threat {
+        path: PathBuf,
+        name: String,
+        /// The detected file if `path` is an unpacked archive
+        member: Option<PathBuf>,
+    },
+    Progress {
+        files_scanned: usize,
+        bytes_scanned: u64,
+        /// One of the files that is being scanned right now
+        current_path: Option<PathBuf>,
+        /// Set with `scan.estimate`, once all files were counted
+        estimate: Option<Estimate>,
+        /// Time since the scan started
+        elapsed: Duration,
+    },
+    /// A file couldn't be scanned
+    Error {
+        path: PathBuf,
+        category: ErrorCategory,
+        error: String,
+    },
+    Finished {
+        threats: usize,
+        skipped: usize,
+    },
+}
+
+/// Stops a running scan, can be cloned and triggered from any thread
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Shared state to observe, pause and cancel a running scan from another thread
+#[derive(Debug, Default)]
+pub struct Control {
+    running: AtomicBool,
+    paused: AtomicBool,
+    token: CancellationToken,
+}
+
+impl Control {
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    /// Stop the running scan, the token stays cancelled until the next scan starts
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    #[must_use]
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    #[must_use]
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Mark a scan as running while `f` is executed, cancelling is reset before and pausing after it
+    pub fn run_while<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        // reset at the start, so tokens of a cancelled scan keep reporting it after it returned
+        self.token.reset();
+        self.running.store(true, Ordering::SeqCst);
+        let ret = f();
+        self.running.store(false, Ordering::SeqCst);
+        self.resume();
+        ret
+    }
+
+    fn wait_while_paused(&self) {
+        while self.is_paused() && !self.token.is_cancelled() {
+            thread::sleep(Duration::from_millis(500));
+        }
+    }
+}
+
+/// Everything a scan found, detections inside of archives are reported on the archive
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScanResults {
+    /// The canonicalized paths that were scanned
+    pub roots: Vec<PathBuf>,
+    pub threats: Vec<(PathBuf, String)>,
+    /// Detected files inside of unpacked archives, as `(archive, member)`
+    pub members: Vec<(PathBuf, PathBuf)>,
+    pub skipped: Vec<(PathBuf, SkipReason)>,
+    /// Files and directories the scan failed on, these are also listed in `skipped`
+    pub errors: Vec<ScanError>,
+    /// Files that differ from their distro package, these were scanned regardless
+    pub modified: Vec<PathBuf>,
+    pub files_scanned: usize,
+    pub bytes_scanned: u64,
+    /// Files that were skipped because `scan.clean_cache` already knew them, not part of `files_scanned`
+    pub files_cached: usize,
+    /// Package files that match their distro package and weren't scanned, not part of `files_scanned`
+    pub files_unmodified: usize,
+    pub signature_count: usize,
+    pub signature_sources: Vec<(PathBuf, usize)>,
+    pub signatures_age: DateTime<Utc>,
+    /// The scan was stopped with a `CancellationToken` and didn't cover all files
+    pub cancelled: bool,
+}
+
+/// A scan that can be embedded in other programs
+///
+/// Unlike `run` this doesn't load the config, write the database or show notifications. It still
+/// updates the clean cache if `scan.clean_cache` is enabled, and reloads the signatures during the
+/// scan if `update.reload_on_change` is.
+pub struct ScanJob {
+    config: Config,
+    paths: Vec<PathBuf>,
+    control: Arc<Control>,
+    lower_priority: bool,
+    scope: bool,
+}
+
+/// What to scan, the engine is loaded by the process that scans
+#[derive(Serialize, Deserialize)]
+struct Plan {
+    config: Config,
+    roots: Vec<PathBuf>,
+    explicit: bool,
+    priority: Vec<PathBuf>,
+    traverse: Vec<PathBuf>,
+    lower_priority: bool,
+}
+
+/// Sent to `libredefender scan-child`, it sets itself up and scans like this process would
+#[derive(Serialize, Deserialize)]
+struct ChildJob {
+    plan: Plan,
+    user: Option<privdrop::User>,
+    cgroup: Option<(CgroupConfig, Option<u64>)>,
+    /// The archives and the directories they were unpacked into by the parent
+    archives: Vec<(PathBuf, PathBuf)>,
+}
+
+impl ScanJob {
+    #[must_use]
+    pub fn new(config: Config) -> ScanJob {
+        ScanJob {
+            config,
+            paths: Vec::new(),
+            control: Arc::new(Control::default()),
+            lower_priority: false,
+            scope: false,
+        }
+    }
+
+    /// Scan these paths instead of `scan.paths` or the home directory
+    #[must_use]
+    pub fn paths(mut self, paths: Vec<PathBuf>) -> ScanJob {
+        self.paths = paths;
+        self
+    }
+
+    /// Share the pause and cancel state with another thread
+    #[must_use]
+    pub fn control(mut self, control: Arc<Control>) -> ScanJob {
+        self.control = control;
+        self
+    }
+
+    /// Set the configured io priority on every worker thread
+    #[must_use]
+    pub fn lower_priority(mut self, lower_priority: bool) -> ScanJob {
+        self.lower_priority = lower_priority;
+        self
+    }
+
+    /// Apply the cgroup settings to a child process that runs the scan, this process isn't moved
+    #[must_use]
+    pub fn scope(mut self, scope: bool) -> ScanJob {
+        self.scope = scope;
+        self
+    }
+
+    #[must_use]
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.control.cancellation_token()
+    }
+
+    pub fn run(self) -> Result<ScanResults> {
+        self.run_with(|_| ())
+    }
+
+    pub fn run_with<F: FnMut(Event)>(self, mut on_event: F) -> Result<ScanResults> {
+        let control = self.control.clone();
+        control.run_while(|| self.execute(&mut on_event))
+    }
+
+    fn execute<F: FnMut(Event)>(self, on_event: &mut F) -> Result<ScanResults> {
+        let ScanJob {
+            config,
+            paths,
+            control,
+            lower_priority,
+            scope,
+        } = self;
+
+        // excludes still apply to the configured paths, but not to paths that were requested explicitly
+        let explicit = !paths.is_empty();
+        let paths = resolve_paths(&config.scan, paths)?;
+        let roots = paths
+            .iter()
+            .map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
+            .collect::<Vec<_>>();
+
+        // likely infection locations are scanned first, they are only traversed once
+        let priority = priority_paths(&config.scan, &roots);
+        if !priority.is_empty() {
+            info!("Scanning priority paths first: {:?}", priority);
+        }
+
+        // archives and disk images are unpacked so their members can be scanned individually
+        let mut extracted = Vec::new();
+        let mut traverse = Vec::new();
+        for path in paths {
+            if path.is_file() && archive::is_archive(&path) {
+                match Extracted::new(&path, config.scan.max_unpacked_size()) {
+                    Ok(archive) => {
+                        traverse.push(archive.path().to_path_buf());
+                        extracted.push(archive);
+                        continue;
+                    }
+                    Err(err) => warn!(
+                        "Failed to unpack {:?}, scanning it as a single file: {:#}",
+                        path, err
+                    ),
+                }
+            }
+            traverse.push(path);
+        }
+
+        let user = privdrop::scan_user(config.scan.user.as_deref())
+            .context("Failed to drop privileges")?;
+        let cgroup = if scope {
+            scan_cgroup(&config.scan)
+        } else {
+            None
+        };
+        let plan = Plan {
+            config,
+            roots,
+            explicit,
+            priority,
+            traverse,
+            lower_priority,
+        };
+        match (user, cgroup) {
+            (None, None) => Self::scan_here(plan, control, &extracted, on_event),
+            // libclamav runs in a child process, the results are written by this one
+            #[cfg(target_os = "linux")]
+            (user, cgroup) => {
+                if plan.config.scan.clean_cache {
+                    // the child could mark anything as clean for later scans in this process
+                    info!("Not using scan.clean_cache, the scan runs in a separate process");
+                }
+                if let Some(user) = &user {
+                    // the temporary directories are only accessible by root
+                    for archive in &extracted {
+                        std::os::unix::fs::chown(archive.path(), Some(user.uid), Some(user.gid))
+                            .context("Failed to change owner of unpacked archive")?;
+                    }
+                }
+                let job = ChildJob {
+                    plan,
+                    user,
+                    cgroup,
+                    archives: extracted
+                        .iter()
+                        .map(|a| (a.archive.clone(), a.path().to_path_buf()))
+                        .collect(),
+                };
+                privsep::run(&job, &control, on_event)
+            }
+            #[cfg(not(target_os = "linux"))]
+            (_, cgroup) => {
+                if let Some((cgroup, max_memory)) = &cgroup {
+                    setup_cgroup(cgroup, *max_memory);
+                }
+                Self::scan_here(plan, control, &extracted, on_event)
+            }
+        }
+    }
+
+    /// Load the engine and scan in this process
+    fn scan_here(
+        plan: Plan,
+        control: Arc<Control>,
+        extracted: &[Extracted],
+        on_event: &mut dyn FnMut(Event),
+    ) -> Result<ScanResults> {
+        let scanner = Scanner::from_config(&plan.config)?;
+        let clean_cache = if plan.config.scan.clean_cache {
+            match load_clean_cache(&plan.config) {
+                Ok(cache) => Some(cache),
+                Err(err) => {
+                    warn!("Failed to load clean cache, scanning all files: {:#}", err);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let results = Self::scan_files(
+            plan,
+            scanner,
+            clean_cache.clone(),
+            control,
+            extracted,
+            on_event,
+        )?;
+
+        if let Some(cache) = &clean_cache {
+            let ret = cache::path().and_then(|path| cache.store(&path));
+            if let Err(err) = ret {
+                warn!("Failed to write clean cache: {:#}", err);
+            }
+        }
+        Ok(results)
+    }
+
+    /// Entry point of `libredefender scan-child`, started for `scan.user` and cgroup scopes
+    #[cfg(target_os = "linux")]
+    pub fn run_child() -> ! {
+        privsep::child(|job: ChildJob, control, on_event| {
+            let ChildJob {
+                mut plan,
+                user,
+                cgroup,
+                archives,
+            } = job;
+            if let Some((cgroup, max_memory)) = &cgroup {
+                setup_cgroup(cgroup, *max_memory);
+            }
+            // everything root needs to read is loaded before the privileges are dropped
+            config::load_exclude_file(&mut plan.config.scan)?;
+            init()?;
+            let scanner = Scanner::from_config(&plan.config)?;
+            if let Some(user) = &user {
+                privdrop::drop_privileges(user).context("Failed to drop privileges")?;
+                privsep::die_with_parent();
+            }
+            let extracted = archives
+                .into_iter()
+                .map(|(archive, path)| Extracted::unpacked_in(archive, path))
+                .collect::<Vec<_>>();
+            Self::scan_files(plan, scanner, None, control, &extracted, on_event)
+        })
+    }
+
+    fn scan_files(
+        plan: Plan,
+        scanner: Scanner,
+        clean_cache: Option<Arc<CleanCache>>,
+        control: Arc<Control>,
+        extracted: &[Extracted],
+        on_event: &mut dyn FnMut(Event),
+    ) -> Result<ScanResults> {
+        let Plan {
+            config,
+            roots,
+            explicit,
+            priority,
+            traverse,
+            lower_priority,
+        } = plan;
+        let manifest = if config.scan.skip_package_files {
+            load_manifest()
+        } else {
+            None
+        };
+        let token = control.cancellation_token();
+        let (results_tx, results_rx) = crossbeam_channel::unbounded();
+        let (errors_tx, errors_rx) = crossbeam_channel::unbounded();
+        let modified = Arc::new(Mutex::new(Vec::new()));
+        let signature_count = scanner.signature_count();
+        let signature_sources = scanner
+            .signature_sources()
+            .iter()
+            .map(|(path, n)| (path.clone(), *n as usize))
+            .collect();
+        let signatures_age = scanner.signatures_age();
+        let scanner = SharedScanner::new(scanner);
+        if config.update.reload_on_change {
+            if let Err(err) = reload::watch(&scanner, &config) {
+                warn!("Failed to watch for signature updates: {:#}", err);
+            }
+        }
+
+        let cpus = config.scan.workers();
+        let quick = config.scan.quick;
+        let scan_config = Arc::new(config.scan);
+        if quick {
+            info!("Quick scan, only scanning executables, scripts, documents and archives");
+        }
+
+        let files_scanned = Arc::new(AtomicUsize::new(0));
+        let bytes_scanned = Arc::new(AtomicU64::new(0));
+        let files_cached = Arc::new(AtomicUsize::new(0));
+        let files_unmodified = Arc::new(AtomicUsize::new(0));
+        let current_path = Arc::new(Mutex::new(None));
+        let started = Instant::now();
+        let new_visited = || {
+            let mut visited = Visited::new(&scan_config);
+            visited.set_roots(&roots);
+            if !explicit {
+                visited.filter_roots();
+            }
+            for archive in extracted {
+                visited.add_archive(archive.path(), &archive.archive);
+            }
+            visited
+        };
+        let estimate = Arc::new(OnceLock::new());
+        // stops counting once the scan is done, finished or cancelled
+        let stop_estimate = Arc::new(AtomicBool::new(false));
+        let estimate_thread = scan_config.estimate.then(|| {
+            let scan_config = scan_config.clone();
+            let mut visited = new_visited();
+            let paths = traverse.clone();
+            let estimate = estimate.clone();
+            let stop = stop_estimate.clone();
+            // counting runs next to the scan instead of delaying it
+            thread::spawn(move || {
+                if let Some(total) = self::estimate(&scan_config, &mut visited, &paths, &stop) {
+                    info!(
+                        "Counted {} file(s) ({:.1} MB) to scan",
+                        total.files,
+                        total.bytes as f64 / 1_000_000.0
+                    );
+                    estimate.set(total).ok();
+                }
+            })
+        });
+        let mut progress_log = match scan_config
+            .progress_log_interval
+            .unwrap_or(DEFAULT_PROGRESS_LOG_INTERVAL)
+        {
+            0 => None,
+            secs => Some(ProgressLog::new(Duration::from_secs(secs))),
+        };
+
+        let read_throttle = scan_config.max_read_mbps.map(|mbps| {
+            info!("Limiting reads to {} MB/s", mbps);
+            Arc::new(Throttle::new(mbps * 1_000_000.0))
+        });
+        let files_throttle = scan_config.files_per_second.map(|n| {
+            info!("Limiting scans to {} files per second", n);
+            Arc::new(Throttle::new(n))
+        });
+
+        let (fs_tx, fs_rx) = crossbeam_channel::bounded::<DirEntry>(128);
+        let mut pools = vec![(None, cpus, fs_rx)];
+        let mut router = Router::new(fs_tx);
+        for (path, path_override) in &scan_config.path_overrides {
+            let path = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+            // pools for paths that aren't part of this scan would stay idle
+            if !roots
+                .iter()
+                .any(|root| root.starts_with(&path) || path.starts_with(root))
+            {
+                continue;
+            }
+            let workers = path_override.concurrency.unwrap_or(cpus);
+            info!("Scanning {:?} with {} separate worker(s)", path, workers);
+            let (tx, rx) = crossbeam_channel::bounded::<DirEntry>(128);
+            pools.push((Some(path_override.clone()), workers, rx));
+            router.add(path, tx);
+        }
+
+        info!("Spawning {} scanner(s)...", cpus);
+        let worker = Worker {
+            config: scan_config.clone(),
+            scanner,
+            clean_cache,
+            manifest,
+            modified: modified.clone(),
+            results_tx: results_tx.clone(),
+            errors_tx: errors_tx.clone(),
+            control: control.clone(),
+            files_scanned: files_scanned.clone(),
+            bytes_scanned: bytes_scanned.clone(),
+            files_cached: files_cached.clone(),
+            files_unmodified: files_unmodified.clone(),
+            current_path: current_path.clone(),
+            read_throttle,
+            files_throttle,
+        };
+        for (path_override, workers, fs_rx) in pools {
+            for _ in 0..workers {
+                let worker = worker.clone();
+                let path_override = path_override.clone();
+                let fs_rx = fs_rx.clone();
+                thread::spawn(move || worker.run(&fs_rx, path_override.as_ref(), lower_priority));
+            }
+        }
+        mem::drop(worker);
+        mem::drop(results_tx);
+        mem::drop(errors_tx);
+
+        let traversal = {
+            let token = token.clone();
+            let mut visited = new_visited();
+            thread::spawn(move || {
+                for path in priority {
+                    if token.is_cancelled() {
+                        break;
+                    }
+                    info!("Scanning directory {}...", path.display());
+                    walk_directory(&scan_config, &mut visited, &path, |e| router.send(e));
+                }
+
+                if token.is_cancelled() {
+                    // nothing left to do
+                } else if scan_config.newest_first {
+                    for entry in index_newest_first(&scan_config, &mut visited, &traverse) {
+                        if !router.send(entry) {
+                            break;
+                        }
+                    }
+                } else {
+                    for path in traverse {
+                        if token.is_cancelled() {
+                            break;
+                        }
+                        info!("Scanning directory {}...", path.display());
+                        walk_directory(&scan_config, &mut visited, &path, |e| router.send(e));
+                    }
+                }
+                if !token.is_cancelled() {
+                    router.finish();
+                }
+                debug!("Finished traversing directories");
+                (visited.take_skipped(), visited.take_hardlinks())
+            })
+        };
+
+        let mut threats = Vec::new();
+        let mut members = Vec::new();
+        // the paths the scanner reported, to match them with skipped hardlinks
+        let mut raw_threats = Vec::new();
+        let mut skipped = Vec::new();
+        let mut errors = Vec::new();
+        let mut results_rx = results_rx;
+        let mut errors_rx = errors_rx;
+        let (mut results_done, mut errors_done) = (false, false);
+        let mut last_progress = Instant::now();
+        loop {
+            if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                on_event(Event::Progress {
+                    files_scanned: files_scanned.load(Ordering::Relaxed),
+                    bytes_scanned: bytes_scanned.load(Ordering::Relaxed),
+                    current_path: current_path.lock().ok().and_then(|p| p.clone()),
+                    estimate: estimate.get().copied(),
+                    elapsed: started.elapsed(),
+                });
+                last_progress = Instant::now();
+            }
+            if let Some(progress_log) = &mut progress_log {
+                progress_log.tick(
+                    files_scanned.load(Ordering::Relaxed),
+                    bytes_scanned.load(Ordering::Relaxed),
+                    raw_threats.len(),
+                    estimate.get(),
+                );
+            }
+
+            crossbeam_channel::select! {
+                recv(results_rx) -> result => match result {
+                    Ok((path, name)) => {
+                        raw_threats.push((path.clone(), name.clone()));
+                        let (path, member) = report_on_archive(extracted, path);
+                        on_event(Event::Threat {
+                            path: path.clone(),
+                            name: name.clone(),
+                            member: member.clone(),
+                        });
+                        if let Some(member) = member {
+                            members.push((path.clone(), member));
+                        }
+                        threats.push((path, name));
+                    }
+                    Err(_) => {
+                        results_rx = crossbeam_channel::never();
+                        results_done = true;
+                    }
+                },
+                recv(errors_rx) -> result => match result {
+                    Ok(error) => {
+                        on_event(Event::Error {
+                            path: error.path.clone(),
+                            category: error.category,
+                            error: error.message.clone(),
+                        });
+                        skipped.push((error.path.clone(), error.category.skip_reason()));
+                        errors.push(error);
+                    }
+                    Err(_) => {
+                        errors_rx = crossbeam_channel::never();
+                        errors_done = true;
+                    }
+                },
+                default(PROGRESS_INTERVAL) => (),
+            }
+
+            if results_done && errors_done {
+                break;
+            }
+        }
+
+        let (traversal_skipped, hardlinks) = traversal.join().unwrap_or_default();
+        stop_estimate.store(true, Ordering::Relaxed);
+        if let Some(estimate_thread) = estimate_thread {
+            estimate_thread.join().ok();
+        }
+        // directories that couldn't be read, everything below them is missing from the scan
+        for error in traversal_errors(&traversal_skipped) {
+            on_event(Event::Error {
+                path: error.path.clone(),
+                category: error.category,
+                error: error.message.clone(),
+            });
+            errors.push(error);
+        }
+        skipped.extend(traversal_skipped);
+
+        // detections are also reported on the hardlinks that weren't scanned
+        if !hardlinks.is_empty() {
+            info!("Skipped {} additional hardlink(s)", hardlinks.len());
+        }
+        for (scanned, link) in hardlinks {
+            for (_, name) in raw_threats.iter().filter(|(path, _)| *path == scanned) {
+                let (path, member) = report_on_archive(extracted, link.clone());
+                on_event(Event::Threat {
+                    path: path.clone(),
+                    name: name.clone(),
+                    member: member.clone(),
+                });
+                if let Some(member) = member {
+                    members.push((path.clone(), member));
+                }
+                threats.push((path, name.clone()));
+            }
+        }
+        let skipped = skipped
+            .into_iter()
+            .map(|(path, reason)| {
+                // files in unpacked archives are reported on the archive
+                let path = extracted
+                    .iter()
+                    .find(|a| a.member(&path).is_some())
+                    .map_or(path, |a| a.archive.clone());
+                (path, reason)
+            })
+            .collect::<Vec<_>>();
+
+        let cancelled = token.is_cancelled();
+        if cancelled {
+            info!("Scan was cancelled");
+        }
+        on_event(Event::Finished {
+            threats: threats.len(),
+            skipped: skipped.len(),
+        });
+
+        let modified = mem::take(&mut *modified.lock().unwrap_or_else(|err| err.into_inner()));
+        if !modified.is_empty() {
+            warn!(
+                "{} package file(s) were modified since they were installed",
+                modified.len()
+            );
+        }
+
+        Ok(ScanResults {
+            roots,
+            threats,
+            members,
+            skipped,
+            errors,
+            modified,
+            files_scanned: files_scanned.load(Ordering::Relaxed),
+            bytes_scanned: bytes_scanned.load(Ordering::Relaxed),
+            files_cached: files_cached.load(Ordering::Relaxed),
+            files_unmodified: files_unmodified.load(Ordering::Relaxed),
+            signature_count,
+            signature_sources,
+            signatures_age,
+            cancelled,
+        })
+    }
+}
+
+/// Directories that couldn't be read during traversal, as errors of the scan
+fn traversal_errors(skipped: &[(PathBuf, SkipReason)]) -> Vec<ScanError> {
+    skipped
+        .iter()
+        .filter_map(|(path, reason)| {
+            let category = match reason {
+                SkipReason::PermissionDenied => ErrorCategory::Permission,
+                SkipReason::Unreadable => ErrorCategory::Io,
+                SkipReason::TooLarge | SkipReason::InUse => return None,
+            };
+            Some(ScanError {
+                path: path.clone(),
+                category,
+                message: format!("Failed to read {:?}: {}", path, reason),
+            })
+        })
+        .collect()
+}
+
+/// What a worker did with a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Handled {
+    Scanned,
+    /// Excluded by `quick` or `skip_mime`, this isn't known before the file is opened
+    Filtered,
+    /// Known to the clean cache, the file was hashed but not scanned
+    Cached,
+    /// Matches the distro package it was installed from
+    Unmodified,
+}
+
+/// The state a scan worker thread needs, every thread gets its own clone
+#[derive(Clone)]
+struct Worker {
+    config: Arc<ScanConfig>,
+    scanner: Arc<SharedScanner>,
+    clean_cache: Option<Arc<CleanCache>>,
+    manifest: Option<Arc<Manifest>>,
+    modified: Arc<Mutex<Vec<PathBuf>>>,
+    results_tx: Sender<(PathBuf, String)>,
+    errors_tx: Sender<ScanError>,
+    control: Arc<Control>,
+    files_scanned: Arc<AtomicUsize>,
+    bytes_scanned: Arc<AtomicU64>,
+    files_cached: Arc<AtomicUsize>,
+    files_unmodified: Arc<AtomicUsize>,
+    current_path: Arc<Mutex<Option<PathBuf>>>,
+    read_throttle: Option<Arc<Throttle>>,
+    files_throttle: Option<Arc<Throttle>>,
+}
+
+impl Worker {
+    /// Scan the files of a pool until the traversal is done or the scan is cancelled
+    fn run(
+        self,
+        fs_rx: &Receiver<DirEntry>,
+        path_override: Option<&PathOverride>,
+        lower_priority: bool,
+    ) {
+        nice::setup_worker(&self.config, path_override, lower_priority);
+        for entry in fs_rx {
+            self.control.wait_while_paused();
+            if self.control.token.is_cancelled() {
+                break;
+            }
+            let mut size = 0;
+            match catch_panic(entry.path(), || self.handle(&entry, &mut size)) {
+                Ok(Handled::Scanned) => {
+                    self.files_scanned.fetch_add(1, Ordering::Relaxed);
+                    self.bytes_scanned.fetch_add(size, Ordering::Relaxed);
+                }
+                Ok(Handled::Cached) => {
+                    self.files_cached.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(Handled::Unmodified) => {
+                    self.files_unmodified.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(Handled::Filtered) => (),
+                Err(err) => {
+                    error!("{:#}", err);
+                    let error = ScanError::new(entry.path().to_path_buf(), &err);
+                    self.errors_tx.send(error).ok();
+                }
+            }
+        }
+    }
+
+    fn handle(&self, entry: &DirEntry, size: &mut u64) -> Result<Handled> {
+        let path = entry.path();
+        if entry.depth() > 0 && self.is_filtered(path) {
+            return Ok(Handled::Filtered);
+        }
+        if let Ok(mut current) = self.current_path.lock() {
+            *current = Some(path.to_path_buf());
+        }
+        if self.config.builtin_heuristics {
+            if let Some(name) = heuristic::check(path) {
+                warn!(
+                    detection = true,
+                    "Found suspicious file: {} ({:?})",
+                    path.display(),
+                    name
+                );
+                self.results_tx.send((path.to_path_buf(), name)).ok();
+            }
+        }
+        if self.is_unmodified_package_file(path) {
+            return Ok(Handled::Unmodified);
+        }
+        let hashed = match &self.clean_cache {
+            Some(cache) => match self.cache_lookup(cache, path)? {
+                Some(hashed) => Some((cache, hashed)),
+                None => return Ok(Handled::Cached),
+            },
+            None => None,
+        };
+
+        // only files that are scanned count towards the limits
+        *size = entry.metadata().map(|md| md.len()).unwrap_or(0);
+        self.throttle(*size);
+        match hashed {
+            Some((cache, (mut file, hash))) => {
+                let found =
+                    self.scanner
+                        .get()
+                        .scan_descriptor(&mut file, path, &self.results_tx)?;
+                if !found {
+                    cache.insert(hash);
+                }
+            }
+            None => {
+                self.scanner.get().scan_file(path, &self.results_tx)?;
+            }
+        }
+        Ok(Handled::Scanned)
+    }
+
+    /// Wait for `files_per_second` and `max_read_mbps`
+    fn throttle(&self, size: u64) {
+        if let Some(throttle) = &self.files_throttle {
+            throttle.acquire(1.0);
+        }
+        if let Some(throttle) = &self.read_throttle {
+            throttle.acquire(size as f64);
+        }
+    }
+
+    /// The filters that depend on the file name or content, applied to files found during traversal
+    fn is_filtered(&self, path: &Path) -> bool {
+        (self.config.quick && skipped_by_quick(path)) || skipped_by_mime(&self.config, path)
+    }
+
+    /// Compare the file with the installed package it belongs to, modified files are recorded
+    fn is_unmodified_package_file(&self, path: &Path) -> bool {
+        let Some(manifest) = &self.manifest else {
+            return false;
+        };
+        match manifest.verify(path) {
+            Ok(Verdict::Unmodified) => {
+                debug!("Skipping unmodified package file {}", path.display());
+                return true;
+            }
+            Ok(Verdict::Modified) => {
+                warn!(
+                    "File differs from its {} package: {:?}",
+                    manifest.manager(),
+                    path
+                );
+                if let Ok(mut modified) = self.modified.lock() {
+                    modified.push(path.to_path_buf());
+                }
+            }
+            Ok(Verdict::Unknown) => (),
+            Err(err) => debug!("Failed to verify package file: {:#}", err),
+        }
+        false
+    }
+
+    /// Unchanged files don't need to be scanned again with the same signatures, returns `None`
+    /// for known clean files, otherwise the opened file is scanned through the same descriptor
+    fn cache_lookup(&self, cache: &CleanCache, path: &Path) -> Result<Option<(File, String)>> {
+        let (file, hash) = hash_file(path)?;
+        if cache.contains(&hash) {
+            debug!("Skipping known clean file {}", path.display());
+            return Ok(None);
+        }
+        Ok(Some((file, hash)))
+    }
+}
+
+fn load_manifest() -> Option<Arc<Manifest>> {
+    match Manifest::load() {
+        Ok(Some(manifest)) => {
+            info!(
+                "Loaded {} package file(s) from {}, unmodified ones are skipped",
+                manifest.len(),
+                manifest.manager()
+            );
+            Some(Arc::new(manifest))
+        }
+        Ok(None) => {
+            warn!("No supported package manager found, scanning package files too");
+            None
+        }
+        Err(err) => {
+            warn!(
+                "Failed to load package manifests, scanning package files too: {:#}",
+                err
+            );
+            None
+        }
+    }
+}
+
+/// Open and hash a file, the descriptor is scanned afterwards
+fn hash_file(path: &Path) -> Result<(File, String)> {
+    let mut file = File::open(path).with_context(|| anyhow!("Failed to open {:?}", path))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).with_context(|| anyhow!("Failed to read {:?}", path))?;
+    Ok((file, format!("{:x}", hasher.finalize())))
+}
+
+fn load_clean_cache(config: &Config) -> Result<Arc<CleanCache>> {
+    if config.scan.backend != Backend::Libclamav {
+        bail!("The clean cache only works with the libclamav backend");
+    }
+    let signatures = cache::signature_version(config)?;
+    let cache = CleanCache::load(&cache::path()?, signatures)?;
+    info!("Loaded {} known clean file(s)", cache.len());
+    Ok(Arc::new(cache))
+}
+
+/// Files that wait for a pool with a full channel, the traversal blocks once there are more
+const MAX_BACKLOG: usize = 1024;
+
+/// A pool of scan workers, files wait in the backlog while its channel is full
+struct Pool {
+    tx: Sender<DirEntry>,
+    backlog: VecDeque<DirEntry>,
+}
+
+impl Pool {
+    fn new(tx: Sender<DirEntry>) -> Pool {
+        Pool {
+            tx,
+            backlog: VecDeque::new(),
+        }
+    }
+}
+
+/// Hands files to the worker pool of the most specific `path_overrides` entry they are in
+///
+/// A slow pool doesn't hold up the traversal until its backlog is full, other pools are
+/// still fed while waiting for it.
+struct Router {
+    default: Pool,
+    /// Sorted by depth, deepest first
+    pools: Vec<(PathBuf, Pool)>,
+}
+
+impl Router {
+    fn new(default: Sender<DirEntry>) -> Router {
+        Router {
+            default: Pool::new(default),
+            pools: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, path: PathBuf, tx: Sender<DirEntry>) {
+        self.pools.push((path, Pool::new(tx)));
+        self.pools
+            .sort_by_key(|(path, _)| Reverse(path.components().count()));
+    }
+
+    /// The index of the pool for this path in `all_pools`
+    fn position(&self, path: &Path) -> usize {
+        self.pools
+            .iter()
+            .position(|(prefix, _)| path.starts_with(prefix))
+            .map_or(0, |i| i + 1)
+    }
+
+    fn all_pools(&mut self) -> impl Iterator<Item = &mut Pool> {
+        iter::once(&mut self.default).chain(self.pools.iter_mut().map(|(_, pool)| pool))
+    }
+
+    fn nth(&mut self, i: usize) -> &mut Pool {
+        match i {
+            0 => &mut self.default,
+            i => &mut self.pools[i - 1].1,
+        }
+    }
+
+    /// Returns false once the workers are gone and the traversal should stop
+    fn send(&mut self, entry: DirEntry) -> bool {
+        let i = self.position(entry.path());
+        self.nth(i).backlog.push_back(entry);
+        // files of other pools may have been waiting for room
+        for pool in self.all_pools() {
+            while let Some(entry) = pool.backlog.pop_front() {
+                match pool.tx.try_send(entry) {
+                    Ok(()) => (),
+                    Err(TrySendError::Full(entry)) => {
+                        pool.backlog.push_front(entry);
+                        break;
+                    }
+                    Err(TrySendError::Disconnected(_)) => return false,
+                }
+            }
+        }
+        // the traversal waits instead of keeping the whole tree in memory
+        while self.nth(i).backlog.len() > MAX_BACKLOG {
+            if !self.send_next() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Wait for room in any pool with a backlog, false if nothing is pending or the workers are gone
+    fn send_next(&mut self) -> bool {
+        let pending = self
+            .all_pools()
+            .enumerate()
+            .filter(|(_, pool)| !pool.backlog.is_empty())
+            .map(|(i, pool)| (i, pool.tx.clone()))
+            .collect::<Vec<_>>();
+        if pending.is_empty() {
+            return false;
+        }
+        let mut select = Select::new();
+        for (_, tx) in &pending {
+            select.send(tx);
+        }
+        let oper = select.select();
+        let (i, tx) = &pending[oper.index()];
+        let Some(entry) = self.nth(*i).backlog.pop_front() else {
+            return false;
+        };
+        oper.send(tx, entry).is_ok()
+    }
+
+    /// Wait until the backlogs were handed to the workers, whichever pool has room first
+    fn finish(mut self) {
+        while self.send_next() {}
+    }
+}
+
+/// The provided paths, `scan.paths` or the home directory, without paths covered by another one
+pub fn resolve_paths(config: &ScanConfig, paths: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+    let paths = if !paths.is_empty() {
+        info!("Scanning provided paths: {:?}", paths);
+        paths
+    } else if !config.paths.is_empty() {
+        info!("Scanning configured paths: {:?}", config.paths);
+        config.paths.clone()
+    } else {
+        let home_dir = dirs::home_dir().context("Failed to find home directory")?;
+        info!("Scanning home directory: {:?}", home_dir);
+        vec![home_dir]
+    };
+    Ok(dedup_paths(paths))
+}
+
+/// Drop paths that are inside of another path (or the same), compared by their canonical path
+#[must_use]
+pub fn dedup_paths(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    let canonical = paths
+        .iter()
+        .map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
+        .collect::<Vec<_>>();
+    paths
+        .into_iter()
+        .enumerate()
+        .filter(|(i, path)| {
+            let current = &canonical[*i];
+            let covered = canonical.iter().enumerate().any(|(j, other)| {
+                // of identical paths the first one is kept
+                (current == other && j < *i) || (current != other && current.starts_with(other))
+            });
+            if covered {
+                info!("Skipping {:?}: already covered by another path", path);
+            }
+            !covered
+        })
+        .map(|(_, path)| path)
+        .collect()
+}
+
+/// Periodic progress lines so long running scans show signs of life in the journal
+struct ProgressLog {
+    interval: Duration,
+    started: Instant,
+    last: Instant,
+    last_files: usize,
+    last_bytes: u64,
+}
 
-        Ok(Scanner {
-            engine: scanner,
-            signature_count: stats.signature_count,
-            signatures_age,
-        })
+impl ProgressLog {
+    fn new(interval: Duration) -> ProgressLog {
+        ProgressLog {
+            interval,
+            started: Instant::now(),
+            last: Instant::now(),
+            last_files: 0,
+            last_bytes: 0,
+        }
     }
 
-    fn find_daily_db_path(base_dir: &Path) -> Result<PathBuf> {
-        for filename in &["daily.cld", "daily.cvd"] {
-            let daily_path = base_dir.join(filename);
-            debug!("Checking if database exists: {:?}", daily_path);
-            if daily_path.exists() {
-                return Ok(daily_path);
-            }
+    fn tick(&mut self, files: usize, bytes: u64, threats: usize, estimate: Option<&Estimate>) {
+        let elapsed = self.last.elapsed();
+        if elapsed < self.interval {
+            return;
         }
+        let secs = elapsed.as_secs_f64();
+        let remaining = estimate
+            .map(|estimate| {
+                let eta = estimate
+                    .eta(bytes, self.started.elapsed())
+                    .map(|eta| format!(", about {} min left", eta.as_secs().div_ceil(60)))
+                    .unwrap_or_default();
+                format!(", {:.0}% done{}", estimate.percent(bytes), eta)
+            })
+            .unwrap_or_default();
+        info!(
+            "Scanned {} files ({:.1} MB), currently {:.1} files/s and {:.1} MB/s, {} threat(s) so far{}",
+            files,
+            bytes as f64 / 1_000_000.0,
+            (files - self.last_files) as f64 / secs,
+            (bytes - self.last_bytes) as f64 / 1_000_000.0 / secs,
+            threats,
+            remaining
+        );
+        self.last = Instant::now();
+        self.last_files = files;
+        self.last_bytes = bytes;
+    }
+}
 
-        bail!("Couldn't find clamav database file");
+/// Report detections inside of archives on the archive itself, with canonical paths
+///
+/// Returns the path of the archive and the member that was detected, or the path itself.
+pub(crate) fn report_on_archive(
+    extracted: &[Extracted],
+    path: PathBuf,
+) -> (PathBuf, Option<PathBuf>) {
+    let (path, member) = match extracted
+        .iter()
+        .find_map(|a| a.member(&path).map(|member| (a, member.to_path_buf())))
+    {
+        Some((archive, member)) => (archive.archive.clone(), Some(member)),
+        None => (path, None),
+    };
+
+    match fs::canonicalize(&path) {
+        Ok(path) => (path, member),
+        Err(err) => {
+            error!("Failed to canonicalize path {:?}: {:#}", path, err);
+            (path, member)
+        }
     }
+}
 
-    #[must_use]
-    pub fn signature_count(&self) -> usize {
-        self.signature_count as usize
+/// The cgroup settings and memory limit of the scope a scan runs in, if any
+fn scan_cgroup(config: &ScanConfig) -> Option<(CgroupConfig, Option<u64>)> {
+    let max_memory = config.max_memory.as_ref().map(HumanSize::as_bytes);
+    if config.cgroup.is_some() || (max_memory.is_some() && cgroup::is_systemd()) {
+        Some((config.cgroup.clone().unwrap_or_default(), max_memory))
+    } else {
+        if max_memory.is_some() {
+            info!(
+                "System isn't running systemd, scan.max_memory only limits the size of scanned files"
+            );
+        }
+        None
     }
+}
 
-    #[must_use]
-    pub fn signatures_age(&self) -> DateTime<Utc> {
-        self.signatures_age
+fn setup_cgroup(config: &CgroupConfig, max_memory: Option<u64>) {
+    if let Err(err) = cgroup::setup(config, max_memory) {
+        warn!("Failed to setup cgroup: {:#}", err);
     }
+}
 
-    pub fn scan_file(&self, path: &Path, results_tx: &Sender<(PathBuf, String)>) -> Result<()> {
-        debug!("Scanning file {}...", path.display());
+/// What a scan found, so callers don't need to read the database afterwards
+#[derive(Debug, Default)]
+pub struct ScanSummary {
+    pub files_scanned: usize,
+    pub bytes_scanned: u64,
+    /// Files that were skipped because the clean cache knew them
+    pub files_cached: usize,
+    /// Package files that were skipped because they match their package
+    pub files_unmodified: usize,
+    pub duration: Duration,
+    pub threats: Vec<(PathBuf, String)>,
+    /// Threats that weren't recorded before this scan
+    pub new_threats: Vec<(PathBuf, String)>,
+    pub errors: Vec<ScanError>,
+    pub skipped: Vec<(PathBuf, SkipReason)>,
+    /// Package files that were modified since they were installed
+    pub modified: Vec<PathBuf>,
+    pub cancelled: bool,
+}
 
-        let path_str = path_to_string(path)?;
-        let mut settings = ScanSettings::default();
-        let hit = self
-            .engine
-            .scan_file(&path_str, &mut settings)
-            .map_err(|e| anyhow!("Failed to scan file {:?}: {:#}", path, e))?;
+/// Threats that were already recorded before a scan
+///
+/// With `--diff` they don't show notifications, push messages, fire `[hooks]` or write audit entries again.
+struct KnownThreats {
+    threats: HashSet<(PathBuf, String)>,
+    diff: bool,
+}
 
-        match hit {
-            ScanResult::Virus(name) => {
-                warn!("Found threat: {} ({:?})", path.display(), name);
-                results_tx.send((path.to_path_buf(), name)).ok();
-            }
-            ScanResult::Clean | ScanResult::Whitelisted => (),
-        }
+impl KnownThreats {
+    fn new(data: &Data, diff: bool) -> KnownThreats {
+        let threats = data
+            .threats
+            .iter()
+            .flat_map(|(path, threat)| {
+                threat
+                    .names
+                    .iter()
+                    .map(move |name| (path.clone(), name.clone()))
+            })
+            .collect();
+        KnownThreats { threats, diff }
+    }
 
-        debug!("Finished scanning file {}", path.display());
+    fn is_new(&self, path: &Path, name: &str) -> bool {
+        !self
+            .threats
+            .contains(&(path.to_path_buf(), name.to_string()))
+    }
 
-        Ok(())
+    /// Whether a detection is reported while the scan is running
+    fn alerts(&self, path: &Path, name: &str) -> bool {
+        !self.diff || self.is_new(path, name)
     }
 }
 
-pub fn run(args: args::Scan) -> Result<()> {
-    let config = config::load(Some(&args)).context("Failed to load config")?;
+pub fn run(config_path: Option<&Path>, args: args::Scan) -> Result<ScanSummary> {
+    run_with(config_path, args, &Arc::new(Control::default()), |_| ())
+}
+
+/// Run a scan from the cli or the scheduler, this shows notifications and records the results
+pub fn run_with<F: FnMut(Event)>(
+    config_path: Option<&Path>,
+    args: args::Scan,
+    control: &Arc<Control>,
+    mut on_event: F,
+) -> Result<ScanSummary> {
+    let started = Instant::now();
+    let config = config::load(config_path, Some(&args)).context("Failed to load config")?;
 
     let mut db = Database::load().context("Failed to load database")?;
 
-    let paths = if !args.paths.is_empty() {
-        info!("Scanning provided paths: {:?}", args.paths);
-        args.paths
-    } else if !config.scan.paths.is_empty() {
-        info!("Scanning configured paths: {:?}", config.scan.paths);
-        config.scan.paths.clone()
+    let known = KnownThreats::new(db.data(), args.diff);
+
+    let notify_config = config.notify.clone();
+    let metrics_textfile = config.metrics.textfile.clone();
+    let reporting = config.reporting.clone();
+    let hooks = config.hooks.clone();
+    let descriptions = Descriptions::from_config(&config.update);
+    if hooks.on_scan_start.is_some() {
+        let paths = resolve_paths(&config.scan, args.paths.clone())?;
+        hooks::fire(&hooks, &HookEvent::ScanStart { paths: &paths });
+    }
+    let threat_hooks = ThreatHooks::spawn(&hooks);
+    let mut status_file = StatusFile::new(statusfile::path());
+    let mut finished = None;
+    let results = ScanJob::new(config)
+        .paths(args.paths)
+        .control(control.clone())
+        .lower_priority(!args.no_nice)
+        .scope(true)
+        .run_with(|event| {
+            if let Event::Threat { path, name, member } = &event {
+                // members of archives are shown like a file inside of a directory
+                let shown = member
+                    .as_ref()
+                    .map_or_else(|| path.clone(), |member| path.join(member));
+                if !known.alerts(path, name) {
+                    debug!("Not reporting known threat {:?}", path);
+                } else {
+                    if let Err(err) = notify::threat(&notify_config, &descriptions, &shown, name) {
+                        warn!("Failed to display notification: {:#}", err);
+                    }
+                    threat_hooks.fire(path, name);
+                    let entry =
+                        audit::Entry::new(Action::Detected, path, std::slice::from_ref(name));
+                    if let Err(err) = audit::record(&entry) {
+                        warn!("Failed to write audit log: {:#}", err);
+                    }
+                }
+            }
+            status_file.update(&event);
+            // held back until the results are written to the database
+            if let Event::Finished { .. } = event {
+                finished = Some(event);
+            } else {
+                on_event(event);
+            }
+        });
+    let results = match results {
+        Ok(results) => results,
+        Err(err) => {
+            status_file.finish(StatusState::Failed, 0, 0);
+            return Err(err);
+        }
+    };
+    let state = if results.cancelled {
+        StatusState::Cancelled
     } else {
-        let home_dir = dirs::home_dir().context("Failed to find home directory")?;
-        info!("Scanning home directory: {:?}", home_dir);
-        vec![home_dir]
+        StatusState::Finished
+    };
+    status_file.finish(state, results.files_scanned, results.bytes_scanned);
+
+    let ScanResults {
+        roots,
+        threats,
+        members,
+        skipped,
+        errors,
+        modified,
+        files_scanned,
+        bytes_scanned,
+        files_cached,
+        files_unmodified,
+        signature_count,
+        signature_sources,
+        signatures_age,
+        cancelled,
+    } = results;
+    let new_threats = threats
+        .iter()
+        .filter(|(path, name)| known.is_new(path, name))
+        .cloned()
+        .collect();
+    let summary = ScanSummary {
+        files_scanned,
+        bytes_scanned,
+        files_cached,
+        files_unmodified,
+        duration: started.elapsed(),
+        threats: threats.clone(),
+        new_threats,
+        errors,
+        skipped: skipped.clone(),
+        modified,
+        cancelled,
     };
 
     let data = db.data_mut();
-    data.threats.clear();
+    // results outside of the scanned paths are not affected by this scan,
+    // a cancelled scan didn't cover everything and only adds to them
+    let in_scope = |path: &Path| !cancelled && roots.iter().any(|root| path.starts_with(root));
+
+    // keep the previous results around to carry over when a threat was first seen
+    let previous_threats = mem::take(&mut data.threats);
+    data.threats = previous_threats
+        .iter()
+        .filter(|(path, _)| !in_scope(path))
+        .map(|(path, threat)| (path.clone(), threat.clone()))
+        .collect();
+    for (path, name) in threats {
+        let first_seen = previous_threats
+            .get(&path)
+            .and_then(|threat| threat.first_seen)
+            .unwrap_or_else(Utc::now);
+        let threat = data
+            .threats
+            .entry(path)
+            .or_insert_with(|| Threat::new(first_seen));
+        threat.add_name(name);
+    }
+    for (path, member) in members {
+        if let Some(threat) = data.threats.get_mut(&path) {
+            threat.add_member(member);
+        }
+    }
+    for (path, threat) in previous_threats {
+        if in_scope(&path) && !data.threats.contains_key(&path) {
+            info!("Threat is gone: {:?}", path);
+            let resolved = Resolved {
+                names: threat.names,
+                first_seen: threat.first_seen,
+                resolved: Utc::now(),
+            };
+            data.resolved.insert(path, resolved);
+        }
+    }
+    let threats = &data.threats;
+    data.resolved.retain(|path, _| !threats.contains_key(path));
 
-    let (results_tx, results_rx) = crossbeam_channel::unbounded();
-    let (fs_tx, fs_rx) = crossbeam_channel::bounded::<DirEntry>(128);
+    data.skipped = mem::take(&mut data.skipped)
+        .into_iter()
+        .filter(|(path, _)| !in_scope(path))
+        .collect();
+    data.skipped.extend(skipped);
 
-    let scanner = Scanner::new(&config.update.path)?;
-    let scanner = Arc::new(scanner);
+    data.signature_count = signature_count;
+    data.signature_sources = signature_sources.into_iter().collect();
+    data.signatures_age = Some(signatures_age);
 
-    let cpus = config.scan.concurrency.unwrap_or_else(num_cpus::get);
+    info!("Scan finished, found {} threat(s)!", data.threats.len());
+    if !data.skipped.is_empty() {
+        warn!(
+            "{} file(s) could not be scanned, use `scan --show-skipped` to list them",
+            data.skipped.len()
+        );
+    }
 
-    info!("Spawning {} scanner(s)...", cpus);
-    for _ in 0..cpus {
-        let results_tx = results_tx.clone();
-        let fs_rx = fs_rx.clone();
-        let scanner = scanner.clone();
-        thread::spawn(move || {
-            for entry in fs_rx {
-                if let Err(err) = scanner.scan_file(entry.path(), &results_tx) {
-                    error!("{:#}", err);
-                }
-            }
-            mem::drop(results_tx);
+    if !cancelled {
+        data.last_scan = Some(Utc::now());
+        data.last_scan_stats = Some(ScanStats {
+            files_scanned: summary.files_scanned,
+            bytes_scanned: summary.bytes_scanned,
+            duration_secs: summary.duration.as_secs_f64(),
+            errors: summary.errors.len(),
+            error_categories: count_errors(&summary.errors),
         });
     }
-    mem::drop(results_tx);
+    db.store().context("Failed to write database")?;
+    if let Some(event) = finished {
+        on_event(event);
+    }
 
-    thread::spawn(move || {
-        for path in paths {
-            info!("Scanning directory {}...", path.display());
-            ingest_directory(&config.scan, &fs_tx, &path);
+    if let Some(path) = &metrics_textfile {
+        if let Err(err) = metrics::write_textfile(path, db.data()) {
+            warn!("Failed to write metrics: {:#}", err);
         }
-        debug!("Finished traversing directories");
-    });
+    }
+    if let Some(reporting) = &reporting {
+        let report = ScanReport::new(&summary, db.data());
+        if let Err(err) = reporting::submit(reporting, &report) {
+            warn!("Failed to queue scan report: {:#}", err);
+        }
+    }
+    threat_hooks.finish();
+    hooks::fire(&hooks, &HookEvent::scan_end(&summary));
 
-    data.signature_count = scanner.signature_count();
-    data.signatures_age = Some(scanner.signatures_age());
-    for (path, name) in results_rx {
-        let path = match fs::canonicalize(&path) {
-            Ok(path) => path,
-            Err(err) => {
-                error!("Failed to canonicalize path {:?}: {:#}", path, err);
-                path
-            }
-        };
+    Ok(summary)
+}
 
-        if let Err(err) = notify::show(&path, &name) {
-            warn!("Failed to display notification: {:#}", err);
+fn find_daily_db_path(base_dir: &Path) -> Result<PathBuf> {
+    for filename in &["daily.cld", "daily.cvd"] {
+        let daily_path = base_dir.join(filename);
+        debug!("Checking if database exists: {:?}", daily_path);
+        if daily_path.exists() {
+            return Ok(daily_path);
         }
-        data.threats.entry(path).or_default().push(name);
     }
-    info!("Scan finished, found {} threat(s)!", data.threats.len());
 
-    data.last_scan = Some(Utc::now());
-    db.store().context("Failed to write database")?;
+    bail!("Couldn't find clamav database file");
+}
 
-    Ok(())
+/// Read when the daily database in this directory was built
+pub fn database_age(path: &Path) -> Result<DateTime<Utc>> {
+    let daily_path = find_daily_db_path(path)?;
+    let mut buf = [0; 512];
+    read_clamav_header(&daily_path, &mut buf)?;
+    parse_database_age(&buf)
 }
 
 pub fn read_clamav_header(path: &Path, buf: &mut [u8]) -> Result<()> {
@@ -291,6 +2565,70 @@ pub fn read_clamav_header(path: &Path, buf: &mut [u8]) -> Result<()> {
     Ok(())
 }
 
+/// The signature count of a database is the fourth field of its header
+pub fn parse_signature_count(buf: &[u8]) -> Result<u32> {
+    let field = buf
+        .split(|b| *b == b':')
+        .nth(3)
+        .context("Failed to select signature count field")?;
+    atoi::atoi::<u32>(field).context("Failed to parse signature count as number")
+}
+
+/// Signature files with one signature per line
+const TEXT_DATABASES: &[&str] = &[
+    "hdb", "hsb", "hdu", "hsu", "mdb", "msb", "mdu", "msu", "ndb", "ndu", "ldb", "ldu", "idb",
+    "cdb", "pdb", "gdb", "wdb", "ftm", "fp", "sfp", "ign", "ign2",
+];
+
+fn count_file_signatures(path: &Path) -> Result<u32> {
+    let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    let count = match ext {
+        "cvd" | "cld" | "cud" => {
+            let mut buf = [0; 512];
+            read_clamav_header(path, &mut buf)?;
+            parse_signature_count(&buf)?
+        }
+        "yar" | "yara" => {
+            let text = fs::read_to_string(path)?;
+            text.lines()
+                .filter(|line| line.trim_start().starts_with("rule "))
+                .count() as u32
+        }
+        ext if TEXT_DATABASES.contains(&ext) => {
+            let text = fs::read_to_string(path)?;
+            text.lines()
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .count() as u32
+        }
+        _ => 0,
+    };
+    Ok(count)
+}
+
+/// Count the signatures in a database directory or file, libclamav reports this while loading
+fn count_signatures(path: &Path) -> u32 {
+    let files = if path.is_dir() {
+        match fs::read_dir(path) {
+            Ok(dir) => dir.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+            Err(err) => {
+                warn!("Failed to read database directory {:?}: {:#}", path, err);
+                return 0;
+            }
+        }
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let mut count = 0;
+    for file in files {
+        match count_file_signatures(&file) {
+            Ok(n) => count += n,
+            Err(err) => warn!("Failed to read signature count from {:?}: {:#}", file, err),
+        }
+    }
+    count
+}
+
 pub fn parse_database_age(mut buf: &[u8]) -> Result<DateTime<Utc>> {
     for i in 0..8 {
         let idx = memchr::memchr(b':', buf)
@@ -314,6 +2652,93 @@ pub fn parse_database_age(mut buf: &[u8]) -> Result<DateTime<Utc>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::HumanSize;
+    use crate::patterns::Pattern;
+    use std::io::Write;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_router_pools() {
+        let (default, _rx) = crossbeam_channel::bounded(1);
+        let (usb, _rx) = crossbeam_channel::bounded(1);
+        let (nested, _rx) = crossbeam_channel::bounded(1);
+        let mut router = Router::new(default.clone());
+        router.add(PathBuf::from("/mnt/usb"), usb.clone());
+        router.add(PathBuf::from("/mnt/usb/backup"), nested.clone());
+
+        let i = router.position(Path::new("/home/user/a.exe"));
+        assert!(router.nth(i).tx.same_channel(&default));
+        let i = router.position(Path::new("/mnt/usb/a.exe"));
+        assert!(router.nth(i).tx.same_channel(&usb));
+        let i = router.position(Path::new("/mnt/usb/backup/a.exe"));
+        assert!(router.nth(i).tx.same_channel(&nested));
+        // only whole path components match
+        let i = router.position(Path::new("/mnt/usb2/a.exe"));
+        assert!(router.nth(i).tx.same_channel(&default));
+    }
+
+    #[test]
+    fn test_router_backlog() {
+        let dir = tempfile::tempdir().unwrap();
+        let slow = dir.path().join("slow");
+        fs::create_dir(&slow).unwrap();
+        for name in ["a", "b", "c"] {
+            fs::write(slow.join(name), b"").unwrap();
+        }
+        fs::write(dir.path().join("d"), b"").unwrap();
+
+        let (default, default_rx) = crossbeam_channel::bounded(1);
+        let (slow_tx, slow_rx) = crossbeam_channel::bounded(1);
+        let mut router = Router::new(default);
+        router.add(slow.clone(), slow_tx);
+        let entries = WalkDir::new(dir.path())
+            .sort_by_file_name()
+            .min_depth(1)
+            .into_iter()
+            .flatten()
+            .filter(|e| e.file_type().is_file());
+        for entry in entries {
+            assert!(router.send(entry));
+        }
+        // the full pool didn't hold up the other one
+        assert_eq!(default_rx.try_recv().unwrap().path(), dir.path().join("d"));
+        assert_eq!(router.pools[0].1.backlog.len(), 2);
+
+        let receiver = thread::spawn(move || {
+            slow_rx
+                .iter()
+                .map(|e| e.file_name().to_owned())
+                .collect::<Vec<_>>()
+        });
+        router.finish();
+        assert_eq!(receiver.join().unwrap(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_router_backlog_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..MAX_BACKLOG + 10 {
+            fs::write(dir.path().join(i.to_string()), b"").unwrap();
+        }
+
+        let (default, default_rx) = crossbeam_channel::bounded(1);
+        let mut router = Router::new(default);
+        let mut entries = WalkDir::new(dir.path()).min_depth(1).into_iter().flatten();
+        for entry in entries.by_ref().take(MAX_BACKLOG + 1) {
+            assert!(router.send(entry));
+        }
+        // one file is in the channel, the rest waits in the backlog
+        assert_eq!(router.default.backlog.len(), MAX_BACKLOG);
+
+        // further files block until the workers took some
+        let receiver = thread::spawn(move || default_rx.iter().count());
+        for entry in entries {
+            assert!(router.send(entry));
+            assert!(router.default.backlog.len() <= MAX_BACKLOG);
+        }
+        router.finish();
+        assert_eq!(receiver.join().unwrap(), MAX_BACKLOG + 10);
+    }
 
     #[test]
     fn is_hidden_regular_file() {
@@ -345,6 +2770,397 @@ mod tests {
         assert!(hidden);
     }
 
+    #[test]
+    fn test_is_included() {
+        let mut config = ScanConfig::default();
+        assert!(is_included(&config, Path::new("/home/user/x.mp4")));
+
+        config.includes = vec!["*.exe".parse().unwrap(), "*.doc*".parse().unwrap()];
+        assert!(is_included(&config, Path::new("/home/user/setup.exe")));
+        assert!(is_included(&config, Path::new("/home/user/invoice.docx")));
+        assert!(!is_included(&config, Path::new("/home/user/x.mp4")));
+    }
+
+    #[test]
+    fn test_is_skipped_owner() {
+        let mut config = ScanConfig::default();
+        assert!(!is_skipped_owner(&config, 1000));
+
+        config.skip_uids = vec![1001];
+        assert!(is_skipped_owner(&config, 1001));
+        assert!(!is_skipped_owner(&config, utils::euid()));
+
+        config.skip_other_users = true;
+        assert!(is_skipped_owner(&config, utils::euid() + 1));
+        assert!(!is_skipped_owner(&config, utils::euid()));
+    }
+
+    #[test]
+    fn test_priority_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let roots = vec![root.clone()];
+        for name in ["Downloads", ".cache", "excluded"] {
+            fs::create_dir(root.join(name)).unwrap();
+        }
+
+        let mut cfg = ScanConfig {
+            priority_paths: Some(vec![
+                root.join("Downloads"),
+                root.join(".cache"),
+                root.join("excluded"),
+                root.join("missing"),
+                PathBuf::from("/"),
+            ]),
+            excludes: vec![Pattern::from_str(&root.join("excluded").to_string_lossy()).unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(
+            priority_paths(&cfg, &roots),
+            vec![root.join("Downloads"), root.join(".cache")]
+        );
+
+        cfg.skip_hidden = true;
+        assert_eq!(priority_paths(&cfg, &roots), vec![root.join("Downloads")]);
+    }
+
+    #[test]
+    fn test_index_newest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = std::time::SystemTime::now();
+        for (name, age) in [("old", 300), ("new", 0), ("middle", 60)] {
+            let path = dir.path().join(name);
+            let file = File::create(&path).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+
+        let entries = index_newest_first(
+            &ScanConfig::default(),
+            &mut Visited::default(),
+            &[dir.path().to_path_buf()],
+        );
+        let names = entries
+            .iter()
+            .filter(|e| e.depth() > 0)
+            .map(|e| e.file_name().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["new", "middle", "old"]);
+    }
+
+    #[test]
+    fn test_error_category() {
+        let err = Error::from(io::Error::from(io::ErrorKind::PermissionDenied)).context("Failed");
+        assert_eq!(error_category(&err), ErrorCategory::Permission);
+        let err = Error::from(io::Error::from(io::ErrorKind::UnexpectedEof));
+        assert_eq!(error_category(&err), ErrorCategory::Io);
+        let err = Error::new(EngineError("CL_ETIMEOUT".to_string())).context("Failed to scan file");
+        assert_eq!(error_category(&err), ErrorCategory::Timeout);
+        let err = Error::new(EngineError("CL_EFORMAT".to_string()))
+            .context("Failed to scan file \"/home/user/timeout.txt\"");
+        assert_eq!(error_category(&err), ErrorCategory::Engine);
+
+        let errors = [err, anyhow!("CL_EMEM")]
+            .iter()
+            .map(|err| ScanError::new(PathBuf::from("/a"), err))
+            .collect::<Vec<_>>();
+        assert_eq!(count_errors(&errors)[&ErrorCategory::Engine], 2);
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        let path = Path::new("/a.exe");
+        assert_eq!(catch_panic(path, || Ok(23)).unwrap(), 23);
+        let err = catch_panic::<(), _>(path, || panic!("invalid header at {}", 42)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Scan of \"/a.exe\" panicked: invalid header at 42"
+        );
+    }
+
+    #[test]
+    fn test_estimate() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut exe = b"MZ".to_vec();
+        exe.resize(100, 0);
+        fs::write(dir.path().join("a.exe"), exe).unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/b.mp4"), [0; 50]).unwrap();
+        let roots = [dir.path().to_path_buf()];
+
+        let stop = AtomicBool::new(false);
+        let estimate = estimate(
+            &ScanConfig::default(),
+            &mut Visited::default(),
+            &roots,
+            &stop,
+        );
+        assert_eq!(
+            estimate,
+            Some(Estimate {
+                files: 2,
+                bytes: 150
+            })
+        );
+
+        let cfg = ScanConfig {
+            quick: true,
+            ..Default::default()
+        };
+        let estimate = super::estimate(&cfg, &mut Visited::default(), &roots, &stop).unwrap();
+        assert_eq!(estimate.files, 1);
+        assert_eq!(estimate.percent(50), 50.0);
+        assert_eq!(
+            estimate.eta(50, Duration::from_secs(10)),
+            Some(Duration::from_secs(10))
+        );
+
+        stop.store(true, Ordering::Relaxed);
+        assert_eq!(
+            super::estimate(&cfg, &mut Visited::default(), &roots, &stop),
+            None
+        );
+    }
+
+    #[test]
+    fn test_filter_roots_and_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("cache");
+        fs::create_dir_all(cache.join("node_modules")).unwrap();
+        fs::write(cache.join("a.exe"), b"MZ").unwrap();
+        fs::write(cache.join("node_modules/b.js"), b"").unwrap();
+        let cfg = ScanConfig {
+            excludes: vec![
+                "**/cache".parse().unwrap(),
+                "**/backup.tar/node_modules".parse().unwrap(),
+            ],
+            ..Default::default()
+        };
+        let walk = |visited: &mut Visited| {
+            let mut files = Vec::new();
+            walk_directory(&cfg, visited, &cache, |e| {
+                if e.file_type().is_file() {
+                    files.push(e.path().strip_prefix(&cache).unwrap().to_path_buf());
+                }
+                true
+            });
+            files.sort();
+            files
+        };
+
+        // explicit paths are scanned even if they're excluded
+        assert_eq!(
+            walk(&mut Visited::default()),
+            [PathBuf::from("a.exe"), PathBuf::from("node_modules/b.js")]
+        );
+
+        let mut visited = Visited::default();
+        visited.filter_roots();
+        assert!(walk(&mut visited).is_empty());
+
+        // members are matched as if the archive was a directory
+        let mut visited = Visited::default();
+        visited.filter_roots();
+        visited.add_archive(&cache, Path::new("/home/user/backup.tar"));
+        assert_eq!(walk(&mut visited), [PathBuf::from("a.exe")]);
+    }
+
+    #[test]
+    fn test_known_threats() {
+        let mut data = Data::default();
+        let mut threat = Threat::new(Utc::now());
+        threat.add_name("Win.Test.EICAR_HDB-1".to_string());
+        data.threats
+            .insert(PathBuf::from("/archive/eicar.txt"), threat);
+
+        let known = KnownThreats::new(&data, true);
+        assert!(!known.alerts(Path::new("/archive/eicar.txt"), "Win.Test.EICAR_HDB-1"));
+        // a new name or a new path is reported
+        assert!(known.alerts(Path::new("/archive/eicar.txt"), "Win.Trojan.Agent"));
+        assert!(known.alerts(Path::new("/home/user/eicar.txt"), "Win.Test.EICAR_HDB-1"));
+
+        let known = KnownThreats::new(&data, false);
+        assert!(known.alerts(Path::new("/archive/eicar.txt"), "Win.Test.EICAR_HDB-1"));
+        assert!(!known.is_new(Path::new("/archive/eicar.txt"), "Win.Test.EICAR_HDB-1"));
+    }
+
+    #[test]
+    fn test_child_job_roundtrip() {
+        let config = serde_json::from_value::<Config>(serde_json::json!({
+            "scan": {
+                "excludes": ["/home/*/.cache", {"pattern": "/tmp/{a,b}", "brace_expansion": true}],
+                "skip_larger_than": "10 MB",
+            },
+            "update": {"path": "/var/lib/clamav"},
+        }))
+        .unwrap();
+        let job = ChildJob {
+            plan: Plan {
+                config,
+                roots: vec![PathBuf::from("/home")],
+                explicit: false,
+                priority: Vec::new(),
+                traverse: vec![PathBuf::from("/home")],
+                lower_priority: true,
+            },
+            user: Some(privdrop::User {
+                uid: 1000,
+                gid: 1000,
+            }),
+            cgroup: None,
+            archives: Vec::new(),
+        };
+        let buf = serde_json::to_vec(&job).unwrap();
+        let job = serde_json::from_slice::<ChildJob>(&buf).unwrap();
+        let scan = &job.plan.config.scan;
+        assert!(scan.excludes[0].matches(Path::new("/home/user/.cache")));
+        assert!(scan.excludes[1].matches(Path::new("/tmp/b")));
+        assert!(!scan.excludes[1].matches(Path::new("/tmp/c")));
+        assert_eq!(
+            scan.skip_larger_than.as_ref().map(HumanSize::as_bytes),
+            Some(10_000_000)
+        );
+        assert_eq!(job.user.map(|user| user.uid), Some(1000));
+    }
+
+    #[test]
+    fn test_scan_cgroup() {
+        assert!(scan_cgroup(&ScanConfig::default()).is_none());
+
+        let cfg = ScanConfig {
+            max_memory: Some(HumanSize::from_str("1 GiB").unwrap()),
+            cgroup: Some(CgroupConfig {
+                cpu_weight: Some(20),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let (cgroup, max_memory) = scan_cgroup(&cfg).unwrap();
+        assert_eq!(cgroup.cpu_weight, Some(20));
+        assert_eq!(max_memory, Some(1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_skipped_too_large() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("small"), b"a").unwrap();
+        fs::write(dir.path().join("large"), [0; 100]).unwrap();
+
+        let cfg = ScanConfig {
+            skip_larger_than: Some(HumanSize::from_str("10 B").unwrap()),
+            ..Default::default()
+        };
+        let mut visited = Visited::default();
+        let mut files = Vec::new();
+        walk_directory(&cfg, &mut visited, dir.path(), |e| {
+            files.push(e.file_name().to_owned());
+            true
+        });
+        assert!(files.contains(&"small".into()));
+        assert!(!files.contains(&"large".into()));
+        assert_eq!(
+            visited.take_skipped(),
+            vec![(dir.path().join("large"), SkipReason::TooLarge)]
+        );
+
+        // libclamav wouldn't scan more than its share of max_memory
+        let cfg = ScanConfig {
+            max_memory: Some(HumanSize::from_str("40 B").unwrap()),
+            concurrency: Some(4),
+            backend: Backend::Libclamav,
+            ..Default::default()
+        };
+        let mut visited = Visited::default();
+        walk_directory(&cfg, &mut visited, dir.path(), |_| true);
+        assert_eq!(
+            visited.take_skipped(),
+            vec![(dir.path().join("large"), SkipReason::TooLarge)]
+        );
+
+        // excluded for another reason, it wouldn't be scanned at any size
+        let cfg = ScanConfig {
+            skip_larger_than: Some(HumanSize::from_str("10 B").unwrap()),
+            excludes: vec![Pattern::from_str(&dir.path().join("large").to_string_lossy()).unwrap()],
+            ..Default::default()
+        };
+        let mut visited = Visited::default();
+        walk_directory(&cfg, &mut visited, dir.path(), |_| true);
+        assert!(visited.take_skipped().is_empty());
+    }
+
+    #[test]
+    fn test_skipped_in_use() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("done"), b"a").unwrap();
+        let mut writer = File::create(dir.path().join("downloading")).unwrap();
+        writer.write_all(&[0; 100]).unwrap();
+        let mut small = File::create(dir.path().join("small")).unwrap();
+        small.write_all(b"a").unwrap();
+
+        let cfg = ScanConfig {
+            skip_in_use: true,
+            skip_in_use_above: Some(HumanSize::from_str("10 B").unwrap()),
+            ..Default::default()
+        };
+        let mut visited = Visited::new(&cfg);
+        let mut files = Vec::new();
+        walk_directory(&cfg, &mut visited, dir.path(), |e| {
+            files.push(e.file_name().to_owned());
+            true
+        });
+        assert!(files.contains(&"done".into()));
+        // small files that are in use are scanned anyway
+        assert!(files.contains(&"small".into()));
+        assert!(!files.contains(&"downloading".into()));
+        assert_eq!(
+            visited.take_skipped(),
+            vec![(dir.path().join("downloading"), SkipReason::InUse)]
+        );
+    }
+
+    #[test]
+    fn test_walk_ignore_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("research")).unwrap();
+        fs::create_dir_all(root.join("vms")).unwrap();
+        fs::write(root.join("research/.nocheck"), "").unwrap();
+        fs::write(root.join("research/sample.exe"), "a").unwrap();
+        fs::write(root.join("vms/.libredefender-ignore"), "*.qcow2\n").unwrap();
+        fs::write(root.join("vms/win10.qcow2"), "a").unwrap();
+        fs::write(root.join("vms/notes.txt"), "a").unwrap();
+        fs::write(root.join("invoice.pdf"), "a").unwrap();
+
+        let walk = |cfg: &ScanConfig| {
+            let mut files = Vec::new();
+            walk_directory(cfg, &mut Visited::default(), root, |e| {
+                if e.file_type().is_file() {
+                    files.push(e.path().strip_prefix(root).unwrap().to_path_buf());
+                }
+                true
+            });
+            files.sort();
+            files
+        };
+
+        let mut cfg = ScanConfig {
+            ignore_files: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            walk(&cfg),
+            vec![
+                PathBuf::from("invoice.pdf"),
+                PathBuf::from("vms/.libredefender-ignore"),
+                PathBuf::from("vms/notes.txt"),
+            ]
+        );
+
+        cfg.ignore_files = Some(false);
+        assert_eq!(walk(&cfg).len(), 6);
+        // ignore files are opt-in, malware could drop one next to itself
+        assert_eq!(walk(&ScanConfig::default()).len(), 6);
+    }
+
     #[test]
     fn test_datetime_from_header() {
         let dt = parse_database_age(
@@ -358,4 +3174,110 @@ mod tests {
                 .unwrap()
         );
     }
+
+    #[test]
+    fn test_dedup_overlapping_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        let downloads = tmp.path().join("Downloads");
+        fs::create_dir(&downloads).unwrap();
+        std::os::unix::fs::symlink(&downloads, tmp.path().join("link")).unwrap();
+
+        let paths = dedup_paths(vec![
+            downloads.clone(),
+            tmp.path().to_path_buf(),
+            tmp.path().join("link"),
+            tmp.path().to_path_buf(),
+        ]);
+        assert_eq!(paths, vec![tmp.path().to_path_buf()]);
+
+        let paths = dedup_paths(vec![downloads.clone(), tmp.path().join("link")]);
+        assert_eq!(paths, vec![downloads]);
+    }
+
+    #[test]
+    fn test_skip_symlinks_into_roots() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(tmp.path()).unwrap();
+        fs::write(root.join("a"), b"a").unwrap();
+        std::os::unix::fs::symlink(root.join("a"), root.join("b")).unwrap();
+
+        let cfg = ScanConfig {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let mut visited = Visited::default();
+        visited.set_roots(std::slice::from_ref(&root));
+        let mut files = Vec::new();
+        walk_directory(&cfg, &mut visited, &root, |e| {
+            if !e.file_type().is_dir() {
+                files.push(e.file_name().to_owned());
+            }
+            true
+        });
+        assert_eq!(files, vec![OsStr::new("a").to_owned()]);
+    }
+
+    #[test]
+    fn test_skip_hardlinks() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::write(tmp.path().join("a"), b"a").unwrap();
+        fs::hard_link(tmp.path().join("a"), tmp.path().join("b")).unwrap();
+        fs::write(tmp.path().join("c"), b"c").unwrap();
+
+        let mut visited = Visited::default();
+        let mut files = Vec::new();
+        walk_directory(&ScanConfig::default(), &mut visited, tmp.path(), |e| {
+            if !e.file_type().is_dir() {
+                files.push(e.path().to_path_buf());
+            }
+            true
+        });
+        assert_eq!(files.len(), 2);
+        assert!(files.contains(&tmp.path().join("c")));
+
+        let links = visited.take_hardlinks();
+        assert_eq!(links.len(), 1);
+        let (scanned, skipped) = &links[0];
+        assert!(files.contains(scanned));
+        assert!(!files.contains(skipped));
+    }
+
+    #[test]
+    fn test_cancel_while_paused() {
+        let control = Control::default();
+        let token = control.cancellation_token();
+        control.pause();
+        control.cancel();
+        assert!(token.is_cancelled());
+        // returns immediately instead of waiting for resume
+        control.wait_while_paused();
+    }
+
+    #[test]
+    fn test_signature_count_from_header() {
+        let count = parse_signature_count(
+            b"ClamAV-VDB:09 May 2021 07-08 -0400:26165:3978101:63:X:X:raynman:1620558516    ",
+        )
+        .unwrap();
+        assert_eq!(count, 3978101);
+    }
+
+    #[test]
+    fn test_signature_count_of_extra_databases() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::write(
+            tmp.path().join("corporate.hdb"),
+            "# internal samples\n44d88612fea8a8f36de82e1278abb02f:68:Eicar-Test-Signature\n\n3b9a1a2c7d4f6e8b0a1c2d3e4f5a6b7c:1024:Corp.Dropper\n",
+        )
+        .unwrap();
+        fs::write(
+            tmp.path().join("rules.yara"),
+            "rule first {\n  condition: true\n}\nrule second {\n  condition: false\n}\n",
+        )
+        .unwrap();
+        fs::write(tmp.path().join("README"), "not a database\n").unwrap();
+
+        assert_eq!(count_signatures(&tmp.path().join("corporate.hdb")), 2);
+        assert_eq!(count_signatures(tmp.path()), 4);
+    }
 }
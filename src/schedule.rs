@@ -1,23 +1,65 @@
 use crate::args;
-use crate::config;
-use crate::db::Database;
+use crate::audit::{self, Action};
+use crate::config::{
+    self, Config, HumanSize, Interval, OutsidePreferredHours, Placement, ScheduleConfig,
+    UpdateConfig,
+};
+use crate::db::{Data, Database, ScheduleSkip, SchedulerState};
+use crate::dbus;
 use crate::errors::*;
-use crate::scan;
+use crate::logind::{Inhibitor, Logind};
+use crate::multiuser;
+use crate::notify;
+use crate::pidfile::{self, PidFile};
+use crate::privdrop;
+use crate::push;
+use crate::quarantine::Quarantine;
+#[cfg(target_os = "linux")]
+use crate::removable;
+use crate::rtc;
+use crate::scan::{self, Control, ScanSummary};
+use crate::update;
+use crate::utils;
 use chrono::{DateTime, Datelike, Local, NaiveTime, TimeZone, Timelike, Utc};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
 use rand::Rng;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use starship_battery as battery;
 use std::cmp;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::thread;
+use zbus::zvariant::OwnedFd;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PreferedHours {
     start: NaiveTime,
     end: NaiveTime,
 }
 
 impl PreferedHours {
+    #[must_use]
+    pub fn start(&self) -> NaiveTime {
+        self.start
+    }
+
+    /// The length of the window, it can span midnight
+    #[must_use]
+    pub fn length(&self) -> chrono::Duration {
+        let length = self.end - self.start;
+        if length <= chrono::Duration::zero() {
+            length + chrono::Duration::hours(24)
+        } else {
+            length
+        }
+    }
+
+    fn contains(&self, dt: DateTime<Local>) -> bool {
+        self.until_next_start(dt).is_zero()
+    }
+
     fn until_next_start(&self, dt: DateTime<Local>) -> chrono::Duration {
         let t = dt.time();
         if self.start <= t && (self.end > t || self.end < self.start) {
@@ -126,7 +168,24 @@ impl<'de> Deserialize<'de> for PreferedHours {
     }
 }
 
-fn robust_sleep(sleep: chrono::Duration) -> Result<()> {
+/// Requests for the scheduler that are sent from other threads
+#[derive(Debug)]
+pub enum Command {
+    StartScan,
+    ScanRemovable(PathBuf),
+}
+
+/// Sleep for the given duration, returns early if a command was received in the meantime
+fn robust_sleep(sleep: chrono::Duration, commands: &Receiver<Command>) -> Result<Option<Command>> {
+    robust_sleep_with(sleep, commands, || ())
+}
+
+/// Like `robust_sleep`, `tick` is called every time the wall clock is checked
+fn robust_sleep_with(
+    sleep: chrono::Duration,
+    commands: &Receiver<Command>,
+    mut tick: impl FnMut(),
+) -> Result<Option<Command>> {
     let target_time = Utc::now() + sleep;
 
     let duration_seconds = sleep.num_seconds() as u64;
@@ -145,131 +204,781 @@ fn robust_sleep(sleep: chrono::Duration) -> Result<()> {
     );
 
     loop {
+        tick();
         let remaining = target_time.signed_duration_since(Utc::now());
         trace!("Remaining time: {:?}", remaining);
         if remaining <= chrono::Duration::zero() {
             break;
         }
 
-        let next_sleep = cmp::min(chrono::Duration::seconds(600), remaining);
+        // the timeout doesn't advance during suspend, wake up regularly to check the wall clock
+        let next_sleep = cmp::min(chrono::Duration::seconds(60), remaining);
         trace!("Sleeping for {:?}", next_sleep);
 
-        thread::sleep(next_sleep.to_std()?);
+        match commands.recv_timeout(next_sleep.to_std()?) {
+            Ok(command) => {
+                debug!("Received command: {:?}", command);
+                return Ok(Some(command));
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            // nothing is sending commands, keep sleeping without them
+            Err(RecvTimeoutError::Disconnected) => {
+                thread::sleep(next_sleep.to_std()?);
+            }
+        }
     }
 
-    Ok(())
+    Ok(None)
 }
 
-pub fn run(_args: &args::Scheduler) -> Result<()> {
-    let interval = chrono::Duration::hours(24);
+/// How long to wait before checking again if a scan was postponed by an inhibitor
+const INHIBITOR_RETRY: i64 = 15 * 60;
 
-    loop {
-        let now = Local::now();
+/// Find an inhibitor lock of another program that blocks any of the given operations
+fn blocking_inhibitor(operations: &[String]) -> Option<Inhibitor> {
+    if operations.is_empty() {
+        return None;
+    }
+    let inhibitors = Logind::connect().and_then(|logind| logind.list_inhibitors());
+    match inhibitors {
+        Ok(inhibitors) => inhibitors
+            .into_iter()
+            .find(|i| i.pid != process::id() && i.blocks(operations)),
+        Err(err) => {
+            warn!("Failed to check inhibitor locks: {:#}", err);
+            None
+        }
+    }
+}
 
-        let config = match config::load(None) {
-            Ok(config) => config,
-            Err(err) => {
-                warn!("Failed to load config, skipping this scan: {:#}", err);
-                robust_sleep(interval)?;
-                continue;
+/// Keep the machine from sleeping while scanning, if configured
+fn take_inhibitor(config_path: Option<&Path>) -> Option<OwnedFd> {
+    let what = config::load(config_path, None).ok()?.schedule.inhibit?;
+    match Logind::connect().and_then(|logind| logind.inhibit(&what, "Scanning for malware")) {
+        Ok(fd) => {
+            debug!("Took inhibitor lock for {:?}", what);
+            Some(fd)
+        }
+        Err(err) => {
+            warn!("Failed to take inhibitor lock: {:#}", err);
+            None
+        }
+    }
+}
+
+fn run_scan(
+    config_path: Option<&Path>,
+    args: args::Scan,
+    control: &Arc<Control>,
+    server: Option<&dbus::Server>,
+) -> Option<ScanSummary> {
+    // released when this function returns
+    let _inhibitor = take_inhibitor(config_path);
+    let ret = scan::run_with(config_path, args, control, |event| {
+        if let Some(server) = server {
+            if let Err(err) = server.emit(&event) {
+                warn!("Failed to emit dbus signal: {:#}", err);
             }
-        };
+        }
+    });
+    match ret {
+        Ok(summary) => {
+            info!(
+                "Scheduled scan finished, scanned {} file(s) in {}s",
+                summary.files_scanned,
+                summary.duration.as_secs()
+            );
+            Some(summary)
+        }
+        Err(err) => {
+            error!("Error: {:#}", err);
+            push_failure(config_path, "Scheduled scan failed", &format!("{:#}", err));
+            None
+        }
+    }
+}
+
+/// Scan the home directory of every human user, returns `true` if all of them were scanned
+fn run_system_scan(
+    config_path: Option<&Path>,
+    config: &ScheduleConfig,
+    args: &args::Scan,
+    control: &Control,
+) -> bool {
+    let _inhibitor = take_inhibitor(config_path);
+    match multiuser::scan_users(config, args, control) {
+        Ok(completed) => {
+            info!("Scheduled scan of all users finished");
+            completed
+        }
+        Err(err) => {
+            error!("Error: {:#}", err);
+            push_failure(config_path, "Scheduled scan failed", &format!("{:#}", err));
+            false
+        }
+    }
+}
+
+/// Wait on `done` for up to `duration`, returns `true` if the scan finished in the meantime
+fn wait_for_scan(done: &Receiver<()>, duration: chrono::Duration) -> bool {
+    let duration = duration.to_std().unwrap_or_default();
+    !matches!(done.recv_timeout(duration), Err(RecvTimeoutError::Timeout))
+}
 
-        if config.schedule.skip_on_battery {
-            let battery_manager = battery::Manager::new()?;
-
-            let batteries = battery_manager
-                .batteries()
-                .context("Failed to detect batteries")?
-                .collect::<battery::Result<Vec<_>>>()
-                .context("Failed to read battery status")?;
-
-            // Check if there even are batteries in the system. If we don't
-            // find any batteries we assume that the system has no batteries
-            // and we start a scan.
-            if batteries.is_empty() {
-                debug!("No batteries present in system");
-            } else {
-                // List all batteries and check if any are in state Discharging
-                let battery_discharging = batteries.iter().fold(false, |discharging, battery| {
-                    let state = battery.state();
-                    debug!(
-                        "Found battery: {} {}, {:?}% ({:?})",
-                        battery.vendor().unwrap_or("-"),
-                        battery.model().unwrap_or("-"),
-                        battery.state_of_charge() * 100.0,
-                        state,
-                    );
-                    discharging || state == battery::State::Discharging
-                });
-
-                if battery_discharging {
-                    info!("Battery is discharging, skipping this scan");
-                    robust_sleep(interval)?;
-                    continue;
+/// Pause or stop a scheduled scan that runs past the end of the preferred hours,
+/// the returned thread yields `true` if the scan was aborted
+fn enforce_preferred_hours(
+    hours: PreferedHours,
+    mode: OutsidePreferredHours,
+    control: Arc<Control>,
+    done: Receiver<()>,
+) -> thread::JoinHandle<bool> {
+    thread::spawn(move || loop {
+        if wait_for_scan(&done, hours.until_next_end(Local::now())) {
+            return false;
+        }
+        // only a running scan is stopped, wait for it to start or for the job to wrap up
+        while !control.is_running() {
+            if wait_for_scan(&done, chrono::Duration::seconds(1)) {
+                return false;
+            }
+        }
+        match mode {
+            OutsidePreferredHours::Continue => return false,
+            OutsidePreferredHours::Abort => {
+                info!("Preferred hours are over, stopping scan");
+                control.cancel();
+                return true;
+            }
+            // a scan that was paused by the user is left alone, it's resumed by them too
+            OutsidePreferredHours::Pause if control.is_paused() => {
+                let finished = wait_for_scan(&done, hours.until_next_start(Local::now()));
+                if finished {
+                    return false;
                 }
             }
+            OutsidePreferredHours::Pause => {
+                info!("Preferred hours are over, pausing scan");
+                control.pause();
+                let finished = wait_for_scan(&done, hours.until_next_start(Local::now()));
+                control.resume();
+                if finished {
+                    return false;
+                }
+                info!("Preferred hours started, resuming scan");
+            }
         }
+    })
+}
 
-        match config.schedule.automatic_scans.as_deref() {
-            Some("off") => {
-                info!("Automatic scanning is disabled, skipping this scan");
-                robust_sleep(interval)?;
-                continue;
+fn handle_command(
+    config_path: Option<&Path>,
+    command: Command,
+    control: &Arc<Control>,
+    server: Option<&dbus::Server>,
+) {
+    match command {
+        Command::StartScan => {
+            info!("Scan was requested over dbus");
+            run_scan(config_path, args::Scan::default(), control, server);
+        }
+        Command::ScanRemovable(path) => {
+            if notify::ask_skip_removable(&path) {
+                info!("Skipping scan of removable media: {:?}", path);
+                return;
             }
-            Some("daily") | None => (),
-            value => {
-                error!(
-                    "Invalid value for automatic_scans, skipping this scan: {:?}",
-                    value
+            let args = args::Scan {
+                paths: vec![path],
+                foreign: true,
+                ..Default::default()
+            };
+            run_scan(config_path, args, control, server);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn watch_removable(commands_tx: Sender<Command>) {
+    thread::spawn(move || {
+        if let Err(err) = removable::watch(&commands_tx) {
+            error!("Failed to watch for removable media: {:#}", err);
+        }
+    });
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch_removable(_commands_tx: Sender<Command>) {
+    warn!("Watching for removable media is only supported on linux");
+}
+
+const CONFIG_HINT: &str = "Run `libredefender config check` for details.";
+
+fn record_state<F: FnOnce(&mut SchedulerState)>(update: F) {
+    let ret = Database::load().and_then(|mut db| {
+        update(&mut db.data_mut().scheduler);
+        db.store()
+    });
+    if let Err(err) = ret {
+        warn!("Failed to record scheduler state: {:#}", err);
+    }
+}
+
+/// Remember why a scan didn't happen, so `libredefender` can show it
+fn record_skip(reason: &str) {
+    record_state(|state| {
+        state.next_scan = None;
+        state.next_job = None;
+        state.last_skip = Some(ScheduleSkip {
+            time: Utc::now(),
+            reason: reason.to_string(),
+        });
+    });
+}
+
+/// Send a push notification about a scheduled scan, if configured
+fn push_failure(config_path: Option<&Path>, title: &str, body: &str) {
+    if let Ok(config) = config::load(config_path, None) {
+        push::send(&config.notify.push, title, body, false);
+    }
+}
+
+/// Errors shouldn't silently disable scans, so the user is notified about them
+fn notify_skipped(config_path: Option<&Path>, reason: &str, hint: &str) {
+    error!("{}, skipping this scan", reason);
+    record_skip(reason);
+    let body = format!("{}\n{}", reason, hint);
+    push_failure(config_path, "Scheduled scan skipped", &body);
+    if let Err(err) = notify::alert("Scheduled scan skipped", &body) {
+        warn!("Failed to send notification: {:#}", err);
+    }
+}
+
+fn signatures_outdated(config: &UpdateConfig) -> bool {
+    match scan::database_age(&config.path) {
+        Ok(age) => Utc::now().signed_duration_since(age) > config.max_age(),
+        Err(err) => {
+            warn!("Failed to check signature age: {:#}", err);
+            false
+        }
+    }
+}
+
+/// Notify about outdated signatures before a scheduled scan, optionally refreshing them first
+fn check_signatures(config: &UpdateConfig) {
+    if !signatures_outdated(config) {
+        return;
+    }
+
+    if config.refresh_when_stale && !update::is_deferred(config) {
+        match update::freshclam(config) {
+            Ok(_) if !signatures_outdated(config) => return,
+            Ok(_) => (),
+            Err(err) => warn!("Failed to update signatures: {:#}", err),
+        }
+    }
+
+    warn!(
+        "Signatures are older than {} days, scanning anyway",
+        config.max_age_days
+    );
+    let body = format!(
+        "The clamav signatures haven't been updated in more than {} days, check that clamav-freshclam.service is running.",
+        config.max_age_days
+    );
+    if let Err(err) = notify::alert("Signatures are outdated", &body) {
+        warn!("Failed to send notification: {:#}", err);
+    }
+}
+
+/// Enforce `quarantine.retention_days` and `quarantine.max_size`, deletions to stay below the quota are notified
+pub fn prune_quarantine(config: &Config) {
+    let retention = config.quarantine.retention();
+    let max_size = config.quarantine.max_size.as_ref().map(HumanSize::as_bytes);
+    if retention.is_none() && max_size.is_none() {
+        return;
+    }
+    let pruned = match Quarantine::open().and_then(|q| q.prune(retention, max_size)) {
+        Ok(pruned) => pruned,
+        Err(err) => {
+            warn!("Failed to prune quarantine: {:#}", err);
+            return;
+        }
+    };
+    for err in &pruned.errors {
+        warn!("Failed to prune quarantine: {:#}", err);
+    }
+    for (entries, reason) in [
+        (&pruned.expired, "retention"),
+        (&pruned.over_quota, "quota"),
+    ] {
+        for entry in entries {
+            info!(
+                "Deleted {:?} from quarantine ({}): {}",
+                entry.original_path, reason, entry.id
+            );
+            let audit_entry =
+                audit::Entry::new(Action::Deleted, &entry.original_path, &entry.names)
+                    .detail(format!("{} {}", entry.id, reason));
+            if let Err(err) = audit::record(&audit_entry) {
+                warn!("Failed to write audit log: {:#}", err);
+            }
+        }
+    }
+    if !pruned.over_quota.is_empty() {
+        let body = format!(
+            "{} quarantined file(s) were deleted to stay below quarantine.max_size, the oldest was {:?}",
+            pruned.over_quota.len(),
+            pruned.over_quota[0].original_path
+        );
+        warn!("{}", body);
+        push::send(&config.notify.push, "Quarantine is full", &body, false);
+        if let Err(err) = notify::alert("Quarantine is full", &body) {
+            warn!("Failed to send notification: {:#}", err);
+        }
+    }
+}
+
+/// Prune our own quarantine, and in system mode the quarantine of every user with the same limits
+fn prune_all(config: &Config) {
+    prune_quarantine(config);
+    if config.schedule.system {
+        if let Err(err) = multiuser::prune_users(config) {
+            warn!("Failed to prune the quarantine of users: {:#}", err);
+        }
+    }
+}
+
+/// A scheduled scan, either one of `[[schedule.job]]` or the scan configured in `[schedule]`
+#[derive(Debug)]
+struct Job {
+    name: Option<String>,
+    interval: chrono::Duration,
+    preferred_hours: Option<PreferedHours>,
+    profile: Option<String>,
+    placement: Placement,
+    jitter: i64,
+}
+
+impl Job {
+    /// Unnamed jobs use the time of the last scan, this includes manual ones
+    fn last_run(&self, data: &Data) -> Option<DateTime<Utc>> {
+        match &self.name {
+            Some(name) => data.job_runs.get(name).copied(),
+            None => data.last_scan,
+        }
+    }
+
+    fn until_due(&self, data: &Data, now: DateTime<Local>) -> chrono::Duration {
+        let mut rng = rand::thread_rng();
+        let jitter = chrono::Duration::seconds(rng.gen_range(0..=self.jitter));
+
+        let Some(last_run) = self.last_run(data) else {
+            return jitter;
+        };
+        let last_run = last_run.with_timezone(&Local);
+        if now - last_run > self.interval {
+            return jitter;
+        }
+
+        let Some(ph) = &self.preferred_hours else {
+            return self.interval - (now - last_run) + jitter;
+        };
+        let (start, length) = next_window(ph, self.interval, last_run, now);
+        start + place_in_window(self.placement, length, jitter, &mut rng)
+    }
+}
+
+fn jobs(config: &ScheduleConfig) -> Vec<Job> {
+    if config.job.is_empty() {
+        return vec![Job {
+            name: None,
+            interval: Interval::Daily.duration(),
+            preferred_hours: config.preferred_hours.clone(),
+            profile: config.profile.clone(),
+            placement: config.placement,
+            jitter: i64::from(config.jitter),
+        }];
+    }
+    config
+        .job
+        .iter()
+        .map(|job| Job {
+            name: Some(job.name.clone()),
+            interval: job.interval.duration(),
+            preferred_hours: job.preferred_hours.clone(),
+            profile: job.profile.clone(),
+            placement: config.placement,
+            jitter: i64::from(config.jitter),
+        })
+        .collect()
+}
+
+/// Find the preferred hours the next run falls into, relative to `now`,
+/// this is the first window that starts at least `interval - 1 day` after the last run
+fn next_window(
+    ph: &PreferedHours,
+    interval: chrono::Duration,
+    last_run: DateTime<Local>,
+    now: DateTime<Local>,
+) -> (chrono::Duration, chrono::Duration) {
+    let mut earliest = last_run + interval - chrono::Duration::days(1);
+    if ph.contains(earliest) {
+        // don't run twice in the same window
+        earliest = earliest + ph.until_next_end(earliest);
+    }
+    let from = cmp::max(earliest, now);
+    let start = from + ph.until_next_start(from);
+    (start - now, ph.until_next_end(start))
+}
+
+/// Offset of the scan from the start of a window that is `length` long
+fn place_in_window<R: Rng>(
+    placement: Placement,
+    length: chrono::Duration,
+    jitter: chrono::Duration,
+    rng: &mut R,
+) -> chrono::Duration {
+    // the last second still counts as inside of the window
+    let latest = cmp::max(
+        length - chrono::Duration::seconds(1),
+        chrono::Duration::zero(),
+    );
+    match placement {
+        Placement::Start => cmp::min(jitter, latest),
+        Placement::Random => chrono::Duration::seconds(rng.gen_range(0..=latest.num_seconds())),
+        Placement::End => cmp::max(latest - jitter, chrono::Duration::zero()),
+    }
+}
+
+fn is_idle() -> bool {
+    match Logind::connect().and_then(|logind| logind.is_idle()) {
+        Ok(idle) => idle,
+        Err(err) => {
+            warn!("Failed to check if the machine is idle: {:#}", err);
+            false
+        }
+    }
+}
+
+fn record_job_run(name: &str) {
+    let ret = Database::load().and_then(|mut db| {
+        db.data_mut().job_runs.insert(name.to_string(), Utc::now());
+        db.store()
+    });
+    if let Err(err) = ret {
+        error!("Failed to record run of job {:?}: {:#}", name, err);
+    }
+}
+
+pub fn run(config_path: Option<&Path>, _args: &args::Scheduler) -> Result<()> {
+    let interval = chrono::Duration::hours(24);
+
+    // `status` checks this to tell if the scheduler is running
+    let _pidfile =
+        PidFile::acquire(&pidfile::scheduler_path()).context("Failed to start scheduler")?;
+
+    let control = Arc::new(Control::default());
+    let (commands_tx, commands) = crossbeam_channel::unbounded();
+
+    // scans run inside of this process, root is still needed after them
+    privdrop::stay_privileged();
+    if utils::is_root() && config::load(config_path, None).is_ok_and(|c| c.scan.user.is_some()) {
+        warn!("scan.user is not supported by the scheduler, scheduled scans run as root");
+    }
+
+    let scan_removable = config::load(config_path, None).is_ok_and(|c| c.schedule.scan_removable);
+    if scan_removable {
+        watch_removable(commands_tx.clone());
+    }
+
+    let max_age = config::load(config_path, None).map_or_else(
+        |_| chrono::Duration::days(config::DEFAULT_MAX_AGE_DAYS.into()),
+        |c| c.update.max_age(),
+    );
+    let server = match dbus::Server::start(control.clone(), commands_tx, max_age) {
+        Ok(server) => Some(server),
+        Err(err) => {
+            warn!("Failed to start dbus service: {:#}", err);
+            None
+        }
+    };
+    let mut pending = None;
+
+    loop {
+        if let Some(command) = pending.take() {
+            handle_command(config_path, command, &control, server.as_ref());
+            continue;
+        }
+
+        let now = Local::now();
+
+        let config = match config::load(config_path, None) {
+            Ok(config) => config,
+            Err(err) => {
+                notify_skipped(
+                    config_path,
+                    &format!("Failed to load config: {:#}", err),
+                    CONFIG_HINT,
                 );
-                robust_sleep(interval)?;
+                pending = robust_sleep(interval, &commands)?;
                 continue;
             }
+        };
+
+        prune_all(&config);
+        if !ready(config_path, &config)? {
+            pending = robust_sleep(interval, &commands)?;
+            continue;
         }
 
         let db = match Database::load() {
             Ok(db) => db,
             Err(err) => {
                 error!("Failed to load database: {:#}", err);
-                robust_sleep(interval)?;
+                pending = robust_sleep(interval, &commands)?;
                 continue;
             }
         };
-        let data = db.data();
-
-        let sleep = data
-            .last_scan
-            .map_or_else(chrono::Duration::zero, |last_scan| {
-                let duration_since_last_scan = now - last_scan.with_timezone(&Local);
-
-                if duration_since_last_scan > interval {
-                    chrono::Duration::zero()
-                } else {
-                    config.schedule.preferred_hours.map_or_else(
-                        // no preferred hours
-                        || interval - (now - last_scan.with_timezone(&Local)),
-                        // there are preferred hours
-                        |ph| {
-                            let start = ph.until_next_start(now);
-                            let end = ph.until_next_end(now);
-
-                            let mut rng = rand::thread_rng();
-                            let preferred_hours_duration = (end - start).num_seconds();
-                            let jitter = rng.gen_range(0..preferred_hours_duration);
-
-                            start + chrono::Duration::seconds(jitter)
-                        },
-                    )
+        let (job, sleep) = jobs(&config.schedule)
+            .into_iter()
+            .map(|job| {
+                let sleep = job.until_due(db.data(), now);
+                (job, sleep)
+            })
+            .min_by_key(|(_, sleep)| *sleep)
+            .expect("there's always at least one job");
+
+        if let Some(name) = &job.profile {
+            if !config.profile.contains_key(name) {
+                notify_skipped(
+                    config_path,
+                    &format!("Profile not found in config: {:?}", name),
+                    CONFIG_HINT,
+                );
+                pending = robust_sleep(interval, &commands)?;
+                continue;
+            }
+        }
+        if let Some(name) = &job.name {
+            info!("Next scheduled scan is {:?}", name);
+        }
+
+        record_state(|state| {
+            state.next_scan = Some(Utc::now() + sleep);
+            state.next_job = job.name.clone();
+        });
+
+        let mut alarm = None;
+        if config.schedule.use_rtc_wake && sleep > chrono::Duration::zero() {
+            match rtc::set_wake_alarm(Utc::now() + sleep) {
+                Ok(a) => alarm = Some(a),
+                Err(err) => warn!("Failed to program rtc wake alarm: {:#}", err),
+            }
+        }
+
+        pending = robust_sleep_with(sleep, &commands, || {
+            if let Some(alarm) = &mut alarm {
+                alarm.tick();
+            }
+        })?;
+        // only a machine that was woken up by our alarm is suspended again after the scan
+        let woke_us = alarm.as_ref().is_some_and(|alarm| alarm.woke_us());
+        if let Some(Err(err)) = alarm.map(|alarm| alarm.restore()) {
+            warn!("Failed to restore rtc wake alarm: {:#}", err);
+        }
+        if pending.is_none() {
+            let server = server.as_ref();
+            pending = match run_job(config_path, &config, &job, &control, server, woke_us) {
+                JobResult::Finished => None,
+                JobResult::Skipped => robust_sleep(interval, &commands)?,
+                JobResult::Blocked => {
+                    robust_sleep(chrono::Duration::seconds(INHIBITOR_RETRY), &commands)?
                 }
+                JobResult::Stopped(until) => robust_sleep(until, &commands)?,
+            };
+        }
+    }
+}
+
+/// Run a single scheduled scan with the same checks as the scheduler, for systemd timers and cron
+///
+/// Without `job` the scan configured in `[schedule]` is started.
+pub fn run_once(config_path: Option<&Path>, job: Option<&str>) -> Result<()> {
+    // like the scheduler, to scan the home directories of all users in system mode
+    privdrop::stay_privileged();
+    let config = config::load(config_path, None).context("Failed to load config")?;
+    prune_all(&config);
+    if !ready(config_path, &config)? {
+        return Ok(());
+    }
+
+    let job = jobs(&config.schedule)
+        .into_iter()
+        .find(|j| j.name.as_deref() == job)
+        .with_context(|| anyhow!("Scheduled scan not found in config: {:?}", job))?;
+    if let Some(name) = &job.profile {
+        if !config.profile.contains_key(name) {
+            bail!("Profile not found in config: {:?}", name);
+        }
+    }
+
+    let control = Arc::new(Control::default());
+    loop {
+        match run_job(config_path, &config, &job, &control, None, false) {
+            JobResult::Blocked => {
+                thread::sleep(std::time::Duration::from_secs(INHIBITOR_RETRY as u64))
+            }
+            JobResult::Finished | JobResult::Skipped | JobResult::Stopped(_) => return Ok(()),
+        }
+    }
+}
+
+/// Checks that apply to every scheduled scan, returns `false` if the scan is skipped
+fn ready(config_path: Option<&Path>, config: &Config) -> Result<bool> {
+    if config.schedule.skip_on_battery {
+        let battery_manager = battery::Manager::new()?;
+
+        let batteries = battery_manager
+            .batteries()
+            .context("Failed to detect batteries")?
+            .collect::<battery::Result<Vec<_>>>()
+            .context("Failed to read battery status")?;
+
+        // Check if there even are batteries in the system. If we don't
+        // find any batteries we assume that the system has no batteries
+        // and we start a scan.
+        if batteries.is_empty() {
+            debug!("No batteries present in system");
+        } else {
+            // List all batteries and check if any are in state Discharging
+            let battery_discharging = batteries.iter().fold(false, |discharging, battery| {
+                let state = battery.state();
+                debug!(
+                    "Found battery: {} {}, {:?}% ({:?})",
+                    battery.vendor().unwrap_or("-"),
+                    battery.model().unwrap_or("-"),
+                    battery.state_of_charge() * 100.0,
+                    state,
+                );
+                discharging || state == battery::State::Discharging
             });
 
-        robust_sleep(sleep)?;
+            if battery_discharging {
+                info!("Battery is discharging, skipping this scan");
+                record_skip("Battery is discharging");
+                return Ok(false);
+            }
+        }
+    }
 
-        if let Err(err) = scan::run(args::Scan::default()) {
-            error!("Error: {:#}", err);
+    match config.schedule.automatic_scans.as_deref() {
+        Some("off") => {
+            info!("Automatic scanning is disabled, skipping this scan");
+            record_skip("Automatic scanning is disabled");
+            Ok(false)
+        }
+        Some("daily") | None => Ok(true),
+        value => {
+            notify_skipped(
+                config_path,
+                &format!("Invalid value for automatic_scans: {:?}", value),
+                CONFIG_HINT,
+            );
+            Ok(false)
+        }
+    }
+}
+
+/// What happened to a scheduled scan that was due
+enum JobResult {
+    Finished,
+    /// Skipped until the next interval, e.g. signatures couldn't be updated
+    Skipped,
+    /// Another program holds an inhibitor lock that is respected, retry later
+    Blocked,
+    /// Stopped at the end of the preferred hours, continues when they start again
+    Stopped(chrono::Duration),
+}
+
+fn run_job(
+    config_path: Option<&Path>,
+    config: &Config,
+    job: &Job,
+    control: &Arc<Control>,
+    server: Option<&dbus::Server>,
+    woke_us: bool,
+) -> JobResult {
+    if let Some(inhibitor) = blocking_inhibitor(&config.schedule.respect_inhibitors) {
+        let reason = format!(
+            "{:?} is blocking {:?}: {}",
+            inhibitor.who, inhibitor.what, inhibitor.why
+        );
+        info!("Postponing scan, {}", reason);
+        record_skip(&reason);
+        return JobResult::Blocked;
+    }
+    // on metered connections the scan runs with the signatures that are already there
+    if config.schedule.update_before_scan && !update::is_deferred(&config.update) {
+        if let Err(err) = update::freshclam(&config.update) {
+            warn!("Failed to update signatures: {:#}", err);
+        }
+        if signatures_outdated(&config.update) {
+            notify_skipped(
+                config_path,
+                &format!(
+                    "Signatures are older than {} days",
+                    config.update.max_age_days
+                ),
+                "Check that freshclam is able to download new signatures.",
+            );
+            return JobResult::Skipped;
+        }
+    } else {
+        check_signatures(&config.update);
+    }
+    let args = args::Scan {
+        profile: job.profile.clone(),
+        diff: config.schedule.diff,
+        ..Default::default()
+    };
+
+    // only scans that started inside of the preferred hours are held to them
+    let mode = config.schedule.outside_preferred_hours;
+    let enforce = match job.preferred_hours.clone() {
+        Some(ph) if mode != OutsidePreferredHours::Continue && ph.contains(Local::now()) => {
+            let (done_tx, done_rx) = crossbeam_channel::bounded(1);
+            let handle = enforce_preferred_hours(ph.clone(), mode, control.clone(), done_rx);
+            Some((ph, done_tx, handle))
+        }
+        _ => None,
+    };
+
+    record_state(|state| state.next_scan = None);
+
+    // nobody started using the machine since our alarm woke it up
+    let resuspend = woke_us && is_idle();
+    let completed = if config.schedule.system {
+        run_system_scan(config_path, &config.schedule, &args, control)
+    } else {
+        run_scan(config_path, args, control, server).is_some_and(|summary| !summary.cancelled)
+    };
+    if resuspend && is_idle() {
+        info!("Machine is still idle, suspending again");
+        if let Err(err) = Logind::connect().and_then(|logind| logind.suspend()) {
+            warn!("Failed to suspend: {:#}", err);
+        }
+    }
+    if let (Some(name), true) = (&job.name, completed) {
+        record_job_run(name);
+    }
+
+    if let Some((ph, done_tx, handle)) = enforce {
+        drop(done_tx);
+        if handle.join().unwrap_or(false) {
+            return JobResult::Stopped(ph.until_next_start(Local::now()));
         }
     }
+    JobResult::Finished
 }
 
 #[cfg(test)]
@@ -314,6 +1023,71 @@ mod tests {
         assert_eq!(duration, chrono::Duration::seconds(5 * 3600 + 23 * 60));
     }
 
+    #[test]
+    fn test_next_window_skips_window_of_last_run() {
+        let at = |d, h, m| {
+            Local
+                .with_ymd_and_hms(1970, 1, d, h, m, 0)
+                .single()
+                .unwrap()
+        };
+        let ph = PreferedHours::from_str("09:00:00-19:00:00").unwrap();
+        let (start, length) =
+            next_window(&ph, Interval::Daily.duration(), at(1, 18, 0), at(1, 18, 30));
+        assert_eq!(start, chrono::Duration::minutes(14 * 60 + 30));
+        assert_eq!(length, chrono::Duration::hours(10));
+    }
+
+    #[test]
+    fn test_next_window_weekly() {
+        let at = |d, h, m| {
+            Local
+                .with_ymd_and_hms(1970, 1, d, h, m, 0)
+                .single()
+                .unwrap()
+        };
+        let ph = PreferedHours::from_str("01:00:00-07:00:00").unwrap();
+        let (start, length) =
+            next_window(&ph, Interval::Weekly.duration(), at(1, 2, 0), at(2, 12, 0));
+        assert_eq!(start, chrono::Duration::hours(5 * 24 + 13));
+        assert_eq!(length, chrono::Duration::hours(6));
+    }
+
+    #[test]
+    fn test_place_in_window() {
+        let hours = chrono::Duration::hours;
+        let minutes = chrono::Duration::minutes;
+        let place = |placement, jitter| {
+            place_in_window(placement, hours(6), jitter, &mut rand::thread_rng())
+        };
+        assert_eq!(place(Placement::Start, minutes(5)), minutes(5));
+        assert_eq!(
+            place(Placement::Start, hours(8)),
+            hours(6) - chrono::Duration::seconds(1)
+        );
+        assert_eq!(
+            place(Placement::End, minutes(30)),
+            minutes(330) - chrono::Duration::seconds(1)
+        );
+        assert_eq!(place(Placement::End, hours(8)), chrono::Duration::zero());
+        let random = place(Placement::Random, minutes(5));
+        assert!(random >= chrono::Duration::zero() && random < hours(6));
+    }
+
+    #[test]
+    fn test_preferred_hours_contains() {
+        let ph = PreferedHours::from_str("01:00:00-07:00:00").unwrap();
+        let at = |h| {
+            Local
+                .with_ymd_and_hms(1970, 1, 2, h, 0, 0)
+                .single()
+                .unwrap()
+        };
+        assert!(ph.contains(at(3)));
+        assert!(!ph.contains(at(7)));
+        assert!(!ph.contains(at(13)));
+    }
+
     #[test]
     fn test_until_next_preferred_hour_end() {
         let now = Local
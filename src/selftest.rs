@@ -0,0 +1,46 @@
+use crate::args;
+use crate::config;
+use crate::describe::Descriptions;
+use crate::errors::*;
+use crate::notify;
+use crate::scan::Scanner;
+use std::fs;
+use std::path::Path;
+
+// split in two so other antivirus software doesn't flag our binary
+const EICAR: [&str; 2] = [
+    "X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR",
+    "-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*",
+];
+
+/// Scan an EICAR test file with the configured backend and show a notification for it
+pub fn run(config_path: Option<&Path>, args: &args::SelfTest) -> Result<()> {
+    let config = config::load(config_path, None).context("Failed to load config")?;
+
+    let tmp = tempfile::Builder::new()
+        .prefix("libredefender-selftest-")
+        .tempdir()
+        .context("Failed to create temporary directory")?;
+    let path = tmp.path().join("eicar.com");
+    fs::write(&path, EICAR.concat()).context("Failed to write test file")?;
+
+    let scanner = Scanner::from_config(&config)?;
+
+    info!("Scanning test file {:?}...", path);
+    let (results_tx, results_rx) = crossbeam_channel::unbounded();
+    scanner.scan_file(&path, &results_tx)?;
+    let (_, name) = results_rx
+        .try_recv()
+        .context("EICAR test file was not detected, the scanner is not working")?;
+    info!("Test file was detected as {:?}", name);
+
+    if !args.no_notify {
+        info!("Sending notification...");
+        let description = Descriptions::from_config(&config.update).describe(&name);
+        notify::show(&path, &name, description.as_ref()).context("Failed to show notification")?;
+    }
+
+    tmp.close()
+        .context("Failed to remove temporary directory")?;
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use crate::heuristic;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How dangerous a detection is, based on the family in the signature name
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Severity {
+    /// Test signatures like eicar
+    #[default]
+    Low,
+    /// Potentially unwanted programs, adware and heuristics
+    Medium,
+    /// Regular malware
+    High,
+    /// Ransomware, backdoors and rootkits
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Low => write!(w, "low"),
+            Severity::Medium => write!(w, "medium"),
+            Severity::High => write!(w, "high"),
+            Severity::Critical => write!(w, "critical"),
+        }
+    }
+}
+
+/// Classify a signature name like `Win.Trojan.Agent-1234` or `PUA.Win.Adware.Foo-1`
+///
+/// Only the category after the platform is looked at, the family name could contain any word.
+/// Older databases recorded archive members as ` (member)` suffix, it's ignored.
+#[must_use]
+pub fn classify(name: &str) -> Severity {
+    if name.starts_with(heuristic::PREFIX) {
+        return Severity::Low;
+    }
+    let name = name.split_once(" (").map_or(name, |(name, _)| name);
+    let mut parts = name.split('.').map(str::to_ascii_lowercase);
+    let category = match parts.next().as_deref() {
+        // `PUA.{platform}.{category}.{name}`, potentially unwanted regardless of the category
+        Some("pua" | "heuristics" | "heuristic") => return Severity::Medium,
+        // `{platform}.{category}.{name}`
+        _ => parts.next(),
+    };
+    category_severity(category.as_deref())
+}
+
+fn category_severity(category: Option<&str>) -> Severity {
+    match category {
+        Some("test") => Severity::Low,
+        Some("pua" | "adware" | "spyware") => Severity::Medium,
+        Some("ransomware" | "ransom" | "backdoor" | "rootkit" | "wiper") => Severity::Critical,
+        // everything else, including third party signatures, is treated as malware
+        _ => Severity::High,
+    }
+}
+
+/// The severity of a file is the highest of all its detections
+#[must_use]
+pub fn classify_all<S: AsRef<str>>(names: &[S]) -> Severity {
+    names
+        .iter()
+        .map(|name| classify(name.as_ref()))
+        .max()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify("Win.Test.EICAR_HDB-1"), Severity::Low);
+        assert_eq!(classify("PUA.Win.Adware.Agent-6781912-0"), Severity::Medium);
+        assert_eq!(
+            classify("Heuristics.Phishing.Email.SpoofedDomain"),
+            Severity::Medium
+        );
+        assert_eq!(classify("Win.Trojan.Agent-1234"), Severity::High);
+        assert_eq!(classify("Win.Ransomware.Locky-9753"), Severity::Critical);
+        assert_eq!(classify("YARA.my_rule.UNOFFICIAL"), Severity::High);
+        assert_eq!(classify("Win.Trojan.Test-1"), Severity::High);
+        assert_eq!(classify("Win.Trojan.X (foo.test.js)"), Severity::High);
+        assert_eq!(
+            classify("Win.Test.EICAR_HDB-1 (dist/eicar.com)"),
+            Severity::Low
+        );
+        assert_eq!(
+            classify("Libredefender.Suspicious.DoubleExtension"),
+            Severity::Low
+        );
+    }
+
+    #[test]
+    fn test_classify_all() {
+        assert_eq!(
+            classify_all(&["Win.Test.EICAR_HDB-1", "Unix.Backdoor.Foo-1"]),
+            Severity::Critical
+        );
+        assert_eq!(classify_all::<&str>(&[]), Severity::Low);
+    }
+}
@@ -0,0 +1,375 @@
+use crate::db::{Data, Database, ScanStats, SchedulerState, SkipReason};
+use crate::errors::*;
+use crate::pidfile;
+use crate::quarantine::Quarantine;
+use crate::utils;
+use chrono::{DateTime, Duration, Utc};
+use chrono_humanize::HumanTime;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Ok,
+    Warning,
+    Critical,
+}
+
+impl State {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::Ok => "ok",
+            State::Warning => "warning",
+            State::Critical => "critical",
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        w.write_str(self.as_str())
+    }
+}
+
+/// Signatures that haven't been updated for longer than `max_age` miss recent threats
+#[must_use]
+pub fn is_outdated(data: &Data, max_age: Duration) -> bool {
+    data.signatures_age
+        .is_some_and(|age| Utc::now().signed_duration_since(age) > max_age)
+}
+
+#[must_use]
+pub fn state(data: &Data, max_age: Duration) -> State {
+    if !data.threats.is_empty() {
+        State::Critical
+    } else if data.last_scan.is_none() || data.signature_count == 0 || is_outdated(data, max_age) {
+        State::Warning
+    } else {
+        State::Ok
+    }
+}
+
+/// Something that needs attention, with the command that fixes it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Problem {
+    pub message: String,
+    pub fix: String,
+}
+
+impl Problem {
+    fn new<M: Into<String>, F: Into<String>>(message: M, fix: F) -> Problem {
+        Problem {
+            message: message.into(),
+            fix: fix.into(),
+        }
+    }
+}
+
+/// The problems shown by `status`, most urgent first
+#[must_use]
+pub fn problems(data: &Data, max_age: Duration, scheduler_running: bool) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    if !data.threats.is_empty() {
+        problems.push(Problem::new(
+            format!("{} threat(s) found", data.threats.len()),
+            "libredefender infections",
+        ));
+    }
+    if data.last_scan.is_none() {
+        problems.push(Problem::new(
+            "No scan has finished yet",
+            "libredefender scan",
+        ));
+    } else if data.signature_count == 0 {
+        problems.push(Problem::new(
+            "The last scan ran without signatures",
+            "sudo freshclam",
+        ));
+    }
+    if is_outdated(data, max_age) {
+        problems.push(Problem::new(
+            format!(
+                "Signatures haven't been updated for more than {} days",
+                max_age.num_days()
+            ),
+            "sudo systemctl enable --now clamav-freshclam.service",
+        ));
+    }
+    if !scheduler_running {
+        problems.push(Problem::new(
+            "The scheduler is not running, scans only run when started manually",
+            "libredefender scheduler",
+        ));
+    }
+    let unreadable = data
+        .skipped
+        .values()
+        .filter(|reason| {
+            matches!(
+                reason,
+                SkipReason::PermissionDenied | SkipReason::Unreadable
+            )
+        })
+        .count();
+    if unreadable > 0 {
+        problems.push(Problem::new(
+            format!("{} file(s) couldn't be read by the last scan", unreadable),
+            "libredefender scan --show-skipped",
+        ));
+    }
+    problems
+}
+
+/// Whether `libredefender scheduler` is running and what it plans to do
+#[derive(Debug, Serialize)]
+pub struct SchedulerStatus {
+    pub running: bool,
+    pub pid: Option<u32>,
+    /// The scheduler runs as root and scans the home directories of all users
+    pub system: bool,
+    #[serde(flatten)]
+    pub state: SchedulerState,
+}
+
+impl SchedulerStatus {
+    #[must_use]
+    pub fn new(state: &SchedulerState) -> SchedulerStatus {
+        let (pid, system) = match pidfile::running(&pidfile::scheduler_path()) {
+            Some(pid) => (Some(pid), utils::is_root()),
+            None => (pidfile::running(Path::new(pidfile::SYSTEM_SCHEDULER)), true),
+        };
+        SchedulerStatus {
+            running: pid.is_some(),
+            pid,
+            system: pid.is_some() && system,
+            state: state.clone(),
+        }
+    }
+}
+
+/// Everything the `status` dashboard shows, printed by `status --format json`
+#[derive(Debug, Serialize)]
+pub struct Dashboard {
+    pub state: State,
+    pub last_scan: Option<DateTime<Utc>>,
+    pub last_scan_stats: Option<ScanStats>,
+    pub threats: usize,
+    pub skipped: usize,
+    pub signature_count: usize,
+    pub signature_sources: BTreeMap<PathBuf, usize>,
+    pub signatures_age: Option<DateTime<Utc>>,
+    pub signatures_outdated: bool,
+    pub scheduler: SchedulerStatus,
+    /// `None` if the quarantine couldn't be read
+    pub quarantined: Option<usize>,
+    pub database_size: Option<u64>,
+    pub problems: Vec<Problem>,
+}
+
+impl Dashboard {
+    #[must_use]
+    pub fn new(data: &Data, max_age: Duration) -> Dashboard {
+        let scheduler = SchedulerStatus::new(&data.scheduler);
+        let quarantined = match Quarantine::list() {
+            Ok(list) => Some(list.len()),
+            Err(err) => {
+                debug!("Failed to list quarantine: {:#}", err);
+                None
+            }
+        };
+        let database_size = Database::path()
+            .ok()
+            .and_then(|path| fs::metadata(path).ok())
+            .map(|md| md.len());
+        Dashboard {
+            state: state(data, max_age),
+            last_scan: data.last_scan,
+            last_scan_stats: data.last_scan_stats.clone(),
+            threats: data.threats.len(),
+            skipped: data.skipped.len(),
+            signature_count: data.signature_count,
+            signature_sources: data
+                .signature_sources
+                .iter()
+                .map(|(path, n)| (path.clone(), *n))
+                .collect(),
+            signatures_age: data.signatures_age,
+            signatures_outdated: is_outdated(data, max_age),
+            problems: problems(data, max_age, scheduler.running),
+            scheduler,
+            quarantined,
+            database_size,
+        }
+    }
+}
+
+pub fn json(data: &Data, max_age: Duration) -> Result<String> {
+    let json = serde_json::to_string_pretty(&Dashboard::new(data, max_age))?;
+    Ok(json)
+}
+
+fn humanize(dt: &Option<DateTime<Utc>>) -> String {
+    if let Some(dt) = dt {
+        HumanTime::from(dt.signed_duration_since(Utc::now())).to_string()
+    } else {
+        "never".to_string()
+    }
+}
+
+fn tooltip(data: &Data) -> String {
+    format!(
+        "Last scan: {}\nThreats present: {}\nSignatures: {}\nSignatures updated: {}",
+        humanize(&data.last_scan),
+        data.threats.len(),
+        data.signature_count,
+        humanize(&data.signatures_age),
+    )
+}
+
+/// Custom module output, see waybar-custom(5)
+#[derive(Debug, Serialize)]
+pub struct Waybar {
+    pub text: String,
+    pub alt: State,
+    pub tooltip: String,
+    pub class: State,
+}
+
+impl Waybar {
+    #[must_use]
+    pub fn new(data: &Data, max_age: Duration) -> Waybar {
+        let state = state(data, max_age);
+        Waybar {
+            text: data.threats.len().to_string(),
+            alt: state,
+            tooltip: tooltip(data),
+            class: state,
+        }
+    }
+}
+
+pub fn waybar(data: &Data, max_age: Duration) -> Result<String> {
+    let json = serde_json::to_string(&Waybar::new(data, max_age))?;
+    Ok(json)
+}
+
+/// Replace `{state}`, `{threats}`, `{last_scan}`, `{signatures}` and `{signatures_age}` in the template
+#[must_use]
+pub fn render_template(template: &str, data: &Data, max_age: Duration) -> String {
+    template
+        .replace("{state}", state(data, max_age).as_str())
+        .replace("{threats}", &data.threats.len().to_string())
+        .replace("{last_scan}", &humanize(&data.last_scan))
+        .replace("{signatures}", &data.signature_count.to_string())
+        .replace("{signatures_age}", &humanize(&data.signatures_age))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Threat;
+
+    #[test]
+    fn test_state_never_scanned() {
+        let data = Data::default();
+        assert_eq!(state(&data, Duration::days(7)), State::Warning);
+    }
+
+    #[test]
+    fn test_state_threats() {
+        let mut data = Data {
+            last_scan: Some(Utc::now()),
+            signature_count: 1,
+            ..Default::default()
+        };
+        assert_eq!(state(&data, Duration::days(7)), State::Ok);
+        data.threats
+            .insert(PathBuf::from("/a"), Threat::new(Utc::now()));
+        assert_eq!(state(&data, Duration::days(7)), State::Critical);
+    }
+
+    #[test]
+    fn test_render_template() {
+        let data = Data {
+            signature_count: 1337,
+            ..Default::default()
+        };
+        let txt = render_template(
+            "{state}: {threats} threats, {signatures} sigs ({last_scan})",
+            &data,
+            Duration::days(7),
+        );
+        assert_eq!(txt, "warning: 0 threats, 1337 sigs (never)");
+    }
+
+    #[test]
+    fn test_waybar_json() {
+        let data = Data::default();
+        let json = waybar(&data, Duration::days(7)).unwrap();
+        assert!(json
+            .starts_with("{\"text\":\"0\",\"alt\":\"warning\",\"tooltip\":\"Last scan: never\\n"));
+        assert!(json.ends_with(",\"class\":\"warning\"}"));
+    }
+
+    #[test]
+    fn test_dashboard_json() {
+        let data = Data {
+            signature_count: 1337,
+            ..Default::default()
+        };
+        let json = json(&data, Duration::days(7)).unwrap();
+        let json = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+        assert_eq!(json["state"], "warning");
+        assert_eq!(json["signature_count"], 1337);
+        assert!(json["last_scan"].is_null());
+        assert!(json["scheduler"]["next_scan"].is_null());
+        assert_eq!(json["problems"][0]["fix"], "libredefender scan");
+    }
+
+    #[test]
+    fn test_problems() {
+        let mut data = Data {
+            last_scan: Some(Utc::now()),
+            signature_count: 1,
+            signatures_age: Some(Utc::now()),
+            ..Default::default()
+        };
+        assert_eq!(problems(&data, Duration::days(7), true), vec![]);
+
+        data.signatures_age = Some(Utc::now() - Duration::days(8));
+        data.skipped
+            .insert(PathBuf::from("/a"), SkipReason::PermissionDenied);
+        data.skipped
+            .insert(PathBuf::from("/b"), SkipReason::TooLarge);
+        let fixes = problems(&data, Duration::days(7), false)
+            .into_iter()
+            .map(|p| p.fix)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fixes,
+            vec![
+                "sudo systemctl enable --now clamav-freshclam.service",
+                "libredefender scheduler",
+                "libredefender scan --show-skipped",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_state_outdated() {
+        let mut data = Data {
+            last_scan: Some(Utc::now()),
+            signature_count: 1,
+            signatures_age: Some(Utc::now() - Duration::days(3)),
+            ..Default::default()
+        };
+        assert_eq!(state(&data, Duration::days(7)), State::Ok);
+        data.signatures_age = Some(Utc::now() - Duration::days(8));
+        assert_eq!(state(&data, Duration::days(7)), State::Warning);
+    }
+}
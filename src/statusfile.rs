@@ -0,0 +1,220 @@
+use crate::errors::*;
+use crate::scan::Event;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use tempfile::NamedTempFile;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Scanning,
+    Finished,
+    Cancelled,
+    Failed,
+}
+
+impl State {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::Scanning => "scanning",
+            State::Finished => "finished",
+            State::Cancelled => "cancelled",
+            State::Failed => "failed",
+        }
+    }
+}
+
+/// The contents of the status file, widgets poll this instead of talking to the scheduler
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LiveStatus {
+    pub state: State,
+    pub pid: u32,
+    pub started: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    pub files_scanned: usize,
+    pub bytes_scanned: u64,
+    pub threats: usize,
+    pub errors: usize,
+    /// One of the files that is being scanned right now
+    pub current_path: Option<PathBuf>,
+    /// Known once the files were counted, with `scan.estimate`
+    pub files_total: Option<usize>,
+    pub bytes_total: Option<u64>,
+    pub percent: Option<f64>,
+    pub eta_secs: Option<u64>,
+}
+
+/// `$XDG_RUNTIME_DIR/libredefender/status.json`, or `/run/libredefender/status/status.json` for root
+#[must_use]
+pub fn path() -> PathBuf {
+    match dirs::runtime_dir() {
+        Some(dir) => dir.join("libredefender").join("status.json"),
+        // the directory is handed to `scan.user`, it must not contain anything else
+        None => PathBuf::from("/run/libredefender/status/status.json"),
+    }
+}
+
+/// Read the status file of the running or last scan, `None` if there wasn't one yet
+pub fn read(path: &Path) -> Result<Option<LiveStatus>> {
+    let buf = match fs::read(path) {
+        Ok(buf) => buf,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| anyhow!("Failed to read {:?}", path)),
+    };
+    let status = serde_json::from_slice(&buf).context("Failed to parse status file")?;
+    Ok(Some(status))
+}
+
+/// Keeps the status file up to date during a scan
+pub struct StatusFile {
+    path: PathBuf,
+    status: LiveStatus,
+}
+
+impl StatusFile {
+    #[must_use]
+    pub fn new(path: PathBuf) -> StatusFile {
+        let now = Utc::now();
+        let status = LiveStatus {
+            state: State::Scanning,
+            pid: process::id(),
+            started: now,
+            updated: now,
+            files_scanned: 0,
+            bytes_scanned: 0,
+            threats: 0,
+            errors: 0,
+            current_path: None,
+            files_total: None,
+            bytes_total: None,
+            percent: None,
+            eta_secs: None,
+        };
+        let mut file = StatusFile { path, status };
+        file.write_or_warn();
+        file
+    }
+
+    pub fn update(&mut self, event: &Event) {
+        match event {
+            Event::Progress {
+                files_scanned,
+                bytes_scanned,
+                current_path,
+                estimate,
+                elapsed,
+            } => {
+                self.status.files_scanned = *files_scanned;
+                self.status.bytes_scanned = *bytes_scanned;
+                self.status.current_path.clone_from(current_path);
+                if let Some(estimate) = estimate {
+                    self.status.files_total = Some(estimate.files);
+                    self.status.bytes_total = Some(estimate.bytes);
+                    self.status.percent = Some(estimate.percent(*bytes_scanned));
+                    self.status.eta_secs = estimate
+                        .eta(*bytes_scanned, *elapsed)
+                        .map(|eta| eta.as_secs());
+                }
+            }
+            Event::Threat { .. } => self.status.threats += 1,
+            // errors can be frequent, they are written with the next progress update
+            Event::Error { .. } => {
+                self.status.errors += 1;
+                return;
+            }
+            Event::Finished { threats, .. } => {
+                self.status.threats = *threats;
+                self.status.current_path = None;
+            }
+        }
+        self.write_or_warn();
+    }
+
+    /// Record how the scan ended, the file stays around until the next scan
+    pub fn finish(mut self, state: State, files_scanned: usize, bytes_scanned: u64) {
+        self.status.state = state;
+        self.status.files_scanned = files_scanned;
+        self.status.bytes_scanned = bytes_scanned;
+        self.status.current_path = None;
+        self.status.eta_secs = None;
+        self.write_or_warn();
+    }
+
+    fn write_or_warn(&mut self) {
+        self.status.updated = Utc::now();
+        if let Err(err) = write(&self.path, &self.status) {
+            warn!("Failed to write status file: {:#}", err);
+        }
+    }
+}
+
+/// Replace the file atomically, so readers never see a partial write
+fn write(path: &Path, status: &LiveStatus) -> Result<()> {
+    let dir = path
+        .parent()
+        .context("Status file has no parent directory")?;
+    fs::create_dir_all(dir).with_context(|| anyhow!("Failed to create {:?}", dir))?;
+    let mut tmp = NamedTempFile::new_in(dir)?;
+    let mut buf = serde_json::to_vec(status)?;
+    buf.push(b'\n');
+    tmp.write_all(&buf)?;
+    fs::set_permissions(tmp.path(), fs::Permissions::from_mode(0o644))?;
+    tmp.persist(path)
+        .with_context(|| anyhow!("Failed to write {:?}", path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::Estimate;
+    use std::time::Duration;
+
+    #[test]
+    fn test_status_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("libredefender/status.json");
+        let read =
+            || serde_json::from_slice::<serde_json::Value>(&fs::read(&path).unwrap()).unwrap();
+
+        let mut file = StatusFile::new(path.clone());
+        assert_eq!(read()["state"], "scanning");
+
+        file.update(&Event::Progress {
+            files_scanned: 23,
+            bytes_scanned: 42,
+            current_path: Some(PathBuf::from("/home/user/a.exe")),
+            estimate: Some(Estimate {
+                files: 46,
+                bytes: 84,
+            }),
+            elapsed: Duration::from_secs(5),
+        });
+        file.update(&Event::Threat {
+            path: PathBuf::from("/home/user/a.exe"),
+            name: "Win.Test.EICAR_HDB-1".to_string(),
+            member: None,
+        });
+        let status = read();
+        assert_eq!(status["files_scanned"], 23);
+        assert_eq!(status["threats"], 1);
+        assert_eq!(status["current_path"], "/home/user/a.exe");
+        assert_eq!(status["percent"], 50.0);
+        assert_eq!(status["eta_secs"], 5);
+
+        file.finish(State::Finished, 24, 50);
+        let status = super::read(&path).unwrap().unwrap();
+        assert_eq!(status.state, State::Finished);
+        assert_eq!(status.threats, 1);
+        let status = read();
+        assert_eq!(status["state"], "finished");
+        assert_eq!(status["files_scanned"], 24);
+        assert!(status["current_path"].is_null());
+    }
+}
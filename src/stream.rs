@@ -0,0 +1,55 @@
+use crate::errors::*;
+use crate::scan::{CancellationToken, Event, ScanJob, ScanResults};
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// The events of a scan running on tokio's blocking thread pool
+pub struct ScanStream {
+    events: mpsc::UnboundedReceiver<Event>,
+    handle: JoinHandle<Result<ScanResults>>,
+    token: CancellationToken,
+}
+
+impl ScanStream {
+    #[must_use]
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// Wait for the scan to finish, remaining events are discarded
+    pub async fn finish(self) -> Result<ScanResults> {
+        drop(self.events);
+        self.handle
+            .await
+            .map_err(|err| anyhow!("Scan task failed: {:#}", err))?
+    }
+}
+
+impl Stream for ScanStream {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+/// Start a scan in the background, this needs to be called from within a tokio runtime
+#[must_use]
+pub fn run_async(job: ScanJob) -> ScanStream {
+    let (tx, events) = mpsc::unbounded_channel();
+    let token = job.cancellation_token();
+    let handle = tokio::task::spawn_blocking(move || {
+        job.run_with(|event| {
+            // the receiver is gone if the caller is only waiting for the results
+            tx.send(event).ok();
+        })
+    });
+    ScanStream {
+        events,
+        handle,
+        token,
+    }
+}
@@ -0,0 +1,193 @@
+use crate::errors::*;
+use crate::utils;
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{self, Layer};
+
+const SOCKET: &str = "/dev/log";
+const APP_NAME: &str = "libredefender";
+
+/// Events with `detection = true` are sent with LOG_ALERT
+pub const DETECTION_FIELD: &str = "detection";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogTarget {
+    #[default]
+    Stderr,
+    /// RFC 5424 messages to /dev/log
+    Syslog,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Facility {
+    User = 1,
+    Daemon = 3,
+}
+
+const LOG_ALERT: u8 = 1;
+const LOG_ERR: u8 = 3;
+const LOG_WARNING: u8 = 4;
+const LOG_INFO: u8 = 6;
+const LOG_DEBUG: u8 = 7;
+
+fn severity(level: Level, detection: bool) -> u8 {
+    if detection {
+        LOG_ALERT
+    } else if level == Level::ERROR {
+        LOG_ERR
+    } else if level == Level::WARN {
+        LOG_WARNING
+    } else if level == Level::INFO {
+        LOG_INFO
+    } else {
+        LOG_DEBUG
+    }
+}
+
+#[derive(Default)]
+struct Visitor {
+    message: String,
+    fields: String,
+    detection: bool,
+}
+
+impl Visit for Visitor {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if field.name() == DETECTION_FIELD {
+            self.detection = value;
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            write!(self.message, "{:?}", value).ok();
+        } else {
+            write!(self.fields, " {}={:?}", field.name(), value).ok();
+        }
+    }
+}
+
+/// Format a message with the header of RFC 5424, without structured data
+#[must_use]
+pub fn format_message(
+    facility: Facility,
+    severity: u8,
+    hostname: &str,
+    msgid: Option<&str>,
+    msg: &str,
+) -> String {
+    let pri = (facility as u8) * 8 + severity;
+    format!(
+        "<{}>1 {} {} {} {} {} - {}",
+        pri,
+        Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+        hostname,
+        APP_NAME,
+        process::id(),
+        msgid.unwrap_or("-"),
+        msg
+    )
+}
+
+fn connect() -> Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket
+        .connect(SOCKET)
+        .with_context(|| anyhow!("Failed to connect to syslog socket {:?}", SOCKET))?;
+    Ok(socket)
+}
+
+/// A tracing layer that sends every event to the local syslog daemon
+pub struct SyslogLayer {
+    facility: Facility,
+    hostname: String,
+    socket: Mutex<UnixDatagram>,
+}
+
+impl SyslogLayer {
+    pub fn new(facility: Facility) -> Result<SyslogLayer> {
+        Ok(SyslogLayer {
+            facility,
+            hostname: utils::hostname(),
+            socket: Mutex::new(connect()?),
+        })
+    }
+
+    fn send(&self, msg: &str) {
+        let Ok(mut socket) = self.socket.lock() else {
+            return;
+        };
+        if socket.send(msg.as_bytes()).is_ok() {
+            return;
+        }
+        // the syslog daemon may have been restarted
+        if let Ok(new) = connect() {
+            *socket = new;
+            socket.send(msg.as_bytes()).ok();
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for SyslogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: layer::Context<'_, S>) {
+        let mut visitor = Visitor::default();
+        event.record(&mut visitor);
+        let severity = severity(*event.metadata().level(), visitor.detection);
+        let msgid = visitor.detection.then_some("DETECTION");
+        let msg = format!("{}{}", visitor.message, visitor.fields);
+        self.send(&format_message(
+            self.facility,
+            severity,
+            &self.hostname,
+            msgid,
+            &msg,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let msg = format_message(
+            Facility::Daemon,
+            severity(Level::WARN, true),
+            "example",
+            Some("DETECTION"),
+            "Found threat: /tmp/eicar.com (\"Win.Test.EICAR_HDB-1\")",
+        );
+        assert!(msg.starts_with("<25>1 "));
+        let parts = msg.splitn(8, ' ').collect::<Vec<_>>();
+        assert_eq!(parts[2], "example");
+        assert_eq!(parts[3], "libredefender");
+        assert_eq!(parts[4], process::id().to_string());
+        assert_eq!(parts[5], "DETECTION");
+        assert_eq!(parts[6], "-");
+        assert_eq!(
+            parts[7],
+            "Found threat: /tmp/eicar.com (\"Win.Test.EICAR_HDB-1\")"
+        );
+
+        let msg = format_message(
+            Facility::User,
+            severity(Level::DEBUG, false),
+            "example",
+            None,
+            "hello",
+        );
+        assert!(msg.starts_with("<15>1 "));
+        assert!(msg.ends_with(" - - hello"));
+    }
+}
@@ -0,0 +1,65 @@
+use std::cmp;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Rate limiter shared by all scan workers
+#[derive(Debug)]
+pub struct Throttle {
+    per_second: f64,
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    #[must_use]
+    pub fn new(per_second: f64) -> Throttle {
+        Throttle {
+            per_second,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Block until `amount` units may be used, the cost is paid by the next caller
+    pub fn acquire(&self, amount: f64) {
+        if self.per_second <= 0.0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let start = {
+            let mut next = self.next.lock().unwrap();
+            let start = cmp::max(*next, now);
+            *next = start + Duration::from_secs_f64(amount / self.per_second);
+            start
+        };
+
+        if start > now {
+            thread::sleep(start - now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle() {
+        let throttle = Throttle::new(100.0);
+        let started = Instant::now();
+        for _ in 0..4 {
+            throttle.acquire(1.0);
+        }
+        // the first call is free, the other three wait 10ms each
+        assert!(started.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_throttle_disabled() {
+        let throttle = Throttle::new(0.0);
+        let started = Instant::now();
+        throttle.acquire(1_000_000.0);
+        throttle.acquire(1_000_000.0);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
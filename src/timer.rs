@@ -0,0 +1,532 @@
+use crate::config::{Config, Interval, Placement};
+use crate::errors::*;
+use crate::schedule::PreferedHours;
+use crate::utils;
+use chrono::NaiveTime;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const UNIT_PREFIX: &str = "libredefender-scan";
+const SYSTEM_UNIT_DIR: &str = "/etc/systemd/system";
+const SYSTEM_CRONTAB: &str = "/etc/cron.d/libredefender";
+const CRON_BEGIN: &str = "# BEGIN libredefender install-timer";
+const CRON_END: &str = "# END libredefender install-timer";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    User,
+    System,
+}
+
+impl Scope {
+    fn systemctl(self) -> Command {
+        let mut cmd = Command::new("systemctl");
+        if self == Scope::User {
+            cmd.arg("--user");
+        }
+        cmd
+    }
+
+    fn unit_dir(self) -> Result<PathBuf> {
+        match self {
+            Scope::User => {
+                let config = dirs::config_dir().context("Failed to find config directory")?;
+                Ok(config.join("systemd/user"))
+            }
+            Scope::System => Ok(PathBuf::from(SYSTEM_UNIT_DIR)),
+        }
+    }
+}
+
+/// A scheduled scan, either the default one or one of `[[schedule.job]]`
+#[derive(Debug, PartialEq, Eq)]
+pub struct Timer {
+    /// Name of the systemd units, without the `.service` or `.timer` suffix
+    pub unit: String,
+    pub interval: Interval,
+    /// When the timer elapses
+    pub time: NaiveTime,
+    /// `RandomizedDelaySec=`, to spread the scan over the preferred hours
+    pub delay: u32,
+    /// `WakeSystem=`, with `schedule.use_rtc_wake`
+    pub wake: bool,
+    pub command: Vec<String>,
+}
+
+/// The start time and random delay that match `schedule.placement` in the preferred hours
+fn placement(ph: Option<&PreferedHours>, placement: Placement, jitter: u32) -> (NaiveTime, u32) {
+    let Some(ph) = ph else {
+        return (NaiveTime::MIN, jitter);
+    };
+    // the last second still counts as inside of the window, like in the scheduler
+    let latest = (ph.length().num_seconds() - 1).max(0) as u32;
+    match placement {
+        Placement::Start => (ph.start(), jitter.min(latest)),
+        Placement::Random => (ph.start(), latest),
+        Placement::End => {
+            let offset = latest.saturating_sub(jitter);
+            let time = ph.start() + chrono::Duration::seconds(i64::from(offset));
+            (time, jitter.min(latest))
+        }
+    }
+}
+
+/// The timers for the configured schedule, they run `libredefender scheduler --once` for their job
+///
+/// The checks of the scheduler (battery, inhibitors, signature updates, preferred hours, system mode)
+/// still apply, only watching for removable media needs the scheduler to keep running.
+pub fn timers(config: &Config, config_path: Option<&Path>, scope: Scope) -> Result<Vec<Timer>> {
+    let schedule = &config.schedule;
+    if schedule.automatic_scans.as_deref() == Some("off") {
+        bail!("Automatic scanning is disabled in the config (schedule.automatic_scans)");
+    }
+    match scope {
+        Scope::User if schedule.system => {
+            bail!("schedule.system scans the home directories of all users, this needs `install-timer --system`")
+        }
+        Scope::System if !schedule.system => warn!(
+            "The scans run as root with the paths of root, set schedule.system = true to scan the home directories of all users instead"
+        ),
+        _ => (),
+    }
+    if schedule.scan_removable {
+        warn!("schedule.scan_removable needs `libredefender scheduler` to keep running, removable media isn't scanned by timers");
+    }
+
+    let exe = env::current_exe().context("Failed to find libredefender executable")?;
+    let mut base = vec![exe.to_string_lossy().into_owned()];
+    if let Some(path) = config_path {
+        let path = fs::canonicalize(path).with_context(|| anyhow!("Failed to find {:?}", path))?;
+        base.extend(["--config".to_string(), path.to_string_lossy().into_owned()]);
+    }
+    if let Some(data) = utils::custom_data_dir() {
+        base.extend(["--data".to_string(), data.to_string_lossy().into_owned()]);
+    }
+    base.extend(["scheduler".to_string(), "--once".to_string()]);
+
+    let jobs = if schedule.job.is_empty() {
+        vec![(None, Interval::Daily, schedule.preferred_hours.as_ref())]
+    } else {
+        schedule
+            .job
+            .iter()
+            .map(|job| (Some(&job.name), job.interval, job.preferred_hours.as_ref()))
+            .collect()
+    };
+
+    let mut timers = Vec::new();
+    for (name, interval, preferred_hours) in jobs {
+        let mut command = base.clone();
+        let unit = match name {
+            Some(name) => {
+                command.extend(["--job".to_string(), name.clone()]);
+                format!("{}-{}", UNIT_PREFIX, unit_name(name))
+            }
+            None => UNIT_PREFIX.to_string(),
+        };
+        let (time, delay) = placement(preferred_hours, schedule.placement, schedule.jitter);
+        timers.push(Timer {
+            unit,
+            interval,
+            time,
+            delay,
+            wake: schedule.use_rtc_wake,
+            command,
+        });
+    }
+    Ok(timers)
+}
+
+/// Keep job names usable in unit names and the crontab
+fn unit_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+fn systemd_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && !arg
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | '%' | '$' | ';'))
+    {
+        return arg.to_string();
+    }
+    let arg = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{}\"", arg)
+}
+
+/// Quote for `/bin/sh`, `%` has to be escaped in a crontab too
+fn cron_quote(arg: &str) -> String {
+    let arg = format!("'{}'", arg.replace('\'', "'\\''"));
+    arg.replace('%', "\\%")
+}
+
+#[must_use]
+pub fn on_calendar(interval: Interval, time: NaiveTime) -> String {
+    let time = time.format("%H:%M:%S");
+    match interval {
+        Interval::Daily => format!("*-*-* {}", time),
+        Interval::Weekly => format!("Mon *-*-* {}", time),
+        Interval::Monthly => format!("*-*-01 {}", time),
+    }
+}
+
+#[must_use]
+pub fn cron_schedule(interval: Interval, time: NaiveTime) -> String {
+    let time = time.format("%-M %-H");
+    match interval {
+        Interval::Daily => format!("{} * * *", time),
+        Interval::Weekly => format!("{} * * 1", time),
+        Interval::Monthly => format!("{} 1 * *", time),
+    }
+}
+
+impl Timer {
+    #[must_use]
+    pub fn service_unit(&self) -> String {
+        let command = self
+            .command
+            .iter()
+            .map(|arg| systemd_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        format!(
+            "[Unit]
+Description=libredefender scan ({unit})
+Documentation=https://github.com/kpcyrd/libredefender
+
+[Service]
+Type=oneshot
+ExecStart={command}
+",
+            unit = self.unit,
+            command = command,
+        )
+    }
+
+    #[must_use]
+    pub fn timer_unit(&self) -> String {
+        let mut timer = format!(
+            "[Unit]
+Description=Scheduled libredefender scan ({unit})
+
+[Timer]
+OnCalendar={calendar}
+Persistent=true
+",
+            unit = self.unit,
+            calendar = on_calendar(self.interval, self.time),
+        );
+        if self.delay > 0 {
+            timer.push_str(&format!("RandomizedDelaySec={}\n", self.delay));
+        }
+        if self.wake {
+            timer.push_str("WakeSystem=true\n");
+        }
+        timer.push_str("\n[Install]\nWantedBy=timers.target\n");
+        timer
+    }
+
+    /// A crontab line, cron has no random delay so scans always start at `time`
+    #[must_use]
+    pub fn cron_entry(&self, user: Option<&str>) -> String {
+        let command = self
+            .command
+            .iter()
+            .map(|arg| cron_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let schedule = cron_schedule(self.interval, self.time);
+        match user {
+            Some(user) => format!("{} {} {}\n", schedule, user, command),
+            None => format!("{} {}\n", schedule, command),
+        }
+    }
+}
+
+fn run(cmd: &mut Command) -> Result<()> {
+    debug!("Running {:?}", cmd);
+    let status = cmd
+        .status()
+        .with_context(|| anyhow!("Failed to run {:?}", cmd.get_program()))?;
+    if !status.success() {
+        bail!("{:?} exited with {}", cmd.get_program(), status);
+    }
+    Ok(())
+}
+
+/// The units written by a previous `install-timer`, jobs may have been renamed since
+fn installed_units(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| anyhow!("Failed to read {:?}", dir)),
+    };
+    let mut units = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if name.starts_with(UNIT_PREFIX) && (name.ends_with(".service") || name.ends_with(".timer"))
+        {
+            units.push(path);
+        }
+    }
+    units.sort();
+    Ok(units)
+}
+
+fn uninstall_systemd(scope: Scope, dir: &Path) -> Result<()> {
+    let units = installed_units(dir)?;
+    let timers = units
+        .iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "timer"))
+        .filter_map(|path| path.file_name())
+        .collect::<Vec<_>>();
+    if !timers.is_empty() {
+        if let Err(err) = run(scope.systemctl().args(["disable", "--now"]).args(&timers)) {
+            warn!("Failed to disable timers: {:#}", err);
+        }
+    }
+    for path in &units {
+        fs::remove_file(path).with_context(|| anyhow!("Failed to remove {:?}", path))?;
+        info!("Removed {:?}", path);
+    }
+    Ok(())
+}
+
+/// Write a service and a timer for every scheduled scan and enable the timers
+pub fn install_systemd(scope: Scope, timers: &[Timer]) -> Result<()> {
+    let dir = scope.unit_dir()?;
+    uninstall_systemd(scope, &dir)?;
+    fs::create_dir_all(&dir).with_context(|| anyhow!("Failed to create directory {:?}", dir))?;
+    for timer in timers {
+        for (suffix, content) in [
+            ("service", timer.service_unit()),
+            ("timer", timer.timer_unit()),
+        ] {
+            let path = dir.join(format!("{}.{}", timer.unit, suffix));
+            fs::write(&path, content).with_context(|| anyhow!("Failed to write {:?}", path))?;
+            info!("Installed {:?}", path);
+        }
+    }
+    run(scope.systemctl().arg("daemon-reload"))?;
+    let names = timers
+        .iter()
+        .map(|timer| format!("{}.timer", timer.unit))
+        .collect::<Vec<_>>();
+    run(scope.systemctl().args(["enable", "--now"]).args(&names))?;
+    Ok(())
+}
+
+pub fn uninstall_systemd_timers(scope: Scope) -> Result<()> {
+    let dir = scope.unit_dir()?;
+    uninstall_systemd(scope, &dir)?;
+    run(scope.systemctl().arg("daemon-reload"))
+}
+
+/// Replace the lines between the markers, or append them, `entries` being empty removes the block
+#[must_use]
+pub fn replace_cron_block(crontab: &str, entries: &str) -> String {
+    let mut out = String::new();
+    let mut inside = false;
+    for line in crontab.lines() {
+        if line == CRON_BEGIN {
+            inside = true;
+        } else if line == CRON_END {
+            inside = false;
+        } else if !inside {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    if !entries.is_empty() {
+        out.push_str(CRON_BEGIN);
+        out.push('\n');
+        out.push_str(entries);
+        out.push_str(CRON_END);
+        out.push('\n');
+    }
+    out
+}
+
+fn read_user_crontab() -> Result<String> {
+    let output = Command::new("crontab")
+        .arg("-l")
+        .stderr(Stdio::null())
+        .output()
+        .context("Failed to run crontab")?;
+    // exits with 1 if the user doesn't have a crontab yet
+    if !output.status.success() {
+        return Ok(String::new());
+    }
+    String::from_utf8(output.stdout).context("Crontab is not valid utf-8")
+}
+
+fn write_user_crontab(crontab: &str) -> Result<()> {
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to run crontab")?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .context("Failed to get stdin of crontab")?;
+    stdin.write_all(crontab.as_bytes())?;
+    drop(stdin);
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("crontab exited with {}", status);
+    }
+    Ok(())
+}
+
+/// Add the scheduled scans to the crontab of the user, or to /etc/cron.d
+pub fn install_cron(scope: Scope, timers: &[Timer]) -> Result<()> {
+    match scope {
+        Scope::User => {
+            let entries = timers
+                .iter()
+                .map(|timer| timer.cron_entry(None))
+                .collect::<String>();
+            let crontab = replace_cron_block(&read_user_crontab()?, &entries);
+            write_user_crontab(&crontab)?;
+            info!("Updated crontab");
+        }
+        Scope::System => {
+            let mut crontab =
+                String::from("# installed by `libredefender install-timer --system --cron`\n");
+            for timer in timers {
+                crontab.push_str(&timer.cron_entry(Some("root")));
+            }
+            fs::write(SYSTEM_CRONTAB, crontab)
+                .with_context(|| anyhow!("Failed to write {:?}", SYSTEM_CRONTAB))?;
+            info!("Installed {:?}", SYSTEM_CRONTAB);
+        }
+    }
+    Ok(())
+}
+
+pub fn uninstall_cron(scope: Scope) -> Result<()> {
+    match scope {
+        Scope::User => {
+            let crontab = read_user_crontab()?;
+            let updated = replace_cron_block(&crontab, "");
+            if updated != crontab {
+                write_user_crontab(&updated)?;
+                info!("Removed scheduled scans from crontab");
+            }
+        }
+        Scope::System => {
+            utils::ensure_deleted(Path::new(SYSTEM_CRONTAB))?;
+            info!("Removed {:?}", SYSTEM_CRONTAB);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn timer() -> Timer {
+        Timer {
+            unit: "libredefender-scan-full".to_string(),
+            interval: Interval::Weekly,
+            time: NaiveTime::from_hms_opt(1, 30, 0).unwrap(),
+            delay: 3600,
+            wake: false,
+            command: vec![
+                "/usr/bin/libredefender".to_string(),
+                "--config".to_string(),
+                "/home/user/my config.toml".to_string(),
+                "scheduler".to_string(),
+                "--once".to_string(),
+                "--job".to_string(),
+                "full".to_string(),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_placement() {
+        let ph = PreferedHours::from_str("22:00:00-02:00:00").unwrap();
+        let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        assert_eq!(
+            placement(Some(&ph), Placement::Start, 600),
+            (time(22, 0), 600)
+        );
+        assert_eq!(
+            placement(Some(&ph), Placement::Random, 600),
+            (time(22, 0), 4 * 3600 - 1)
+        );
+        assert_eq!(
+            placement(Some(&ph), Placement::End, 3599),
+            (time(1, 0), 3599)
+        );
+        assert_eq!(placement(None, Placement::Random, 0), (time(0, 0), 0));
+    }
+
+    #[test]
+    fn test_units() {
+        let timer = timer();
+        assert!(timer.service_unit().contains(
+            "ExecStart=/usr/bin/libredefender --config \"/home/user/my config.toml\" scheduler --once --job full\n"
+        ));
+        assert_eq!(
+            timer.timer_unit(),
+            "[Unit]
+Description=Scheduled libredefender scan (libredefender-scan-full)
+
+[Timer]
+OnCalendar=Mon *-*-* 01:30:00
+Persistent=true
+RandomizedDelaySec=3600
+
+[Install]
+WantedBy=timers.target
+"
+        );
+        assert_eq!(
+            timer.cron_entry(Some("root")),
+            "30 1 * * 1 root '/usr/bin/libredefender' '--config' '/home/user/my config.toml' 'scheduler' '--once' '--job' 'full'\n"
+        );
+        let timer = Timer {
+            wake: true,
+            ..timer
+        };
+        assert!(timer.timer_unit().contains("WakeSystem=true\n"));
+        assert_eq!(unit_name("nightly full/scan"), "nightly-full-scan");
+    }
+
+    #[test]
+    fn test_cron_block() {
+        let crontab = "MAILTO=root\n0 4 * * * backup\n";
+        let installed = replace_cron_block(crontab, "0 0 * * * libredefender scan\n");
+        assert_eq!(
+            installed,
+            "MAILTO=root\n0 4 * * * backup\n# BEGIN libredefender install-timer\n0 0 * * * libredefender scan\n# END libredefender install-timer\n"
+        );
+        let updated = replace_cron_block(&installed, "0 1 * * 1 libredefender scan\n");
+        assert_eq!(updated.matches(CRON_BEGIN).count(), 1);
+        assert!(updated.contains("0 1 * * 1"));
+        assert_eq!(replace_cron_block(&installed, ""), crontab);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::errors::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::{Fd, OwnedObjectPath, Value};
+
+const DESTINATION: &str = "org.freedesktop.UDisks2";
+const MANAGER_PATH: &str = "/org/freedesktop/UDisks2/Manager";
+const MANAGER_INTERFACE: &str = "org.freedesktop.UDisks2.Manager";
+const BLOCK_INTERFACE: &str = "org.freedesktop.UDisks2.Block";
+const FILESYSTEM_INTERFACE: &str = "org.freedesktop.UDisks2.Filesystem";
+const LOOP_INTERFACE: &str = "org.freedesktop.UDisks2.Loop";
+const BLOCK_DEVICES: &str = "/org/freedesktop/UDisks2/block_devices/";
+
+/// The object path of a device name like `sda1`, udisks escapes everything that's not alphanumeric
+#[must_use]
+pub fn block_path(name: &str) -> String {
+    let mut path = BLOCK_DEVICES.to_string();
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() {
+            path.push(char::from(b));
+        } else {
+            path.push_str(&format!("_{:02x}", b));
+        }
+    }
+    path
+}
+
+pub struct Udisks {
+    conn: Connection,
+}
+
+impl Udisks {
+    pub fn connect() -> Result<Udisks> {
+        let conn = Connection::system().context("Failed to connect to system bus")?;
+        Ok(Udisks { conn })
+    }
+
+    fn proxy<'a>(&self, path: &'a str, interface: &'a str) -> Result<Proxy<'a>> {
+        let proxy = Proxy::new(&self.conn, DESTINATION, path, interface)
+            .with_context(|| anyhow!("Failed to create proxy for {}", path))?;
+        Ok(proxy)
+    }
+
+    /// Attach a disk image to a read-only loop device, returns the name of the device
+    pub fn loop_setup(&self, image: &Path) -> Result<String> {
+        let file =
+            File::open(image).with_context(|| anyhow!("Failed to open image {:?}", image))?;
+        let manager = self.proxy(MANAGER_PATH, MANAGER_INTERFACE)?;
+        let mut options = HashMap::<&str, Value>::new();
+        options.insert("read-only", true.into());
+        let path: OwnedObjectPath = manager
+            .call("LoopSetup", &(Fd::from(&file), options))
+            .with_context(|| anyhow!("Failed to setup loop device for {:?}", image))?;
+        let name = path
+            .as_str()
+            .strip_prefix(BLOCK_DEVICES)
+            .with_context(|| anyhow!("Unexpected object path for loop device: {:?}", path))?;
+        Ok(name.to_string())
+    }
+
+    pub fn loop_delete(&self, name: &str) -> Result<()> {
+        let path = block_path(name);
+        let device = self.proxy(&path, LOOP_INTERFACE)?;
+        device
+            .call::<_, _, ()>("Delete", &(HashMap::<&str, Value>::new(),))
+            .with_context(|| anyhow!("Failed to delete loop device {:?}", name))?;
+        Ok(())
+    }
+
+    /// The filesystem type udisks detected on the device, like `ext4` or `ntfs`
+    pub fn fstype(&self, name: &str) -> Result<String> {
+        let path = block_path(name);
+        let block = self.proxy(&path, BLOCK_INTERFACE)?;
+        let fstype = block
+            .get_property("IdType")
+            .with_context(|| anyhow!("Failed to read filesystem type of {:?}", name))?;
+        Ok(fstype)
+    }
+
+    /// Mount a filesystem with these options, this may ask for authentication through polkit
+    pub fn mount(&self, name: &str, mount_options: &str) -> Result<PathBuf> {
+        let path = block_path(name);
+        let filesystem = self.proxy(&path, FILESYSTEM_INTERFACE)?;
+        let mut options = HashMap::<&str, Value>::new();
+        options.insert("options", mount_options.into());
+        let mountpoint: String = filesystem
+            .call("Mount", &(options,))
+            .with_context(|| anyhow!("Failed to mount {:?}", name))?;
+        Ok(PathBuf::from(mountpoint))
+    }
+
+    pub fn unmount(&self, name: &str) -> Result<()> {
+        let path = block_path(name);
+        let filesystem = self.proxy(&path, FILESYSTEM_INTERFACE)?;
+        filesystem
+            .call::<_, _, ()>("Unmount", &(HashMap::<&str, Value>::new(),))
+            .with_context(|| anyhow!("Failed to unmount {:?}", name))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_path() {
+        assert_eq!(
+            block_path("nvme0n1p3"),
+            "/org/freedesktop/UDisks2/block_devices/nvme0n1p3"
+        );
+        assert_eq!(
+            block_path("dm-0"),
+            "/org/freedesktop/UDisks2/block_devices/dm_2d0"
+        );
+    }
+}
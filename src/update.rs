@@ -0,0 +1,93 @@
+use crate::config::UpdateConfig;
+use crate::errors::*;
+use crate::networkmanager;
+use std::fs;
+use std::io::Write;
+use std::process::Command;
+
+/// Where distributions install the config of freshclam
+const FRESHCLAM_CONFIGS: &[&str] = &["/etc/clamav/freshclam.conf", "/etc/freshclam.conf"];
+
+/// The system config of freshclam without its DNS lookup of the database version, it would bypass the proxy
+fn proxy_config(system: &str) -> String {
+    let mut config = String::new();
+    for line in system.lines() {
+        let key = line.split_whitespace().next().unwrap_or_default();
+        if key.eq_ignore_ascii_case("DNSDatabaseInfo") {
+            continue;
+        }
+        config.push_str(line);
+        config.push('\n');
+    }
+    config.push_str("DNSDatabaseInfo no\n");
+    config
+}
+
+/// Download new signatures into the configured database directory
+pub fn freshclam(config: &UpdateConfig) -> Result<()> {
+    info!("Updating signatures with freshclam...");
+    let mut cmd = Command::new("freshclam");
+    cmd.arg("--datadir").arg(&config.path);
+    // kept until freshclam exited
+    let mut config_file = None;
+    // libcurl in freshclam picks these up, unless HTTPProxyServer is set in freshclam.conf
+    if let Some(proxy) = &config.proxy {
+        debug!("Downloading signatures through proxy {:?}", proxy);
+        for key in ["http_proxy", "https_proxy", "HTTPS_PROXY", "ALL_PROXY"] {
+            cmd.env(key, proxy);
+        }
+        let system = FRESHCLAM_CONFIGS
+            .iter()
+            .find_map(|path| fs::read_to_string(path).ok())
+            .unwrap_or_default();
+        let mut file = tempfile::Builder::new()
+            .prefix("libredefender-freshclam-")
+            .suffix(".conf")
+            .tempfile()
+            .context("Failed to create freshclam config")?;
+        file.write_all(proxy_config(&system).as_bytes())
+            .context("Failed to write freshclam config")?;
+        cmd.arg("--config-file").arg(file.path());
+        config_file = Some(file);
+    }
+    let status = cmd.status().context("Failed to run freshclam")?;
+    drop(config_file);
+    if !status.success() {
+        bail!("freshclam exited with {}", status);
+    }
+    Ok(())
+}
+
+/// Signature downloads wait for an unmetered connection unless `update.allow_metered` is set
+#[must_use]
+pub fn is_deferred(config: &UpdateConfig) -> bool {
+    if config.allow_metered {
+        return false;
+    }
+    match networkmanager::metered() {
+        Ok(metered) if metered.is_metered() => {
+            info!("Connection is metered, deferring signature update");
+            true
+        }
+        Ok(_) => false,
+        Err(err) => {
+            debug!("Failed to check for a metered connection: {:#}", err);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proxy_config() {
+        let system = "DatabaseMirror database.clamav.net\nDNSDatabaseInfo current.cvd.clamav.net\n";
+        assert_eq!(
+            proxy_config(system),
+            "DatabaseMirror database.clamav.net\nDNSDatabaseInfo no\n"
+        );
+        assert_eq!(proxy_config(""), "DNSDatabaseInfo no\n");
+    }
+}
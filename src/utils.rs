@@ -1,8 +1,15 @@
 use crate::errors::*;
-use std::fs;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::ffi::CStr;
+use std::fs::{self, File};
 use std::io;
 use std::io::prelude::*;
-use std::path::Path;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
 
 pub fn ask_confirmation(text: &str) -> Result<bool> {
     let mut stdout = io::stdout();
@@ -18,6 +25,28 @@ pub fn ask_confirmation(text: &str) -> Result<bool> {
     Ok(input == 'y')
 }
 
+/// Store the database, quarantine and audit log in this directory, set with `--data`
+pub fn set_data_dir(path: PathBuf) {
+    if DATA_DIR.set(path).is_err() {
+        warn!("Data directory was already set");
+    }
+}
+
+/// The directory given with `--data`, if any
+#[must_use]
+pub fn custom_data_dir() -> Option<&'static Path> {
+    DATA_DIR.get().map(PathBuf::as_path)
+}
+
+/// Where the files of libredefender are stored, `~/.local/share/libredefender` by default
+pub fn data_dir() -> Result<PathBuf> {
+    if let Some(dir) = custom_data_dir() {
+        return Ok(dir.to_path_buf());
+    }
+    let data_dir = dirs::data_dir().context("Failed to find data directory")?;
+    Ok(data_dir.join("libredefender"))
+}
+
 pub fn ensure_deleted(path: &Path) -> Result<()> {
     match fs::remove_file(path) {
         Ok(()) => (),
@@ -26,3 +55,141 @@ pub fn ensure_deleted(path: &Path) -> Result<()> {
     }
     Ok(())
 }
+
+/// Check if any error in the chain is EACCES or EPERM
+#[must_use]
+pub fn is_permission_denied(err: &Error) -> bool {
+    err.chain().any(|err| {
+        err.downcast_ref::<io::Error>()
+            .is_some_and(|err| err.kind() == io::ErrorKind::PermissionDenied)
+    })
+}
+
+#[must_use]
+pub fn euid() -> u32 {
+    unsafe { libc::geteuid() }
+}
+
+#[must_use]
+pub fn is_root() -> bool {
+    euid() == 0
+}
+
+#[must_use]
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if ret != 0 {
+        return "-".to_string();
+    }
+    CStr::from_bytes_until_nul(&buf)
+        .ok()
+        .and_then(|s| s.to_str().ok())
+        .filter(|s| !s.is_empty())
+        .unwrap_or("-")
+        .to_string()
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).with_context(|| anyhow!("Failed to open {:?}", path))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).with_context(|| anyhow!("Failed to read {:?}", path))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Apply an flock operation like `LOCK_EX | LOCK_NB`, it's released when the file is closed
+pub fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    let ret = unsafe { libc::flock(file.as_raw_fd(), operation) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Expand a leading `~` and `${VAR}`, `$${` is a literal `${`
+///
+/// Any other `$` is kept as it is, e.g. in `$Recycle.Bin`. The values are passed through `quote`,
+/// e.g. to escape them inside of a glob.
+pub fn expand_vars<F: Fn(&str) -> String>(s: &str, quote: F) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = s;
+    if rest == "~" || rest.starts_with("~/") {
+        let home_dir = dirs::home_dir().context("Failed to find home directory")?;
+        let home_dir = home_dir
+            .to_str()
+            .context("Home directory contains invalid utf-8")?;
+        out.push_str(&quote(home_dir));
+        rest = &rest[1..];
+    }
+
+    while let Some(idx) = rest.find("${") {
+        if let Some(before) = rest[..idx].strip_suffix('$') {
+            out.push_str(before);
+            out.push_str("${");
+            rest = &rest[idx + 2..];
+            continue;
+        }
+        out.push_str(&rest[..idx]);
+        let r = &rest[idx + 2..];
+        let end = r
+            .find('}')
+            .with_context(|| anyhow!("Unclosed ${{ in {:?}", s))?;
+        let (name, r) = (&r[..end], &r[end + 1..]);
+        if name.is_empty() {
+            bail!("Expected a variable name in ${{}} in {:?}", s);
+        }
+        let value = env::var(name).with_context(|| {
+            anyhow!("Environment variable {:?} used in {:?} is not set", name, s)
+        })?;
+        out.push_str(&quote(&value));
+        rest = r;
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Expand `~` and environment variables in a path from the config
+pub fn expand_path(path: &Path) -> Result<PathBuf> {
+    // paths that aren't utf-8 can't contain anything to expand that we could parse
+    let Some(s) = path.to_str() else {
+        return Ok(path.to_path_buf());
+    };
+    let expanded = expand_vars(s, str::to_string)?;
+    Ok(PathBuf::from(expanded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_vars() {
+        let home = dirs::home_dir().unwrap();
+        let home = home.to_str().unwrap();
+        assert_eq!(
+            expand_vars("~/VirtualBox VMs/**", str::to_string).unwrap(),
+            format!("{}/VirtualBox VMs/**", home)
+        );
+        assert_eq!(expand_vars("/a/~/b", str::to_string).unwrap(), "/a/~/b");
+        assert_eq!(
+            expand_vars("~/x", |s| format!("[{}]", s)).unwrap(),
+            format!("[{}]/x", home)
+        );
+        assert_eq!(
+            expand_vars("/${HOME}/a", str::to_string).unwrap(),
+            format!("/{}/a", env::var("HOME").unwrap())
+        );
+        assert_eq!(
+            expand_vars("/mnt/c/$Recycle.Bin/**", str::to_string).unwrap(),
+            "/mnt/c/$Recycle.Bin/**"
+        );
+        assert_eq!(expand_vars("/price$5", str::to_string).unwrap(), "/price$5");
+        assert_eq!(
+            expand_vars("/a$${HOME}", str::to_string).unwrap(),
+            "/a${HOME}"
+        );
+        assert!(expand_vars("/${LIBREDEFENDER_TEST_UNSET}/a", str::to_string).is_err());
+        assert!(expand_vars("/${HOME", str::to_string).is_err());
+        assert!(expand_vars("/${}", str::to_string).is_err());
+    }
+}
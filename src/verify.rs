@@ -0,0 +1,419 @@
+use crate::config::UpdateConfig;
+use crate::errors::*;
+use crate::utils;
+use chrono::{DateTime, TimeZone, Utc};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::NamedTempFile;
+
+/// The header in front of the compressed database
+const HEADER_SIZE: usize = 512;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Valid,
+    Tampered(String),
+    Unchecked(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, w: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Valid => write!(w, "ok"),
+            Outcome::Tampered(reason) => write!(w, "tampered ({})", reason),
+            Outcome::Unchecked(reason) => write!(w, "not checked ({})", reason),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Verification {
+    pub path: PathBuf,
+    pub md5: Outcome,
+    pub signature: Outcome,
+}
+
+impl Verification {
+    #[must_use]
+    pub fn is_tampered(&self) -> bool {
+        matches!(self.md5, Outcome::Tampered(_)) || matches!(self.signature, Outcome::Tampered(_))
+    }
+}
+
+/// The md5 of the compressed data is the sixth field of the header
+fn parse_md5(header: &[u8]) -> Result<String> {
+    let field = header
+        .split(|b| *b == b':')
+        .nth(5)
+        .context("Failed to select md5 field")?;
+    let md5 = std::str::from_utf8(field).context("md5 field is not valid utf8")?;
+    if md5.len() != 32 || !md5.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!("md5 field is malformed: {:?}", md5);
+    }
+    Ok(md5.to_lowercase())
+}
+
+/// The version information in the header of a `.cvd` or `.cld` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseInfo {
+    pub version: u32,
+    pub signatures: u64,
+    pub built: Option<DateTime<Utc>>,
+}
+
+/// `ClamAV-VDB:<build time>:<version>:<signatures>:<level>:<md5>:<dsig>:<builder>:<unix time>`
+fn parse_header(header: &[u8]) -> Result<DatabaseInfo> {
+    let header = std::str::from_utf8(header).context("Header is not valid utf8")?;
+    let fields = header.trim_end().split(':').collect::<Vec<_>>();
+    if fields.first() != Some(&"ClamAV-VDB") || fields.len() < 4 {
+        bail!("Not a clamav database header");
+    }
+    let version = fields[2]
+        .parse()
+        .with_context(|| anyhow!("Invalid version in header: {:?}", fields[2]))?;
+    let signatures = fields[3]
+        .parse()
+        .with_context(|| anyhow!("Invalid signature count in header: {:?}", fields[3]))?;
+    let built = fields
+        .get(8)
+        .and_then(|time| time.trim().parse().ok())
+        .and_then(|time| Utc.timestamp_opt(time, 0).single());
+    Ok(DatabaseInfo {
+        version,
+        signatures,
+        built,
+    })
+}
+
+/// Read the header of a `.cvd` or `.cld` file, freshclam keeps it current for incremental updates
+pub fn database_info(path: &Path) -> Result<DatabaseInfo> {
+    let mut file = File::open(path).with_context(|| anyhow!("Failed to open {:?}", path))?;
+    let mut header = [0; HEADER_SIZE];
+    file.read_exact(&mut header)
+        .with_context(|| anyhow!("Failed to read header of {:?}", path))?;
+    parse_header(&header).with_context(|| anyhow!("Failed to parse header of {:?}", path))
+}
+
+fn check_md5(path: &Path) -> Result<Outcome> {
+    let mut file = File::open(path).with_context(|| anyhow!("Failed to open {:?}", path))?;
+    let mut header = [0; HEADER_SIZE];
+    if file.read_exact(&mut header).is_err() {
+        return Ok(Outcome::Tampered("header is truncated".to_string()));
+    }
+    let expected = match parse_md5(&header) {
+        Ok(md5) => md5,
+        Err(err) => return Ok(Outcome::Tampered(format!("{:#}", err))),
+    };
+
+    let mut hasher = Md5::new();
+    io::copy(&mut file, &mut hasher).with_context(|| anyhow!("Failed to read {:?}", path))?;
+    let actual = format!("{:x}", hasher.finalize());
+
+    if actual == expected {
+        Ok(Outcome::Valid)
+    } else {
+        Ok(Outcome::Tampered(format!(
+            "md5 is {}, header says {}",
+            actual, expected
+        )))
+    }
+}
+
+/// The digital signature is checked with sigtool, it ships with clamav
+fn check_signature(path: &Path) -> Outcome {
+    let output = match Command::new("sigtool").arg("--info").arg(path).output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Outcome::Unchecked("sigtool is not installed".to_string())
+        }
+        Err(err) => return Outcome::Unchecked(format!("failed to run sigtool: {:#}", err)),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.contains("Verification OK") {
+        Outcome::Valid
+    } else if stdout.contains("Verification FAILED") {
+        Outcome::Tampered("digital signature is invalid".to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Outcome::Unchecked(format!(
+            "sigtool exited with {}: {}",
+            output.status,
+            stderr.trim()
+        ))
+    }
+}
+
+/// Verify a single `.cvd` or `.cld` file, the digital signature is only checked if `signature` is set
+pub fn verify_file(path: &Path, signature: bool) -> Result<Verification> {
+    let verification = if path.extension().is_some_and(|ext| ext == "cvd") {
+        Verification {
+            path: path.to_path_buf(),
+            md5: check_md5(path)?,
+            signature: if signature {
+                check_signature(path)
+            } else {
+                Outcome::Unchecked("skipped".to_string())
+            },
+        }
+    } else {
+        // freshclam writes incremental updates without md5 and digital signature, only the cdiffs were signed
+        let reason = "incremental update, can't be verified";
+        Verification {
+            path: path.to_path_buf(),
+            md5: Outcome::Unchecked(reason.to_string()),
+            signature: Outcome::Unchecked(reason.to_string()),
+        }
+    };
+    Ok(verification)
+}
+
+/// Find the `.cvd` and `.cld` files in the configured database directories
+pub fn databases(config: &UpdateConfig) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for dir in std::iter::once(&config.path).chain(config.paths.iter()) {
+        let entries = fs::read_dir(dir)
+            .with_context(|| anyhow!("Failed to read database directory {:?}", dir))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|ext| ext == "cvd" || ext == "cld")
+            {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub fn verify(config: &UpdateConfig, signature: bool) -> Result<Vec<Verification>> {
+    databases(config)?
+        .iter()
+        .map(|path| verify_file(path, signature))
+        .collect()
+}
+
+/// When a database was verified, it's checked again once it's modified
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Stamp {
+    modified: i64,
+    modified_nsec: i64,
+    size: u64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Result<Stamp> {
+        let md = fs::metadata(path).with_context(|| anyhow!("Failed to access {:?}", path))?;
+        Ok(Stamp {
+            modified: md.mtime(),
+            modified_nsec: md.mtime_nsec(),
+            size: md.len(),
+        })
+    }
+}
+
+/// `~/.local/share/libredefender/cache/verified.json`, the databases that passed `before_load`
+fn cache_path() -> Result<PathBuf> {
+    Ok(utils::data_dir()?.join("cache").join("verified.json"))
+}
+
+fn load_verified(path: &Path) -> HashMap<PathBuf, Stamp> {
+    let buf = match fs::read(path) {
+        Ok(buf) => buf,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return HashMap::new(),
+        Err(err) => {
+            warn!(
+                "Failed to read {:?}, verifying all databases: {:#}",
+                path, err
+            );
+            return HashMap::new();
+        }
+    };
+    serde_json::from_slice(&buf).unwrap_or_else(|err| {
+        warn!(
+            "Failed to parse {:?}, verifying all databases: {:#}",
+            path, err
+        );
+        HashMap::new()
+    })
+}
+
+fn store_verified(path: &Path, verified: &HashMap<PathBuf, Stamp>) -> Result<()> {
+    let dir = path
+        .parent()
+        .context("Cache path has no parent directory")?;
+    fs::create_dir_all(dir).with_context(|| anyhow!("Failed to create {:?}", dir))?;
+    let mut tmp = NamedTempFile::new_in(dir)?;
+    serde_json::to_writer(&mut tmp, verified)?;
+    tmp.persist(path)
+        .with_context(|| anyhow!("Failed to write {:?}", path))?;
+    Ok(())
+}
+
+/// Verify the `.cvd` files that changed since they were verified, returns true if `verified` was updated
+fn verify_changed(
+    files: &[PathBuf],
+    verified: &mut HashMap<PathBuf, Stamp>,
+    signature: bool,
+    refuse_tampered: bool,
+) -> Result<bool> {
+    let mut changed = false;
+    for path in files {
+        if path.extension().is_none_or(|ext| ext != "cvd") {
+            // freshclam writes the patched database with the md5 and digital signature fields
+            // cleared, only the cdiffs it applied were signed
+            debug!("Can't verify incrementally updated database {:?}", path);
+            continue;
+        }
+        let stamp = Stamp::of(path)?;
+        if verified.get(path) == Some(&stamp) {
+            debug!("Database {:?} was already verified", path);
+            continue;
+        }
+        let verification = verify_file(path, signature)?;
+        let tampered = [&verification.md5, &verification.signature]
+            .iter()
+            .find_map(|outcome| match outcome {
+                Outcome::Tampered(reason) => Some(reason),
+                _ => None,
+            });
+        if let Some(reason) = tampered {
+            if refuse_tampered {
+                bail!("Refusing to load tampered database {:?}: {}", path, reason);
+            }
+            warn!("Loading tampered database {:?}: {}", path, reason);
+            changed |= verified.remove(path).is_some();
+            continue;
+        }
+        if let Outcome::Unchecked(reason) = &verification.signature {
+            warn!(
+                "Digital signature of {:?} was not checked: {}",
+                path, reason
+            );
+        }
+        verified.insert(path.clone(), stamp);
+        changed = true;
+    }
+    Ok(changed)
+}
+
+/// Check the md5 and digital signature of every `.cvd` file before an engine loads them
+///
+/// Files that passed are remembered with their modification time and size, they aren't
+/// hashed again until freshclam replaces them.
+pub fn before_load(config: &UpdateConfig) -> Result<()> {
+    debug!("Verifying signature databases...");
+    let cache_path = cache_path()?;
+    let mut verified = load_verified(&cache_path);
+    if verify_changed(
+        &databases(config)?,
+        &mut verified,
+        true,
+        config.refuse_tampered,
+    )? {
+        if let Err(err) = store_verified(&cache_path, &verified) {
+            warn!("Failed to remember verified databases: {:#}", err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_cvd(path: &Path, body: &[u8]) {
+        let md5 = format!("{:x}", Md5::digest(body));
+        let header = format!(
+            "ClamAV-VDB:09 May 2021 07-08 -0400:26165:3978101:63:{}:X:raynman:1620558516",
+            md5
+        );
+        let mut data = format!("{:<512}", header).into_bytes();
+        data.extend_from_slice(body);
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn test_parse_md5() {
+        let md5 = parse_md5(
+            b"ClamAV-VDB:09 May 2021 07-08 -0400:26165:3978101:63:D41D8CD98F00B204E9800998ECF8427E:X:raynman:1620558516    ",
+        )
+        .unwrap();
+        assert_eq!(md5, "d41d8cd98f00b204e9800998ecf8427e");
+    }
+
+    #[test]
+    fn test_database_info() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("daily.cvd");
+        write_cvd(&path, b"compressed signatures");
+        assert_eq!(
+            database_info(&path).unwrap(),
+            DatabaseInfo {
+                version: 26165,
+                signatures: 3978101,
+                built: Utc.timestamp_opt(1620558516, 0).single(),
+            }
+        );
+        assert!(parse_header(b"not a database").is_err());
+    }
+
+    #[test]
+    fn test_detect_tampered_cvd() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("daily.cvd");
+
+        write_cvd(&path, b"compressed signatures");
+        assert_eq!(check_md5(&path).unwrap(), Outcome::Valid);
+
+        let mut data = fs::read(&path).unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        fs::write(&path, data).unwrap();
+        assert!(matches!(check_md5(&path).unwrap(), Outcome::Tampered(_)));
+    }
+
+    #[test]
+    fn test_verify_changed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("daily.cvd");
+        write_cvd(&path, b"compressed signatures");
+        let files = vec![path.clone(), tmp.path().join("main.cld")];
+
+        let mut verified = HashMap::new();
+        assert!(verify_changed(&files, &mut verified, false, true).unwrap());
+        assert_eq!(verified.len(), 1);
+        assert!(!verify_changed(&files, &mut verified, false, true).unwrap());
+
+        // files aren't hashed again until they are modified
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let mut data = fs::read(&path).unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        fs::write(&path, &data).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(!verify_changed(&files, &mut verified, false, true).unwrap());
+
+        let later = modified + std::time::Duration::from_secs(1);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(verify_changed(&files, &mut verified, false, true).is_err());
+        assert!(verify_changed(&files, &mut verified, false, false).unwrap());
+        assert!(verified.is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+use crate::config::Config;
+use crate::scan;
+use crate::verify;
+use chrono::SecondsFormat;
+
+/// Cargo features this binary was built with
+const FEATURES: &[(&str, bool)] = &[
+    ("libclamav", cfg!(feature = "libclamav")),
+    ("async", cfg!(feature = "async")),
+];
+
+#[must_use]
+pub fn features() -> Vec<&'static str> {
+    FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// The details bug reports need, `config` is `None` if it failed to load
+#[must_use]
+pub fn verbose(config: Option<&Config>) -> Vec<(String, String)> {
+    let features = features();
+    let mut info = vec![
+        (
+            "libredefender".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        ("libclamav".to_string(), scan::clamav_version()),
+        (
+            "features".to_string(),
+            if features.is_empty() {
+                "none".to_string()
+            } else {
+                features.join(", ")
+            },
+        ),
+        (
+            "target".to_string(),
+            format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS),
+        ),
+    ];
+
+    let Some(config) = config else {
+        return info;
+    };
+    info.push((
+        "backend".to_string(),
+        format!("{:?}", config.scan.backend).to_lowercase(),
+    ));
+    match verify::databases(&config.update) {
+        Ok(databases) => {
+            for path in databases {
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let value = match verify::database_info(&path) {
+                    Ok(db) => {
+                        let built = db.built.map_or_else(
+                            || "unknown".to_string(),
+                            |built| built.to_rfc3339_opts(SecondsFormat::Secs, true),
+                        );
+                        format!(
+                            "version {}, {} signatures, built {}",
+                            db.version, db.signatures, built
+                        )
+                    }
+                    Err(err) => format!("{:#}", err),
+                };
+                info.push((name, value));
+            }
+        }
+        Err(err) => info.push(("databases".to_string(), format!("{:#}", err))),
+    }
+    info
+}
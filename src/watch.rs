@@ -0,0 +1,193 @@
+use crate::args;
+use crate::audit::{self, Action};
+use crate::canary::Canaries;
+use crate::config::{self, NotifyConfig, ScanConfig};
+use crate::db::{Database, Threat};
+use crate::describe::Descriptions;
+use crate::errors::*;
+use crate::heuristic;
+use crate::hooks::ThreatHooks;
+use crate::notify;
+use crate::privdrop;
+use crate::reload::{self, SharedScanner};
+use crate::scan::{self, Scanner};
+use chrono::Utc;
+use inotify::{Inotify, WatchDescriptor, WatchMask};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Apply the same filters as a regular scan to a single file
+fn should_scan(config: &ScanConfig, path: &Path) -> bool {
+    match scan::file_filter_reason(config, path) {
+        Some(reason) => {
+            debug!("Skipping path {}: {}", path.display(), reason);
+            false
+        }
+        None => true,
+    }
+}
+
+fn record_threat(
+    config: &NotifyConfig,
+    descriptions: &Descriptions,
+    hooks: &ThreatHooks,
+    path: PathBuf,
+    name: String,
+) -> Result<()> {
+    let path = fs::canonicalize(&path).unwrap_or(path);
+
+    if let Err(err) = notify::threat(config, descriptions, &path, &name) {
+        warn!("Failed to display notification: {:#}", err);
+    }
+    hooks.fire(&path, &name);
+    let entry = audit::Entry::new(Action::Detected, &path, std::slice::from_ref(&name));
+    if let Err(err) = audit::record(&entry) {
+        warn!("Failed to write audit log: {:#}", err);
+    }
+
+    // reload the database every time, a regular scan may have written it in the meantime
+    let mut db = Database::load().context("Failed to load database")?;
+    let threat = db
+        .data_mut()
+        .threats
+        .entry(path)
+        .or_insert_with(|| Threat::new(Utc::now()));
+    threat.add_name(name);
+    db.store().context("Failed to write database")?;
+
+    Ok(())
+}
+
+pub fn run(config_path: Option<&Path>, args: args::Watch) -> Result<()> {
+    let config = config::load(config_path, None).context("Failed to load config")?;
+
+    let paths = if !args.paths.is_empty() {
+        args.paths
+    } else if !config.watch.paths.is_empty() {
+        config.watch.paths.clone()
+    } else {
+        let download_dir = dirs::download_dir().context("Failed to find downloads directory")?;
+        vec![download_dir]
+    };
+
+    let scanner = Scanner::from_config(&config)?;
+    privdrop::apply(config.scan.user.as_deref()).context("Failed to drop privileges")?;
+    let scanner = SharedScanner::new(scanner);
+    if config.update.reload_on_change {
+        if let Err(err) = reload::watch(&scanner, &config) {
+            warn!("Failed to watch for signature updates: {:#}", err);
+        }
+    }
+
+    let mut inotify = Inotify::init().context("Failed to initialize inotify")?;
+    let mut watches = HashMap::<WatchDescriptor, PathBuf>::new();
+    for path in paths {
+        // browsers write to a temporary file and rename it once the download is complete
+        let wd = inotify
+            .watches()
+            .add(&path, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+            .with_context(|| anyhow!("Failed to watch directory {:?}", path))?;
+        info!("Watching directory {}", path.display());
+        watches.insert(wd, path);
+    }
+
+    let mut canaries = if config.canary.enabled {
+        Some(Canaries::plant(&config.canary, &mut inotify)?)
+    } else {
+        None
+    };
+
+    let descriptions = Descriptions::from_config(&config.update);
+    let hooks = ThreatHooks::spawn(&config.hooks);
+    let (results_tx, results_rx) = crossbeam_channel::unbounded();
+    let mut buf = [0; 4096];
+    loop {
+        let events = inotify
+            .read_events_blocking(&mut buf)
+            .context("Failed to read inotify events")?;
+
+        let mut canary_events = Vec::new();
+        for event in events {
+            if canaries.as_ref().is_some_and(|c| c.contains(&event.wd)) {
+                canary_events.push(event.wd.clone());
+                continue;
+            }
+            let (Some(dir), Some(name)) = (watches.get(&event.wd), event.name) else {
+                continue;
+            };
+            let path = dir.join(name);
+            let ret = scan::catch_panic(&path, || {
+                if should_scan(&config.scan, &path) {
+                    if config.scan.builtin_heuristics {
+                        if let Some(name) = heuristic::check(&path) {
+                            results_tx.send((path.clone(), name)).ok();
+                        }
+                    }
+                    scanner.get().scan_file(&path, &results_tx)?;
+                }
+                Ok(())
+            });
+            if let Err(err) = ret {
+                error!("{:#}", err);
+            }
+        }
+
+        if let Some(canaries) = &mut canaries {
+            for wd in canary_events {
+                canaries.check(&mut inotify, wd, &config.notify);
+            }
+        }
+
+        for (path, name) in results_rx.try_iter() {
+            if let Err(err) = record_threat(&config.notify, &descriptions, &hooks, path, name) {
+                error!("Failed to record threat: {:#}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HumanSize;
+    use crate::patterns::Pattern;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_should_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("invoice.pdf");
+        fs::write(&file, b"%PDF-").unwrap();
+        let hidden = dir.path().join(".invoice.pdf");
+        fs::write(&hidden, b"%PDF-").unwrap();
+        let excluded = dir.path().join("setup.iso");
+        fs::write(&excluded, b"CD001").unwrap();
+        let large = dir.path().join("video.mp4");
+        fs::write(&large, [0; 64]).unwrap();
+
+        let config = ScanConfig {
+            skip_hidden: true,
+            excludes: vec![Pattern::from_str("**/*.iso").unwrap()],
+            skip_larger_than: Some(HumanSize::from_str("10 B").unwrap()),
+            ..Default::default()
+        };
+        assert!(should_scan(&config, &file));
+        assert!(!should_scan(&config, &hidden));
+        assert!(!should_scan(&config, &excluded));
+        assert!(!should_scan(&config, &large));
+        assert!(!should_scan(&config, dir.path()));
+        assert!(!should_scan(&config, &dir.path().join("missing")));
+
+        // ignore files are honored like during a scan
+        let research = dir.path().join("research");
+        fs::create_dir(&research).unwrap();
+        fs::write(research.join(".nocheck"), "").unwrap();
+        let sample = research.join("sample.exe");
+        fs::write(&sample, b"MZ").unwrap();
+        let mut config = ScanConfig::default();
+        assert!(should_scan(&config, &sample));
+        config.ignore_files = Some(true);
+        assert!(!should_scan(&config, &sample));
+    }
+}
@@ -1,10 +1,10 @@
 use crossbeam_channel::Receiver;
-use env_logger::Env;
-use libredefender::config::ScanConfig;
+use libredefender::config::{Config, ScanConfig};
 use libredefender::errors::*;
 use libredefender::patterns::Pattern;
 use libredefender::scan;
-use libredefender::scan::Scanner;
+use libredefender::scan::{ScanJob, Scanner, Visited};
+use libredefender::utils;
 use std::env;
 use std::fs;
 use std::mem;
@@ -13,13 +13,16 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
+use tracing_subscriber::EnvFilter;
 use walkdir::DirEntry;
 
 const EICAR: &str = "X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
 
 fn init() {
-    let _ = env_logger::Builder::from_env(Env::default().default_filter_or("info"))
-        .is_test(true)
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_test_writer()
         .try_init();
 }
 
@@ -206,3 +209,108 @@ fn test_skips_excluded_files_by_absolute_directory_path() {
 
     assert!(results_rx.recv().is_err());
 }
+
+#[test]
+#[ignore]
+fn test_visits_directory_only_once() {
+    init();
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+
+    let eicar_file_path = tmp_dir.path().join("eicar.txt");
+    fs::write(eicar_file_path, EICAR).unwrap();
+
+    let (results_tx, results_rx) = crossbeam_channel::unbounded();
+    let (fs_tx, fs_rx) = crossbeam_channel::unbounded::<DirEntry>();
+
+    // the same directory is reachable twice, like with a bind mount
+    let cfg = ScanConfig::default();
+    let mut visited = Visited::default();
+    scan::ingest_directory_with(&cfg, &mut visited, &fs_tx, tmp_dir.path());
+    scan::ingest_directory_with(&cfg, &mut visited, &fs_tx, tmp_dir.path());
+    mem::drop(fs_tx);
+
+    let scanner = Scanner::new(&clamav_dir()).unwrap();
+    for entry in fs_rx {
+        scanner.scan_file(entry.path(), &results_tx).unwrap();
+    }
+    mem::drop(results_tx);
+
+    assert_eq!(results_rx.iter().count(), 1);
+}
+
+#[test]
+#[ignore]
+fn test_follow_symlinks_with_loop() {
+    init();
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+
+    let dir = tmp_dir.path().join("dir");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("eicar.txt"), EICAR).unwrap();
+    std::os::unix::fs::symlink(tmp_dir.path(), dir.join("loop")).unwrap();
+
+    let scan_config = ScanConfig {
+        follow_symlinks: true,
+        ..Default::default()
+    };
+    let results_rx = run_scan(&scan_config, tmp_dir.path());
+
+    let (_path, res) = results_rx.recv().unwrap();
+    assert_eq!(res, "Win.Test.EICAR_HDB-1");
+
+    assert!(results_rx.recv().is_err());
+}
+
+#[test]
+#[ignore]
+fn test_max_depth() {
+    init();
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+
+    let deep_dir = tmp_dir.path().join("a").join("b");
+    fs::create_dir_all(&deep_dir).unwrap();
+    fs::write(deep_dir.join("eicar.txt"), EICAR).unwrap();
+
+    let scan_config = ScanConfig {
+        max_depth: Some(2),
+        ..Default::default()
+    };
+    let results_rx = run_scan(&scan_config, tmp_dir.path());
+
+    assert!(results_rx.recv().is_err());
+}
+
+#[test]
+#[ignore]
+fn test_clean_cache_hits_are_not_scans() {
+    init();
+
+    let data_dir = tempfile::tempdir().unwrap();
+    utils::set_data_dir(data_dir.path().to_path_buf());
+    let tmp_dir = tempfile::tempdir().unwrap();
+    for i in 0..3 {
+        fs::write(tmp_dir.path().join(format!("clean_{}.txt", i)), "no virus").unwrap();
+    }
+
+    let scan = || {
+        let config = serde_json::from_value::<Config>(serde_json::json!({
+            "scan": {"clean_cache": true},
+            "update": {"path": clamav_dir(), "reload_on_change": false},
+        }))
+        .unwrap();
+        ScanJob::new(config)
+            .paths(vec![tmp_dir.path().to_path_buf()])
+            .run()
+            .unwrap()
+    };
+
+    let first = scan();
+    assert_eq!((first.files_scanned, first.files_cached), (3, 0));
+    // the second scan only hashes the files, they aren't counted as scanned
+    let second = scan();
+    assert_eq!((second.files_scanned, second.files_cached), (0, 3));
+    assert_eq!(second.bytes_scanned, 0);
+}